@@ -52,6 +52,35 @@ cargo build \
     --features=embassy-usb-0_3-server \
     --target thumbv7em-none-eabihf
 
+# Embedded + shared memory server impl
+cargo check \
+    --manifest-path source/postcard-rpc/Cargo.toml \
+    --no-default-features \
+    --features=spsc-server \
+    --target thumbv7em-none-eabihf
+cargo test \
+    --manifest-path source/postcard-rpc/Cargo.toml \
+    --no-default-features \
+    --features=spsc-server
+
+# Embedded + queued TX
+cargo check \
+    --manifest-path source/postcard-rpc/Cargo.toml \
+    --no-default-features \
+    --features=channel-sender \
+    --target thumbv7em-none-eabihf
+cargo test \
+    --manifest-path source/postcard-rpc/Cargo.toml \
+    --no-default-features \
+    --features=channel-sender
+
+# Embedded client
+cargo check \
+    --manifest-path source/postcard-rpc/Cargo.toml \
+    --no-default-features \
+    --features=embedded-client \
+    --target thumbv7em-none-eabihf
+
 # Example projects
 cargo build \
     --manifest-path example/workbook-host/Cargo.toml
@@ -1,260 +1,15 @@
-use core::{
-    sync::atomic::{AtomicUsize, Ordering},
-    time::Duration,
-};
-use std::{sync::Arc, time::Instant};
-
-use postcard_schema::{schema::owned::OwnedNamedType, Schema};
-use serde::{Deserialize, Serialize};
-use tokio::{sync::mpsc, task::yield_now, time::timeout};
-
-use postcard_rpc::{
-    define_dispatch, endpoints,
-    header::{VarHeader, VarKey, VarKeyKind, VarSeq, VarSeqKind},
-    host_client::{test_channels as client, HostClient},
-    server::{
-        impls::test_channels::{
-            dispatch_impl::{new_server, new_server_stoppable, spawn_fn, Settings, WireSpawnImpl, WireTxImpl},
-            ChannelWireRx, ChannelWireSpawn, ChannelWireTx,
-        },
-        Dispatch, Sender, SpawnContext,
-    },
-    topics, Endpoint, Topic,
-};
-
-#[derive(Serialize, Deserialize, Schema)]
-pub struct AReq(pub u8);
-#[derive(Serialize, Deserialize, Schema)]
-pub struct AResp(pub u8);
-#[derive(Serialize, Deserialize, Schema)]
-pub struct BReq(pub u16);
-#[derive(Serialize, Deserialize, Schema)]
-pub struct BResp(pub u32);
-#[derive(Serialize, Deserialize, Schema)]
-pub struct GReq;
-#[derive(Serialize, Deserialize, Schema)]
-pub struct GResp;
-#[derive(Serialize, Deserialize, Schema)]
-pub struct DReq;
-#[derive(Serialize, Deserialize, Schema)]
-pub struct DResp;
-#[derive(Serialize, Deserialize, Schema)]
-pub struct EReq;
-#[derive(Serialize, Deserialize, Schema)]
-pub struct EResp;
-#[derive(Serialize, Deserialize, Schema)]
-pub struct ZMsg(pub i16);
-
-#[cfg(feature = "alpha")]
-#[derive(Serialize, Deserialize, Schema)]
-pub struct Message<'a> {
-    data: &'a str,
-}
-
-#[cfg(not(feature = "alpha"))]
-#[derive(Serialize, Deserialize, Schema)]
-pub struct Message {
-    data: String,
-}
-
-#[derive(Serialize, Deserialize, Schema)]
-pub struct DoubleMessage<'a, 'b> {
-    data1: &'a str,
-    data2: &'b str,
-}
-
-endpoints! {
-    list = ENDPOINT_LIST;
-    | EndpointTy        | RequestTy             | ResponseTy            | Path              | Cfg                    |
-    | ----------        | ---------             | ----------            | ----              | ---                    |
-    | AlphaEndpoint     | AReq                  | AResp                 | "alpha"           |                        |
-    | BetaEndpoint      | BReq                  | BResp                 | "beta"            |                        |
-    | GammaEndpoint     | GReq                  | GResp                 | "gamma"           |                        |
-    | DeltaEndpoint     | DReq                  | DResp                 | "delta"           |                        |
-    | EpsilonEndpoint   | EReq                  | EResp                 | "epsilon"         |                        |
-    | BorrowEndpoint1   | Message<'a>           | u8                    | "borrow1"         | cfg(feature = "alpha") |
-    | BorrowEndpoint2   | ()                    | Message<'a>           | "borrow2"         |                        |
-    | BorrowEndpoint3   | Message<'a>           | Message<'b>           | "borrow3"         |                        |
-    | BorrowEndpoint4   | DoubleMessage<'a, 'b> | DoubleMessage<'c, 'd> | "borrow4"         |                        |
-}
-
-topics! {
-    list = TOPICS_IN_LIST;
-    direction = postcard_rpc::TopicDirection::ToServer;
-    | TopicTy       | MessageTy             | Path      | Cfg                           |
-    | ----------    | ---------             | ----      | ---                           |
-    | ZetaTopic1    | ZMsg                  | "zeta1"   |                               |
-    | ZetaTopic2    | ZMsg                  | "zeta2"   |                               |
-    | ZetaTopic3    | ZMsg                  | "zeta3"   |                               |
-    | BorrowTopic   | Message<'a>           | "msg1"    | cfg(feature = "alpha")        |
-    | BorrowTopic   | DoubleMessage<'a, 'b> | "msg1"    | cfg(not(feature = "alpha"))   |
-    | BerpTopic1    | u8                    | "empty"   |                               |
-    | BerpTopic2    | ()                    | "empty"   |                               |
-}
-
-topics! {
-    list = TOPICS_OUT_LIST;
-    direction = postcard_rpc::TopicDirection::ToClient;
-    | TopicTy           | MessageTy     | Path              |
-    | ----------        | ---------     | ----              |
-    | ZetaTopic10       | ZMsg          | "zeta10"          |
-}
-
-pub struct TestContext {
-    pub ctr: Arc<AtomicUsize>,
-    pub topic_ctr: Arc<AtomicUsize>,
-    pub msg: String,
-}
-
-pub struct TestSpawnContext {
-    pub ctr: Arc<AtomicUsize>,
-    pub topic_ctr: Arc<AtomicUsize>,
-}
-
-impl SpawnContext for TestContext {
-    type SpawnCtxt = TestSpawnContext;
-
-    fn spawn_ctxt(&mut self) -> Self::SpawnCtxt {
-        TestSpawnContext {
-            ctr: self.ctr.clone(),
-            topic_ctr: self.topic_ctr.clone(),
-        }
-    }
-}
-
-define_dispatch! {
-    app: SingleDispatcher;
-    spawn_fn: spawn_fn;
-    tx_impl: WireTxImpl;
-    spawn_impl: WireSpawnImpl;
-    context: TestContext;
-
-    endpoints: {
-        list: ENDPOINT_LIST;
-
-        | EndpointTy        | kind      | handler                   |
-        | ----------        | ----      | -------                   |
-        | AlphaEndpoint     | async     | test_alpha_handler        |
-        | BetaEndpoint      | spawn     | test_beta_handler         |
-        | BorrowEndpoint1   | blocking  | test_borrowep_blocking    |
-        | BorrowEndpoint2   | blocking  | test_borrowep_blocking2   |
-    };
-    topics_in: {
-        list: TOPICS_IN_LIST;
-
-        | TopicTy           | kind      | handler               |
-        | ----------        | ----      | -------               |
-        | ZetaTopic1        | blocking  | test_zeta_blocking    |
-        | ZetaTopic2        | async     | test_zeta_async       |
-        | ZetaTopic3        | spawn     | test_zeta_spawn       |
-        | BorrowTopic       | blocking  | test_borrow_blocking  |
-    };
-    topics_out: {
-        list: TOPICS_OUT_LIST;
-    };
-}
-
-fn test_borrowep_blocking2(
-    context: &mut TestContext,
-    _header: VarHeader,
-    _body: (),
-) -> Message<'_> {
-    Message {
-        data: context.msg.as_str(),
-    }
-}
-
-fn test_borrowep_blocking(
-    _context: &mut TestContext,
-    _header: VarHeader,
-    _body: Message<'_>,
-) -> u8 {
-    0
-}
-
-fn test_zeta_blocking(
-    context: &mut TestContext,
-    _header: VarHeader,
-    _body: ZMsg,
-    _out: &Sender<ChannelWireTx>,
-) {
-    context.topic_ctr.fetch_add(1, Ordering::Relaxed);
-}
-
-fn test_borrow_blocking(
-    context: &mut TestContext,
-    _header: VarHeader,
-    _body: Message,
-    _out: &Sender<ChannelWireTx>,
-) {
-    context.topic_ctr.fetch_add(1, Ordering::Relaxed);
-}
-
-async fn test_zeta_async(
-    context: &mut TestContext,
-    _header: VarHeader,
-    _body: ZMsg,
-    _out: &Sender<ChannelWireTx>,
-) {
-    context.topic_ctr.fetch_add(1, Ordering::Relaxed);
-}
-
-async fn test_zeta_spawn(
-    context: TestSpawnContext,
-    _header: VarHeader,
-    _body: ZMsg,
-    _out: Sender<ChannelWireTx>,
-) {
-    context.topic_ctr.fetch_add(1, Ordering::Relaxed);
-}
-
-async fn test_alpha_handler(context: &mut TestContext, _header: VarHeader, body: AReq) -> AResp {
-    context.ctr.fetch_add(1, Ordering::Relaxed);
-    AResp(body.0)
-}
+mod common;
 
-async fn test_beta_handler(
-    context: TestSpawnContext,
-    header: VarHeader,
-    body: BReq,
-    out: Sender<ChannelWireTx>,
-) {
-    context.ctr.fetch_add(1, Ordering::Relaxed);
-    let _ = out
-        .reply::<BetaEndpoint>(header.seq_no, &BResp(body.0.into()))
-        .await;
-}
+use common::*;
 
 #[tokio::test]
 async fn smoke() {
-    let (client_tx, server_rx) = mpsc::channel(16);
-    let (server_tx, mut client_rx) = mpsc::channel(16);
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
-
-    let app = SingleDispatcher::new(
-        TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
-            topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
-        },
-        ChannelWireSpawn {},
-    );
-
-    let cwrx = ChannelWireRx::new(server_rx);
-    let cwtx = ChannelWireTx::new(server_tx);
-    let kkind = app.min_key_len();
-    let mut server = new_server(
-        app,
-        Settings {
-            tx: cwtx,
-            rx: cwrx,
-            buf: 1024,
-            kkind,
-        },
-    );
-    tokio::task::spawn(async move {
-        server.run().await;
-    });
+    let SingleFixture {
+        client_tx,
+        mut client_rx,
+        topic_ctr,
+        ..
+    } = single_server(None, |_| {});
 
     // manually build request - Alpha
     let mut msg = VarHeader {
@@ -371,38 +126,195 @@ async fn smoke() {
 
 #[tokio::test]
 async fn end_to_end() {
-    let (client_tx, server_rx) = mpsc::channel(16);
-    let (server_tx, client_rx) = mpsc::channel(16);
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
 
-    let app = SingleDispatcher::new(
-        TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
-            topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
-        },
-        ChannelWireSpawn {},
-    );
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
 
-    let cwrx = ChannelWireRx::new(server_rx);
-    let cwtx = ChannelWireTx::new(server_tx);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    let resp = cli.send_resp::<BetaEndpoint>(&BReq(1234)).await.unwrap();
+    assert_eq!(resp.0, 1234);
+}
 
-    let kkind = app.min_key_len();
-    let mut server = new_server(
-        app,
-        Settings {
-            tx: cwtx,
-            rx: cwrx,
-            buf: 1024,
-            kkind,
-        },
-    );
+#[tokio::test]
+async fn end_to_end_batch() {
+    let SingleFixture {
+        client_tx: tap_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+    let (client_tx, mut tap_rx) = mpsc::channel::<Vec<u8>>(16);
+
+    // Count the transfers from the client to the server
+    let transfers = Arc::new(AtomicUsize::new(0));
+    let tap_ctr = transfers.clone();
     tokio::task::spawn(async move {
-        server.run().await;
+        while let Some(frame) = tap_rx.recv().await {
+            tap_ctr.fetch_add(1, Ordering::Relaxed);
+            if tap_tx.send(frame).await.is_err() {
+                break;
+            }
+        }
     });
 
     let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
 
+    let reqs = [AReq(1), AReq(2), AReq(3), AReq(4)];
+    let resps = cli.call_batch::<AlphaEndpoint>(&reqs).await;
+    assert_eq!(resps.len(), 4);
+    for (i, resp) in resps.into_iter().enumerate() {
+        assert_eq!(resp.unwrap().0, (i + 1) as u8);
+    }
+    assert_eq!(transfers.load(Ordering::Relaxed), 1);
+
+    assert!(cli.call_batch::<AlphaEndpoint>(&[]).await.is_empty());
+    assert_eq!(transfers.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn batch_frames_dispatched_in_order() {
+    let SingleFixture {
+        client_tx,
+        mut client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    // An Alpha and a Beta request, followed by a frame with a bad length prefix
+    let mut alpha = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq4(1),
+    }
+    .write_to_vec();
+    alpha.extend_from_slice(&postcard::to_stdvec(&AReq(42)).unwrap());
+    let mut beta = VarHeader {
+        key: VarKey::Key8(BetaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq4(2),
+    }
+    .write_to_vec();
+    beta.extend_from_slice(&postcard::to_stdvec(&BReq(1000)).unwrap());
+    let mut frames = postcard::to_stdvec(alpha.as_slice()).unwrap();
+    frames.extend_from_slice(&postcard::to_stdvec(beta.as_slice()).unwrap());
+    frames.push(0x80);
+
+    let mut msg = VarHeader {
+        key: VarKey::Key8(BatchTopic::TOPIC_KEY),
+        seq_no: VarSeq::Seq4(1),
+    }
+    .write_to_vec();
+    msg.extend_from_slice(&postcard::to_stdvec(&Batch { frames: &frames }).unwrap());
+    client_tx.send(msg).await.unwrap();
+
+    let resp = client_rx.recv().await.unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&resp).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(AlphaEndpoint::RESP_KEY));
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(1));
+    assert_eq!(postcard::from_bytes::<AResp>(body).unwrap().0, 42);
+
+    let resp = client_rx.recv().await.unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&resp).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(BetaEndpoint::RESP_KEY));
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(2));
+    assert_eq!(postcard::from_bytes::<BResp>(body).unwrap().0, 1000);
+
+    // The malformed frame ends the batch, with an error for the batch itself
+    let resp = client_rx.recv().await.unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&resp).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(ERROR_KEY));
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(1));
+    assert_eq!(postcard::from_bytes::<WireError>(body).unwrap(), WireError::DeserFailed);
+}
+
+#[tokio::test]
+async fn end_to_end_reserved() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let (seq_no, resp_fut) = cli.reserve::<BetaEndpoint>().await.unwrap();
+    assert_eq!(resp_fut.seq_no(), seq_no);
+    let resp_fut = cli.send_reserved(resp_fut, &BReq(1234)).await.unwrap();
+    // Give the server plenty of time to reply before we start awaiting
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let resp = timeout(Duration::from_millis(100), resp_fut.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.0, 1234);
+}
+
+#[tokio::test]
+async fn dedup_retransmit() {
+    let SingleFixture {
+        client_tx,
+        mut client_rx,
+        ctr,
+        ..
+    } = single_server(None, |_| {});
+
+    // Send the same request twice, then a new request
+    for (seq, expected_ctr) in [(10u32, 1), (10, 1), (11, 2)] {
+        let msg = VarHeader {
+            key: VarKey::Key8(GammaEndpoint::REQ_KEY),
+            seq_no: VarSeq::Seq4(seq),
+        }
+        .write_to_vec();
+        client_tx.send(msg).await.unwrap();
+        let resp = client_rx.recv().await.unwrap();
+
+        let (hdr, body) = VarHeader::take_from_slice(&resp).unwrap();
+        let _resp = postcard::from_bytes::<<GammaEndpoint as Endpoint>::Response>(body).unwrap();
+        assert_eq!(hdr.seq_no, VarSeq::Seq4(seq));
+        assert_eq!(ctr.load(Ordering::Relaxed), expected_ctr);
+    }
+}
+
+#[tokio::test]
+async fn borrowed_request() {
+    let SingleFixture {
+        client_tx,
+        mut client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let mut msg = VarHeader {
+        key: VarKey::Key8(BorrowEndpoint4::REQ_KEY),
+        seq_no: VarSeq::Seq4(5),
+    }
+    .write_to_vec();
+    let body = postcard::to_stdvec(&DoubleMessage {
+        data1: "left",
+        data2: "right",
+    })
+    .unwrap();
+    msg.extend_from_slice(&body);
+    client_tx.send(msg).await.unwrap();
+    let resp = client_rx.recv().await.unwrap();
+
+    let (hdr, body) = VarHeader::take_from_slice(&resp).unwrap();
+    let resp = postcard::from_bytes::<DoubleMessage<'_, '_>>(body).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(BorrowEndpoint4::RESP_KEY));
+    assert_eq!(resp.data1, "right");
+    assert_eq!(resp.data2, "left");
+}
+
+#[tokio::test]
+async fn end_to_end_force8() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(Some(VarKeyKind::Key8), |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq4);
+
     let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
     assert_eq!(resp.0, 42);
     let resp = cli.send_resp::<BetaEndpoint>(&BReq(1234)).await.unwrap();
@@ -410,75 +322,284 @@ async fn end_to_end() {
 }
 
 #[tokio::test]
-async fn end_to_end_schema() {
-    let (client_tx, server_rx) = mpsc::channel(16);
+async fn dispatch_stats() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    cli.send_resp::<AlphaEndpoint>(&AReq(2)).await.unwrap();
+    // Not handled by the dispatcher
+    let res = cli.send_resp::<DeltaEndpoint>(&DReq).await;
+    assert!(matches!(res, Err(HostErr::UnknownKey(_))));
+
+    // Two alphas, one delta, and the stats request itself
+    let stats = cli.get_stats(true).await.unwrap();
+    assert_eq!(stats.frames, 4);
+    assert_eq!(stats.errors, 1);
+    let alpha = stats
+        .keys
+        .iter()
+        .find(|kc| kc.key == AlphaEndpoint::REQ_KEY)
+        .unwrap();
+    assert_eq!(alpha.count, 2);
+
+    // Counters were reset after the last report
+    let stats = cli.get_stats(false).await.unwrap();
+    assert_eq!(stats.frames, 1);
+    assert_eq!(stats.errors, 0);
+    assert!(stats.keys.iter().all(|kc| kc.count == 0));
+}
+
+#[tokio::test]
+async fn fragmented_request() {
+    let (client_tx, server_rx) = mpsc::channel(64);
     let (server_tx, client_rx) = mpsc::channel(16);
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
 
-    let app = SingleDispatcher::new(
-        TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
-            topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
-        },
-        ChannelWireSpawn {},
-    );
+    let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
 
     let cwrx = ChannelWireRx::new(server_rx);
     let cwtx = ChannelWireTx::new(server_tx);
-
     let kkind = app.min_key_len();
-    let report = app.device_map;
-    let mut server = new_server(
+    // The receive buffer is much smaller than the request
+    let mut server = new_server_reassembling(
         app,
         Settings {
             tx: cwtx,
             rx: cwrx,
-            buf: 1024,
+            buf: 64,
             kkind,
         },
+        2048,
     );
     tokio::task::spawn(async move {
         server.run().await;
     });
 
-    let cli: HostClient<_> = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
-    let schema = cli.get_schema_report().await.unwrap();
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let req: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let expected: u32 = req.iter().map(|b| u32::from(*b)).sum();
+    let resp = timeout(
+        Duration::from_millis(100),
+        cli.send_resp_fragmented::<FragEndpoint>(&req, 32),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(resp, expected);
+
+    // Frames that fit are still handled normally
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+}
+
+#[tokio::test]
+async fn inline_closure_handler() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let resp = cli.send_resp::<InlineEndpoint>(&21).await.unwrap();
+    assert_eq!(resp, 42);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn ref_handler_response() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let resp = cli.send_resp::<TableEndpoint>(&()).await.unwrap();
+    assert_eq!(resp, TABLE);
+}
+
+#[tokio::test]
+async fn request_length_limit() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // A length prefix and seven bytes fit in the limit
+    let resp = cli.send_resp::<LimitedEndpoint>(&vec![1; 7]).await.unwrap();
+    assert_eq!(resp, 7);
+
+    let err = cli.send_resp::<LimitedEndpoint>(&vec![1; 8]).await.unwrap_err();
+    assert_eq!(
+        err,
+        HostErr::Wire(WireError::FrameTooLong(FrameTooLong { len: 9, max: 8 }))
+    );
+}
+
+#[tokio::test]
+async fn notify_sends_no_reply() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    cli.notify::<NotifyEndpoint>(&3).await.unwrap();
+    cli.notify::<NotifyEndpoint>(&4).await.unwrap();
+
+    // Requests are handled in order, so once this reply arrives, both
+    // notifications have been handled (the alpha handler also counts)
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    assert_eq!(ctr.load(Ordering::Relaxed), 3 + 4 + 1);
+}
+
+#[tokio::test]
+async fn replay_recorded_frames() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let mut app = SingleDispatcher::new(context, ChannelWireSpawn {});
+    let kkind = app.min_key_len();
 
-    for ep in &schema.endpoints {
-        println!("'{}': {} -> {}", ep.path, ep.req_ty, ep.resp_ty);
+    fn frame<T: Serialize>(key: VarKey, seq_no: u16, body: &T) -> Vec<u8> {
+        let mut out = VarHeader {
+            key,
+            seq_no: VarSeq::Seq2(seq_no),
+        }
+        .write_to_vec();
+        out.extend_from_slice(&postcard::to_stdvec(body).unwrap());
+        out
     }
-    for tp in &schema.topics_in {
-        println!("'{}' ---> {}", tp.path, tp.ty);
+    fn shrunk(key: postcard_rpc::Key, kkind: VarKeyKind) -> VarKey {
+        let mut key = VarKey::Key8(key);
+        key.shrink_to(kkind);
+        key
     }
-    for tp in &schema.topics_out {
-        println!("'{}' <--- {}", tp.path, tp.ty);
+
+    // As if captured from the wire
+    let ping = frame(VarKey::Key8(PingEndpoint::REQ_KEY), 1, &42u32);
+    let alpha = frame(VarKey::Key8(AlphaEndpoint::REQ_KEY), 2, &AReq(3));
+    let beta = frame(VarKey::Key8(BetaEndpoint::REQ_KEY), 3, &BReq(4));
+    let replies = replay(&mut app, &[&ping, &[], &alpha, &beta]).await;
+
+    // The golden replies, the empty frame is skipped
+    let expected = vec![
+        frame(shrunk(PingEndpoint::RESP_KEY, kkind), 1, &42u32),
+        frame(shrunk(AlphaEndpoint::RESP_KEY, kkind), 2, &AResp(3)),
+        // Sent by a spawned handler
+        frame(shrunk(BetaEndpoint::RESP_KEY, kkind), 3, &BResp(4)),
+    ];
+    assert_eq!(replies, expected);
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
+
+// Not a `tokio::test`, as `fuzz_dispatch` runs its own runtime
+#[test]
+fn fuzz_dispatch_frames() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let mut app = SingleDispatcher::new(context, ChannelWireSpawn {});
+
+    let mut alpha = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq2(1),
     }
+    .write_to_vec();
+    alpha.extend_from_slice(&postcard::to_stdvec(&AReq(3)).unwrap());
 
-    assert_eq!(schema.endpoints.len(), report.endpoints.len());
-    assert_eq!(schema.topics_in.len(), report.topics_in.len());
-    assert_eq!(schema.topics_out.len(), report.topics_out.len());
+    // Every truncation of a valid frame, only the full frame is handled
+    for len in 0..=alpha.len() {
+        fuzz_dispatch(&mut app, &alpha[..len]);
+    }
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Arbitrary bytes, from a fixed seed
+    let mut seed = 0x2545_F491_u32;
+    for _ in 0..256 {
+        let data: Vec<u8> = (0..16)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        fuzz_dispatch(&mut app, &data);
+    }
 }
 
 #[tokio::test]
-async fn end_to_end_force8() {
+async fn keyed_errors() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |server| server.set_keyed_errors(true));
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let err = cli.send_resp::<DeltaEndpoint>(&DReq).await.unwrap_err();
+    assert_eq!(err, HostErr::UnknownKey(VarKey::Key8(DeltaEndpoint::REQ_KEY)));
+    let resp = cli.send_resp::<PingEndpoint>(&9).await.unwrap();
+    assert_eq!(resp, 9);
+
+    // Now act as the server by hand, to send an error for a different request
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let req = tokio::task::spawn(async move { cli.send_resp::<AlphaEndpoint>(&AReq(1)).await });
+
+    let frame = server_rx.recv().await.unwrap();
+    let (hdr, _body) = VarHeader::take_from_slice(&frame).unwrap();
+    for (key, error) in [
+        (BetaEndpoint::REQ_KEY, WireError::UnknownKey),
+        (AlphaEndpoint::REQ_KEY, WireError::DeserFailed),
+    ] {
+        let mut out = VarHeader {
+            key: VarKey::Key8(KEYED_ERROR_KEY),
+            seq_no: hdr.seq_no,
+        }
+        .write_to_vec();
+        let err = KeyedError {
+            key: VarKey::Key8(key).into(),
+            error,
+        };
+        out.extend_from_slice(&postcard::to_stdvec(&err).unwrap());
+        server_tx.send(out).await.unwrap();
+    }
+
+    // The error for the other request is ignored
+    let err = req.await.unwrap().unwrap_err();
+    assert_eq!(err, HostErr::Wire(WireError::DeserFailed));
+}
+
+/// Start a `SingleDispatcher` server with the given policy, returning a client, and a
+/// clone of the server's transport to inject failures with
+fn start_reply_failure_server(policy: ReplyFailure) -> (HostClient<WireError>, ChannelWireTx) {
     let (client_tx, server_rx) = mpsc::channel(16);
     let (server_tx, client_rx) = mpsc::channel(16);
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
 
-    let app = SingleDispatcher::new(
-        TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
-            topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
-        },
-        ChannelWireSpawn {},
-    );
+    let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
 
     let cwrx = ChannelWireRx::new(server_rx);
     let cwtx = ChannelWireTx::new(server_tx);
-
-    let kkind = VarKeyKind::Key8;
+    let faults = cwtx.clone();
+    let kkind = app.min_key_len();
     let mut server = new_server(
         app,
         Settings {
@@ -488,100 +609,262 @@ async fn end_to_end_force8() {
             kkind,
         },
     );
+    server.set_reply_failure(policy);
     tokio::task::spawn(async move {
         server.run().await;
     });
 
-    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq4);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    (cli, faults)
+}
 
-    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
-    assert_eq!(resp.0, 42);
-    let resp = cli.send_resp::<BetaEndpoint>(&BReq(1234)).await.unwrap();
-    assert_eq!(resp.0, 1234);
+#[tokio::test]
+async fn reply_failure_policy() {
+    // By default, the client is told that the transport failed
+    let (cli, faults) = start_reply_failure_server(ReplyFailure::Report);
+    faults.fail_next_sends(1);
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap_err();
+    assert_eq!(err, HostErr::Wire(WireError::TransportFailed));
+
+    // Retried writes succeed once the transport recovers
+    let (cli, faults) = start_reply_failure_server(ReplyFailure::Retry(2));
+    faults.fail_next_sends(2);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(2)).await.unwrap();
+    assert_eq!(resp.0, 2);
+
+    // Dropped replies leave the client waiting
+    let (cli, faults) = start_reply_failure_server(ReplyFailure::Drop);
+    faults.fail_next_sends(1);
+    let res = timeout(
+        Duration::from_millis(100),
+        cli.send_resp::<AlphaEndpoint>(&AReq(3)),
+    )
+    .await;
+    assert!(res.is_err());
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(4)).await.unwrap();
+    assert_eq!(resp.0, 4);
 }
 
 #[test]
-fn device_map() {
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
-    let app = SingleDispatcher::new(
-        TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
-            topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
-        },
-        ChannelWireSpawn {},
+fn try_dispatch_routes_without_handling() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = SingleDispatcher::new(context, ChannelWireSpawn {});
+    let hdr = |key: Key| VarHeader {
+        key: VarKey::Key8(key),
+        seq_no: VarSeq::Seq1(0),
+    };
+
+    let alpha = AlphaEndpoint::REQ_KEY;
+    assert_eq!(
+        app.try_dispatch(&hdr(alpha), &[1]),
+        DispatchDecision::Handle(alpha)
+    );
+    assert_eq!(
+        app.try_dispatch(&hdr(alpha), &[]),
+        DispatchDecision::DeserFailed(alpha)
+    );
+    let ping = PingEndpoint::REQ_KEY;
+    assert_eq!(
+        app.try_dispatch(&hdr(ping), &[42]),
+        DispatchDecision::Handle(ping)
+    );
+    let zeta = ZetaTopic1::TOPIC_KEY;
+    let body = postcard::to_stdvec(&ZMsg(-3)).unwrap();
+    assert_eq!(
+        app.try_dispatch(&hdr(zeta), &body),
+        DispatchDecision::Handle(zeta)
+    );
+    let unknown = Key::for_path::<u8>("nowhere");
+    assert_eq!(
+        app.try_dispatch(&hdr(unknown), &[]),
+        DispatchDecision::Unknown
     );
 
-    println!("# SingleDispatcher");
-    println!();
+    // No handler ran
+    assert_eq!(ctr.load(Ordering::Relaxed), 0);
+}
 
-    println!("## Types");
-    println!();
-    for ty in app.device_map.types {
-        let ty = OwnedNamedType::from(*ty);
-        println!("* {ty}");
-    }
+#[tokio::test]
+async fn sender_raw_bytes() {
+    let (server_tx, mut client_rx) = mpsc::channel(16);
+    let sender = Sender::new(ChannelWireTx::new(server_tx), VarKeyKind::Key8);
 
-    println!();
-    println!("## Endpoints");
-    println!();
-    for ep in app.device_map.endpoints {
-        println!(
-            "* {} ({:016X} -> {:016X})",
-            ep.0,
-            u64::from_le_bytes(ep.1.to_bytes()),
-            u64::from_le_bytes(ep.2.to_bytes()),
-        );
-    }
+    // Sent as-is, without a header
+    sender.send_raw(&[0xDF, 0x00, 0x01]).await.unwrap();
+    assert_eq!(client_rx.recv().await.unwrap(), vec![0xDF, 0x00, 0x01]);
+}
 
-    println!();
-    println!("## Topics (In)");
-    println!();
-    for tp in app.device_map.topics_in {
-        println!(
-            "* {} <- ({:016X})",
-            tp.0,
-            u64::from_le_bytes(tp.1.to_bytes()),
-        );
-    }
+#[tokio::test]
+async fn encode_matches_wire() {
+    let SingleFixture {
+        client_tx: fwd_tx,
+        client_rx,
+        ..
+    } = single_server(Some(VarKeyKind::Key8), |_| {});
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+
+    // Capture the request on the way to the server
+    let (seen_tx, mut seen_rx) = mpsc::channel::<Vec<u8>>(16);
+    tokio::task::spawn(async move {
+        while let Some(frame) = server_rx.recv().await {
+            let _ = seen_tx.send(frame.clone()).await;
+            let _ = fwd_tx.send(frame).await;
+        }
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+
+    let sent = seen_rx.recv().await.unwrap();
+    let (hdr, _body) = VarHeader::take_from_slice(&sent).unwrap();
+    let req = encode_request::<AlphaEndpoint, 64>(hdr.seq_no, &AReq(42)).unwrap();
+    assert_eq!(&req[..], &sent[..]);
+
+    let reply = encode_response::<AlphaEndpoint, 64>(hdr.seq_no, &resp).unwrap();
+    let (rhdr, body) = VarHeader::take_from_slice(&reply).unwrap();
+    assert_eq!(rhdr.key, VarKey::Key8(AlphaEndpoint::RESP_KEY));
+    assert_eq!(body, &[42]);
+
+    // Too large for the buffer
+    assert!(encode_request::<AlphaEndpoint, 4>(hdr.seq_no, &AReq(42)).is_err());
+}
+
+#[test]
+fn encoding_is_deterministic() {
+    let seq_no = VarSeq::Seq2(7);
+    let hdr = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no,
+    };
+    let mut expected = hdr.write_to_vec();
+    expected.push(42);
 
-    println!();
-    println!("## Topics (Out)");
-    println!();
-    for tp in app.device_map.topics_out {
-        println!(
-            "* {} -> ({:016X})",
-            tp.0,
-            u64::from_le_bytes(tp.1.to_bytes()),
-        );
+    // The same request always encodes to the same bytes
+    for _ in 0..2 {
+        let req = encode_request::<AlphaEndpoint, 64>(seq_no, &AReq(42)).unwrap();
+        assert_frame_eq(&expected, &req);
     }
-    println!();
-    println!("## Min Key Length");
-    println!();
-    println!("{:?}", app.device_map.min_key_len);
-    println!();
+
+    let table = Table {
+        points: [1, 2, 300, 4, 5, 6, 7, 8],
+    };
+    assert_stable_encoding(&table);
+    let map = std::collections::BTreeMap::from([(3u8, 30u16), (1, 10), (2, 20)]);
+    assert_stable_encoding(&map);
+}
+
+#[test]
+#[should_panic(expected = "frame bodies differ at byte 0")]
+fn assert_frame_eq_reports_difference() {
+    let req = encode_request::<AlphaEndpoint, 64>(VarSeq::Seq2(7), &AReq(42)).unwrap();
+    let other = encode_request::<AlphaEndpoint, 64>(VarSeq::Seq2(7), &AReq(43)).unwrap();
+    assert_frame_eq(&req, &other);
 }
 
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+pub struct CaptureMeta {
+    pub rate_hz: u32,
+    pub channel: u8,
+}
+
+endpoint!(CaptureEndpoint, u8, CaptureMeta, "capture");
+
 #[tokio::test]
-async fn end_to_end_stoppable() {
-    let (client_tx, server_rx) = mpsc::channel(16);
+async fn reply_with_blob() {
+    let (client_tx, mut server_rx) = mpsc::channel(16);
     let (server_tx, client_rx) = mpsc::channel(16);
-    let topic_ctr = Arc::new(AtomicUsize::new(0));
+    let sender = Sender::new(ChannelWireTx::new(server_tx), VarKeyKind::Key8);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
 
-    let app = SingleDispatcher::new(
-        TestContext {
-            ctr: Arc::new(AtomicUsize::new(0)),
-            topic_ctr: topic_ctr.clone(),
-            msg: String::from("hello"),
-        },
-        ChannelWireSpawn {},
+    // Reply to each request with the metadata, and as many samples as requested
+    tokio::task::spawn(async move {
+        while let Some(frame) = server_rx.recv().await {
+            let (hdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            let len = postcard::from_bytes::<u8>(body).unwrap();
+            let meta = CaptureMeta {
+                rate_hz: 48_000,
+                channel: 2,
+            };
+            let samples = (0..len).collect::<Vec<u8>>();
+            sender
+                .reply_with_blob::<CaptureEndpoint>(hdr.seq_no, &meta, &samples)
+                .await
+                .unwrap();
+        }
+    });
+
+    let (meta, blob) = cli.send_resp_with_blob::<CaptureEndpoint>(&200).await.unwrap();
+    assert_eq!(
+        meta,
+        CaptureMeta {
+            rate_hz: 48_000,
+            channel: 2,
+        }
     );
+    assert_eq!(blob, (0..200).collect::<Vec<u8>>());
+
+    // An empty attachment is still length-prefixed
+    let (_, blob) = cli.send_resp_with_blob::<CaptureEndpoint>(&0).await.unwrap();
+    assert!(blob.is_empty());
+
+    // The blob is not part of the response type
+    let meta = cli.send_resp::<CaptureEndpoint>(&3).await.unwrap();
+    assert_eq!(meta.channel, 2);
+}
+
+#[tokio::test]
+async fn reply_raw() {
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let sender = Sender::new(ChannelWireTx::new(server_tx), VarKeyKind::Key8);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // The response as it would be forwarded, already encoded, from another MCU
+    let meta = CaptureMeta {
+        rate_hz: 16_000,
+        channel: 1,
+    };
+    let encoded = postcard::to_stdvec(&meta).unwrap();
+    tokio::task::spawn(async move {
+        while let Some(frame) = server_rx.recv().await {
+            let (hdr, _body) = VarHeader::take_from_slice(&frame).unwrap();
+            sender
+                .reply_raw::<CaptureEndpoint>(hdr.seq_no, &encoded)
+                .await
+                .unwrap();
+        }
+    });
+
+    let resp = cli.send_resp::<CaptureEndpoint>(&0).await.unwrap();
+    assert_eq!(resp, meta);
+}
+
+#[tokio::test]
+async fn dyn_dispatch() {
+    use postcard_rpc::server::dynamic::DispatchBuilder;
+
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = DispatchBuilder::<TestContext, WireTxImpl>::new(context)
+        .register::<AlphaEndpoint>(|context, _header, req| {
+            Box::pin(async move {
+                context.ctr.fetch_add(1, Ordering::Relaxed);
+                AResp(req.0 * 2)
+            })
+        })
+        .register::<BetaEndpoint>(|_context, _header, req| {
+            Box::pin(async move { BResp(req.0.into()) })
+        })
+        .build();
 
     let cwrx = ChannelWireRx::new(server_rx);
     let cwtx = ChannelWireTx::new(server_tx);
-
     let kkind = app.min_key_len();
-    let (mut server, stopper) = new_server_stoppable(
+    let mut server = new_server(
         app,
         Settings {
             tx: cwtx,
@@ -590,18 +873,127 @@ async fn end_to_end_stoppable() {
             kkind,
         },
     );
-    let hdl = tokio::task::spawn(async move {
-        server.run().await;
-    });
 
-    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    // The boxed handlers are not `Send`, so the server runs on a local set
+    let local = tokio::task::LocalSet::new();
+    local
+        .run_until(async move {
+            tokio::task::spawn_local(async move {
+                server.run().await;
+            });
+
+            let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+            let resp = cli.send_resp::<AlphaEndpoint>(&AReq(21)).await.unwrap();
+            assert_eq!(resp.0, 42);
+            let resp = cli.send_resp::<BetaEndpoint>(&BReq(1000)).await.unwrap();
+            assert_eq!(resp.0, 1000);
+            assert_eq!(ctr.load(Ordering::Relaxed), 1);
+            let resp = cli.send_resp::<PingEndpoint>(&7).await.unwrap();
+            assert_eq!(resp, 7);
+
+            // Same errors as a `define_dispatch!` dispatcher
+            let res = cli.send_resp::<DeltaEndpoint>(&DReq).await;
+            assert!(matches!(res, Err(HostErr::UnknownKey(_))));
+            let frame = postcard_rpc::host_client::RpcFrame {
+                header: VarHeader {
+                    key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+                    seq_no: VarSeq::Seq2(100),
+                },
+                body: vec![],
+            };
+            let res = cli.send_resp_raw(frame, AlphaEndpoint::RESP_KEY).await;
+            assert!(matches!(res, Err(HostErr::Wire(WireError::DeserFailed))));
+            assert_eq!(ctr.load(Ordering::Relaxed), 1);
+        })
+        .await;
+}
 
-    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
-    assert_eq!(resp.0, 42);
-    stopper.stop();
-    match timeout(Duration::from_millis(100), hdl).await {
-        Ok(Ok(())) => {},
-        Ok(Err(e)) => panic!("Server task panicked? {e:?}"),
-        Err(_) => panic!("Server task did not stop!"),
+#[tokio::test]
+async fn try_publish_when_full() {
+    let (server_tx, mut client_rx) = mpsc::channel(2);
+    let sender = Sender::new(ChannelWireTx::new(server_tx), VarKeyKind::Key8);
+
+    sender.try_publish::<ZetaTopic10>(VarSeq::Seq2(1), &ZMsg(1)).unwrap();
+    sender.try_publish::<ZetaTopic10>(VarSeq::Seq2(2), &ZMsg(2)).unwrap();
+    // Returns right away instead of waiting, and the message is dropped
+    let res = sender.try_publish::<ZetaTopic10>(VarSeq::Seq2(3), &ZMsg(3));
+    assert!(matches!(res, Err(TrySendError::Full)));
+
+    for seq in [1, 2] {
+        let frame = client_rx.recv().await.unwrap();
+        let (hdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+        assert_eq!(hdr.seq_no, VarSeq::Seq2(seq));
+        assert_eq!(postcard::from_bytes::<ZMsg>(body).unwrap().0, seq as i16);
+    }
+    sender.try_publish::<ZetaTopic10>(VarSeq::Seq2(4), &ZMsg(4)).unwrap();
+
+    drop(client_rx);
+    let res = sender.try_publish::<ZetaTopic10>(VarSeq::Seq2(5), &ZMsg(5));
+    assert!(matches!(res, Err(TrySendError::Tx(_))));
+}
+
+#[tokio::test]
+async fn embedded_client() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        ..
+    } = single_server(None, |_| {});
+
+    // Room for a single request in flight
+    let client = EmbeddedClient::<NoopRawMutex, ChannelWireTx, 1, 64>::new(
+        ChannelWireTx::new(client_tx),
+        VarSeqKind::Seq2,
+    );
+    let first = client.send_resp::<AlphaEndpoint>(&AReq(1));
+    tokio::pin!(first);
+    // Nothing routes the response yet, so the request stays in flight
+    assert!(timeout(Duration::from_millis(10), &mut first).await.is_err());
+    let res = client.send_resp::<AlphaEndpoint>(&AReq(2)).await;
+    assert!(matches!(res, Err(ClientErr::TooManyInFlight)));
+
+    let mut buf = [0u8; 128];
+    let requests = async {
+        assert_eq!(first.await.unwrap().0, 1);
+        let resp = client.send_resp::<AlphaEndpoint>(&AReq(3)).await.unwrap();
+        assert_eq!(resp.0, 3);
+        let resp = client.send_resp::<PingEndpoint>(&7).await.unwrap();
+        assert_eq!(resp, 7);
+        let res = client.send_resp::<DeltaEndpoint>(&DReq).await;
+        assert!(matches!(res, Err(ClientErr::Wire(WireError::UnknownKey))));
+    };
+    tokio::select! {
+        _ = client.run(ChannelWireRx::new(client_rx), &mut buf) => panic!("client stopped"),
+        _ = requests => {}
     }
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn echo_frame() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli: HostClient<WireError> =
+        client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let data = [0x00, 0x7E, 0x7D, 0xFF, 0x00, 0x01];
+    let echo = cli.echo_frame(&data).await.unwrap();
+    let expected = postcard::to_stdvec(&EchoRequest { data: &data }).unwrap();
+    assert_eq!(echo.body, expected);
+
+    // The header is the one of the request, as the server parsed it
+    let (hdr, rest) = VarHeader::take_from_slice(&echo.header).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(hdr.key, VarKey::Key8(EchoFrameEndpoint::REQ_KEY));
+    assert!(matches!(hdr.seq_no, VarSeq::Seq2(_)));
+
+    // An empty payload is echoed as well
+    let echo = cli.echo_frame(&[]).await.unwrap();
+    assert_eq!(echo.body, [0]);
 }
@@ -0,0 +1,522 @@
+mod common;
+
+use common::*;
+
+#[tokio::test]
+async fn end_to_end_stoppable() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+
+    let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let kkind = app.min_key_len();
+    let (mut server, stopper) = new_server_stoppable(
+        app,
+        Settings {
+            tx: ChannelWireTx::new(server_tx),
+            rx: ChannelWireRx::new(server_rx),
+            buf: 1024,
+            kkind,
+        },
+    );
+    let hdl = tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    stopper.stop();
+    match timeout(Duration::from_millis(100), hdl).await {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => panic!("Server task panicked? {e:?}"),
+        Err(_) => panic!("Server task did not stop!"),
+    }
+}
+
+#[tokio::test]
+async fn reconnect() {
+    let (conn_tx, conn_rx) = mpsc::channel(4);
+    let cli = client::new_from_channels_reconnecting(conn_rx, VarSeqKind::Seq1);
+    assert_eq!(cli.state(), ConnectionState::Reconnecting);
+
+    // Connect to a server twice, disconnecting in between
+    for i in 0..2 {
+        let (client_tx, server_rx) = mpsc::channel(16);
+        let (server_tx, client_rx) = mpsc::channel(16);
+
+        let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+        let kkind = app.min_key_len();
+        let (mut server, stopper) = new_server_stoppable(
+            app,
+            Settings {
+                tx: ChannelWireTx::new(server_tx),
+                rx: ChannelWireRx::new(server_rx),
+                buf: 1024,
+                kkind,
+            },
+        );
+        let hdl = tokio::task::spawn(async move {
+            server.run().await;
+        });
+
+        conn_tx.send((client_tx, client_rx)).await.unwrap();
+        timeout(Duration::from_millis(100), cli.wait_connected())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(cli.state(), ConnectionState::Connected);
+
+        let resp = cli.send_resp::<AlphaEndpoint>(&AReq(i)).await.unwrap();
+        assert_eq!(resp.0, i);
+
+        // Dropping the server's channels disconnects the client
+        stopper.stop();
+        timeout(Duration::from_millis(100), hdl).await.unwrap().unwrap();
+        let start = Instant::now();
+        while cli.state() == ConnectionState::Connected {
+            assert!(start.elapsed() < Duration::from_millis(100));
+            yield_now().await;
+        }
+        assert!(!cli.is_closed());
+    }
+
+    // A pending request is resolved when the connection is lost
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel::<Vec<u8>>(16);
+    conn_tx.send((client_tx, client_rx)).await.unwrap();
+    timeout(Duration::from_millis(100), cli.wait_connected())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let cli2 = cli.clone();
+    let req = tokio::task::spawn(async move { cli2.send_resp::<AlphaEndpoint>(&AReq(1)).await });
+    let _frame = server_rx.recv().await.unwrap();
+    drop(server_tx);
+    drop(server_rx);
+
+    let res = timeout(Duration::from_millis(100), req).await.unwrap().unwrap();
+    assert!(matches!(res, Err(HostErr::Disconnected)));
+}
+
+#[tokio::test]
+async fn no_request_sent_while_reconnecting() {
+    let (conn_tx, conn_rx) = mpsc::channel(4);
+    let cli = client::new_from_channels_reconnecting(conn_rx, VarSeqKind::Seq1);
+    assert_eq!(cli.state(), ConnectionState::Reconnecting);
+
+    // A call made before the device is connected fails right away
+    let res = timeout(
+        Duration::from_millis(100),
+        cli.send_resp::<AlphaEndpoint>(&AReq(1)),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(res, Err(HostErr::Disconnected)));
+
+    // ...and its request is not sent once the device is connected
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+    let (_server_tx, client_rx) = mpsc::channel::<Vec<u8>>(16);
+    conn_tx.send((client_tx, client_rx)).await.unwrap();
+    timeout(Duration::from_millis(100), cli.wait_connected())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(timeout(Duration::from_millis(50), server_rx.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn custom_seq_no_source() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq4);
+
+    // Wide, non-sequential values are echoed back by the server
+    let next = Arc::new(AtomicUsize::new(0xABCD_0000));
+    cli.set_seq_no_source({
+        let next = next.clone();
+        move || next.fetch_add(0x0101_0101, Ordering::Relaxed) as u32
+    });
+    let (seq_no, resp_fut) = cli.reserve::<AlphaEndpoint>().await.unwrap();
+    assert_eq!(seq_no, VarSeq::Seq4(0xABCD_0000));
+    let resp_fut = cli.send_reserved(resp_fut, &AReq(1)).await.unwrap();
+    assert_eq!(resp_fut.recv().await.unwrap().0, 1);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(2)).await.unwrap();
+    assert_eq!(resp.0, 2);
+
+    // Sequence numbers still in use by a pending request are skipped
+    cli.set_seq_no_source(|| 7u32);
+    let (seq_no, resp_fut) = cli.reserve::<AlphaEndpoint>().await.unwrap();
+    assert_eq!(seq_no, VarSeq::Seq4(7));
+    let res = cli.reserve::<AlphaEndpoint>().await;
+    assert!(matches!(res, Err(HostErr::SeqNoInUse)));
+    drop(resp_fut);
+
+    // Once released, the sequence number can be used again
+    cli.set_seq_no_source(MonotonicSeqNo::new(7));
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(3)).await.unwrap();
+    assert_eq!(resp.0, 3);
+}
+
+#[tokio::test]
+async fn retry_reuses_seq_no() {
+    let SingleFixture {
+        client_tx,
+        client_rx: mut lossy_rx,
+        ctr,
+        ..
+    } = single_server(None, |_| {});
+    let (lossy_tx, client_rx) = mpsc::channel(16);
+
+    // Drop the first response, as well as every response once `drop_all` is set
+    let drop_all = Arc::new(AtomicUsize::new(0));
+    tokio::task::spawn({
+        let drop_all = drop_all.clone();
+        async move {
+            let mut first = true;
+            while let Some(msg) = lossy_rx.recv().await {
+                if first || drop_all.load(Ordering::Relaxed) != 0 {
+                    first = false;
+                    continue;
+                }
+                if lossy_tx.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        timeout: Duration::from_millis(50),
+        backoff: Duration::from_millis(10),
+        ..Default::default()
+    };
+
+    // The retransmission is answered from the dedup cache
+    let _resp = cli
+        .send_resp_with_retry::<GammaEndpoint>(&GReq, &policy)
+        .await
+        .unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Errors from the server are not retried
+    let res = cli.send_resp_with_retry::<DeltaEndpoint>(&DReq, &policy).await;
+    assert!(matches!(res, Err(HostErr::UnknownKey(_))));
+
+    drop_all.store(1, Ordering::Relaxed);
+    let res = cli.send_resp_with_retry::<GammaEndpoint>(&GReq, &policy).await;
+    assert!(matches!(
+        res,
+        Err(HostErr::RetriesExhausted {
+            last: AttemptFailure::TimedOut
+        })
+    ));
+}
+
+define_client! {
+    client: TestClient;
+    endpoints: {
+        | EndpointTy        | method    |
+        | ----------        | ------    |
+        | AlphaEndpoint     | alpha     |
+        | InlineEndpoint    | inline    |
+        | DeltaEndpoint     | delta     |
+    };
+}
+
+#[tokio::test]
+async fn typed_client() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = TestClient::new(client::new_from_channels(
+        client_tx,
+        client_rx,
+        VarSeqKind::Seq2,
+    ));
+
+    let resp = cli.alpha(&AReq(7)).await.unwrap();
+    assert_eq!(resp.0, 7);
+    let resp = cli.inline(&21).await.unwrap();
+    assert_eq!(resp, 42);
+    let err = cli.delta(&DReq).await.unwrap_err();
+    assert_eq!(err, HostErr::UnknownKey(VarKey::Key8(DeltaEndpoint::REQ_KEY)));
+
+    // The HostClient is still available
+    let resp = cli.client().send_resp::<PingEndpoint>(&3).await.unwrap();
+    assert_eq!(resp, 3);
+}
+
+#[test]
+#[should_panic(expected = "endpoint \"delta\": missing on the device")]
+fn icd_match_reports_divergence() {
+    // `SingleDispatcher` has no handler for `DeltaEndpoint`
+    assert_icd_match!(SingleDispatcher, TestClient);
+}
+
+define_topic_events! {
+    events: TestEvent;
+    topics: {
+        | TopicTy           | variant   |
+        | ----------        | -------   |
+        | ZetaTopic1        | Zeta1     |
+        | ZetaTopic10       | Zeta10    |
+    };
+}
+
+#[tokio::test]
+async fn topic_events() {
+    let (client_tx, _server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let mut events = cli.subscribe_events::<TestEvent>(8).await.unwrap();
+
+    let frame = |key, body: &[u8]| {
+        let mut out = VarHeader {
+            key: VarKey::Key8(key),
+            seq_no: VarSeq::Seq2(0),
+        }
+        .write_to_vec();
+        out.extend_from_slice(body);
+        out
+    };
+
+    let body = postcard::to_stdvec(&ZMsg(1)).unwrap();
+    server_tx.send(frame(ZetaTopic1::TOPIC_KEY, &body)).await.unwrap();
+    let event = timeout(Duration::from_millis(100), events.recv()).await.unwrap();
+    assert!(matches!(event, Some(TestEvent::Zeta1(ZMsg(1)))));
+
+    let body = postcard::to_stdvec(&ZMsg(-2)).unwrap();
+    server_tx.send(frame(ZetaTopic10::TOPIC_KEY, &body)).await.unwrap();
+    let event = timeout(Duration::from_millis(100), events.recv()).await.unwrap();
+    assert!(matches!(event, Some(TestEvent::Zeta10(ZMsg(-2)))));
+
+    // A message that fails to deserialize is passed on as is
+    server_tx.send(frame(ZetaTopic10::TOPIC_KEY, &[])).await.unwrap();
+    let event = timeout(Duration::from_millis(100), events.recv()).await.unwrap();
+    let Some(TestEvent::Unknown(key, body)) = event else {
+        panic!("expected an unknown event");
+    };
+    assert_eq!(key, ZetaTopic10::TOPIC_KEY);
+    assert!(body.is_empty());
+
+    cli.close();
+    let event = timeout(Duration::from_millis(100), events.recv()).await.unwrap();
+    assert!(event.is_none());
+}
+
+#[tokio::test]
+async fn topic_router() {
+    let (client_tx, _server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let (ev_tx, mut ev_rx) = mpsc::unbounded_channel();
+    let (ev_tx1, ev_tx10, ev_default) = (ev_tx.clone(), ev_tx.clone(), ev_tx);
+    cli.topic_router()
+        .on::<ZetaTopic1>(move |msg| ev_tx1.send(format!("zeta1: {}", msg.0)).unwrap())
+        .on::<ZetaTopic10>(move |msg| ev_tx10.send(format!("zeta10: {}", msg.0)).unwrap())
+        .default(move |frame| {
+            let key = frame.header.key;
+            ev_default.send(format!("default: {key:?}")).unwrap()
+        })
+        .install();
+    let mut sub = cli.subscribe_multi::<ZetaTopic1>(8).await.unwrap();
+
+    let frame = |key, body: &[u8]| {
+        let mut out = VarHeader {
+            key: VarKey::Key8(key),
+            seq_no: VarSeq::Seq2(0),
+        }
+        .write_to_vec();
+        out.extend_from_slice(body);
+        out
+    };
+    async fn next(rx: &mut mpsc::UnboundedReceiver<String>) -> String {
+        timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    // Each message goes to the handler of its topic, and to subscriptions
+    let body = postcard::to_stdvec(&ZMsg(1)).unwrap();
+    server_tx.send(frame(ZetaTopic1::TOPIC_KEY, &body)).await.unwrap();
+    assert_eq!(next(&mut ev_rx).await, "zeta1: 1");
+    let msg = timeout(Duration::from_millis(100), sub.recv()).await.unwrap();
+    assert!(matches!(msg, Ok(ZMsg(1))));
+
+    let body = postcard::to_stdvec(&ZMsg(-2)).unwrap();
+    server_tx.send(frame(ZetaTopic10::TOPIC_KEY, &body)).await.unwrap();
+    assert_eq!(next(&mut ev_rx).await, "zeta10: -2");
+
+    // Unregistered topics, and messages that fail to deserialize, go to the default
+    server_tx.send(frame(ZetaTopic2::TOPIC_KEY, &body)).await.unwrap();
+    let expected = format!("default: {:?}", VarKey::Key8(ZetaTopic2::TOPIC_KEY));
+    assert_eq!(next(&mut ev_rx).await, expected);
+    server_tx.send(frame(ZetaTopic10::TOPIC_KEY, &[])).await.unwrap();
+    let expected = format!("default: {:?}", VarKey::Key8(ZetaTopic10::TOPIC_KEY));
+    assert_eq!(next(&mut ev_rx).await, expected);
+}
+
+#[test]
+fn blocking_client() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+
+    // Run the server on its own runtime, the client doesn't need one
+    let server = std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+            let cwrx = ChannelWireRx::new(server_rx);
+            let cwtx = ChannelWireTx::new(server_tx);
+            let kkind = app.min_key_len();
+            let mut server = new_server(
+                app,
+                Settings {
+                    tx: cwtx,
+                    rx: cwrx,
+                    buf: 1024,
+                    kkind,
+                },
+            );
+            server.run().await;
+        });
+    });
+
+    let cli = BlockingClient::new(move || {
+        client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2)
+    });
+    let resp = cli.call::<AlphaEndpoint>(&AReq(5)).unwrap();
+    assert_eq!(resp.0, 5);
+    let resp = cli.call::<PingEndpoint>(&11).unwrap();
+    assert_eq!(resp, 11);
+    let err = cli.call::<DeltaEndpoint>(&DReq).unwrap_err();
+    assert_eq!(err, HostErr::UnknownKey(VarKey::Key8(DeltaEndpoint::REQ_KEY)));
+    let resp = cli.block_on(cli.client().send_resp::<InlineEndpoint>(&4));
+    assert_eq!(resp.unwrap(), 8);
+
+    // Dropping the client closes the connection, which stops the server
+    drop(cli);
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn ack_before_response() {
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let (release_tx, mut release_rx) = mpsc::channel::<()>(1);
+    let sender = Sender::new(ChannelWireTx::new(server_tx), VarKeyKind::Key8);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Acknowledge each request at once, and reply once released
+    tokio::task::spawn(async move {
+        while let Some(frame) = server_rx.recv().await {
+            let (hdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            let req = postcard::from_bytes::<AReq>(body).unwrap();
+            sender.ack(hdr.seq_no).await.unwrap();
+            release_rx.recv().await.unwrap();
+            sender
+                .reply::<AlphaEndpoint>(hdr.seq_no, &AResp(req.0))
+                .await
+                .unwrap();
+        }
+    });
+
+    let (ack, resp) = cli.send_resp_with_ack::<AlphaEndpoint>(&AReq(9)).await.unwrap();
+    timeout(Duration::from_millis(100), ack.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    release_tx.send(()).await.unwrap();
+    assert_eq!(resp.recv().await.unwrap().0, 9);
+
+    // Clients that don't wait for the acknowledgement ignore it
+    release_tx.send(()).await.unwrap();
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(4)).await.unwrap();
+    assert_eq!(resp.0, 4);
+}
+
+#[tokio::test]
+async fn progress_before_response() {
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let sender = Sender::new(ChannelWireTx::new(server_tx), VarKeyKind::Key8);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Report progress for each request, then reply
+    tokio::task::spawn(async move {
+        while let Some(frame) = server_rx.recv().await {
+            let (hdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            let req = postcard::from_bytes::<AReq>(body).unwrap();
+            for percent in [0, 50, 200] {
+                sender.progress(hdr.seq_no, percent).await.unwrap();
+            }
+            sender
+                .reply::<AlphaEndpoint>(hdr.seq_no, &AResp(req.0))
+                .await
+                .unwrap();
+        }
+    });
+
+    let mut seen = vec![];
+    let resp = cli
+        .send_resp_with_progress::<AlphaEndpoint>(&AReq(9), |p| seen.push(p))
+        .await
+        .unwrap();
+    assert_eq!(resp.0, 9);
+    // The percentage is capped at 100
+    assert_eq!(seen, [0, 50, 100]);
+
+    // Clients that don't wait for progress ignore it
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(4)).await.unwrap();
+    assert_eq!(resp.0, 4);
+}
+
+#[tokio::test]
+async fn ordered_call_keeps_order() {
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let sender = Sender::new(ChannelWireTx::new(server_tx), VarKeyKind::Key8);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Reply to each request in the order received, recording that order
+    let (seen_tx, mut seen_rx) = mpsc::channel(16);
+    tokio::task::spawn(async move {
+        while let Some(frame) = server_rx.recv().await {
+            let (hdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            let req = postcard::from_bytes::<AReq>(body).unwrap();
+            seen_tx.send(req.0).await.unwrap();
+            sender
+                .reply::<AlphaEndpoint>(hdr.seq_no, &AResp(req.0))
+                .await
+                .unwrap();
+        }
+    });
+
+    let (a, b, c) = tokio::join!(
+        cli.ordered_call::<AlphaEndpoint>(&AReq(1)),
+        cli.ordered_call::<AlphaEndpoint>(&AReq(2)),
+        cli.ordered_call::<AlphaEndpoint>(&AReq(3)),
+    );
+    assert_eq!((a.unwrap().0, b.unwrap().0, c.unwrap().0), (1, 2, 3));
+    for expected in 1..=3 {
+        assert_eq!(seen_rx.recv().await.unwrap(), expected);
+    }
+}
@@ -0,0 +1,475 @@
+//! The ICD, dispatcher and fixtures shared by the integration tests
+//!
+//! Each test file is its own crate, and uses only some of these.
+#![allow(dead_code, unused_imports)]
+
+pub use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+pub use std::{rc::Rc, sync::Arc, time::Instant};
+
+pub use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+pub use postcard_schema::{schema::owned::OwnedNamedType, Schema};
+pub use serde::{Deserialize, Serialize};
+pub use tokio::{sync::mpsc, task::yield_now, time::timeout};
+
+pub use postcard_rpc::{
+    assert_icd_match,
+    cipher::{open_frame, seal_frame, BodyCipher, CipherState},
+    crc::{checked_len, crc32, CrcMode},
+    define_client, define_dispatch, define_topic_events,
+    embedded_client::{ClientErr, EmbeddedClient},
+    encode::{encode_request, encode_response},
+    endpoint, endpoints,
+    header::{AuthToken, VarHeader, VarKey, VarKeyKind, VarSeq, VarSeqKind},
+    host_client::{
+        test_channels as client, AttemptFailure, BlockingClient, ConnectionState,
+        EndpointMismatch, HostClient, HostErr, IcdCheckError, MonotonicSeqNo, RetryPolicy,
+        TopicMismatch,
+    },
+    rpc_log,
+    server::{
+        dedup::DedupCache,
+        disconnect::{ConnectionSignal, DisconnectSignal},
+        heartbeat::heartbeat_task,
+        impls::test_channels::{
+            dispatch_impl::{
+                fuzz_dispatch, new_server, new_server_protected, new_server_reassembling,
+                new_server_stoppable, replay, sleep_ms, spawn_fn, spawn_fn_local, Settings,
+                WireRxBuf, WireRxImpl, WireSpawnImpl, WireTxImpl,
+            },
+            ChannelWireRx, ChannelWireSpawn, ChannelWireTx, ChannelWireTxError,
+        },
+        request_pool::RequestPool,
+        transaction::Transaction,
+        Dispatch, DispatchDecision, LinkProtection, ReplyFailure, Sender, Server, ServerError,
+        SpawnContext, TrySendError, WireRx,
+    },
+    standard_icd::{
+        Batch, BatchTopic, Busy, EchoFrameEndpoint, EchoRequest, EndpointStatus, FrameTooLong,
+        KeyedError, LogLevel, LogRecordTopic, OwnedLogRecord, PingEndpoint, RebootMode,
+        ResponseTooLarge, WireError, CRATE_VERSION, ERROR_KEY, KEYED_ERROR_KEY,
+        PROTOCOL_VERSION,
+    },
+    test_utils::{assert_frame_eq, assert_stable_encoding},
+    topics, Endpoint, Key, Topic,
+};
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct AReq(pub u8);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct AResp(pub u8);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct BReq(pub u16);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct BResp(pub u32);
+#[derive(Serialize, Deserialize, Schema)]
+pub struct GReq;
+#[derive(Serialize, Deserialize, Schema)]
+pub struct GResp;
+#[derive(Serialize, Deserialize, Schema)]
+pub struct DReq;
+#[derive(Serialize, Deserialize, Schema)]
+pub struct DResp;
+#[derive(Serialize, Deserialize, Schema)]
+pub struct EReq;
+#[derive(Serialize, Deserialize, Schema)]
+pub struct EResp;
+#[derive(Serialize, Deserialize, Schema)]
+pub struct ZMsg(pub i16);
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+pub struct Table {
+    pub points: [u16; 8],
+}
+
+#[cfg(feature = "alpha")]
+#[derive(Serialize, Deserialize, Schema)]
+pub struct Message<'a> {
+    pub data: &'a str,
+}
+
+#[cfg(not(feature = "alpha"))]
+#[derive(Serialize, Deserialize, Schema)]
+pub struct Message {
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct DoubleMessage<'a, 'b> {
+    pub data1: &'a str,
+    pub data2: &'b str,
+}
+
+// The type columns of `endpoints!` only take generic lifetimes, not types
+pub type Bytes = Vec<u8>;
+
+// The message ID of a "legacy" device, instead of a calculated key
+pub const LEGACY_KEY: [u8; 8] = [0x4C, 0x45, 0x47, 0x41, 0x43, 0x59, 0x00, 0x01];
+
+endpoints! {
+    list = ENDPOINT_LIST;
+    | EndpointTy        | RequestTy             | ResponseTy            | Path              | Cfg                    |
+    | ----------        | ---------             | ----------            | ----              | ---                    |
+    | AlphaEndpoint     | AReq                  | AResp                 | "alpha"           |                        |
+    | BetaEndpoint      | BReq                  | BResp                 | "beta"            |                        |
+    | GammaEndpoint     | GReq                  | GResp                 | "gamma"           |                        |
+    | DeltaEndpoint     | DReq                  | DResp                 | "delta"           |                        |
+    | EpsilonEndpoint   | EReq                  | EResp                 | "epsilon"         |                        |
+    | BorrowEndpoint1   | Message<'a>           | u8                    | "borrow1"         | cfg(feature = "alpha") |
+    | BorrowEndpoint2   | ()                    | Message<'a>           | "borrow2"         |                        |
+    | BorrowEndpoint3   | Message<'a>           | Message<'b>           | "borrow3"         |                        |
+    | BorrowEndpoint4   | DoubleMessage<'a, 'b> | DoubleMessage<'c, 'd> | "borrow4"         |                        |
+    | FragEndpoint      | Bytes                 | u32                   | "frag"            |                        |
+    | InlineEndpoint    | u16                   | u32                   | "inline"          |                        |
+    | NotifyEndpoint    | u32                   | ()                    | "notify"          |                        |
+    | TableEndpoint     | ()                    | Table                 | "table"           |                        |
+    | LimitedEndpoint   | Bytes                 | u32                   | "limited"         |                        |
+    | AlphaV0Endpoint   | AReq                  | AResp                 | "alpha_v0"        |                        |
+    | ReadLogEndpoint   | u32                   | Bytes                 | "read_log"        |                        |
+}
+
+topics! {
+    list = TOPICS_IN_LIST;
+    direction = postcard_rpc::TopicDirection::ToServer;
+    | TopicTy       | MessageTy             | Path      | Cfg                           |
+    | ----------    | ---------             | ----      | ---                           |
+    | ZetaTopic1    | ZMsg                  | "zeta1"   |                               |
+    | ZetaTopic2    | ZMsg                  | "zeta2"   |                               |
+    | ZetaTopic3    | ZMsg                  | "zeta3"   |                               |
+    | BorrowTopic   | Message<'a>           | "msg1"    | cfg(feature = "alpha")        |
+    | BorrowTopic   | DoubleMessage<'a, 'b> | "msg1"    | cfg(not(feature = "alpha"))   |
+    | BerpTopic1    | u8                    | "empty"   |                               |
+    | BerpTopic2    | ()                    | "empty"   |                               |
+}
+
+topics! {
+    list = TOPICS_OUT_LIST;
+    direction = postcard_rpc::TopicDirection::ToClient;
+    | TopicTy           | MessageTy     | Path              |
+    | ----------        | ---------     | ----              |
+    | ZetaTopic10       | ZMsg          | "zeta10"          |
+}
+
+pub struct TestContext {
+    pub ctr: Arc<AtomicUsize>,
+    pub topic_ctr: Arc<AtomicUsize>,
+    pub msg: String,
+}
+
+pub struct TestSpawnContext {
+    pub ctr: Arc<AtomicUsize>,
+    pub topic_ctr: Arc<AtomicUsize>,
+}
+
+impl Default for TestContext {
+    fn default() -> Self {
+        Self {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: Arc::new(AtomicUsize::new(0)),
+            msg: String::from("hello"),
+        }
+    }
+}
+
+impl SpawnContext for TestContext {
+    type SpawnCtxt = TestSpawnContext;
+
+    fn spawn_ctxt(&mut self) -> Self::SpawnCtxt {
+        TestSpawnContext {
+            ctr: self.ctr.clone(),
+            topic_ctr: self.topic_ctr.clone(),
+        }
+    }
+}
+
+define_dispatch! {
+    app: SingleDispatcher;
+    spawn_fn: spawn_fn;
+    tx_impl: WireTxImpl;
+    spawn_impl: WireSpawnImpl;
+    context: TestContext;
+    dedup: DedupCache<4, 16>;
+
+    endpoints: {
+        list: ENDPOINT_LIST;
+
+        | EndpointTy        | kind      | handler                   |
+        | ----------        | ----      | -------                   |
+        | AlphaEndpoint     | async     | test_alpha_handler        |
+        | BetaEndpoint      | spawn     | test_beta_handler         |
+        | GammaEndpoint     | dedup     | test_gamma_handler        |
+        | EpsilonEndpoint   | spawn     | test_epsilon_handler      |
+        | FragEndpoint      | async     | test_frag_handler         |
+        | InlineEndpoint    | async     | {
+            |ctx, _hdr, req| async move {
+                ctx.ctr.fetch_add(1, Ordering::Relaxed);
+                u32::from(req) * 2
+            }
+        } |
+        | NotifyEndpoint    | notify    | test_notify_handler       |
+        | TableEndpoint     | ref       | test_table_handler        |
+        | LimitedEndpoint [max_len = 8] | async | test_frag_handler |
+        | BorrowEndpoint1   | blocking  | test_borrowep_blocking    |
+        | BorrowEndpoint2   | blocking  | test_borrowep_blocking2   |
+        | BorrowEndpoint4   | async     | test_borrowep_async       |
+    };
+    topics_in: {
+        list: TOPICS_IN_LIST;
+
+        | TopicTy           | kind      | handler               |
+        | ----------        | ----      | -------               |
+        | ZetaTopic1        | blocking  | test_zeta_blocking    |
+        | ZetaTopic2        | async     | test_zeta_async       |
+        | ZetaTopic3        | spawn     | test_zeta_spawn       |
+        | BorrowTopic       | blocking  | test_borrow_blocking  |
+    };
+    topics_out: {
+        list: TOPICS_OUT_LIST;
+    };
+}
+
+pub static TABLE: Table = Table {
+    points: [0, 10, 25, 45, 70, 100, 135, 175],
+};
+
+pub fn test_table_handler(
+    _context: &mut TestContext,
+    _header: VarHeader,
+    _body: (),
+) -> &'static Table {
+    &TABLE
+}
+
+pub async fn test_notify_handler(context: &mut TestContext, _header: VarHeader, body: u32) {
+    context.ctr.fetch_add(body as usize, Ordering::Relaxed);
+}
+
+pub fn test_borrowep_blocking2(
+    context: &mut TestContext,
+    _header: VarHeader,
+    _body: (),
+) -> Message<'_> {
+    Message {
+        data: context.msg.as_str(),
+    }
+}
+
+pub fn test_borrowep_blocking(
+    _context: &mut TestContext,
+    _header: VarHeader,
+    _body: Message<'_>,
+) -> u8 {
+    0
+}
+
+pub async fn test_borrowep_async<'a>(
+    _context: &mut TestContext,
+    _header: VarHeader,
+    body: DoubleMessage<'a, 'a>,
+) -> DoubleMessage<'a, 'a> {
+    DoubleMessage {
+        data1: body.data2,
+        data2: body.data1,
+    }
+}
+
+pub fn test_zeta_blocking(
+    context: &mut TestContext,
+    _header: VarHeader,
+    _body: ZMsg,
+    _out: &Sender<ChannelWireTx>,
+) {
+    context.topic_ctr.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn test_borrow_blocking(
+    context: &mut TestContext,
+    _header: VarHeader,
+    _body: Message,
+    _out: &Sender<ChannelWireTx>,
+) {
+    context.topic_ctr.fetch_add(1, Ordering::Relaxed);
+}
+
+pub async fn test_zeta_async(
+    context: &mut TestContext,
+    _header: VarHeader,
+    _body: ZMsg,
+    _out: &Sender<ChannelWireTx>,
+) {
+    context.topic_ctr.fetch_add(1, Ordering::Relaxed);
+}
+
+pub async fn test_zeta_spawn(
+    context: TestSpawnContext,
+    _header: VarHeader,
+    _body: ZMsg,
+    _out: Sender<ChannelWireTx>,
+) {
+    context.topic_ctr.fetch_add(1, Ordering::Relaxed);
+}
+
+pub async fn test_alpha_handler(
+    context: &mut TestContext,
+    _header: VarHeader,
+    body: AReq,
+) -> AResp {
+    context.ctr.fetch_add(1, Ordering::Relaxed);
+    AResp(body.0)
+}
+
+pub async fn test_gamma_handler(
+    context: &mut TestContext,
+    _header: VarHeader,
+    _body: GReq,
+) -> GResp {
+    context.ctr.fetch_add(1, Ordering::Relaxed);
+    GResp
+}
+
+pub async fn test_beta_handler(
+    context: TestSpawnContext,
+    header: VarHeader,
+    body: BReq,
+    out: Sender<ChannelWireTx>,
+) {
+    context.ctr.fetch_add(1, Ordering::Relaxed);
+    let _ = out
+        .reply::<BetaEndpoint>(header.seq_no, &BResp(body.0.into()))
+        .await;
+}
+
+pub async fn test_frag_handler(
+    _context: &mut TestContext,
+    _header: VarHeader,
+    body: Vec<u8>,
+) -> u32 {
+    body.iter().map(|b| u32::from(*b)).sum()
+}
+
+pub async fn test_epsilon_handler(
+    context: TestSpawnContext,
+    header: VarHeader,
+    _body: EReq,
+    out: Sender<ChannelWireTx>,
+) {
+    // Do some "long running" work before replying
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    context.ctr.fetch_add(1, Ordering::Relaxed);
+    let _ = out.reply::<EpsilonEndpoint>(header.seq_no, &EResp).await;
+}
+
+/// A server on the test channels, running the dispatcher `D`
+pub type TestServer<D> = Server<WireTxImpl, WireRxImpl, WireRxBuf, D>;
+
+/// The client side of a server started with [`spawn_server()`]
+pub struct ServerFixture {
+    /// Sends frames to the server
+    pub client_tx: mpsc::Sender<Vec<u8>>,
+    /// Receives frames from the server
+    pub client_rx: mpsc::Receiver<Vec<u8>>,
+}
+
+/// Start a server running `app` with a 1024 byte receive buffer, sending keys of
+/// `kkind`, or of the minimum size of the dispatcher if `None`
+///
+/// `configure` is called with the server before it starts running.
+pub fn spawn_server<D>(
+    app: D,
+    kkind: Option<VarKeyKind>,
+    configure: impl FnOnce(&mut TestServer<D>),
+) -> ServerFixture
+where
+    D: Dispatch<Tx = WireTxImpl> + Send + 'static,
+{
+    spawn_server_protected(app, kkind, LinkProtection::default(), configure)
+}
+
+/// Like [`spawn_server()`], protecting the frames on the link with `protection`
+pub fn spawn_server_protected<D>(
+    app: D,
+    kkind: Option<VarKeyKind>,
+    protection: LinkProtection,
+    configure: impl FnOnce(&mut TestServer<D>),
+) -> ServerFixture
+where
+    D: Dispatch<Tx = WireTxImpl> + Send + 'static,
+{
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+
+    let kkind = kkind.unwrap_or(app.min_key_len());
+    let mut server = new_server_protected(
+        app,
+        Settings {
+            tx: ChannelWireTx::new(server_tx),
+            rx: ChannelWireRx::new(server_rx),
+            buf: 1024,
+            kkind,
+        },
+        protection,
+    );
+    configure(&mut server);
+
+    // The handler futures of any `D` aren't known to be `Send`, so the server gets a
+    // thread of its own. The local set also runs handlers spawned with `spawn_fn_local`.
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        tokio::task::LocalSet::new().block_on(&rt, async move {
+            server.run().await;
+        });
+    });
+
+    ServerFixture {
+        client_tx,
+        client_rx,
+    }
+}
+
+/// The client side of a server started with [`single_server()`]
+pub struct SingleFixture {
+    /// Sends frames to the server
+    pub client_tx: mpsc::Sender<Vec<u8>>,
+    /// Receives frames from the server
+    pub client_rx: mpsc::Receiver<Vec<u8>>,
+    /// The `ctr` of the server's `TestContext`
+    pub ctr: Arc<AtomicUsize>,
+    /// The `topic_ctr` of the server's `TestContext`
+    pub topic_ctr: Arc<AtomicUsize>,
+}
+
+/// Start a [`SingleDispatcher`] server with [`spawn_server()`]
+pub fn single_server(
+    kkind: Option<VarKeyKind>,
+    configure: impl FnOnce(&mut TestServer<SingleDispatcher>),
+) -> SingleFixture {
+    single_server_protected(kkind, LinkProtection::default(), configure)
+}
+
+/// Like [`single_server()`], protecting the frames on the link with `protection`
+pub fn single_server_protected(
+    kkind: Option<VarKeyKind>,
+    protection: LinkProtection,
+    configure: impl FnOnce(&mut TestServer<SingleDispatcher>),
+) -> SingleFixture {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let topic_ctr = context.topic_ctr.clone();
+
+    let app = SingleDispatcher::new(context, ChannelWireSpawn {});
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server_protected(app, kkind, protection, configure);
+
+    SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        topic_ctr,
+    }
+}
@@ -0,0 +1,499 @@
+mod common;
+
+use common::*;
+
+mod busy_app {
+    use super::*;
+
+    // Reject everything once the alpha handler has run once
+    fn check_busy(context: &mut TestContext, _header: &VarHeader) -> Option<Busy> {
+        if context.ctr.load(Ordering::Relaxed) >= 1 {
+            Some(Busy { retry_after_ms: 25 })
+        } else {
+            None
+        }
+    }
+
+    define_dispatch! {
+        app: BusyDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        busy: check_busy;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | AlphaEndpoint     | async     | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn busy_rejects_requests() {
+    let app = busy_app::BusyDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    assert_eq!(resp.0, 1);
+
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(2)).await.unwrap_err();
+    assert_eq!(
+        err,
+        HostErr::Wire(WireError::Busy(Busy { retry_after_ms: 25 }))
+    );
+    assert_eq!(err.retry_after(), Some(Duration::from_millis(25)));
+
+    // Standard endpoints are never rejected
+    let resp = cli.send_resp::<PingEndpoint>(&7).await.unwrap();
+    assert_eq!(resp, 7);
+}
+
+mod middleware_app {
+    use super::*;
+    use core::ops::ControlFlow;
+    use postcard_rpc::server::middleware::Middleware;
+
+    // Rejects `AReq(0)`, and counts the calls of its hooks
+    pub struct Gate {
+        pub before: AtomicUsize,
+        pub after: AtomicUsize,
+    }
+
+    impl Middleware for Gate {
+        fn before(&self, hdr: &VarHeader, body: &[u8]) -> ControlFlow<WireError, ()> {
+            self.before.fetch_add(1, Ordering::Relaxed);
+            if hdr.key == VarKey::Key8(AlphaEndpoint::REQ_KEY) && body == [0] {
+                ControlFlow::Break(WireError::UnknownKey)
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+
+        fn after(&self, _hdr: &VarHeader) {
+            self.after.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub static GATE: Gate = Gate {
+        before: AtomicUsize::new(0),
+        after: AtomicUsize::new(0),
+    };
+
+    // Counts the frames that made it past the gate
+    pub static TRACED: AtomicUsize = AtomicUsize::new(0);
+
+    pub struct Tracer;
+
+    impl Middleware for Tracer {
+        fn before(&self, _hdr: &VarHeader, _body: &[u8]) -> ControlFlow<WireError, ()> {
+            TRACED.fetch_add(1, Ordering::Relaxed);
+            ControlFlow::Continue(())
+        }
+    }
+
+    define_dispatch! {
+        app: MiddlewareDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        middleware: [GATE, Tracer];
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | AlphaEndpoint     | async     | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn middleware_short_circuits() {
+    use middleware_app::{GATE, TRACED};
+
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = middleware_app::MiddlewareDispatcher::new(context, ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    assert_eq!(resp.0, 1);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Rejected by the gate, neither the tracer nor the handler run
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(0)).await.unwrap_err();
+    assert_eq!(err, HostErr::UnknownKey(VarKey::Key8(AlphaEndpoint::REQ_KEY)));
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Standard endpoints pass through middleware as well
+    let resp = cli.send_resp::<PingEndpoint>(&7).await.unwrap();
+    assert_eq!(resp, 7);
+
+    // `after` is only called if `before` continued
+    assert_eq!(GATE.before.load(Ordering::Relaxed), 3);
+    assert_eq!(GATE.after.load(Ordering::Relaxed), 2);
+    assert_eq!(TRACED.load(Ordering::Relaxed), 2);
+}
+
+mod cfg_app {
+    use super::*;
+
+    #[cfg(not(feature = "alpha"))]
+    async fn test_delta_handler(
+        _context: &mut TestContext,
+        _header: VarHeader,
+        _body: DReq,
+    ) -> DResp {
+        DResp
+    }
+
+    define_dispatch! {
+        app: CfgDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   | Cfg                           |
+            | ----------        | ----      | -------                   | ---                           |
+            | AlphaEndpoint     | async     | test_alpha_handler        |                               |
+            | DeltaEndpoint     | async     | test_delta_handler        | cfg(not(feature = "alpha"))   |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[cfg(feature = "alpha")]
+#[tokio::test]
+async fn cfg_excluded_endpoint() {
+    let app = cfg_app::CfgDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(3)).await.unwrap();
+    assert_eq!(resp.0, 3);
+
+    // The excluded endpoint is not dispatched, nor reported as handled
+    let err = cli.send_resp::<DeltaEndpoint>(&DReq).await.unwrap_err();
+    assert_eq!(err, HostErr::UnknownKey(VarKey::Key8(DeltaEndpoint::REQ_KEY)));
+    assert!(!cli.has_endpoint::<DeltaEndpoint>().await.unwrap());
+}
+
+mod alias_app {
+    use super::*;
+
+    define_dispatch! {
+        app: AliasDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy                                    | kind      | handler               |
+            | ----------                                    | ----      | -------               |
+            | AlphaEndpoint                                 | async     | test_alpha_handler    |
+            | AlphaV0Endpoint [alias_of = AlphaEndpoint]    | async     | test_alpha_handler    |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn endpoint_alias() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = alias_app::AliasDispatcher::new(context, ChannelWireSpawn {});
+    assert_eq!(
+        app.device_map.aliases,
+        &[(AlphaV0Endpoint::REQ_KEY, AlphaEndpoint::REQ_KEY)]
+    );
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Both the current and the legacy key reach the same handler
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    assert_eq!(resp.0, 1);
+    let resp = cli.send_resp::<AlphaV0Endpoint>(&AReq(2)).await.unwrap();
+    assert_eq!(resp.0, 2);
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+    assert!(cli.has_endpoint::<AlphaV0Endpoint>().await.unwrap());
+}
+
+mod group_app {
+    use super::*;
+
+    define_dispatch! {
+        app: GroupDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy                            | kind      | handler               |
+            | ----------                            | ----      | -------               |
+            | AlphaEndpoint [group = "motor"]       | async     | test_alpha_handler    |
+            | GammaEndpoint [group = "motor"]       | async     | test_gamma_handler    |
+            | FragEndpoint [group = "io"]           | async     | test_frag_handler     |
+            | NotifyEndpoint                        | notify    | test_notify_handler   |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn endpoint_groups() {
+    let app = group_app::GroupDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let expected = [
+        ("motor", AlphaEndpoint::REQ_KEY),
+        ("motor", GammaEndpoint::REQ_KEY),
+        ("io", FragEndpoint::REQ_KEY),
+    ];
+    assert_eq!(app.device_map.groups, &expected);
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Ungrouped endpoints are not listed
+    let groups = cli.get_endpoint_groups().await.unwrap();
+    assert_eq!(groups.endpoints.len(), 3);
+    assert_eq!(groups.names(), ["motor", "io"]);
+    assert_eq!(groups.group_of(FragEndpoint::REQ_KEY), Some("io"));
+    assert_eq!(groups.group_of(NotifyEndpoint::REQ_KEY), None);
+    let motor: Vec<_> = groups.keys_in("motor").collect();
+    assert_eq!(motor, [AlphaEndpoint::REQ_KEY, GammaEndpoint::REQ_KEY]);
+
+    // Groups don't change the dispatching
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(3)).await.unwrap();
+    assert_eq!(resp.0, 3);
+}
+
+define_client! {
+    client: GroupClient;
+    endpoints: {
+        | EndpointTy        | method    |
+        | ----------        | ------    |
+        | AlphaEndpoint     | alpha     |
+        | GammaEndpoint     | gamma     |
+        | FragEndpoint      | frag      |
+        | NotifyEndpoint    | notify    |
+    };
+}
+
+#[test]
+fn icd_match() {
+    assert_icd_match!(group_app::GroupDispatcher, GroupClient);
+}
+
+mod dispatch_hooks_app {
+    use super::*;
+
+    pub static STARTED: AtomicUsize = AtomicUsize::new(0);
+    pub static ENDED: AtomicUsize = AtomicUsize::new(0);
+
+    fn start_watch(_context: &mut TestContext, _header: &VarHeader) {
+        // Never nested: the previous dispatch has ended
+        assert_eq!(STARTED.load(Ordering::Relaxed), ENDED.load(Ordering::Relaxed));
+        STARTED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stop_watch(_context: &mut TestContext, _header: &VarHeader) {
+        assert_eq!(STARTED.load(Ordering::Relaxed), ENDED.load(Ordering::Relaxed) + 1);
+        ENDED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    define_dispatch! {
+        app: HooksDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        on_dispatch_start: start_watch;
+        on_dispatch_end: stop_watch;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | AlphaEndpoint     | async     | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn dispatch_hooks() {
+    use dispatch_hooks_app::{ENDED, STARTED};
+
+    let app = dispatch_hooks_app::HooksDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    assert_eq!(resp.0, 1);
+    let resp = cli.send_resp::<PingEndpoint>(&7).await.unwrap();
+    assert_eq!(resp, 7);
+
+    // Unknown keys are dispatched as well, and answered with an error
+    let res = cli.send_resp::<GammaEndpoint>(&GReq).await;
+    assert!(matches!(res, Err(HostErr::UnknownKey(_))));
+
+    // The end hook is called after the reply has been sent
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(STARTED.load(Ordering::Relaxed), 3);
+    assert_eq!(ENDED.load(Ordering::Relaxed), 3);
+}
+
+mod fallback_app {
+    use super::*;
+    use postcard_rpc::server::fallback::Outcome;
+
+    // Answers `GammaEndpoint` requests on behalf of another device
+    async fn forward_handler(
+        context: &mut TestContext,
+        header: VarHeader,
+        body: &[u8],
+        sender: &Sender<WireTxImpl>,
+    ) -> Outcome {
+        if header.key != VarKey::Key8(GammaEndpoint::REQ_KEY) {
+            return Outcome::Error(WireError::UnknownKey);
+        }
+        if postcard::from_bytes::<GReq>(body).is_err() {
+            return Outcome::Error(WireError::DeserFailed);
+        }
+        context.topic_ctr.fetch_add(1, Ordering::Relaxed);
+        let _ = sender.reply::<GammaEndpoint>(header.seq_no, &GResp).await;
+        Outcome::Deferred
+    }
+
+    define_dispatch! {
+        app: FallbackDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        fallback: forward_handler;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | AlphaEndpoint     | async     | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn fallback_handler() {
+    let context = TestContext::default();
+    let topic_ctr = context.topic_ctr.clone();
+    let app = fallback_app::FallbackDispatcher::new(context, ChannelWireSpawn {});
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Known endpoints are handled as usual
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(3)).await.unwrap();
+    assert_eq!(resp.0, 3);
+
+    // Unknown keys are passed to the fallback handler
+    let _resp = cli.send_resp::<GammaEndpoint>(&GReq).await.unwrap();
+    assert_eq!(topic_ctr.load(Ordering::Relaxed), 1);
+
+    // Which may still reject them
+    let res = cli.send_resp::<DeltaEndpoint>(&DReq).await;
+    assert!(matches!(res, Err(HostErr::UnknownKey(_))));
+
+    // The fallback doesn't count as an endpoint of the device
+    assert!(!cli.has_endpoint::<GammaEndpoint>().await.unwrap());
+}
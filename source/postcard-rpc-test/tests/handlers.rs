@@ -0,0 +1,541 @@
+mod common;
+
+use common::*;
+
+mod transaction_app {
+    use super::*;
+
+    // Prompt the client, then add the value of its follow-up `BetaEndpoint` request
+    pub async fn test_prompt_handler<Rx: WireRx>(
+        context: &mut TestContext,
+        header: VarHeader,
+        body: AReq,
+        sender: &Sender<WireTxImpl>,
+        mut txn: Transaction<'_, Rx>,
+    ) -> AResp {
+        let prompt = ZMsg(body.0.into());
+        let _ = sender.publish::<ZetaTopic10>(header.seq_no, &prompt).await;
+        let mut buf = [0u8; 64];
+        loop {
+            let Ok((hdr, req)) = txn.recv(&mut buf).await else {
+                return AResp(0);
+            };
+            if hdr.key != VarKey::Key8(BetaEndpoint::REQ_KEY) {
+                // Not part of the transaction
+                let busy = WireError::Busy(Busy { retry_after_ms: 10 });
+                let _ = sender.error_for(&hdr, busy).await;
+                continue;
+            }
+            let req = postcard::from_bytes::<BReq>(req).unwrap();
+            context.ctr.fetch_add(1, Ordering::Relaxed);
+            let _ = sender
+                .reply::<BetaEndpoint>(hdr.seq_no, &BResp(req.0.into()))
+                .await;
+            return AResp(body.0 + req.0 as u8);
+        }
+    }
+
+    define_dispatch! {
+        app: TransactionDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind          | handler                   |
+            | ----------        | ----          | -------                   |
+            | AlphaEndpoint     | transaction   | test_prompt_handler       |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn transactions() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = transaction_app::TransactionDispatcher::new(context, ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let mut prompts = cli.subscribe_multi::<ZetaTopic10>(8).await.unwrap();
+
+    let alpha = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<AlphaEndpoint>(&AReq(2)).await }
+    });
+    let prompt = timeout(Duration::from_millis(100), prompts.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(prompt.0, 2);
+
+    // Other requests are received by the handler, and answered by it
+    let busy = HostErr::Wire(WireError::Busy(Busy { retry_after_ms: 10 }));
+    assert_eq!(cli.send_resp::<PingEndpoint>(&5).await.unwrap_err(), busy);
+
+    // The follow-up request completes the transaction
+    let resp = cli.send_resp::<BetaEndpoint>(&BReq(40)).await.unwrap();
+    assert_eq!(resp.0, 40);
+    let resp = alpha.await.unwrap().unwrap();
+    assert_eq!(resp.0, 42);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Then frames are dispatched as usual
+    assert_eq!(cli.send_resp::<PingEndpoint>(&5).await.unwrap(), 5);
+}
+
+mod timeout_app {
+    use super::*;
+
+    endpoints! {
+        list = TIMEOUT_LIST;
+        | EndpointTy        | RequestTy     | ResponseTy    | Path          |
+        | ----------        | ---------     | ----------    | ----          |
+        | SleepEndpoint     | u32           | u32           | "sleep"       |
+    }
+
+    async fn test_sleep_handler(_context: &mut TestContext, _header: VarHeader, ms: u32) -> u32 {
+        sleep_ms(ms).await;
+        ms
+    }
+
+    define_dispatch! {
+        app: TimeoutDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        timer: sleep_ms;
+
+        endpoints: {
+            list: TIMEOUT_LIST;
+
+            | EndpointTy                        | kind      | handler               |
+            | ----------                        | ----      | -------               |
+            | SleepEndpoint [timeout_ms = 50]   | async     | test_sleep_handler    |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn handler_timeout() {
+    let app = timeout_app::TimeoutDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // A handler completing in time replies as usual
+    let resp = cli.send_resp::<timeout_app::SleepEndpoint>(&5).await.unwrap();
+    assert_eq!(resp, 5);
+
+    // A handler running too long is stopped, and replies with an error
+    let res = timeout(
+        Duration::from_millis(500),
+        cli.send_resp::<timeout_app::SleepEndpoint>(&10_000),
+    )
+    .await
+    .unwrap();
+    assert_eq!(res, Err(HostErr::Wire(WireError::HandlerTimeout)));
+
+    // The server keeps handling requests
+    let resp = cli.send_resp::<PingEndpoint>(&7).await.unwrap();
+    assert_eq!(resp, 7);
+}
+
+mod custom_app {
+    use super::*;
+    use postcard_rpc::server::outcome::Outcome;
+
+    // Rejects `AReq(0)`, replies to `AReq(1)` and `AReq(2)` itself, replying to
+    // `AReq(2)` twice by mistake, and echoes everything else
+    async fn custom_alpha_handler(
+        context: &mut TestContext,
+        header: VarHeader,
+        req: AReq,
+        sender: &Sender<WireTxImpl>,
+    ) -> Outcome<AResp> {
+        context.ctr.fetch_add(1, Ordering::Relaxed);
+        match req.0 {
+            0 => Outcome::Error(WireError::Busy(Busy { retry_after_ms: 5 })),
+            1 => {
+                let _ = sender
+                    .reply::<AlphaEndpoint>(header.seq_no, &AResp(100))
+                    .await;
+                // Keep working after replying
+                yield_now().await;
+                context.ctr.fetch_add(1, Ordering::Relaxed);
+                Outcome::AlreadyReplied
+            }
+            2 => {
+                let _ = sender
+                    .reply::<AlphaEndpoint>(header.seq_no, &AResp(200))
+                    .await;
+                Outcome::Reply(AResp(2))
+            }
+            n => Outcome::Reply(AResp(n)),
+        }
+    }
+
+    define_dispatch! {
+        app: CustomDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | AlphaEndpoint     | custom    | custom_alpha_handler      |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn custom_outcome() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = custom_app::CustomDispatcher::new(context, ChannelWireSpawn {});
+    let ctr = Arc::new(AtomicUsize::new(0));
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(7)).await.unwrap();
+    assert_eq!(resp.0, 7);
+
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(0)).await.unwrap_err();
+    assert_eq!(
+        err,
+        HostErr::Wire(WireError::Busy(Busy { retry_after_ms: 5 }))
+    );
+
+    // The handler replied itself, and kept working
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    assert_eq!(resp.0, 100);
+    assert_eq!(ctr.load(Ordering::Relaxed), 4);
+
+    // Replying and returning a response only sends the first reply, as an error
+    let errors = cli.get_stats(false).await.unwrap().errors;
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(2)).await.unwrap();
+    assert_eq!(resp.0, 200);
+    assert_eq!(cli.get_stats(false).await.unwrap().errors, errors + 1);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(9)).await.unwrap();
+    assert_eq!(resp.0, 9);
+}
+
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Schema)]
+pub struct BigReading {
+    pub samples: Vec<u16>,
+    pub label: String,
+}
+
+postcard_rpc::response_enum! {
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Schema)]
+    pub enum Reading {
+        | Variant   | Type          | accessor  |
+        | -------   | ----          | --------  |
+        | Empty     | ()            | empty     |
+        | Small     | u8            | small     |
+        | Large     | BigReading    | large     |
+    }
+}
+
+mod reading_app {
+    use super::*;
+
+    endpoints! {
+        list = READING_LIST;
+        | EndpointTy        | RequestTy     | ResponseTy    | Path          |
+        | ----------        | ---------     | ----------    | ----          |
+        | ReadingEndpoint   | u8            | Reading       | "reading"     |
+    }
+
+    fn reading_handler(_context: &mut TestContext, _header: VarHeader, kind: u8) -> Reading {
+        match kind {
+            0 => Reading::Empty(()),
+            1 => Reading::Small(0xA5),
+            _ => Reading::Large(BigReading {
+                samples: (0..200).collect(),
+                label: "x".repeat(100),
+            }),
+        }
+    }
+
+    define_dispatch! {
+        app: ReadingDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: READING_LIST;
+
+            | EndpointTy        | kind      | handler           |
+            | ----------        | ----      | -------           |
+            | ReadingEndpoint   | blocking  | reading_handler   |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn response_enum_variants() {
+    use reading_app::ReadingEndpoint;
+
+    let app = reading_app::ReadingDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Interleave the variants, so a short response follows a long one
+    for kind in [2, 0, 2, 1, 1, 2, 0] {
+        let resp = cli.send_resp::<ReadingEndpoint>(&kind).await.unwrap();
+        match kind {
+            0 => assert_eq!(resp.empty(), Some(&())),
+            1 => assert_eq!(resp.small(), Some(&0xA5)),
+            _ => {
+                let large = resp.large().unwrap();
+                assert_eq!(large.samples.len(), 200);
+                assert_eq!(large.samples[199], 199);
+                assert_eq!(large.label.len(), 100);
+                assert!(resp.small().is_none());
+            }
+        }
+    }
+    assert_eq!(Reading::VARIANTS, &["Empty", "Small", "Large"]);
+    assert_eq!(Reading::Small(1).variant_name(), "Small");
+
+    // Only the variant that is sent takes up space
+    assert_eq!(postcard::to_stdvec(&Reading::Empty(())).unwrap().len(), 1);
+    assert!(postcard::to_stdvec(&Reading::Large(BigReading {
+        samples: (0..200).collect(),
+        label: "x".repeat(100),
+    }))
+    .unwrap()
+    .len() > 300);
+}
+
+#[tokio::test]
+async fn response_too_large() {
+    use reading_app::ReadingEndpoint;
+
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+
+    let app = reading_app::ReadingDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let mut cwtx = ChannelWireTx::new(server_tx);
+    cwtx.set_max_frame_len(64);
+    let kkind = app.min_key_len();
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Small responses still fit
+    let resp = cli.send_resp::<ReadingEndpoint>(&1).await.unwrap();
+    assert_eq!(resp.small(), Some(&0xA5));
+
+    // The error tells how large the buffer needs to be
+    let err = cli.send_resp::<ReadingEndpoint>(&2).await.unwrap_err();
+    let HostErr::Wire(WireError::ResponseTooLarge(ResponseTooLarge { needed, have })) = err else {
+        panic!("unexpected error: {err:?}");
+    };
+    assert_eq!(have, 64);
+    let body_len = ReadingEndpoint::response_len(&Reading::Large(BigReading {
+        samples: (0..200).collect(),
+        label: "x".repeat(100),
+    }));
+    let needed = needed as usize;
+    assert!(needed > body_len);
+    assert!(needed <= body_len + postcard_rpc::max_size::MAX_HEADER_SIZE);
+
+    // And the connection is still usable afterwards
+    let resp = cli.send_resp::<ReadingEndpoint>(&0).await.unwrap();
+    assert_eq!(resp.empty(), Some(&()));
+}
+
+mod duplex_app {
+    use super::*;
+    use postcard_rpc::server::duplex::Duplex;
+
+    // Echo each message, and send their sum once the client is done sending
+    pub async fn test_echo_handler<Rx: WireRx>(
+        _context: &mut TestContext,
+        _header: VarHeader,
+        first: AReq,
+        _sender: &Sender<WireTxImpl>,
+        duplex: &mut Duplex<'_, AlphaEndpoint, WireTxImpl, Rx>,
+    ) {
+        let mut sum = first.0;
+        let _ = duplex.send(&AResp(first.0)).await;
+        let mut buf = [0u8; 64];
+        while let Ok(Some(req)) = duplex.recv(&mut buf).await {
+            sum += req.0;
+            let _ = duplex.send(&AResp(req.0)).await;
+        }
+        let _ = duplex.send(&AResp(sum)).await;
+    }
+
+    define_dispatch! {
+        app: DuplexDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind          | handler                   |
+            | ----------        | ----          | -------                   |
+            | AlphaEndpoint     | duplex        | test_echo_handler         |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn duplex_stream() {
+    use tokio_stream::StreamExt;
+
+    let app = duplex_app::DuplexDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let (mut sink, mut stream) = cli.duplex::<AlphaEndpoint>(&AReq(1), 8).await.unwrap();
+    assert_eq!(stream.next().await.unwrap().unwrap().0, 1);
+    sink.send(&AReq(2)).await.unwrap();
+    assert_eq!(stream.next().await.unwrap().unwrap().0, 2);
+
+    // Other requests are rejected while the stream is open
+    let busy = HostErr::Wire(WireError::Busy(Busy { retry_after_ms: 0 }));
+    assert_eq!(cli.send_resp::<PingEndpoint>(&5).await.unwrap_err(), busy);
+
+    // The device keeps sending after we are done, until it closes the stream
+    sink.half_close().await.unwrap();
+    assert!(matches!(sink.send(&AReq(3)).await, Err(HostErr::Closed)));
+    assert_eq!(stream.next().await.unwrap().unwrap().0, 3);
+    assert!(stream.next().await.is_none());
+
+    // Then frames are dispatched as usual
+    assert_eq!(cli.send_resp::<PingEndpoint>(&5).await.unwrap(), 5);
+}
+
+mod confirmed_app {
+    use super::*;
+
+    define_dispatch! {
+        app: ConfirmedDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy                    | kind              | handler                   |
+            | ----------                    | ----              | -------                   |
+            | AlphaEndpoint                 | async             | test_alpha_handler        |
+            | NotifyEndpoint                | notify_confirmed  | test_notify_handler       |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn notify_confirmed() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = confirmed_app::ConfirmedDispatcher::new(context, ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Acknowledged once the handler has run
+    cli.notify_confirmed::<NotifyEndpoint>(&3).await.unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 3);
+
+    // The acknowledgement can also be ignored
+    cli.notify::<NotifyEndpoint>(&4).await.unwrap();
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    assert_eq!(ctr.load(Ordering::Relaxed), 3 + 4 + 1);
+}
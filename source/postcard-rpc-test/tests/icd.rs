@@ -0,0 +1,592 @@
+mod common;
+
+use common::*;
+
+#[tokio::test]
+async fn end_to_end_schema() {
+    let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let report = app.device_map;
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli: HostClient<_> = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+    let schema = cli.get_schema_report().await.unwrap();
+
+    for ep in &schema.endpoints {
+        println!("'{}': {} -> {}", ep.path, ep.req_ty, ep.resp_ty);
+    }
+    for tp in &schema.topics_in {
+        println!("'{}' ---> {}", tp.path, tp.ty);
+    }
+    for tp in &schema.topics_out {
+        println!("'{}' <--- {}", tp.path, tp.ty);
+    }
+
+    assert_eq!(schema.endpoints.len(), report.endpoints.len());
+    assert_eq!(schema.topics_in.len(), report.topics_in.len());
+    assert_eq!(schema.topics_out.len(), report.topics_out.len());
+}
+
+#[test]
+fn device_map() {
+    let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    println!("# SingleDispatcher");
+    println!();
+
+    println!("## Types");
+    println!();
+    for ty in app.device_map.types {
+        let ty = OwnedNamedType::from(*ty);
+        println!("* {ty}");
+    }
+
+    println!();
+    println!("## Endpoints");
+    println!();
+    for ep in app.device_map.endpoints {
+        println!(
+            "* {} ({:016X} -> {:016X})",
+            ep.0,
+            u64::from_le_bytes(ep.1.to_bytes()),
+            u64::from_le_bytes(ep.2.to_bytes()),
+        );
+    }
+
+    println!();
+    println!("## Topics (In)");
+    println!();
+    for tp in app.device_map.topics_in {
+        println!(
+            "* {} <- ({:016X})",
+            tp.0,
+            u64::from_le_bytes(tp.1.to_bytes()),
+        );
+    }
+
+    println!();
+    println!("## Topics (Out)");
+    println!();
+    for tp in app.device_map.topics_out {
+        println!(
+            "* {} -> ({:016X})",
+            tp.0,
+            u64::from_le_bytes(tp.1.to_bytes()),
+        );
+    }
+    println!();
+    println!("## Min Key Length");
+    println!();
+    println!("{:?}", app.device_map.min_key_len);
+    println!();
+}
+
+#[test]
+fn max_sizes() {
+    assert_eq!(AlphaEndpoint::MAX_REQUEST_SIZE, Some(1));
+    assert_eq!(AlphaEndpoint::MAX_RESPONSE_SIZE, Some(1));
+    assert_eq!(BetaEndpoint::MAX_REQUEST_SIZE, Some(3));
+    assert_eq!(BetaEndpoint::MAX_RESPONSE_SIZE, Some(5));
+    assert_eq!(GammaEndpoint::MAX_RESPONSE_SIZE, Some(0));
+    assert_eq!(BorrowEndpoint2::MAX_RESPONSE_SIZE, None);
+    assert_eq!(ZetaTopic1::MAX_MESSAGE_SIZE, Some(3));
+
+    // BorrowEndpoint2 has an unbounded response
+    assert_eq!(SingleDispatcher::MAX_RESPONSE_FRAME_SIZE, None);
+    assert!(postcard_rpc::max_size::fits_in(BetaEndpoint::MAX_RESPONSE_SIZE, 5));
+    assert!(!postcard_rpc::max_size::fits_in(BetaEndpoint::MAX_RESPONSE_SIZE, 4));
+
+    // An error reply is larger than the `u8` response of the LegacyEndpoint
+    assert_eq!(
+        legacy_app::LegacyDispatcher::MAX_RESPONSE_FRAME_SIZE,
+        Some(postcard_rpc::max_size::MAX_HEADER_SIZE + postcard_rpc::max_size::MAX_ERROR_SIZE)
+    );
+}
+
+#[test]
+fn serialized_lens() {
+    // Varints are counted by their actual, not their maximum size
+    let req = BReq(0x1234);
+    assert_eq!(BetaEndpoint::request_len(&req), 2);
+    assert_eq!(BetaEndpoint::request_len(&BReq(1)), 1);
+    assert_eq!(BetaEndpoint::MAX_REQUEST_SIZE, Some(3));
+
+    let resp = BResp(u32::MAX);
+    let len = postcard::to_stdvec(&resp).unwrap().len();
+    assert_eq!(BetaEndpoint::response_len(&resp), len);
+    assert_eq!(GammaEndpoint::response_len(&GResp), 0);
+}
+
+// A newer version of `AlphaEndpoint`, with different types on the same path
+endpoint!(AlphaV2Endpoint, u64, u64, "alpha");
+// An endpoint the server does not know about
+endpoint!(OmegaEndpoint, u8, u8, "omega");
+// An endpoint with the same request key as `ZetaTopic1`
+endpoint!(ZetaLikeEndpoint, ZMsg, ZMsg, "zeta1");
+
+#[test]
+fn keys_for_paths() {
+    // The macros calculate the same keys from the path and the types
+    assert_eq!(AlphaEndpoint::REQ_KEY, Key::for_path::<AReq>("alpha"));
+    assert_eq!(AlphaEndpoint::RESP_KEY, Key::for_path::<AResp>("alpha"));
+    assert_eq!(OmegaEndpoint::REQ_KEY, Key::for_path::<u8>("omega"));
+    assert_eq!(ZetaTopic1::TOPIC_KEY, Key::for_path::<ZMsg>("zeta1"));
+
+    let bytes = AlphaEndpoint::REQ_KEY.to_bytes();
+    assert_eq!(unsafe { Key::from_bytes(bytes) }, AlphaEndpoint::REQ_KEY);
+}
+
+#[tokio::test]
+async fn handshake_reports_keys() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let hs = cli.handshake().await.unwrap();
+    assert_eq!(hs.protocol_version, PROTOCOL_VERSION);
+    assert!(hs.protocol_matches());
+    assert_eq!(hs.endpoints.len(), ENDPOINT_LIST.endpoints.len());
+
+    assert_eq!(
+        hs.endpoint_status::<AlphaEndpoint>(),
+        EndpointStatus::Compatible
+    );
+    assert_eq!(
+        hs.endpoint_status::<AlphaV2Endpoint>(),
+        EndpointStatus::Mismatch {
+            request_key: AlphaEndpoint::REQ_KEY,
+            response_key: AlphaEndpoint::RESP_KEY,
+        }
+    );
+    assert_eq!(
+        hs.endpoint_status::<OmegaEndpoint>(),
+        EndpointStatus::Missing
+    );
+}
+
+#[tokio::test]
+async fn has_endpoint_query() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    assert!(cli.has_endpoint::<AlphaEndpoint>().await.unwrap());
+    assert!(cli.has_endpoint::<PingEndpoint>().await.unwrap());
+    // Listed, but without a handler
+    assert!(!cli.has_endpoint::<DeltaEndpoint>().await.unwrap());
+    // Same path, different types
+    assert!(!cli.has_endpoint::<AlphaV2Endpoint>().await.unwrap());
+    assert!(!cli.has_endpoint::<OmegaEndpoint>().await.unwrap());
+    // Topics are not endpoints
+    assert!(!cli.has_endpoint::<ZetaLikeEndpoint>().await.unwrap());
+
+    // No handler was called
+    assert_eq!(ctr.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn unknown_key_falls_through() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Listed, but without a handler
+    let err = cli.send_resp::<DeltaEndpoint>(&DReq).await.unwrap_err();
+    assert_eq!(err, HostErr::UnknownKey(VarKey::Key8(DeltaEndpoint::REQ_KEY)));
+    // Not listed at all
+    let err = cli.send_resp::<OmegaEndpoint>(&1).await.unwrap_err();
+    assert_eq!(err, HostErr::UnknownKey(VarKey::Key8(OmegaEndpoint::REQ_KEY)));
+
+    // Every handled key still reaches its handler
+    let resp = cli.send_resp::<PingEndpoint>(&9).await.unwrap();
+    assert_eq!(resp, 9);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(3)).await.unwrap();
+    assert_eq!(resp.0, 3);
+    let resp = cli.send_resp::<InlineEndpoint>(&5).await.unwrap();
+    assert_eq!(resp, 10);
+}
+
+mod legacy_app {
+    use super::*;
+
+    // Kept out of `ENDPOINT_LIST`: the types of an explicit key can't be found from the
+    // key, so the schema report of a device listing it fails
+    endpoints! {
+        list = LEGACY_ENDPOINT_LIST;
+        | EndpointTy        | RequestTy | ResponseTy    | Path                          |
+        | ----------        | --------- | ----------    | ----                          |
+        | LegacyEndpoint    | u8        | u8            | "legacy" [key = LEGACY_KEY]   |
+    }
+
+    fn test_legacy_handler(_context: &mut TestContext, _header: VarHeader, body: u8) -> u8 {
+        body.wrapping_add(1)
+    }
+
+    define_dispatch! {
+        app: LegacyDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        tx_buf_size: 1024;
+
+        endpoints: {
+            list: LEGACY_ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | LegacyEndpoint    | blocking  | test_legacy_handler       |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn explicit_endpoint_keys() {
+    assert_eq!(legacy_app::LegacyEndpoint::REQ_KEY.to_bytes(), LEGACY_KEY);
+    assert_eq!(legacy_app::LegacyEndpoint::RESP_KEY.to_bytes(), LEGACY_KEY);
+
+    let app = legacy_app::LegacyDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+    let ServerFixture {
+        client_tx: fwd_tx,
+        client_rx,
+    } = spawn_server(app, Some(VarKeyKind::Key8), |_| {});
+    let (client_tx, mut server_rx) = mpsc::channel(16);
+
+    // Check the key on the wire, on the way to the server
+    let (seen_tx, mut seen_rx) = mpsc::channel(16);
+    tokio::task::spawn(async move {
+        while let Some(frame) = server_rx.recv().await {
+            let (hdr, _body) = VarHeader::take_from_slice(&frame).unwrap();
+            let _ = seen_tx.send(hdr.key).await;
+            let _ = fwd_tx.send(frame).await;
+        }
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let resp = cli.send_resp::<legacy_app::LegacyEndpoint>(&41).await.unwrap();
+    assert_eq!(resp, 42);
+    assert_eq!(seen_rx.recv().await.unwrap(), VarKey::Key8(legacy_app::LegacyEndpoint::REQ_KEY));
+}
+
+// A device with a newer version of the response of "grow"
+mod grow_app {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Schema)]
+    pub struct GrowResp {
+        pub val: u8,
+        pub doubled: u16,
+    }
+
+    endpoints! {
+        list = GROW_LIST;
+        | EndpointTy        | RequestTy     | ResponseTy    | Path      |
+        | ----------        | ---------     | ----------    | ----      |
+        | GrowEndpoint      | u8            | GrowResp      | "grow"    |
+    }
+
+    fn test_grow_handler(_context: &mut TestContext, _header: VarHeader, body: u8) -> GrowResp {
+        GrowResp {
+            val: body,
+            doubled: u16::from(body) * 2,
+        }
+    }
+
+    define_dispatch! {
+        app: GrowDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: GROW_LIST;
+
+            | EndpointTy        | kind      | handler               |
+            | ----------        | ----      | -------               |
+            | GrowEndpoint      | blocking  | test_grow_handler     |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+pub struct GrowRespV1 {
+    pub val: u8,
+}
+
+endpoint!(GrowV1Endpoint, u8, GrowRespV1, "grow");
+endpoint!(GrowV0Endpoint, u8, u16, "grow");
+
+#[tokio::test]
+async fn schema_mismatch() {
+    let app = grow_app::GrowDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // After the handshake, the different response key of the device is known
+    cli.handshake().await.unwrap();
+    let res = timeout(
+        Duration::from_millis(100),
+        cli.send_resp::<GrowV0Endpoint>(&3),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        res,
+        Err(HostErr::SchemaMismatch {
+            expected_key: GrowV0Endpoint::RESP_KEY,
+            actual_key: grow_app::GrowEndpoint::RESP_KEY,
+        })
+    );
+
+    // Only a response type that appends fields to ours can be decoded
+    assert!(!cli.allow_lenient::<GrowV0Endpoint>().await.unwrap());
+    assert!(cli.allow_lenient::<GrowV1Endpoint>().await.unwrap());
+    let resp = timeout(
+        Duration::from_millis(100),
+        cli.send_resp::<GrowV1Endpoint>(&3),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(resp, GrowRespV1 { val: 3 });
+
+    let res = cli.send_resp::<GrowV0Endpoint>(&3).await;
+    assert!(matches!(res, Err(HostErr::SchemaMismatch { .. })));
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub enum LedMode {
+    Off,
+    Blink(u16),
+    Color { rgb: [u8; 3], name: Option<String> },
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct LedState {
+    pub modes: Vec<LedMode>,
+    pub active: bool,
+}
+
+endpoint!(SetLedEndpoint, LedState, (), "led/set");
+postcard_rpc::topic!(LedChangedTopic, LedMode, "led/changed");
+
+#[test]
+fn codegen_icd() {
+    use postcard_rpc::{codegen::Icd, TopicDirection};
+
+    let icd = Icd::new()
+        .endpoint::<SetLedEndpoint>()
+        .endpoint::<PingEndpoint>()
+        .topic::<LedChangedTopic>(TopicDirection::ToClient);
+
+    let ts = icd.to_typescript();
+    let expected = [
+        "export type LedMode = \"Off\" | { Blink: number } \
+            | { Color: { rgb: [number, number, number]; name: string | null } };",
+        "export type LedState = { modes: LedMode[]; active: boolean };",
+        "    \"led/set\": { request: LedState; response: null };",
+        "    \"postcard-rpc/ping\": { request: number; response: number };",
+        "export interface TopicsIn {\n}",
+        "export interface TopicsOut {\n    \"led/changed\": LedMode;\n}",
+    ];
+    for line in expected {
+        assert!(ts.contains(line), "missing {line:?} in:\n{ts}");
+    }
+    // Named types are only declared once
+    assert_eq!(ts.matches("export type LedMode").count(), 1);
+
+    let json = icd.to_json();
+    assert!(json.contains("\"path\": \"led/set\""));
+    let key = SetLedEndpoint::REQ_KEY.to_bytes();
+    let hex = key.iter().map(|b| format!("{b:02X}")).collect::<String>();
+    assert!(json.contains(&hex));
+}
+
+// The ICD of a host built against different firmware
+mod skewed_icd {
+    use super::*;
+
+    endpoints! {
+        list = SKEWED_ENDPOINT_LIST;
+        | EndpointTy            | RequestTy | ResponseTy    | Path          |
+        | ----------            | --------- | ----------    | ----          |
+        | SkewedAlphaEndpoint   | u32       | AResp         | "alpha"       |
+        | SkewedGammaEndpoint   | GReq      | GResp         | "gamma"       |
+        | MissingEndpoint       | u8        | u8            | "missing"     |
+    }
+
+    topics! {
+        list = SKEWED_TOPICS_IN_LIST;
+        direction = postcard_rpc::TopicDirection::ToServer;
+        | TopicTy           | MessageTy | Path      |
+        | ----------        | --------- | ----      |
+        | SkewedZetaTopic1  | u8        | "zeta1"   |
+    }
+
+    topics! {
+        list = SKEWED_TOPICS_OUT_LIST;
+        direction = postcard_rpc::TopicDirection::ToClient;
+        | TopicTy           | MessageTy | Path          |
+        | ----------        | --------- | ----          |
+        | SkewedZetaTopic10 | ZMsg      | "zeta10"      |
+        | MissingTopic      | ZMsg      | "missing"     |
+    }
+}
+
+#[tokio::test]
+async fn open_checked() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    // The same ICD as the device
+    let cli: HostClient<WireError> =
+        client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let cli = cli
+        .open_checked(&ENDPOINT_LIST, &TOPICS_IN_LIST, &TOPICS_OUT_LIST)
+        .await
+        .unwrap();
+
+    // A different ICD, every difference is reported
+    let res = cli
+        .check_icd(
+            &skewed_icd::SKEWED_ENDPOINT_LIST,
+            &skewed_icd::SKEWED_TOPICS_IN_LIST,
+            &skewed_icd::SKEWED_TOPICS_OUT_LIST,
+        )
+        .await;
+    let Err(IcdCheckError::Mismatch(mismatch)) = res else {
+        panic!("the ICD matched: {res:?}");
+    };
+
+    assert_eq!(mismatch.endpoints.len(), 2);
+    let EndpointMismatch::Changed {
+        path,
+        request: Some(request),
+        response: None,
+    } = &mismatch.endpoints[0]
+    else {
+        panic!("{:?}", mismatch.endpoints[0]);
+    };
+    assert_eq!(path, "alpha");
+    assert_eq!(request.expected, skewed_icd::SkewedAlphaEndpoint::REQ_KEY);
+    assert_eq!(request.found, AlphaEndpoint::REQ_KEY);
+    assert_eq!(request.found_ty.name, "AReq");
+    let missing = EndpointMismatch::Missing {
+        path: "missing".into(),
+    };
+    assert_eq!(mismatch.endpoints[1], missing);
+
+    assert_eq!(mismatch.topics_in.len(), 1);
+    assert!(matches!(
+        &mismatch.topics_in[0],
+        TopicMismatch::Changed { path, .. } if path == "zeta1"
+    ));
+    let missing = TopicMismatch::Missing {
+        path: "missing".into(),
+    };
+    assert_eq!(mismatch.topics_out, [missing]);
+
+    // Each offending endpoint and topic is named
+    let text = mismatch.to_string();
+    assert!(text.contains("endpoint \"alpha\": request expected"));
+    assert!(text.contains("endpoint \"missing\": missing on the device"));
+    assert!(text.contains("incoming topic \"zeta1\""));
+    assert!(text.contains("outgoing topic \"missing\""));
+
+    // A client for a mismatched device is closed
+    let res = cli
+        .open_checked(
+            &skewed_icd::SKEWED_ENDPOINT_LIST,
+            &TOPICS_IN_LIST,
+            &TOPICS_OUT_LIST,
+        )
+        .await;
+    assert!(matches!(res, Err(IcdCheckError::Mismatch(_))));
+}
+
+#[tokio::test]
+async fn open_compatible() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    // The same ICD as the device
+    let cli: HostClient<WireError> =
+        client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let cli = cli
+        .open_compatible(&ENDPOINT_LIST, &TOPICS_IN_LIST, &TOPICS_OUT_LIST)
+        .await
+        .unwrap();
+    let compat = cli.compat().await.unwrap();
+    assert_eq!(compat.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(compat.crate_version, CRATE_VERSION);
+    assert_eq!(compat.max_frame_len, 1024);
+
+    // The hash doesn't depend on the order of the lists
+    let mut endpoints = ENDPOINT_LIST.endpoints.to_vec();
+    endpoints.reverse();
+    let hash = postcard_rpc::hash::fnv1a64::hash_icd(
+        &endpoints,
+        TOPICS_IN_LIST.topics,
+        TOPICS_OUT_LIST.topics,
+    );
+    assert_eq!(hash, compat.icd_hash);
+
+    // A different ICD is rejected
+    let err = cli
+        .check_compat(
+            &skewed_icd::SKEWED_ENDPOINT_LIST,
+            &TOPICS_IN_LIST,
+            &TOPICS_OUT_LIST,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err, HostErr::Incompatible(compat));
+    let res = cli
+        .open_compatible(
+            &ENDPOINT_LIST,
+            &skewed_icd::SKEWED_TOPICS_IN_LIST,
+            &TOPICS_OUT_LIST,
+        )
+        .await;
+    assert!(matches!(res, Err(HostErr::Incompatible(_))));
+}
@@ -0,0 +1,380 @@
+mod common;
+
+use common::*;
+
+#[tokio::test]
+async fn graceful_shutdown() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // The slow request is already in-flight, and is allowed to complete
+    let slow = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<EpsilonEndpoint>(&EReq).await }
+    });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    cli.shutdown(Duration::from_secs(1)).await;
+    assert!(slow.await.unwrap().is_ok());
+    assert!(cli.is_closed());
+
+    // New requests are rejected
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap_err();
+    assert_eq!(err, HostErr::Shutdown);
+}
+
+#[tokio::test]
+async fn shutdown_deadline() {
+    // Nobody ever answers
+    let (client_tx, _server_rx) = mpsc::channel(16);
+    let (_server_tx, client_rx) = mpsc::channel::<Vec<u8>>(16);
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    let pending = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<AlphaEndpoint>(&AReq(42)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let start = Instant::now();
+    cli.shutdown(Duration::from_millis(50)).await;
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(pending.await.unwrap().unwrap_err(), HostErr::Shutdown);
+}
+
+mod lifecycle_app {
+    use super::*;
+
+    pub fn test_on_connected(context: &mut TestContext) {
+        context.ctr.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn test_on_disconnected(context: &mut TestContext) {
+        context.topic_ctr.fetch_add(1, Ordering::Relaxed);
+    }
+
+    define_dispatch! {
+        app: LifecycleDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        on_connected: test_on_connected;
+        on_disconnected: test_on_disconnected;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | AlphaEndpoint     | async     | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn connection_events() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+
+    // The lifecycle hooks count connections in `ctr`, disconnections in `topic_ctr`
+    let context = TestContext::default();
+    let connected = context.ctr.clone();
+    let disconnected = context.topic_ctr.clone();
+    let app = lifecycle_app::LifecycleDispatcher::new(context, ChannelWireSpawn {});
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+    let kkind = app.min_key_len();
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    let sender = server.sender();
+    let run = tokio::task::spawn(async move {
+        server.run().await;
+    });
+
+    // Channels are always connected
+    sender.wait_connected().await;
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    // The handler counts the request as well
+    assert_eq!(connected.load(Ordering::Relaxed), 2);
+    assert_eq!(disconnected.load(Ordering::Relaxed), 0);
+
+    // Closing the channel ends `run`, after calling `on_disconnected`
+    cli.close();
+    timeout(Duration::from_secs(1), run).await.unwrap().unwrap();
+    assert_eq!(connected.load(Ordering::Relaxed), 2);
+    assert_eq!(disconnected.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn reset_connection() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq4);
+    assert_eq!(cli.epoch(), 0);
+
+    // The second request is answered from the dedup cache
+    cli.set_seq_no_source(|| 7u32);
+    cli.send_resp::<GammaEndpoint>(&GReq).await.unwrap();
+    cli.send_resp::<GammaEndpoint>(&GReq).await.unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // A slow request is still pending when the connection is reset
+    cli.set_seq_no_source(|| 100u32);
+    let slow = tokio::task::spawn({
+        let cli = cli.clone();
+        async move { cli.send_resp::<EpsilonEndpoint>(&EReq).await }
+    });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    cli.set_seq_no_source(MonotonicSeqNo::new(200));
+    assert_eq!(cli.reset().await.unwrap(), 1);
+    assert_eq!(cli.epoch(), 1);
+    assert!(matches!(slow.await.unwrap(), Err(HostErr::Reset)));
+
+    // Its sequence number is not reused until its late response was discarded
+    cli.set_seq_no_source(|| 100u32);
+    let res = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await;
+    assert!(matches!(res, Err(HostErr::SeqNoInUse)));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+    assert_eq!(resp.0, 1);
+
+    // The dedup cache was cleared by the reset
+    cli.set_seq_no_source(|| 7u32);
+    cli.send_resp::<GammaEndpoint>(&GReq).await.unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 4);
+    assert_eq!(cli.reset().await.unwrap(), 2);
+}
+
+mod heartbeat_app {
+    use super::*;
+    use postcard_rpc::server::heartbeat::HeartbeatConfig;
+
+    pub static HEARTBEAT: HeartbeatConfig = HeartbeatConfig::new(20);
+
+    define_dispatch! {
+        app: HeartbeatDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        heartbeat: HEARTBEAT;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy                    | kind      | handler                   |
+            | ----------                    | ----      | -------                   |
+            | AlphaEndpoint                 | async     | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn heartbeat_watch() {
+    let app = heartbeat_app::HeartbeatDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let mut sender = None;
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |server| sender = Some(server.sender()));
+    let sender = sender.unwrap();
+    tokio::task::spawn(async move {
+        heartbeat_task(&sender, &heartbeat_app::HEARTBEAT, sleep_ms).await;
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let mut watch = cli.watch_heartbeat(Duration::from_millis(200)).await.unwrap();
+
+    // Heartbeats arrive in order, alongside normal requests
+    let first = watch.recv().await.unwrap();
+    assert_eq!(first.interval_ms, 20);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    let second = watch.recv().await.unwrap();
+    assert!(second.seq > first.seq);
+
+    // Disabling heartbeats is noticed by the watch
+    assert!(cli.set_heartbeat_interval(0).await.unwrap());
+    let err = loop {
+        match watch.recv().await {
+            Ok(hb) => assert_eq!(hb.interval_ms, 20),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(err, HostErr::HeartbeatLost);
+
+    // And enabling them again recovers it
+    assert!(cli.set_heartbeat_interval(10).await.unwrap());
+    let hb = watch.recv().await.unwrap();
+    assert_eq!(hb.interval_ms, 10);
+}
+
+mod reboot_app {
+    use super::*;
+
+    pub fn prepare_reboot(context: &mut TestContext, mode: RebootMode) -> Option<impl FnOnce()> {
+        match mode {
+            RebootMode::Application => {
+                let ctr = context.ctr.clone();
+                Some(move || {
+                    ctr.fetch_add(1, Ordering::Relaxed);
+                })
+            }
+            RebootMode::Bootloader => None,
+        }
+    }
+
+    define_dispatch! {
+        app: RebootDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        reboot: prepare_reboot;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy                    | kind              | handler                   |
+            | ----------                    | ----              | -------                   |
+            | AlphaEndpoint                 | async             | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn reboot() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = reboot_app::RebootDispatcher::new(context, ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // The reboot function is called after replying
+    assert!(cli.reboot(RebootMode::Application).await.unwrap());
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Unsupported modes are refused
+    assert!(!cli.reboot(RebootMode::Bootloader).await.unwrap());
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn disconnect_signal() {
+    static LINK: DisconnectSignal<4> = DisconnectSignal::new();
+
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+
+    let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+    let kkind = app.min_key_len();
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    server.set_disconnect_signal(Some(&LINK));
+    let live = server.sender();
+
+    // Not connected before the server runs
+    assert!(live.is_disconnected());
+    let run = tokio::task::spawn(async move {
+        let err = server.run().await;
+        (server, err)
+    });
+
+    let cli: HostClient<WireError> =
+        client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    assert_eq!(cli.send_resp::<PingEndpoint>(&1).await.unwrap(), 1);
+    assert!(!live.is_disconnected());
+
+    let conn = LINK.connection();
+    let waiter = tokio::task::spawn(async move { conn.lost().await });
+    yield_now().await;
+    assert!(!waiter.is_finished());
+
+    // The transport trips the signal, e.g. on a USB bus reset
+    LINK.disconnect();
+    timeout(Duration::from_millis(100), waiter)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(conn.is_lost());
+    assert!(live.is_disconnected());
+
+    // Frames fail right away instead of reaching the client
+    let err = live.log_str("lost").await.unwrap_err();
+    assert!(matches!(err, ChannelWireTxError::ChannelClosed));
+
+    // The reply to the next request fails as well, which ends `run`
+    let res = timeout(Duration::from_millis(100), cli.send_resp::<PingEndpoint>(&2)).await;
+    assert!(res.is_err());
+    let (mut server, err) = timeout(Duration::from_millis(100), run)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        err,
+        ServerError::TxFatal(ChannelWireTxError::ChannelClosed)
+    ));
+
+    // Running again starts a new connection, the old one stays lost
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+    assert_eq!(cli.send_resp::<PingEndpoint>(&3).await.unwrap(), 3);
+    assert!(!live.is_disconnected());
+    assert!(conn.is_lost());
+}
@@ -0,0 +1,387 @@
+mod common;
+
+use common::*;
+
+mod compress_app {
+    use super::*;
+
+    fn test_read_log_handler(_context: &mut TestContext, _header: VarHeader, len: u32) -> Bytes {
+        vec![0; len as usize]
+    }
+
+    define_dispatch! {
+        app: CompressDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy                        | kind      | handler                   |
+            | ----------                        | ----      | -------                   |
+            | ReadLogEndpoint [compress = true] | blocking  | test_read_log_handler     |
+            | AlphaEndpoint                     | async     | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn compressed_responses() {
+    let app = compress_app::CompressDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx: mut server_out,
+    } = spawn_server(app, None, |_| {});
+    let (fwd_tx, client_rx) = mpsc::channel(16);
+
+    // Check the size and flag of the frames on the way to the client
+    let (seen_tx, mut seen_rx) = mpsc::channel(16);
+    tokio::task::spawn(async move {
+        while let Some(frame) = server_out.recv().await {
+            let (_hdr, flags, _token, _body) = VarHeader::take_from_slice_flagged(&frame).unwrap();
+            let _ = seen_tx.send((flags.compressed, frame.len())).await;
+            let _ = fwd_tx.send(frame).await;
+        }
+    });
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Large, repetitive responses are compressed, and decompressed by the client
+    let resp = cli.send_resp::<ReadLogEndpoint>(&4000).await.unwrap();
+    assert_eq!(resp, vec![0; 4000]);
+    let (compressed, len) = seen_rx.recv().await.unwrap();
+    assert!(compressed);
+    assert!(len < 100);
+
+    // Short responses are sent as-is
+    let resp = cli.send_resp::<ReadLogEndpoint>(&2).await.unwrap();
+    assert_eq!(resp, vec![0; 2]);
+    assert!(!seen_rx.recv().await.unwrap().0);
+
+    // Other endpoints are not compressed
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap();
+    assert_eq!(resp.0, 42);
+    assert!(!seen_rx.recv().await.unwrap().0);
+}
+
+mod auth_app {
+    use super::*;
+
+    pub const TOKEN: AuthToken = AuthToken(*b"s3cr3t!!");
+
+    fn check_token(_context: &mut TestContext, _header: &VarHeader, token: &AuthToken) -> bool {
+        *token == TOKEN
+    }
+
+    define_dispatch! {
+        app: AuthDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        auth: check_token;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy                    | kind      | handler                   |
+            | ----------                    | ----      | -------                   |
+            | AlphaEndpoint [auth = true]   | async     | test_alpha_handler        |
+            | BetaEndpoint                  | spawn     | test_beta_handler         |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn auth_token() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = auth_app::AuthDispatcher::new(context, ChannelWireSpawn {});
+    let ctr = Arc::new(AtomicUsize::new(0));
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Without a token, only endpoints that don't require one can be used
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap_err();
+    assert_eq!(err, HostErr::Wire(WireError::Unauthorized));
+    let _resp = cli.send_resp::<BetaEndpoint>(&BReq(1)).await.unwrap();
+
+    // A wrong token is rejected as well, without running the handler
+    cli.set_auth_token(Some(AuthToken([0; 8])));
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(2)).await.unwrap_err();
+    assert_eq!(err, HostErr::Wire(WireError::Unauthorized));
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    cli.set_auth_token(Some(auth_app::TOKEN));
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(3)).await.unwrap();
+    assert_eq!(resp.0, 3);
+    let _resp = cli.send_resp::<BetaEndpoint>(&BReq(4)).await.unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 3);
+
+    cli.set_auth_token(None);
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(5)).await.unwrap_err();
+    assert_eq!(err, HostErr::Wire(WireError::Unauthorized));
+}
+
+#[tokio::test]
+async fn frame_crc() {
+    let protection = LinkProtection {
+        crc: CrcMode::Reply,
+        ..Default::default()
+    };
+    let mut early = None;
+    let SingleFixture {
+        client_tx,
+        mut client_rx,
+        ctr,
+        ..
+    } = single_server_protected(None, protection, |server| early = Some(server.sender()));
+
+    // A sender obtained before the server runs appends a CRC as well
+    let early = early.unwrap();
+    early.publish::<ZetaTopic10>(VarSeq::Seq4(7), &ZMsg(-7)).await.unwrap();
+    let msg = client_rx.recv().await.unwrap();
+    let len = checked_len(&msg).unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&msg[..len]).unwrap();
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(7));
+    assert_eq!(postcard::from_bytes::<ZMsg>(body).unwrap().0, -7);
+
+    let mut msg = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq4(123),
+    }
+    .write_to_vec();
+    msg.extend_from_slice(&postcard::to_stdvec(&AReq(42)).unwrap());
+    let crc = crc32(&msg);
+    msg.extend_from_slice(&crc.to_le_bytes());
+    client_tx.send(msg.clone()).await.unwrap();
+
+    // The reply carries a CRC as well
+    let resp = client_rx.recv().await.unwrap();
+    let len = checked_len(&resp).unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&resp[..len]).unwrap();
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(123));
+    assert_eq!(postcard::from_bytes::<AResp>(body).unwrap().0, 42);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Flip a bit of the body, the request is rejected without being handled
+    let body_pos = msg.len() - 5;
+    msg[body_pos] ^= 0x01;
+    client_tx.send(msg).await.unwrap();
+    let resp = client_rx.recv().await.unwrap();
+    let len = checked_len(&resp).unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&resp[..len]).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(postcard_rpc::standard_icd::ERROR_KEY));
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(123));
+    assert_eq!(postcard::from_bytes::<WireError>(body).unwrap(), WireError::CrcMismatch);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // The same for a frame carrying an auth token
+    let mut msg = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq4(124),
+    }
+    .write_to_vec_with_token(&AuthToken(*b"s3cr3t!!"));
+    msg.extend_from_slice(&postcard::to_stdvec(&AReq(42)).unwrap());
+    let crc = crc32(&msg);
+    msg.extend_from_slice(&crc.to_le_bytes());
+    let body_pos = msg.len() - 5;
+    msg[body_pos] ^= 0x01;
+    client_tx.send(msg).await.unwrap();
+    let resp = client_rx.recv().await.unwrap();
+    let len = checked_len(&resp).unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&resp[..len]).unwrap();
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(124));
+    assert_eq!(postcard::from_bytes::<WireError>(body).unwrap(), WireError::CrcMismatch);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn frame_crc_client() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        ..
+    } = single_server_protected(
+        None,
+        LinkProtection {
+            crc: CrcMode::Drop,
+            ..Default::default()
+        },
+        |_| {},
+    );
+
+    let raw_tx = client_tx.clone();
+    let cli = client::new_from_channels_with_crc(client_tx, client_rx, VarSeqKind::Seq2);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(21)).await.unwrap();
+    assert_eq!(resp.0, 21);
+    let resp = cli.send_resp::<PingEndpoint>(&7).await.unwrap();
+    assert_eq!(resp, 7);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // A request without a CRC is silently dropped
+    let mut msg = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq4(1000),
+    }
+    .write_to_vec();
+    msg.extend_from_slice(&postcard::to_stdvec(&AReq(1)).unwrap());
+    raw_tx.send(msg).await.unwrap();
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(22)).await.unwrap();
+    assert_eq!(resp.0, 22);
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
+
+/// A trivial XOR "cipher", with the sum of the encrypted bytes as a one byte tag
+struct XorCipher([u8; 4]);
+
+impl XorCipher {
+    fn apply(&self, state: &CipherState, data: &mut [u8]) {
+        for (i, b) in data.iter_mut().enumerate() {
+            *b ^= self.0[(state.offset + i) % self.0.len()];
+        }
+    }
+
+    fn sum(state: &mut CipherState, data: &[u8]) {
+        for b in data {
+            state.scratch[0] = state.scratch[0].wrapping_add(*b);
+        }
+    }
+}
+
+impl BodyCipher for XorCipher {
+    fn tag_len(&self) -> usize {
+        1
+    }
+
+    fn encrypt(&self, _hdr: &VarHeader, state: &mut CipherState, data: &mut [u8]) {
+        self.apply(state, data);
+        Self::sum(state, data);
+    }
+
+    fn decrypt(&self, _hdr: &VarHeader, state: &mut CipherState, data: &mut [u8]) {
+        Self::sum(state, data);
+        self.apply(state, data);
+    }
+
+    fn tag(&self, _hdr: &VarHeader, state: &mut CipherState, tag: &mut [u8]) {
+        tag[0] = state.scratch[0];
+    }
+}
+
+static XOR_CIPHER: XorCipher = XorCipher([0x5A, 0xA5, 0x0F, 0xF0]);
+
+#[tokio::test]
+async fn body_cipher() {
+    let protection = LinkProtection {
+        cipher: Some(&XOR_CIPHER),
+        ..Default::default()
+    };
+    let mut early = None;
+    let SingleFixture {
+        client_tx,
+        mut client_rx,
+        ctr,
+        ..
+    } = single_server_protected(None, protection, |server| early = Some(server.sender()));
+
+    // A sender obtained before the server runs seals its frames as well
+    let early = early.unwrap();
+    early.publish::<ZetaTopic10>(VarSeq::Seq4(7), &ZMsg(-7)).await.unwrap();
+    let mut msg = client_rx.recv().await.unwrap();
+    let len = open_frame(&XOR_CIPHER, &mut msg).unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&msg[..len]).unwrap();
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(7));
+    assert_eq!(postcard::from_bytes::<ZMsg>(body).unwrap().0, -7);
+
+    let mut msg = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq4(123),
+    }
+    .write_to_vec();
+    msg.extend_from_slice(&postcard::to_stdvec(&AReq(42)).unwrap());
+    seal_frame(&XOR_CIPHER, &mut msg);
+    client_tx.send(msg.clone()).await.unwrap();
+
+    // The reply is sealed as well
+    let mut resp = client_rx.recv().await.unwrap();
+    let (_, sealed) = VarHeader::take_from_slice(&resp).unwrap();
+    assert_ne!(postcard::from_bytes::<AResp>(sealed).ok().map(|r| r.0), Some(42));
+    let len = open_frame(&XOR_CIPHER, &mut resp).unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&resp[..len]).unwrap();
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(123));
+    assert_eq!(postcard::from_bytes::<AResp>(body).unwrap().0, 42);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // Flip a bit of the body, the request is rejected without being handled
+    let body_pos = msg.len() - 2;
+    msg[body_pos] ^= 0x01;
+    client_tx.send(msg).await.unwrap();
+    let mut resp = client_rx.recv().await.unwrap();
+    let len = open_frame(&XOR_CIPHER, &mut resp).unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&resp[..len]).unwrap();
+    assert_eq!(hdr.key, VarKey::Key8(postcard_rpc::standard_icd::ERROR_KEY));
+    assert_eq!(hdr.seq_no, VarSeq::Seq4(123));
+    assert_eq!(postcard::from_bytes::<WireError>(body).unwrap(), WireError::Unauthorized);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn body_cipher_client() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        ..
+    } = single_server_protected(
+        None,
+        LinkProtection {
+            cipher: Some(&XOR_CIPHER),
+            ..Default::default()
+        },
+        |_| {},
+    );
+
+    let raw_tx = client_tx.clone();
+    let cli =
+        client::new_from_channels_with_cipher(client_tx, client_rx, VarSeqKind::Seq2, &XOR_CIPHER);
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(21)).await.unwrap();
+    assert_eq!(resp.0, 21);
+    let resp = cli.send_resp::<PingEndpoint>(&7).await.unwrap();
+    assert_eq!(resp, 7);
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // An unsealed request is not handled, and the client drops the error, as it
+    // didn't send the request
+    let mut msg = VarHeader {
+        key: VarKey::Key8(AlphaEndpoint::REQ_KEY),
+        seq_no: VarSeq::Seq4(1000),
+    }
+    .write_to_vec();
+    msg.extend_from_slice(&postcard::to_stdvec(&AReq(1)).unwrap());
+    raw_tx.send(msg).await.unwrap();
+    let resp = cli.send_resp::<AlphaEndpoint>(&AReq(22)).await.unwrap();
+    assert_eq!(resp.0, 22);
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::*;
+
+#[tokio::test]
+async fn structured_log_records() {
+    let mut sender = None;
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |server| sender = Some(server.sender()));
+    let sender = sender.unwrap();
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let mut sub = cli.subscribe_multi::<LogRecordTopic>(8).await.unwrap();
+
+    rpc_log!(sender, LogLevel::Warn, "temperature high: {}C", 85).unwrap();
+    // Too long, truncated at a char boundary
+    rpc_log!(sender, LogLevel::Info, "{}é", "x".repeat(127)).unwrap();
+    rpc_log!(sender, LogLevel::Debug, "done").unwrap();
+
+    let record = timeout(Duration::from_millis(100), sub.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        record,
+        OwnedLogRecord {
+            level: LogLevel::Warn,
+            msg: String::from("temperature high: 85C"),
+        }
+    );
+    assert_eq!(record.to_string(), "[WARN ] temperature high: 85C");
+
+    let record = timeout(Duration::from_millis(100), sub.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.level, LogLevel::Info);
+    assert_eq!(record.msg, "x".repeat(127));
+
+    let record = timeout(Duration::from_millis(100), sub.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.level, LogLevel::Debug);
+    assert_eq!(record.msg, "done");
+}
+
+#[tokio::test]
+async fn structured_log_records_full() {
+    let (_client_tx, server_rx) = mpsc::channel(16);
+    // Room for a single frame, and nobody reading it
+    let (server_tx, mut client_rx) = mpsc::channel(1);
+
+    let app = SingleDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+    let kkind = app.min_key_len();
+    let server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    let sender = server.sender();
+
+    rpc_log!(sender, LogLevel::Warn, "first").unwrap();
+    // The TX side is full now, these are dropped instead of waiting for room
+    rpc_log!(sender, LogLevel::Warn, "second").unwrap();
+    rpc_log!(sender, LogLevel::Warn, "third").unwrap();
+
+    let frame = client_rx.try_recv().unwrap();
+    let (hdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+    let mut key = VarKey::Key8(LogRecordTopic::TOPIC_KEY);
+    key.shrink_to(kkind);
+    assert_eq!(hdr.key, key);
+    let record = postcard::from_bytes::<OwnedLogRecord>(body).unwrap();
+    assert_eq!(record.msg, "first");
+    assert!(client_rx.try_recv().is_err());
+}
+
+mod errlog_app {
+    use super::*;
+    use postcard_rpc::server::error_log::ErrorLog;
+
+    pub static ERRORS: ErrorLog<2> = ErrorLog::new();
+
+    define_dispatch! {
+        app: ErrLogDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        error_log: ERRORS;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy                    | kind      | handler                   |
+            | ----------                    | ----      | -------                   |
+            | AlphaEndpoint                 | async     | test_alpha_handler        |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn error_log() {
+    let app = errlog_app::ErrLogDispatcher::new(TestContext::default(), ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Nothing recorded yet, and successful requests are not recorded
+    assert_eq!(cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap().0, 42);
+    let log = cli.error_log(false).await.unwrap();
+    assert_eq!(log.total, 0);
+    assert!(log.records.is_empty());
+
+    // Only the most recent two of three errors are kept
+    for _ in 0..3 {
+        let err = cli.send_resp::<BetaEndpoint>(&BReq(1)).await.unwrap_err();
+        assert_eq!(err, HostErr::Wire(WireError::UnknownKey));
+    }
+    let log = cli.error_log(true).await.unwrap();
+    assert_eq!(log.total, 3);
+    assert_eq!(log.records.len(), 2);
+    assert!(log.records[0].seq_no < log.records[1].seq_no);
+    for rec in log.records.iter() {
+        assert_eq!(rec.error, WireError::UnknownKey);
+        assert!(rec.key.is_some());
+    }
+
+    // Cleared by the previous request
+    let log = cli.error_log(false).await.unwrap();
+    assert_eq!(log.total, 0);
+    assert!(log.records.is_empty());
+}
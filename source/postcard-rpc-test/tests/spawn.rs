@@ -0,0 +1,305 @@
+mod common;
+
+use common::*;
+
+#[tokio::test]
+async fn spawn_deferred_reply() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ctr,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    // The request only resolves once the spawned task has finished its work
+    let start = Instant::now();
+    let _resp = cli.send_resp::<EpsilonEndpoint>(&EReq).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn spawned_handler_does_not_stall_dispatch() {
+    let SingleFixture {
+        client_tx,
+        client_rx,
+        ..
+    } = single_server(None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Two slow spawned requests to the same endpoint, and a fast unrelated one
+    let start = Instant::now();
+    let slow1 = cli.send_resp::<EpsilonEndpoint>(&EReq);
+    let slow2 = cli.send_resp::<EpsilonEndpoint>(&EReq);
+    let fast = async {
+        let resp = cli.send_resp::<AlphaEndpoint>(&AReq(1)).await.unwrap();
+        (resp.0, start.elapsed())
+    };
+    let (slow1, slow2, (fast, fast_elapsed)) = tokio::join!(slow1, slow2, fast);
+    slow1.unwrap();
+    slow2.unwrap();
+    assert_eq!(fast, 1);
+
+    // The fast request didn't wait for the slow ones, and the slow ones ran
+    // concurrently rather than one after the other
+    assert!(fast_elapsed < Duration::from_millis(50));
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+mod local_spawn_app {
+    use super::*;
+
+    /// Like a handler written for a single threaded executor, this is not `Send`
+    pub async fn local_epsilon_handler(
+        context: TestSpawnContext,
+        header: VarHeader,
+        _body: EReq,
+        out: Sender<ChannelWireTx>,
+    ) {
+        let ctr = Rc::new(context.ctr);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        ctr.fetch_add(1, Ordering::Relaxed);
+        let _ = out.reply::<EpsilonEndpoint>(header.seq_no, &EResp).await;
+    }
+
+    define_dispatch! {
+        app: LocalSpawnDispatcher;
+        spawn_fn: spawn_fn_local;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | EpsilonEndpoint   | spawn     | local_epsilon_handler     |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn spawn_local_handler() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = local_spawn_app::LocalSpawnDispatcher::new(context, ChannelWireSpawn {});
+
+    // Handlers that are not `Send` are spawned on the local set of the server
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+    let (a, b) = tokio::join!(
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+    );
+    a.unwrap();
+    b.unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
+
+mod spawn_limit_app {
+    use super::*;
+
+    define_dispatch! {
+        app: SpawnLimitDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        max_spawned: 1;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | EpsilonEndpoint   | spawn     | test_epsilon_handler      |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn spawn_limit_rejects_when_full() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = spawn_limit_app::SpawnLimitDispatcher::new(context, ChannelWireSpawn {});
+    assert_eq!(app.live_spawned(), 0);
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Only one of the tasks fits
+    let (first, second) = tokio::join!(
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+    );
+    let busy = HostErr::Wire(WireError::Busy(Busy { retry_after_ms: 0 }));
+    match (first, second) {
+        (Ok(_), Err(e)) | (Err(e), Ok(_)) => assert_eq!(e, busy),
+        (Ok(_), Ok(_)) => panic!("both requests were handled"),
+        (Err(a), Err(b)) => panic!("both requests failed: {a:?}, {b:?}"),
+    }
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // The completed task no longer counts
+    cli.send_resp::<EpsilonEndpoint>(&EReq).await.unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
+
+mod request_pool_app {
+    use super::*;
+
+    // Room for a single `EReq`, which is empty, but not an `AReq`
+    pub static REQUESTS: RequestPool<1, 0> = RequestPool::new();
+
+    define_dispatch! {
+        app: RequestPoolDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        request_pool: REQUESTS;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | AlphaEndpoint     | async     | test_alpha_handler        |
+            | EpsilonEndpoint   | spawn     | test_epsilon_handler      |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn request_pool_rejects_when_full() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = request_pool_app::RequestPoolDispatcher::new(context, ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // Only one of the requests gets the buffer
+    let (first, second) = tokio::join!(
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+    );
+    let busy = HostErr::Wire(WireError::Busy(Busy { retry_after_ms: 0 }));
+    match (first, second) {
+        (Ok(_), Err(e)) | (Err(e), Ok(_)) => assert_eq!(e, busy),
+        (Ok(_), Ok(_)) => panic!("both requests were handled"),
+        (Err(a), Err(b)) => panic!("both requests failed: {a:?}, {b:?}"),
+    }
+    assert_eq!(ctr.load(Ordering::Relaxed), 1);
+
+    // The buffer is returned once the task completes
+    cli.send_resp::<EpsilonEndpoint>(&EReq).await.unwrap();
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+
+    // Requests that don't fit in a buffer are rejected
+    let err = cli.send_resp::<AlphaEndpoint>(&AReq(42)).await.unwrap_err();
+    let too_long = WireError::FrameTooLong(FrameTooLong { len: 1, max: 0 });
+    assert_eq!(err, HostErr::Wire(too_long));
+    assert_eq!(ctr.load(Ordering::Relaxed), 2);
+}
+
+mod fair_spawn_app {
+    use super::*;
+
+    define_dispatch! {
+        app: FairSpawnDispatcher;
+        spawn_fn: spawn_fn;
+        tx_impl: WireTxImpl;
+        spawn_impl: WireSpawnImpl;
+        context: TestContext;
+        max_spawned: 3;
+        max_spawned_per_endpoint: 2;
+
+        endpoints: {
+            list: ENDPOINT_LIST;
+
+            | EndpointTy        | kind      | handler                   |
+            | ----------        | ----      | -------                   |
+            | BetaEndpoint      | spawn     | test_beta_handler         |
+            | EpsilonEndpoint   | spawn     | test_epsilon_handler      |
+        };
+        topics_in: {
+            list: TOPICS_IN_LIST;
+        };
+        topics_out: {
+            list: TOPICS_OUT_LIST;
+        };
+    }
+}
+
+#[tokio::test]
+async fn spawn_limit_per_endpoint() {
+    let context = TestContext::default();
+    let ctr = context.ctr.clone();
+    let app = fair_spawn_app::FairSpawnDispatcher::new(context, ChannelWireSpawn {});
+
+    let ServerFixture {
+        client_tx,
+        client_rx,
+    } = spawn_server(app, None, |_| {});
+
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq2);
+
+    // A flood of slow requests only gets its share of the tasks
+    let (slow1, slow2, slow3, fast) = tokio::join!(
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+        cli.send_resp::<EpsilonEndpoint>(&EReq),
+        async {
+            // Sent after the slow requests were dispatched
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let start = Instant::now();
+            let resp = cli.send_resp::<BetaEndpoint>(&BReq(7)).await;
+            (resp, start.elapsed())
+        },
+    );
+    let slow = [slow1, slow2, slow3];
+    let busy = HostErr::Wire(WireError::Busy(Busy { retry_after_ms: 0 }));
+    assert_eq!(slow.iter().filter(|r| r.is_ok()).count(), 2);
+    assert!(slow.iter().any(|r| r.as_ref().err() == Some(&busy)));
+
+    // The fast request was spawned, and replied before the slow ones
+    let (fast, fast_elapsed) = fast;
+    assert_eq!(fast.unwrap().0, 7);
+    assert!(fast_elapsed < Duration::from_millis(30));
+    assert_eq!(ctr.load(Ordering::Relaxed), 3);
+}
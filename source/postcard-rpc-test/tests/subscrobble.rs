@@ -7,6 +7,7 @@ use std::sync::Arc;
 use postcard_schema::Schema;
 use serde::{Deserialize, Serialize};
 use tokio::{sync::mpsc, time::{sleep, timeout}};
+use tokio_stream::StreamExt;
 
 use postcard_rpc::{
     define_dispatch, endpoints,
@@ -407,3 +408,67 @@ async fn broadcast_subs_work() {
     let _: () = timeout(Duration::from_millis(100), get_fut3).await.unwrap();
 
 }
+
+#[tokio::test]
+async fn stream_subs_work() {
+    let (client_tx, server_rx) = mpsc::channel(16);
+    let (server_tx, client_rx) = mpsc::channel(16);
+    let topic_ctr = Arc::new(AtomicUsize::new(0));
+
+    let app = SingleDispatcher::new(
+        TestContext {
+            ctr: Arc::new(AtomicUsize::new(0)),
+            topic_ctr: topic_ctr.clone(),
+            msg: String::from("hello"),
+        },
+        ChannelWireSpawn {},
+    );
+
+    let cwrx = ChannelWireRx::new(server_rx);
+    let cwtx = ChannelWireTx::new(server_tx);
+
+    let kkind = app.min_key_len();
+    let mut server = new_server(
+        app,
+        Settings {
+            tx: cwtx,
+            rx: cwrx,
+            buf: 1024,
+            kkind,
+        },
+    );
+    let server_sender = server.sender();
+    tokio::task::spawn(async move {
+        server.run().await;
+    });
+    let cli = client::new_from_channels(client_tx, client_rx, VarSeqKind::Seq1);
+
+    // Messages with no subscribers are dropped
+    server_sender.publish::<ZetaTopic10>(VarSeq::Seq4(1), &ZMsg(10)).await.unwrap();
+    sleep(Duration::from_millis(10)).await;
+
+    let mut sub1 = cli.subscribe_stream::<ZetaTopic10>(16).await.unwrap();
+    let mut sub2 = cli.subscribe_stream::<ZetaTopic10>(16).await.unwrap();
+    server_sender.publish::<ZetaTopic10>(VarSeq::Seq4(2), &ZMsg(20)).await.unwrap();
+    server_sender.publish::<ZetaTopic10>(VarSeq::Seq4(3), &ZMsg(30)).await.unwrap();
+    let get_fut = async {
+        assert_eq!(sub1.next().await.unwrap(), ZMsg(20));
+        assert_eq!(sub1.next().await.unwrap(), ZMsg(30));
+        assert_eq!(sub2.next().await.unwrap(), ZMsg(20));
+        assert_eq!(sub2.next().await.unwrap(), ZMsg(30));
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
+
+    // Dropping all streams unregisters the topic, so nothing is buffered
+    drop(sub1);
+    drop(sub2);
+    server_sender.publish::<ZetaTopic10>(VarSeq::Seq4(4), &ZMsg(40)).await.unwrap();
+    sleep(Duration::from_millis(10)).await;
+
+    let mut sub3 = cli.subscribe_stream::<ZetaTopic10>(16).await.unwrap();
+    server_sender.publish::<ZetaTopic10>(VarSeq::Seq4(5), &ZMsg(50)).await.unwrap();
+    let get_fut = async {
+        assert_eq!(sub3.next().await.unwrap(), ZMsg(50));
+    };
+    let _: () = timeout(Duration::from_millis(100), get_fut).await.unwrap();
+}
@@ -0,0 +1,108 @@
+//! Print the wire format of an endpoint, using only its schema
+//!
+//! Run with `cargo run --example schema_walk`.
+
+use postcard_rpc::{endpoint, Endpoint};
+use postcard_schema::{
+    schema::{DataModelType, DataModelVariant, NamedType, NamedValue},
+    Schema,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Schema)]
+pub struct SetLeds {
+    pub brightness: u8,
+    pub colors: Vec<Color>,
+    pub fade_ms: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub enum Color {
+    Off,
+    Rgb(u8, u8, u8),
+    Named(String),
+}
+
+#[derive(Serialize, Deserialize, Schema)]
+pub enum LedResult {
+    Ok,
+    TooMany { max: u16 },
+}
+
+endpoint!(SetLedsEndpoint, SetLeds, LedResult, "leds/set");
+
+fn main() {
+    println!("{}", SetLedsEndpoint::PATH);
+    print!("  request: ");
+    walk(SetLedsEndpoint::REQ_SCHEMA, 2);
+    print!("  response: ");
+    walk(SetLedsEndpoint::RESP_SCHEMA, 2);
+}
+
+fn walk(nt: &NamedType, indent: usize) {
+    match nt.ty {
+        DataModelType::Option(inner) => {
+            print!("optional ");
+            walk(inner, indent);
+        }
+        DataModelType::NewtypeStruct(inner) => {
+            print!("{} of ", nt.name);
+            walk(inner, indent);
+        }
+        DataModelType::Seq(inner) => {
+            print!("sequence of ");
+            walk(inner, indent);
+        }
+        DataModelType::Tuple(nts) | DataModelType::TupleStruct(nts) => {
+            println!("{}", nt.name);
+            for (idx, nt) in nts.iter().enumerate() {
+                print!("{:indent$}.{idx}: ", "", indent = indent + 2);
+                walk(nt, indent + 2);
+            }
+        }
+        DataModelType::Map { key, val } => {
+            print!("map of ");
+            walk(key, indent);
+            print!("{:indent$}to ", "");
+            walk(val, indent);
+        }
+        DataModelType::Struct(nvs) => {
+            println!("struct {}", nt.name);
+            walk_fields(nvs, indent + 2);
+        }
+        DataModelType::Enum(variants) => {
+            println!("enum {}", nt.name);
+            for (idx, var) in variants.iter().enumerate() {
+                let pad = indent + 2;
+                print!("{:pad$}{idx} => {}", "", var.name);
+                match var.ty {
+                    DataModelVariant::UnitVariant => println!(),
+                    DataModelVariant::NewtypeVariant(nt) => {
+                        print!(": ");
+                        walk(nt, pad);
+                    }
+                    DataModelVariant::TupleVariant(nts) => {
+                        println!();
+                        for (idx, nt) in nts.iter().enumerate() {
+                            print!("{:indent$}.{idx}: ", "", indent = pad + 2);
+                            walk(nt, pad + 2);
+                        }
+                    }
+                    DataModelVariant::StructVariant(nvs) => {
+                        println!();
+                        walk_fields(nvs, pad + 2);
+                    }
+                }
+            }
+        }
+        // Primitives, strings, and unit types
+        other => println!("{other:?}"),
+    }
+}
+
+fn walk_fields(nvs: &[&NamedValue], indent: usize) {
+    for nv in nvs {
+        print!("{:indent$}{}: ", "", nv.name);
+        walk(nv.ty, indent);
+    }
+}
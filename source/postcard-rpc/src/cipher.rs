@@ -0,0 +1,310 @@
+//! Encryption of frame bodies
+//!
+//! Some links can be observed or tampered with, e.g. a radio or a shared bus, and
+//! the bodies of frames should then be encrypted and authenticated. A
+//! [`BodyCipher`] transforms the serialized body of each frame as it is
+//! serialized, and may append a tag, e.g. the tag of an AEAD. The header (and any
+//! [`AuthToken`][crate::header::AuthToken]) is not encrypted, as it is needed to
+//! route the frame, but it is passed to the cipher, e.g. to be authenticated as
+//! associated data. Endpoints and topics are not changed in any way, only the bytes
+//! on the wire are.
+//!
+//! Both sides must use the same cipher, as frames are not marked:
+//!
+//! * On the server, pass it to [`Server::new_protected()`]. Every frame sent with
+//!   a [`Sender`] of the server is sealed, except with [`Sender::send_raw()`].
+//!   Received frames that fail to open are not dispatched, and are answered with
+//!   [`WireError::Unauthorized`].
+//! * On the client, wrap the transport in a [`CipherWire`]. Received frames that
+//!   fail to open are dropped.
+//!
+//! Bodies are never compressed while a cipher is set. With a CRC as well, see the
+//! [`crc`][crate::crc] module, the CRC covers the sealed frame, so on the client the
+//! [`CrcWire`] wraps the [`CipherWire`].
+//!
+//! Exchanging keys, and choosing nonces that are never reused with the same key,
+//! is out of scope: this is up to the [`BodyCipher`] impl and the application, e.g.
+//! with keys provisioned at the factory, and a nonce derived from a session id and
+//! the header of each frame.
+//!
+//! ## Example
+//!
+//! A trivial XOR "cipher", with a one byte checksum as the tag. This is NOT secure
+//! in any way, and only shows the shape of an impl:
+//!
+//! ```rust
+//! use postcard_rpc::{
+//!     cipher::{open, seal, BodyCipher, CipherState},
+//!     header::{VarHeader, VarKey, VarSeq},
+//!     Key,
+//! };
+//!
+//! struct XorCipher([u8; 4]);
+//!
+//! impl XorCipher {
+//!     fn apply(&self, state: &CipherState, data: &mut [u8]) {
+//!         for (i, b) in data.iter_mut().enumerate() {
+//!             *b ^= self.0[(state.offset + i) % self.0.len()];
+//!         }
+//!     }
+//! }
+//!
+//! impl BodyCipher for XorCipher {
+//!     fn tag_len(&self) -> usize {
+//!         1
+//!     }
+//!
+//!     fn encrypt(&self, _hdr: &VarHeader, state: &mut CipherState, data: &mut [u8]) {
+//!         self.apply(state, data);
+//!         for b in data.iter() {
+//!             state.scratch[0] = state.scratch[0].wrapping_add(*b);
+//!         }
+//!     }
+//!
+//!     fn decrypt(&self, _hdr: &VarHeader, state: &mut CipherState, data: &mut [u8]) {
+//!         for b in data.iter() {
+//!             state.scratch[0] = state.scratch[0].wrapping_add(*b);
+//!         }
+//!         self.apply(state, data);
+//!     }
+//!
+//!     fn tag(&self, _hdr: &VarHeader, state: &mut CipherState, tag: &mut [u8]) {
+//!         tag[0] = state.scratch[0];
+//!     }
+//! }
+//!
+//! let cipher = XorCipher([0x12, 0x34, 0x56, 0x78]);
+//! let hdr = VarHeader {
+//!     key: VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) }),
+//!     seq_no: VarSeq::Seq2(7),
+//! };
+//!
+//! // The body of a frame, followed by room for the tag
+//! let mut body = *b"hello\0";
+//! let tag = seal(&cipher, &hdr, &mut body[..5]);
+//! body[5] = tag[0];
+//! assert_ne!(&body[..5], b"hello");
+//!
+//! let mut received = body;
+//! assert_eq!(open(&cipher, &hdr, &mut received), Some(5));
+//! assert_eq!(&received[..5], b"hello");
+//!
+//! // Tampering is detected by the tag
+//! let mut received = body;
+//! received[2] ^= 0x01;
+//! assert_eq!(open(&cipher, &hdr, &mut received), None);
+//! ```
+//!
+//! [`Server::new_protected()`]: crate::server::Server::new_protected
+//! [`Sender`]: crate::server::Sender
+//! [`Sender::send_raw()`]: crate::server::Sender::send_raw
+//! [`WireError::Unauthorized`]: crate::standard_icd::WireError::Unauthorized
+//! [`CipherWire`]: crate::host_client::CipherWire
+//! [`CrcWire`]: crate::host_client::CrcWire
+
+use serde::{
+    ser::{Error, SerializeTuple},
+    Serialize, Serializer,
+};
+
+use crate::header::VarHeader;
+
+/// The longest tag a [`BodyCipher`] may append to each body
+pub const MAX_TAG_LEN: usize = 16;
+
+/// The length of [`CipherState::scratch`]
+pub const SCRATCH_LEN: usize = 64;
+
+/// The state of sealing or opening a single body
+///
+/// A new state is used for each body.
+#[derive(Debug, Clone)]
+pub struct CipherState {
+    /// The position in the body of the data passed to [`BodyCipher::encrypt()`] or
+    /// [`BodyCipher::decrypt()`], maintained by the caller
+    pub offset: usize,
+    /// Storage for the cipher, e.g. for the state of its MAC, initially zeroed
+    pub scratch: [u8; SCRATCH_LEN],
+}
+
+impl CipherState {
+    /// The state before the first byte of a body
+    pub const fn new() -> Self {
+        Self {
+            offset: 0,
+            scratch: [0u8; SCRATCH_LEN],
+        }
+    }
+}
+
+impl Default for CipherState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Something that encrypts and authenticates the bodies of frames, see the
+/// [module docs][self]
+///
+/// The body is passed in consecutive pieces, so it never needs to be buffered as a
+/// whole. Sealing must give the same result for the same header and body, as a body
+/// may be serialized more than once, e.g. to calculate its CRC.
+pub trait BodyCipher: Sync {
+    /// The length of the tag appended to each body, at most [`MAX_TAG_LEN`]
+    ///
+    /// Defaults to no tag.
+    fn tag_len(&self) -> usize {
+        0
+    }
+
+    /// Encrypt `data` in place, the next piece of the body of a frame with `hdr`
+    fn encrypt(&self, hdr: &VarHeader, state: &mut CipherState, data: &mut [u8]);
+
+    /// Decrypt `data` in place, the next piece of the body of a frame with `hdr`
+    fn decrypt(&self, hdr: &VarHeader, state: &mut CipherState, data: &mut [u8]);
+
+    /// Write the tag of the body, after all of it has been encrypted or decrypted
+    ///
+    /// `tag` is [`tag_len()`][Self::tag_len] bytes long. Opening a body fails if
+    /// this doesn't match the tag that was received.
+    fn tag(&self, hdr: &VarHeader, state: &mut CipherState, tag: &mut [u8]) {
+        let _ = (hdr, state, tag);
+    }
+}
+
+/// The length of the tag of `cipher`
+fn tag_len(cipher: &dyn BodyCipher) -> usize {
+    cipher.tag_len().min(MAX_TAG_LEN)
+}
+
+/// Encrypt `body`, the body of a frame with `hdr`, in place, returning its tag
+///
+/// Only the first [`BodyCipher::tag_len()`] bytes of the returned tag are used.
+pub fn seal(cipher: &dyn BodyCipher, hdr: &VarHeader, body: &mut [u8]) -> [u8; MAX_TAG_LEN] {
+    let mut state = CipherState::new();
+    cipher.encrypt(hdr, &mut state, body);
+    let mut tag = [0u8; MAX_TAG_LEN];
+    cipher.tag(hdr, &mut state, &mut tag[..tag_len(cipher)]);
+    tag
+}
+
+/// Decrypt `body`, the body of a frame with `hdr` followed by its tag, in place
+///
+/// Returns the length of the decrypted body, or `None` if the tag doesn't match, or
+/// `body` is too short to have one.
+pub fn open(cipher: &dyn BodyCipher, hdr: &VarHeader, body: &mut [u8]) -> Option<usize> {
+    let len = body.len().checked_sub(tag_len(cipher))?;
+    let (data, tag) = body.split_at_mut(len);
+    let mut state = CipherState::new();
+    cipher.decrypt(hdr, &mut state, data);
+    let mut expected = [0u8; MAX_TAG_LEN];
+    let expected = &mut expected[..tag.len()];
+    cipher.tag(hdr, &mut state, expected);
+
+    // Compare all bytes, so the time taken doesn't tell where they differ
+    let diff = expected
+        .iter()
+        .zip(tag.iter())
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    (diff == 0).then_some(len)
+}
+
+/// Seal the body of `frame`, a whole frame including its header, appending the tag
+///
+/// Frames without a valid header are left as-is.
+#[cfg(feature = "use-std")]
+pub fn seal_frame(cipher: &dyn BodyCipher, frame: &mut Vec<u8>) {
+    let Some((hdr, _flags, _token, body)) = VarHeader::take_from_slice_flagged(frame) else {
+        return;
+    };
+    let hdr_len = frame.len() - body.len();
+    let tag = seal(cipher, &hdr, &mut frame[hdr_len..]);
+    frame.extend_from_slice(&tag[..tag_len(cipher)]);
+}
+
+/// Open the body of `frame`, a whole frame including its header, in place
+///
+/// Returns the length of the frame with the decrypted body, or `None` if the frame
+/// has no valid header, or its body fails to open.
+pub fn open_frame(cipher: &dyn BodyCipher, frame: &mut [u8]) -> Option<usize> {
+    let (hdr, _flags, _token, body) = VarHeader::take_from_slice_flagged(frame)?;
+    let hdr_len = frame.len() - body.len();
+    let len = open(cipher, &hdr, &mut frame[hdr_len..])?;
+    Some(hdr_len + len)
+}
+
+/// A postcard flavor that encrypts the serialized bytes, and passes them on as the
+/// elements of a tuple
+struct SealFlavor<'a, Tup: SerializeTuple> {
+    tup: &'a mut Tup,
+    err: &'a mut Option<Tup::Error>,
+    cipher: &'a dyn BodyCipher,
+    hdr: &'a VarHeader,
+    state: CipherState,
+}
+
+impl<Tup: SerializeTuple> postcard::ser_flavors::Flavor for SealFlavor<'_, Tup> {
+    type Output = CipherState;
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.try_extend(&[data])
+    }
+
+    fn try_extend(&mut self, data: &[u8]) -> postcard::Result<()> {
+        let mut buf = [0u8; 32];
+        for piece in data.chunks(buf.len()) {
+            let buf = &mut buf[..piece.len()];
+            buf.copy_from_slice(piece);
+            self.cipher.encrypt(self.hdr, &mut self.state, buf);
+            self.state.offset += buf.len();
+            for b in buf.iter() {
+                if let Err(e) = self.tup.serialize_element(b) {
+                    *self.err = Some(e);
+                    return Err(postcard::Error::SerializeBufferFull);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(self) -> postcard::Result<CipherState> {
+        Ok(self.state)
+    }
+}
+
+/// A message that serializes as `msg` sealed with `cipher`, followed by its tag
+///
+/// Sending this as the body of a frame with `hdr` seals the body, without
+/// serializing it into a separate buffer first.
+pub(crate) struct Sealed<'a, T: ?Sized> {
+    pub(crate) hdr: &'a VarHeader,
+    pub(crate) msg: &'a T,
+    pub(crate) cipher: &'a dyn BodyCipher,
+}
+
+impl<T: Serialize + ?Sized> Serialize for Sealed<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Tuples are serialized without a length, so each element is a single byte
+        let mut tup = serializer.serialize_tuple(usize::MAX)?;
+        let mut err = None;
+        let flavor = SealFlavor {
+            tup: &mut tup,
+            err: &mut err,
+            cipher: self.cipher,
+            hdr: self.hdr,
+            state: CipherState::new(),
+        };
+        let mut state = match postcard::serialize_with_flavor(self.msg, flavor) {
+            Ok(state) => state,
+            Err(_) => return Err(err.unwrap_or_else(|| S::Error::custom("seal"))),
+        };
+
+        let mut tag = [0u8; MAX_TAG_LEN];
+        let tag = &mut tag[..tag_len(self.cipher)];
+        self.cipher.tag(self.hdr, &mut state, tag);
+        for b in tag.iter() {
+            tup.serialize_element(b)?;
+        }
+        tup.end()
+    }
+}
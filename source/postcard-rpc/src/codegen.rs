@@ -0,0 +1,312 @@
+//! Generating interface descriptions of endpoints and topics
+//!
+//! An [`Icd`] collects the paths, keys, and schemas of endpoints and topics, and
+//! describes them to programs that aren't written in Rust, e.g. a web frontend that
+//! talks to a host bridge:
+//!
+//! * [`Icd::to_typescript()`] emits TypeScript types for all messages, and an
+//!   interface per direction mapping each path to its message types
+//! * [`Icd::to_json()`] emits a JSON descriptor, containing the keys and the full
+//!   schema of each endpoint and topic
+//!
+//! This is usually done from a `build.rs`, which depends on the crate defining the
+//! endpoints, and on `postcard-rpc` with the `codegen` feature:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::{codegen::Icd, TopicDirection};
+//! use my_icd::{GetTempEndpoint, SetLedEndpoint, TempTopic};
+//!
+//! fn main() {
+//!     let icd = Icd::new()
+//!         .endpoint::<GetTempEndpoint>()
+//!         .endpoint::<SetLedEndpoint>()
+//!         .topic::<TempTopic>(TopicDirection::ToClient);
+//!     std::fs::write("../frontend/src/icd.ts", icd.to_typescript()).unwrap();
+//! }
+//! ```
+//!
+//! The TypeScript types describe how the messages are represented by `serde_json`,
+//! e.g. when forwarded by the bridge, NOT the postcard wire format: enums are
+//! externally tagged, `Option`s may be `null`, and all numbers are `number`s, so
+//! 64 and 128 bit integers may lose precision. Structs, enums, and other named
+//! types become type aliases, and are declared once per name.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use postcard_schema::schema::{
+    owned::OwnedNamedType, DataModelType, DataModelVariant, NamedType, NamedValue,
+};
+use serde::Serialize;
+
+use crate::{Endpoint, Key, Topic, TopicDirection};
+
+/// A description of a set of endpoints and topics, see the [module docs][self]
+#[derive(Default)]
+pub struct Icd {
+    endpoints: Vec<EndpointDesc>,
+    topics: Vec<TopicDesc>,
+}
+
+struct EndpointDesc {
+    path: &'static str,
+    req_key: Key,
+    resp_key: Key,
+    req: &'static NamedType,
+    resp: &'static NamedType,
+}
+
+struct TopicDesc {
+    path: &'static str,
+    key: Key,
+    direction: TopicDirection,
+    msg: &'static NamedType,
+}
+
+impl Icd {
+    /// Create an empty description
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the endpoint `E`
+    pub fn endpoint<E: Endpoint>(mut self) -> Self {
+        self.endpoints.push(EndpointDesc {
+            path: E::PATH,
+            req_key: E::REQ_KEY,
+            resp_key: E::RESP_KEY,
+            req: E::REQ_SCHEMA,
+            resp: E::RESP_SCHEMA,
+        });
+        self
+    }
+
+    /// Add the topic `T`, with messages sent in the given direction
+    pub fn topic<T: Topic>(mut self, direction: TopicDirection) -> Self {
+        self.topics.push(TopicDesc {
+            path: T::PATH,
+            key: T::TOPIC_KEY,
+            direction,
+            msg: T::MESSAGE_SCHEMA,
+        });
+        self
+    }
+
+    /// Emit a JSON descriptor of all endpoints and topics
+    ///
+    /// Keys are written as hex strings, and types as postcard-schema
+    /// [`OwnedNamedType`]s.
+    pub fn to_json(&self) -> String {
+        let topics = |direction| {
+            self.topics
+                .iter()
+                .filter(|t| t.direction == direction)
+                .map(|t| JsonTopic {
+                    path: t.path,
+                    key: hex(t.key),
+                    message: OwnedNamedType::from(t.msg),
+                })
+                .collect()
+        };
+        let icd = JsonIcd {
+            endpoints: self
+                .endpoints
+                .iter()
+                .map(|e| JsonEndpoint {
+                    path: e.path,
+                    req_key: hex(e.req_key),
+                    resp_key: hex(e.resp_key),
+                    request: OwnedNamedType::from(e.req),
+                    response: OwnedNamedType::from(e.resp),
+                })
+                .collect(),
+            topics_in: topics(TopicDirection::ToServer),
+            topics_out: topics(TopicDirection::ToClient),
+        };
+        serde_json::to_string_pretty(&icd).expect("Serializing to a String can't fail")
+    }
+
+    /// Emit TypeScript declarations of all message types, and the `Endpoints`,
+    /// `TopicsIn`, and `TopicsOut` interfaces, which map paths to message types
+    pub fn to_typescript(&self) -> String {
+        let mut ts = TsTypes::default();
+        let mut body = String::new();
+
+        body.push_str("export interface Endpoints {\n");
+        for e in self.endpoints.iter() {
+            let req = ts.type_of(e.req);
+            let resp = ts.type_of(e.resp);
+            let _ = writeln!(
+                body,
+                "    {:?}: {{ request: {req}; response: {resp} }};",
+                e.path
+            );
+        }
+        body.push_str("}\n");
+
+        for (name, direction) in [
+            ("TopicsIn", TopicDirection::ToServer),
+            ("TopicsOut", TopicDirection::ToClient),
+        ] {
+            let _ = writeln!(body, "\nexport interface {name} {{");
+            for t in self.topics.iter().filter(|t| t.direction == direction) {
+                let msg = ts.type_of(t.msg);
+                let _ = writeln!(body, "    {:?}: {msg};", t.path);
+            }
+            body.push_str("}\n");
+        }
+
+        let mut out = String::from("// Generated by postcard-rpc. Do not edit.\n\n");
+        for (name, decl) in ts.decls.iter() {
+            let _ = writeln!(out, "export type {name} = {decl};");
+        }
+        if !ts.decls.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&body);
+        out
+    }
+}
+
+#[derive(Serialize)]
+struct JsonIcd {
+    endpoints: Vec<JsonEndpoint>,
+    topics_in: Vec<JsonTopic>,
+    topics_out: Vec<JsonTopic>,
+}
+
+#[derive(Serialize)]
+struct JsonEndpoint {
+    path: &'static str,
+    req_key: String,
+    resp_key: String,
+    request: OwnedNamedType,
+    response: OwnedNamedType,
+}
+
+#[derive(Serialize)]
+struct JsonTopic {
+    path: &'static str,
+    key: String,
+    message: OwnedNamedType,
+}
+
+fn hex(key: Key) -> String {
+    key.to_bytes().iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02X}");
+        s
+    })
+}
+
+/// The named types declared so far, by name
+#[derive(Default)]
+struct TsTypes {
+    decls: BTreeMap<String, String>,
+}
+
+impl TsTypes {
+    /// The TypeScript type of `nt`, declaring it (and the types it contains) if named
+    fn type_of(&mut self, nt: &NamedType) -> String {
+        match nt.ty {
+            DataModelType::Bool => "boolean".into(),
+            DataModelType::I8
+            | DataModelType::U8
+            | DataModelType::I16
+            | DataModelType::I32
+            | DataModelType::I64
+            | DataModelType::I128
+            | DataModelType::U16
+            | DataModelType::U32
+            | DataModelType::U64
+            | DataModelType::U128
+            | DataModelType::Usize
+            | DataModelType::Isize
+            | DataModelType::F32
+            | DataModelType::F64 => "number".into(),
+            DataModelType::Char | DataModelType::String => "string".into(),
+            DataModelType::ByteArray => "number[]".into(),
+            DataModelType::Option(inner) => format!("{} | null", self.type_of(inner)),
+            DataModelType::Unit => "null".into(),
+            DataModelType::UnitStruct => self.declare(nt, "null".into()),
+            DataModelType::NewtypeStruct(inner) => {
+                let decl = self.type_of(inner);
+                self.declare(nt, decl)
+            }
+            DataModelType::Seq(inner) => format!("{}[]", parens(self.type_of(inner))),
+            DataModelType::Tuple(nts) => self.tuple_of(nts),
+            DataModelType::TupleStruct(nts) => {
+                let decl = self.tuple_of(nts);
+                self.declare(nt, decl)
+            }
+            DataModelType::Map { val, .. } => format!("Record<string, {}>", self.type_of(val)),
+            DataModelType::Struct(nvs) => {
+                let decl = self.struct_of(nvs);
+                self.declare(nt, decl)
+            }
+            DataModelType::Enum(nvs) => {
+                let variants: Vec<String> = nvs
+                    .iter()
+                    .map(|nv| match nv.ty {
+                        DataModelVariant::UnitVariant => format!("{:?}", nv.name),
+                        DataModelVariant::NewtypeVariant(inner) => {
+                            format!("{{ {}: {} }}", nv.name, self.type_of(inner))
+                        }
+                        DataModelVariant::TupleVariant(nts) => {
+                            format!("{{ {}: {} }}", nv.name, self.tuple_of(nts))
+                        }
+                        DataModelVariant::StructVariant(nvs) => {
+                            format!("{{ {}: {} }}", nv.name, self.struct_of(nvs))
+                        }
+                    })
+                    .collect();
+                let decl = if variants.is_empty() {
+                    "never".into()
+                } else {
+                    variants.join(" | ")
+                };
+                self.declare(nt, decl)
+            }
+            DataModelType::Schema => "unknown".into(),
+        }
+    }
+
+    fn tuple_of(&mut self, nts: &[&NamedType]) -> String {
+        let elems: Vec<String> = nts.iter().map(|nt| self.type_of(nt)).collect();
+        format!("[{}]", elems.join(", "))
+    }
+
+    fn struct_of(&mut self, nvs: &[&NamedValue]) -> String {
+        let fields: Vec<String> = nvs
+            .iter()
+            .map(|nv| format!("{}: {}", nv.name, self.type_of(nv.ty)))
+            .collect();
+        if fields.is_empty() {
+            "{}".into()
+        } else {
+            format!("{{ {} }}", fields.join("; "))
+        }
+    }
+
+    /// Declare `nt` with the given definition, and refer to it by name
+    ///
+    /// Names that aren't valid TypeScript identifiers, e.g. of generic types, are
+    /// inlined instead.
+    fn declare(&mut self, nt: &NamedType, decl: String) -> String {
+        let is_ident = nt.name.chars().enumerate().all(|(i, c)| {
+            c == '_' || c == '$' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())
+        });
+        if !is_ident || nt.name.is_empty() {
+            return parens(decl);
+        }
+        self.decls.entry(nt.name.into()).or_insert(decl);
+        nt.name.into()
+    }
+}
+
+/// Wrap unions in parentheses, so they can be used as array elements
+fn parens(ty: String) -> String {
+    if ty.contains(" | ") {
+        format!("({ty})")
+    } else {
+        ty
+    }
+}
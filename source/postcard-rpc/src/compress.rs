@@ -0,0 +1,240 @@
+//! Compression of frame bodies
+//!
+//! Large responses, e.g. of a data-logging endpoint, often contain long runs of
+//! the same byte, such as zeroes for unused samples. A server may compress the
+//! body of these responses with a simple run-length encoding, to save bandwidth
+//! on slow transports.
+//!
+//! Compression is opt-in per endpoint, see the "Compressed responses" section of
+//! [`define_dispatch!`][crate::define_dispatch]. A compressed frame is marked with
+//! [`VarHeader::COMPRESSED_BITS`] in the discriminant of its header, and the
+//! [`HostClient`][crate::host_client::HostClient] decompresses these frames before
+//! they are deserialized, so no changes are needed on the host. If compressing a
+//! body would not make it shorter, it is sent as-is, without the flag.
+//!
+//! ## Format
+//!
+//! The compressed body is a sequence of runs, each starting with a control byte `C`:
+//!
+//! * `C` in `0x00..=0x7F`: `C + 1` literal bytes follow
+//! * `C` in `0x80..=0xFF`: a single byte follows, which is repeated `C - 0x80 + 3` times
+
+use serde::Serialize;
+
+use crate::header::VarHeader;
+
+/// The most literal bytes in a single run
+const MAX_LITERAL: usize = 0x80;
+/// The fewest repeated bytes in a single run
+const MIN_REPEAT: usize = 3;
+/// The most repeated bytes in a single run
+const MAX_REPEAT: usize = 0x80 + MIN_REPEAT - 1;
+
+/// Compress `input` into `out`, returning the number of bytes used
+///
+/// Returns `None` if the compressed form does not fit in `out`, or would not be
+/// shorter than `input`.
+pub fn compress(input: &[u8], out: &mut [u8]) -> Option<usize> {
+    // Never larger than the input, so no compression is worthwhile
+    let out_len = out.len().min(input.len().saturating_sub(1));
+    let out = &mut out[..out_len];
+
+    let mut used = 0;
+    let mut lit_start = 0;
+    let mut i = 0;
+    while i < input.len() {
+        let byte = input[i];
+        let run = input[i..]
+            .iter()
+            .take(MAX_REPEAT)
+            .take_while(|b| **b == byte)
+            .count();
+
+        if run >= MIN_REPEAT {
+            used = put_literals(&input[lit_start..i], out, used)?;
+            let ctrl = 0x80 + (run - MIN_REPEAT) as u8;
+            used = put(&[ctrl, byte], out, used)?;
+            i += run;
+            lit_start = i;
+        } else {
+            i += 1;
+            if i - lit_start == MAX_LITERAL {
+                used = put_literals(&input[lit_start..i], out, used)?;
+                lit_start = i;
+            }
+        }
+    }
+    let used = put_literals(&input[lit_start..], out, used)?;
+    (used < input.len()).then_some(used)
+}
+
+/// Decompress `input` into `out`, returning the number of bytes used
+///
+/// Returns `None` if `input` is malformed, or the decompressed form does not fit
+/// in `out`.
+pub fn decompress(input: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut used = 0;
+    let mut remain = input;
+    while let Some((ctrl, rest)) = remain.split_first() {
+        let ctrl = usize::from(*ctrl);
+        if ctrl < 0x80 {
+            let (lits, rest) = rest.split_at_checked(ctrl + 1)?;
+            used = put(lits, out, used)?;
+            remain = rest;
+        } else {
+            let (byte, rest) = rest.split_first()?;
+            let run = ctrl - 0x80 + MIN_REPEAT;
+            out.get_mut(used..used + run)?.fill(*byte);
+            used += run;
+            remain = rest;
+        }
+    }
+    Some(used)
+}
+
+/// Decompress `input` into a new `Vec`
+///
+/// Returns `None` if `input` is malformed.
+#[cfg(feature = "use-std")]
+pub fn decompress_to_vec(input: &[u8]) -> Option<Vec<u8>> {
+    // Each run of two bytes decompresses to at most `MAX_REPEAT` bytes
+    let mut out = vec![0u8; input.len().div_ceil(2) * MAX_REPEAT];
+    let used = decompress(input, &mut out)?;
+    out.truncate(used);
+    Some(out)
+}
+
+/// Serialize a frame with the header `hdr` and body `msg` to `buf`, compressing
+/// the body if this makes it shorter
+///
+/// Returns the number of bytes used, or `None` if the frame does not fit in `buf`.
+///
+/// The body is first serialized to the second half of `buf`, so only bodies of up
+/// to half of `buf` are compressed. Larger bodies are sent as-is.
+pub fn write_to_slice<T: Serialize + ?Sized>(
+    hdr: VarHeader,
+    msg: &T,
+    buf: &mut [u8],
+) -> Option<usize> {
+    let hdr_len = hdr.write_to_slice(buf)?.0.len();
+    let (head, rest) = buf.split_at_mut(hdr_len);
+    let mid = rest.len() / 2;
+    let (dst, src) = rest.split_at_mut(mid);
+
+    let body_len = match postcard::to_slice(msg, src) {
+        Ok(body) => {
+            if let Some(used) = compress(body, dst) {
+                head[0] |= VarHeader::COMPRESSED_BITS;
+                return Some(hdr_len + used);
+            }
+            body.len()
+        }
+        Err(_) => {
+            // Too large to compress, use all of the buffer
+            let body = postcard::to_slice(msg, rest).ok()?;
+            return Some(hdr_len + body.len());
+        }
+    };
+
+    // Not worth compressing, move the body next to the header
+    rest.copy_within(mid..mid + body_len, 0);
+    Some(hdr_len + body_len)
+}
+
+/// Write a run of up to [`MAX_LITERAL`] literal bytes
+fn put_literals(lits: &[u8], out: &mut [u8], used: usize) -> Option<usize> {
+    if lits.is_empty() {
+        return Some(used);
+    }
+    let used = put(&[(lits.len() - 1) as u8], out, used)?;
+    put(lits, out, used)
+}
+
+fn put(bytes: &[u8], out: &mut [u8], used: usize) -> Option<usize> {
+    out.get_mut(used..used + bytes.len())?
+        .copy_from_slice(bytes);
+    Some(used + bytes.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress, decompress, write_to_slice};
+    use crate::{
+        header::{VarHeader, VarKey, VarSeq},
+        Key,
+    };
+
+    fn round_trip(input: &[u8]) -> Option<usize> {
+        let mut out = vec![0u8; input.len()];
+        let used = compress(input, &mut out)?;
+        let mut back = vec![0u8; input.len()];
+        assert_eq!(decompress(&out[..used], &mut back), Some(input.len()));
+        assert_eq!(back, input);
+        Some(used)
+    }
+
+    #[test]
+    fn runs() {
+        assert_eq!(round_trip(&[0u8; 1024]), Some(16));
+        assert_eq!(round_trip(&[7u8; 130]), Some(2));
+
+        let mut mixed = vec![0u8; 300];
+        mixed.extend(0..=255u8);
+        mixed.extend([1, 1, 2, 2, 2, 3]);
+        assert!(round_trip(&mixed).is_some());
+    }
+
+    #[test]
+    fn not_worthwhile() {
+        let input: Vec<u8> = (0..=255u8).collect();
+        assert_eq!(round_trip(&input), None);
+        assert_eq!(round_trip(&[]), None);
+        assert_eq!(round_trip(&[1, 2]), None);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut out = [0u8; 16];
+        // Missing literals
+        assert_eq!(decompress(&[0x03, 1, 2], &mut out), None);
+        // Missing repeated byte
+        assert_eq!(decompress(&[0x80], &mut out), None);
+        // Too long for the output
+        assert_eq!(decompress(&[0xFF, 0], &mut out), None);
+    }
+
+    #[test]
+    fn frames() {
+        let hdr = VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) }),
+            seq_no: VarSeq::Seq2(5),
+        };
+        let mut buf = [0u8; 256];
+
+        let zeroes = vec![0u8; 64];
+        let used = write_to_slice(hdr, &zeroes, &mut buf).unwrap();
+        let (rhdr, flags, _token, body) =
+            VarHeader::take_from_slice_flagged(&buf[..used]).unwrap();
+        assert_eq!(rhdr, hdr);
+        assert!(flags.compressed);
+        assert!(VarHeader::take_from_slice(&buf[..used]).is_none());
+        let mut out = [0u8; 128];
+        let len = decompress(body, &mut out).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<Vec<u8>>(&out[..len]).unwrap(),
+            zeroes
+        );
+
+        // Not shorter when compressed
+        let used = write_to_slice(hdr, &12345u32, &mut buf).unwrap();
+        let (rhdr, body) = VarHeader::take_from_slice(&buf[..used]).unwrap();
+        assert_eq!(rhdr, hdr);
+        assert_eq!(postcard::from_bytes::<u32>(body).unwrap(), 12345);
+
+        // Too large to compress
+        let large = [0u8; 200];
+        let used = write_to_slice(hdr, &large[..], &mut buf).unwrap();
+        let (_rhdr, body) = VarHeader::take_from_slice(&buf[..used]).unwrap();
+        assert_eq!(postcard::from_bytes::<&[u8]>(body).unwrap(), &large[..]);
+    }
+}
@@ -0,0 +1,198 @@
+//! Optional CRC32 checksums of frames
+//!
+//! USB checks the integrity of each transfer on its own, but transports like a UART
+//! or shared memory may not, and corrupted frames would then be dispatched (or
+//! received by the client) as if they were valid. For such transports, a CRC32 can
+//! be appended to each frame, as four little endian bytes following the body. The
+//! CRC covers the whole frame before it, including the header and any auth token.
+//!
+//! Both sides must agree on whether frames carry a CRC, as frames are not marked:
+//!
+//! * On the server, set it with [`Server::new_protected()`]. Received frames
+//!   with a wrong CRC are not dispatched, and are answered with
+//!   [`WireError::CrcMismatch`] or dropped, depending on the [`CrcMode`].
+//! * On the client, wrap the transport in a [`CrcWire`], e.g. with
+//!   `HostClient::new_with_wire(CrcWire::new(tx), CrcWire::new(rx), ...)`. Received
+//!   frames with a wrong CRC are dropped.
+//!
+//! This is disabled by default, and costs nothing unless enabled.
+//!
+//! The CRC is the common CRC-32 (ISO-HDLC) also used by Ethernet and zip, so frames
+//! can be checked with other tools as well:
+//!
+//! ```rust
+//! use postcard_rpc::crc::{checked_len, crc32};
+//!
+//! assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+//!
+//! let mut frame = vec![0x00, 0x2A, 0x07];
+//! frame.extend_from_slice(&crc32(&frame).to_le_bytes());
+//! assert_eq!(checked_len(&frame), Some(3));
+//! frame[1] ^= 0x01;
+//! assert_eq!(checked_len(&frame), None);
+//! ```
+//!
+//! [`Server::new_protected()`]: crate::server::Server::new_protected
+//! [`WireError::CrcMismatch`]: crate::standard_icd::WireError::CrcMismatch
+//! [`CrcWire`]: crate::host_client::CrcWire
+
+use serde::{ser::SerializeTuple, Serialize, Serializer};
+
+use crate::header::VarHeader;
+
+/// The length of the CRC appended to each frame
+pub const CRC_LEN: usize = 4;
+
+/// What the server does with frames carrying a CRC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrcMode {
+    /// Frames carry no CRC, the default
+    #[default]
+    Disabled,
+    /// Frames carry a CRC, and frames with a wrong CRC are answered with
+    /// [`WireError::CrcMismatch`][crate::standard_icd::WireError::CrcMismatch]
+    ///
+    /// The header of a corrupted frame may be corrupted as well, so the error is sent
+    /// with the sequence number as received, which the client may not recognize.
+    Reply,
+    /// Frames carry a CRC, and frames with a wrong CRC are silently dropped
+    ///
+    /// Useful for unreliable links, where the client retries requests that time out.
+    Drop,
+}
+
+impl CrcMode {
+    /// Do frames carry a CRC?
+    pub fn enabled(&self) -> bool {
+        *self != CrcMode::Disabled
+    }
+}
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u32; 256] = make_table();
+
+/// A CRC32 calculated incrementally
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    /// Start a new CRC32
+    pub const fn new() -> Self {
+        Self { state: 0xFFFF_FFFF }
+    }
+
+    /// Add `data` to the CRC32
+    pub fn update(&mut self, data: &[u8]) {
+        for b in data {
+            let idx = (self.state ^ u32::from(*b)) as u8;
+            self.state = (self.state >> 8) ^ TABLE[usize::from(idx)];
+        }
+    }
+
+    /// The CRC32 of all data added so far
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The CRC32 of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// The length of `frame` without its CRC, if the CRC matches
+///
+/// Returns `None` if the CRC doesn't match, or `frame` is too short to have one.
+pub fn checked_len(frame: &[u8]) -> Option<usize> {
+    let len = frame.len().checked_sub(CRC_LEN)?;
+    let (data, crc) = frame.split_at(len);
+    let mut crc_bytes = [0u8; CRC_LEN];
+    crc_bytes.copy_from_slice(crc);
+    (crc32(data) == u32::from_le_bytes(crc_bytes)).then_some(len)
+}
+
+/// A postcard flavor that calculates the CRC32 of the serialized bytes
+struct CrcFlavor(Crc32);
+
+impl postcard::ser_flavors::Flavor for CrcFlavor {
+    type Output = u32;
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.0.update(&[data]);
+        Ok(())
+    }
+
+    fn try_extend(&mut self, data: &[u8]) -> postcard::Result<()> {
+        self.0.update(data);
+        Ok(())
+    }
+
+    fn finalize(self) -> postcard::Result<u32> {
+        Ok(self.0.finish())
+    }
+}
+
+/// A message that serializes as `msg` followed by the CRC of a frame of `hdr` and `msg`
+///
+/// Sending this as the body of a frame with `hdr` appends the CRC to the frame,
+/// without serializing it into a separate buffer first.
+pub(crate) struct WithCrc<'a, T: ?Sized> {
+    pub(crate) hdr: &'a VarHeader,
+    pub(crate) msg: &'a T,
+}
+
+impl<T: Serialize + ?Sized> Serialize for WithCrc<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hdr_buf = [0u8; crate::max_size::MAX_HEADER_SIZE];
+        let mut crc = Crc32::new();
+        if let Some((hdr_used, _)) = self.hdr.write_to_slice(&mut hdr_buf) {
+            crc.update(hdr_used);
+        }
+        let crc = postcard::serialize_with_flavor(self.msg, CrcFlavor(crc))
+            .map_err(|_| serde::ser::Error::custom("crc"))?;
+
+        // Tuples are serialized without a length, and so are arrays of bytes
+        let mut tup = serializer.serialize_tuple(2)?;
+        tup.serialize_element(self.msg)?;
+        tup.serialize_element(&crc.to_le_bytes())?;
+        tup.end()
+    }
+}
+
+/// Formats `args` when serialized, like a `str` holding the formatted text
+pub(crate) struct FmtStr<'a>(pub(crate) core::fmt::Arguments<'a>);
+
+impl Serialize for FmtStr<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
@@ -0,0 +1,186 @@
+//! Decoding captured frames, e.g. for debugging tools
+//!
+//! Frames do not carry a length, so a buffer can only contain multiple frames if
+//! they are delimited, as done by the COBS framing of serial transports: each frame
+//! is COBS encoded, and terminated by a `0x00` byte. [`FrameIter`] splits such a
+//! capture into frames:
+//!
+//! ```rust
+//! use postcard_rpc::{
+//!     decode::{CodecError, FrameIter},
+//!     header::VarSeq,
+//! };
+//!
+//! // A frame with a one byte key and sequence number, `[0x00, 0x2A, 0x07]`, followed
+//! // by a frame that was cut off
+//! let mut capture = [0x01, 0x03, 0x2A, 0x07, 0x00, 0x03, 0x01];
+//! let mut frames = FrameIter::new(&mut capture);
+//!
+//! let (hdr, body) = frames.next().unwrap().unwrap();
+//! assert_eq!(hdr.seq_no, VarSeq::Seq1(7));
+//! assert!(body.is_empty());
+//! assert_eq!(frames.next(), Some(Err(CodecError::Incomplete)));
+//! assert_eq!(frames.next(), None);
+//! ```
+//!
+//! The frames are decoded in place, so the buffer is modified. USB transports send
+//! a single frame per transfer, without any encoding, so captured transfers can be
+//! decoded with [`VarHeader::take_from_slice()`] instead.
+
+use crate::header::VarHeader;
+
+/// An error decoding a single frame
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CodecError {
+    /// The buffer ends with a partial frame, which is not terminated by a `0x00` byte
+    Incomplete,
+    /// The COBS encoding of the frame is malformed
+    Cobs,
+    /// The header of the frame is malformed
+    Header,
+    /// The body of the frame is compressed, see the [`compress`][crate::compress]
+    /// module
+    Compressed,
+}
+
+/// An iterator over the COBS encoded frames in a buffer
+///
+/// Yields the header and body of each frame, or an error if a frame could not be
+/// decoded. Decoding continues with the next frame after an error, and empty frames,
+/// e.g. from repeated `0x00` delimiters, are skipped.
+pub struct FrameIter<'a> {
+    remain: &'a mut [u8],
+}
+
+impl<'a> FrameIter<'a> {
+    /// Create a new iterator over the frames in `buf`, decoding them in place
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { remain: buf }
+    }
+}
+
+impl<'a> Iterator for FrameIter<'a> {
+    type Item = Result<(VarHeader, &'a [u8]), CodecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remain.is_empty() {
+                return None;
+            }
+            let buf = core::mem::take(&mut self.remain);
+            let Some(end) = buf.iter().position(|b| *b == 0) else {
+                // Nothing to decode after a partial frame
+                return Some(Err(CodecError::Incomplete));
+            };
+            let (frame, rest) = buf.split_at_mut(end);
+            self.remain = &mut rest[1..];
+            if frame.is_empty() {
+                continue;
+            }
+
+            let Some(used) = cobs_decode_in_place(frame) else {
+                return Some(Err(CodecError::Cobs));
+            };
+            let frame: &'a [u8] = &frame[..used];
+            return Some(match VarHeader::take_from_slice_flagged(frame) {
+                Some((_hdr, flags, _token, _body)) if flags.compressed => {
+                    Err(CodecError::Compressed)
+                }
+                Some((hdr, _flags, None, body)) => Ok((hdr, body)),
+                // Tokens are for the server to check, not skipped here
+                Some((_hdr, _flags, Some(_token), _body)) | None => Err(CodecError::Header),
+            });
+        }
+    }
+}
+
+/// Decode a single COBS encoded frame, without the terminating `0x00`, in place
+///
+/// Returns the length of the decoded frame, or `None` if it is malformed.
+fn cobs_decode_in_place(buf: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < buf.len() {
+        let code = usize::from(buf[read]);
+        if code == 0 {
+            return None;
+        }
+        read += 1;
+        let end = read + code - 1;
+        if end > buf.len() {
+            return None;
+        }
+        // The decoded frame is never longer than the encoded one
+        buf.copy_within(read..end, write);
+        write += end - read;
+        read = end;
+        // Each block is followed by a zero, except for full blocks and the last one
+        if code != 0xFF && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CodecError, FrameIter};
+    use crate::{
+        encode::encode_frame,
+        header::{VarHeader, VarKey, VarSeq},
+        Key,
+    };
+
+    fn cobs_encode(input: &[u8], out: &mut Vec<u8>) {
+        let mut code_idx = out.len();
+        out.push(0);
+        for b in input {
+            if *b == 0 {
+                out[code_idx] = (out.len() - code_idx) as u8;
+                code_idx = out.len();
+                out.push(0);
+                continue;
+            }
+            out.push(*b);
+            if out.len() - code_idx == 0xFF {
+                out[code_idx] = 0xFF;
+                code_idx = out.len();
+                out.push(0);
+            }
+        }
+        out[code_idx] = (out.len() - code_idx) as u8;
+        out.push(0);
+    }
+
+    #[test]
+    fn frames() {
+        let hdr = VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([1, 0, 3, 0, 5, 6, 7, 8]) }),
+            seq_no: VarSeq::Seq2(0x0100),
+        };
+        let long = vec![0u8; 300];
+        let frame1 = encode_frame::<_, 16>(hdr, &42u32).unwrap();
+        let frame2 = encode_frame::<_, 512>(hdr, &long).unwrap();
+
+        let mut capture = vec![0];
+        cobs_encode(&frame1, &mut capture);
+        cobs_encode(&frame2, &mut capture);
+        // Malformed header, malformed encoding, then a partial frame
+        cobs_encode(&[0xFF], &mut capture);
+        capture.extend([0x05, 1, 0]);
+        capture.extend([0x03, 1]);
+
+        let mut frames = FrameIter::new(&mut capture);
+        let (rhdr, body) = frames.next().unwrap().unwrap();
+        assert_eq!(rhdr, hdr);
+        assert_eq!(postcard::from_bytes::<u32>(body).unwrap(), 42);
+        let (rhdr, body) = frames.next().unwrap().unwrap();
+        assert_eq!(rhdr, hdr);
+        assert_eq!(postcard::from_bytes::<Vec<u8>>(body).unwrap(), long);
+        assert_eq!(frames.next(), Some(Err(CodecError::Header)));
+        assert_eq!(frames.next(), Some(Err(CodecError::Cobs)));
+        assert_eq!(frames.next(), Some(Err(CodecError::Incomplete)));
+        assert_eq!(frames.next(), None);
+    }
+}
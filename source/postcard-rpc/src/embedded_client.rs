@@ -0,0 +1,234 @@
+//! A client that doesn't need `std` or an allocator
+//!
+//! The [`HostClient`][crate::host_client::HostClient] requires `std`, and keeps its
+//! pending requests in growable maps. An [`EmbeddedClient`] instead keeps a fixed
+//! number of slots for requests in flight, so it can be used on a device to make
+//! requests to another device, e.g. over a UART or between the cores of a SoC.
+//!
+//! Frames are sent and received with the same [`WireTx`] and [`WireRx`] traits as
+//! used by a [`Server`][crate::server::Server], so the transports of the server can
+//! be reused as they are. A single task must call [`EmbeddedClient::run()`] to route
+//! received responses to the requests waiting for them, while any number of tasks
+//! make requests:
+//!
+//! ```rust,ignore
+//! static CLIENT: StaticCell<EmbeddedClient<ThreadModeRawMutex, MyTx, 4, 64>> =
+//!     StaticCell::new();
+//!
+//! let client = CLIENT.init(EmbeddedClient::new(tx, VarSeqKind::Seq2));
+//! spawner.must_spawn(client_rx_task(client, rx));
+//!
+//! let resp = client.send_resp::<AlphaEndpoint>(&AReq(42)).await?;
+//!
+//! #[embassy_executor::task]
+//! async fn client_rx_task(client: &'static EmbeddedClient<...>, rx: MyRx) {
+//!     let mut buf = [0u8; 64];
+//!     client.run(rx, &mut buf).await;
+//! }
+//! ```
+//!
+//! ## Sizing
+//!
+//! An [`EmbeddedClient`] has two parameters: `N`, the number of requests that can be
+//! in flight at once, and `SZ`, the largest response body that can be received.
+//! Each slot holds a buffer for one response, so the client uses at least
+//! `N * SZ` bytes of RAM.
+//!
+//! * Making a request while all `N` slots are taken fails right away with
+//!   [`ClientErr::TooManyInFlight`], rather than waiting for a slot.
+//! * A response with a body longer than `SZ` fails its request with
+//!   [`ClientErr::ResponseTooLarge`].
+//!
+//! ## Limitations
+//!
+//! Compared to the `HostClient`, this client only makes requests. Requests are sent
+//! with full 8-byte keys, which every server accepts, and received frames that
+//! don't belong to a request in flight, e.g. topic messages, are dropped. Requests
+//! wait for their response for as long as it takes, so consider using a timeout.
+//!
+//! **Requires feature**: `embedded-client`
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::RawMutex, Mutex},
+    signal::Signal,
+};
+use heapless::Vec;
+use portable_atomic::{AtomicU32, Ordering};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    header::{VarHeader, VarKey, VarSeq, VarSeqKind},
+    server::{AsWireRxErrorKind, WireRx, WireRxErrorKind, WireTx},
+    standard_icd::{RequestKey, WireError, ERROR_KEY, KEYED_ERROR_KEY},
+    Endpoint,
+};
+
+/// The error of a request made with an [`EmbeddedClient`]
+#[derive(Debug)]
+pub enum ClientErr<TxErr> {
+    /// The server replied with an error
+    Wire(WireError),
+    /// All slots for requests in flight are taken, and the request was not sent
+    TooManyInFlight,
+    /// The response didn't fit into the buffer of the slot, and was dropped
+    ResponseTooLarge,
+    /// The server replied with a frame that is neither the response nor an error
+    BadResponse,
+    /// Deserialization of the response failed
+    Postcard(postcard::Error),
+    /// Sending the request failed
+    Tx(TxErr),
+}
+
+impl<TxErr> From<postcard::Error> for ClientErr<TxErr> {
+    fn from(value: postcard::Error) -> Self {
+        Self::Postcard(value)
+    }
+}
+
+/// A frame received for a request, the body is `None` if it didn't fit
+type Received<const SZ: usize> = (VarHeader, Option<Vec<u8, SZ>>);
+
+/// The state of a single request in flight
+struct Slot<M: RawMutex, const SZ: usize> {
+    /// The sequence number of the request using this slot, if any
+    seq_no: Mutex<M, Cell<Option<VarSeq>>>,
+    received: Signal<M, Received<SZ>>,
+}
+
+impl<M: RawMutex, const SZ: usize> Slot<M, SZ> {
+    const fn new() -> Self {
+        Self {
+            seq_no: Mutex::new(Cell::new(None)),
+            received: Signal::new(),
+        }
+    }
+}
+
+/// Frees its slot when dropped, e.g. when the request is cancelled
+struct Claim<'a, M: RawMutex, const SZ: usize> {
+    slot: &'a Slot<M, SZ>,
+}
+
+impl<M: RawMutex, const SZ: usize> Drop for Claim<'_, M, SZ> {
+    fn drop(&mut self) {
+        self.slot.seq_no.lock(|s| s.set(None));
+    }
+}
+
+/// A client with a fixed capacity for requests in flight, see the [module docs][self]
+pub struct EmbeddedClient<M: RawMutex, Tx: WireTx, const N: usize, const SZ: usize> {
+    tx: Tx,
+    seq_kind: VarSeqKind,
+    next_seq: AtomicU32,
+    slots: [Slot<M, SZ>; N],
+}
+
+impl<M: RawMutex, Tx: WireTx, const N: usize, const SZ: usize> EmbeddedClient<M, Tx, N, SZ> {
+    /// Create a new client, sending requests with `tx`, and sequence numbers of
+    /// the size `seq_kind`
+    pub fn new(tx: Tx, seq_kind: VarSeqKind) -> Self {
+        Self {
+            tx,
+            seq_kind,
+            next_seq: AtomicU32::new(0),
+            slots: [const { Slot::new() }; N],
+        }
+    }
+
+    /// Receive frames with `rx`, and hand each response to the request waiting for it
+    ///
+    /// This should be run in a dedicated task, and only returns once receiving fails
+    /// with [`WireRxErrorKind::ConnectionClosed`]. `buf` must fit the largest
+    /// received frame, including the header.
+    pub async fn run<Rx: WireRx>(&self, mut rx: Rx, buf: &mut [u8]) -> Rx::Error {
+        loop {
+            let frame = match rx.receive(buf).await {
+                Ok(frame) => frame,
+                Err(e) => match e.as_kind() {
+                    WireRxErrorKind::ConnectionClosed => return e,
+                    _ => continue,
+                },
+            };
+            if let Some((hdr, body)) = VarHeader::take_from_slice(frame) {
+                self.deliver(hdr, body);
+            }
+        }
+    }
+
+    /// Hand the frame to the slot of the request with the same sequence number
+    fn deliver(&self, hdr: VarHeader, body: &[u8]) {
+        let slot = self
+            .slots
+            .iter()
+            .find(|slot| slot.seq_no.lock(|s| s.get()) == Some(hdr.seq_no));
+        if let Some(slot) = slot {
+            slot.received.signal((hdr, Vec::from_slice(body).ok()));
+        }
+    }
+
+    /// Take a free slot for the request with `seq_no`
+    fn claim(&self, seq_no: VarSeq) -> Option<Claim<'_, M, SZ>> {
+        let slot = self.slots.iter().find(|slot| {
+            slot.seq_no.lock(|s| {
+                let free = s.get().is_none();
+                if free {
+                    s.set(Some(seq_no));
+                }
+                free
+            })
+        })?;
+        // Drop any frame received for the previous request of this slot
+        slot.received.reset();
+        Some(Claim { slot })
+    }
+
+    /// Send a request to the [Endpoint] `E`, and await its response
+    ///
+    /// Fails right away with [`ClientErr::TooManyInFlight`] if `N` requests are
+    /// already in flight.
+    pub async fn send_resp<E: Endpoint>(
+        &self,
+        req: &E::Request,
+    ) -> Result<E::Response, ClientErr<Tx::Error>>
+    where
+        E::Request: Serialize,
+        E::Response: DeserializeOwned,
+    {
+        let mut seq_no = VarSeq::Seq4(self.next_seq.fetch_add(1, Ordering::Relaxed));
+        seq_no.resize(self.seq_kind);
+        let claim = self.claim(seq_no).ok_or(ClientErr::TooManyInFlight)?;
+
+        let hdr = VarHeader {
+            key: VarKey::Key8(E::REQ_KEY),
+            seq_no,
+        };
+        self.tx.send(hdr, req).await.map_err(ClientErr::Tx)?;
+
+        loop {
+            let (hdr, body) = claim.slot.received.wait().await;
+            // Received for a previous request of this slot, just before it was freed
+            if hdr.seq_no != seq_no {
+                continue;
+            }
+            let body = body.ok_or(ClientErr::ResponseTooLarge)?;
+
+            if hdr.key == VarKey::Key8(E::RESP_KEY) {
+                return Ok(postcard::from_bytes::<E::Response>(&body)?);
+            } else if hdr.key == VarKey::Key8(ERROR_KEY) {
+                return Err(ClientErr::Wire(postcard::from_bytes::<WireError>(&body)?));
+            } else if hdr.key == VarKey::Key8(KEYED_ERROR_KEY) {
+                let (key, rest) = postcard::take_from_bytes::<RequestKey>(&body)?;
+                if VarKey::from(key) == VarKey::Key8(E::REQ_KEY) {
+                    return Err(ClientErr::Wire(postcard::from_bytes::<WireError>(rest)?));
+                }
+                // An error for a different request with the same sequence number,
+                // keep waiting for ours
+            } else {
+                return Err(ClientErr::BadResponse);
+            }
+        }
+    }
+}
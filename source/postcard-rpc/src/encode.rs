@@ -0,0 +1,102 @@
+//! Encoding frames without a client or server
+//!
+//! These functions produce the same bytes that are sent on the wire, which is useful
+//! for precomputing, logging, or signing frames, or for inspecting them in tests.
+//!
+//! ```rust
+//! # use postcard_schema::Schema;
+//! # use serde::{Serialize, Deserialize};
+//! use postcard_rpc::{encode::encode_request, endpoint, header::VarSeq};
+//!
+//! #[derive(Debug, Serialize, Deserialize, Schema)]
+//! pub struct Req1(u8);
+//!
+//! endpoint!(Endpoint1, Req1, u32, "endpoint/1");
+//!
+//! let frame = encode_request::<Endpoint1, 32>(VarSeq::Seq2(7), &Req1(42)).unwrap();
+//! // The header: discriminant, 8-byte key, 2-byte sequence number
+//! assert_eq!(frame.len(), 1 + 8 + 2 + 1);
+//! ```
+//!
+//! Frames are encoded with the full 8-byte keys, as sent by a client before it has
+//! learned the key length used by the server. Use [`encode_frame()`] with a shrunk
+//! [`VarKey`][crate::header::VarKey] to encode frames with shorter keys.
+//!
+//! ## Determinism
+//!
+//! The same header and message always encode to the same bytes, on any target and
+//! with any toolchain: the header has a fixed layout, keys are hashes of the path
+//! and the schema, and postcard writes the fields of structs in the order they are
+//! declared, with a fixed varint encoding and no padding. Frames can therefore be
+//! compared byte for byte, e.g. in golden tests.
+//!
+//! This only holds if the `Serialize` impl of the message is deterministic itself.
+//! It is NOT for `std::collections::HashMap` and `HashSet`, which iterate in a
+//! random order that differs between instances, so equal maps may encode to
+//! different bytes. Use `BTreeMap`, `heapless::LinearMap` or a sorted `Vec` in
+//! messages instead. Reordering the fields of a type changes its encoding (and its
+//! key), as does a different compression setting or cipher, see
+//! [`compress`][crate::compress] and [`cipher`][crate::cipher].
+//!
+//! With the `test-utils` feature, `test_utils::assert_stable_encoding()` checks that
+//! a message encodes to the same bytes after a round trip, and
+//! `test_utils::assert_frame_eq()` compares frames, reporting the first difference.
+
+use serde::Serialize;
+
+use crate::{
+    header::{VarHeader, VarKey, VarSeq},
+    Endpoint,
+};
+
+/// Encode a frame with the given header and message
+pub fn encode_frame<T, const N: usize>(
+    hdr: VarHeader,
+    msg: &T,
+) -> Result<heapless::Vec<u8, N>, postcard::Error>
+where
+    T: Serialize + ?Sized,
+{
+    let mut out = heapless::Vec::new();
+    out.resize_default(N)
+        .map_err(|_| postcard::Error::SerializeBufferFull)?;
+    let (hdr_used, remain) = hdr
+        .write_to_slice(&mut out)
+        .ok_or(postcard::Error::SerializeBufferFull)?;
+    let hdr_len = hdr_used.len();
+    let body_len = postcard::to_slice(msg, remain)?.len();
+    out.truncate(hdr_len + body_len);
+    Ok(out)
+}
+
+/// Encode a request to the [Endpoint] `E`, as sent by a client
+pub fn encode_request<E, const N: usize>(
+    seq_no: VarSeq,
+    req: &E::Request,
+) -> Result<heapless::Vec<u8, N>, postcard::Error>
+where
+    E: Endpoint,
+    E::Request: Serialize,
+{
+    let hdr = VarHeader {
+        key: VarKey::Key8(E::REQ_KEY),
+        seq_no,
+    };
+    encode_frame(hdr, req)
+}
+
+/// Encode a response of the [Endpoint] `E`, as sent by a server
+pub fn encode_response<E, const N: usize>(
+    seq_no: VarSeq,
+    resp: &E::Response,
+) -> Result<heapless::Vec<u8, N>, postcard::Error>
+where
+    E: Endpoint,
+    E::Response: Serialize,
+{
+    let hdr = VarHeader {
+        key: VarKey::Key8(E::RESP_KEY),
+        seq_no,
+    };
+    encode_frame(hdr, resp)
+}
@@ -59,6 +59,7 @@ pub mod fnv1a64 {
     use postcard_schema::schema::DataModelVariant;
 
     use super::*;
+    use crate::Key;
 
     /// Calculate the Key hash for the given path and type T
     pub const fn hash_ty_path<T: Schema + ?Sized>(path: &str) -> [u8; 8] {
@@ -233,6 +234,47 @@ pub mod fnv1a64 {
         let state = hash_update(state, nt.name.as_bytes());
         hash_named_type(state, nt.ty)
     }
+
+    /// Calculate the hash of a whole ICD, from the keys of its endpoints and topics
+    ///
+    /// Each endpoint is hashed on its own, as a `0x00` byte followed by its request
+    /// and response keys, each topic to the server as a `0x01` byte followed by its
+    /// key, and each topic to the client as a `0x02` byte followed by its key. The
+    /// hash of the ICD is the wrapping sum of these, so it doesn't depend on the order
+    /// of the lists. As the keys are calculated from the paths and schemas, the hash
+    /// changes when any path or type changes.
+    ///
+    /// This is the `icd_hash` reported by the
+    /// [`CompatEndpoint`][crate::standard_icd::CompatEndpoint].
+    pub const fn hash_icd(
+        endpoints: &[(&str, Key, Key)],
+        topics_in: &[(&str, Key)],
+        topics_out: &[(&str, Key)],
+    ) -> u64 {
+        let mut sum = 0u64;
+        let mut idx = 0;
+        while idx < endpoints.len() {
+            let state = hash_update(Fnv1a64Hasher::BASIS, &[0x00]);
+            let state = hash_update(state, &endpoints[idx].1.to_bytes());
+            let state = hash_update(state, &endpoints[idx].2.to_bytes());
+            sum = sum.wrapping_add(state);
+            idx += 1;
+        }
+        let topics: [(u8, &[(&str, Key)]); 2] = [(0x01, topics_in), (0x02, topics_out)];
+        let mut list = 0;
+        while list < topics.len() {
+            let (tag, topics) = topics[list];
+            let mut idx = 0;
+            while idx < topics.len() {
+                let state = hash_update(Fnv1a64Hasher::BASIS, &[tag]);
+                let state = hash_update(state, &topics[idx].1.to_bytes());
+                sum = sum.wrapping_add(state);
+                idx += 1;
+            }
+            list += 1;
+        }
+        sum
+    }
 }
 
 #[cfg(feature = "use-std")]
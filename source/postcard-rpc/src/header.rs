@@ -10,16 +10,30 @@
 //!
 //! ## Discriminant
 //!
-//! The discriminant field is always one byte, and consists of three subfields
-//! in the form `0bNNMM_VVVV`.
+//! The discriminant field is always one byte, and consists of four subfields
+//! in the form `0bNNMM_FFVV`.
 //!
 //! * The two msbits are "key length", where the two N length bits represent
 //!   a key length of 2^N. All values are valid.
 //! * The next two msbits are "sequence number length", where the two M length
 //!   bits represent a sequence number length of 2^M. Values 00, 01, and 10
 //!   are valid.
-//! * The four lsbits are "protocol version", where the four V version bits
-//!   represent an unsigned 4-bit number. Currently only 0000 is a valid value.
+//! * The next two bits are "flags", where each F bit marks an optional feature
+//!   used by the frame, see below.
+//! * The two lsbits are "protocol version", where the two V version bits
+//!   represent an unsigned 2-bit number. Currently only 00 is a valid value.
+//!
+//! The flags are:
+//!
+//! * `1000`: the body is compressed, see the [`compress` module](crate::compress).
+//!   Decoders that don't support compression reject these frames.
+//! * `0100`: the frame carries an [`AuthToken`], whose eight bytes follow the
+//!   sequence number, before the body. Clients only send these frames once a token
+//!   was set, so devices that don't support tokens keep working with clients that
+//!   don't use them.
+//!
+//! Both flags may be set on the same frame. Decoders reject frames with flags they
+//! don't know, as they can't make sense of the body.
 //!
 //! ## Key
 //!
@@ -353,10 +367,17 @@ impl VarHeader {
     /// Mask bits
     pub const SEQ_MASK_BITS: u8 = 0b00_11_0000;
 
+    /// Flag bit for a frame with a compressed body, see [`compress`](crate::compress)
+    pub const COMPRESSED_BITS: u8 = 0b00_00_1000;
+    /// Flag bit for a frame carrying an [`AuthToken`]
+    pub const TOKEN_BITS: u8 = 0b00_00_0100;
+    /// Mask bits
+    pub const FLAG_MASK_BITS: u8 = 0b00_00_1100;
+
     /// Bits for a version number of ZERO
     pub const VER_ZERO_BITS: u8 = 0b00_00_0000;
     /// Mask bits
-    pub const VER_MASK_BITS: u8 = 0b00_00_1111;
+    pub const VER_MASK_BITS: u8 = 0b00_00_0011;
 
     /// Encode the header to a Vec of bytes
     #[cfg(feature = "use-std")]
@@ -403,6 +424,15 @@ impl VarHeader {
         out
     }
 
+    /// Encode the header, followed by the given token, to a Vec of bytes
+    #[cfg(feature = "use-std")]
+    pub fn write_to_vec_with_token(&self, token: &AuthToken) -> Vec<u8> {
+        let mut out = self.write_to_vec();
+        out[0] |= Self::TOKEN_BITS;
+        out.extend_from_slice(&token.0);
+        out
+    }
+
     /// Attempt to write the header to the given slice
     ///
     /// If the slice is large enough, a `Some` will be returned with the bytes used
@@ -473,15 +503,53 @@ impl VarHeader {
     /// decoded header and unused remaining bytes.
     ///
     /// If no well-formed header was found, a `None` will be returned.
+    ///
+    /// Frames with any flags set are rejected, see [`Self::take_from_slice_flagged()`].
     pub fn take_from_slice(buf: &[u8]) -> Option<(Self, &[u8])> {
-        let (disc, mut remain) = buf.split_first()?;
+        match Self::take_from_slice_flagged(buf)? {
+            (hdr, VarHeaderFlags { compressed: false }, None, remain) => Some((hdr, remain)),
+            // The body can't be used without decompressing it first, and the token
+            // can't be ignored
+            _ => None,
+        }
+    }
+
+    /// Attempt to decode a header from the given bytes, along with the optional
+    /// features used by the frame.
+    ///
+    /// Like [`Self::take_from_slice()`], but also returns the [`VarHeaderFlags`] of
+    /// the frame, and the [`AuthToken`] it carries, if any. The token is not part of
+    /// the returned body.
+    pub fn take_from_slice_flagged(
+        buf: &[u8],
+    ) -> Option<(Self, VarHeaderFlags, Option<AuthToken>, &[u8])> {
+        let (disc, remain) = buf.split_first()?;
 
         // For now, we only trust version zero
-        if (*disc & Self::VER_MASK_BITS) != Self::VER_ZERO_BITS {
+        if *disc & Self::VER_MASK_BITS != Self::VER_ZERO_BITS {
             return None;
         }
+        // Reject flags we don't know, as we can't make sense of the body
+        let flags = *disc & Self::FLAG_MASK_BITS;
+        if flags & !(Self::COMPRESSED_BITS | Self::TOKEN_BITS) != 0 {
+            return None;
+        }
+        let (hdr, remain) = Self::take_key_seq(*disc, remain)?;
+        let hflags = VarHeaderFlags {
+            compressed: flags & Self::COMPRESSED_BITS != 0,
+        };
+        if flags & Self::TOKEN_BITS == 0 {
+            return Some((hdr, hflags, None, remain));
+        }
+        let (tokbs, remain) = remain.split_at_checked(8)?;
+        let mut token = [0u8; 8];
+        token.copy_from_slice(tokbs);
+        Some((hdr, hflags, Some(AuthToken(token)), remain))
+    }
 
-        let key = match (*disc) & Self::KEY_MASK_BITS {
+    /// Decode the key and sequence number, with the lengths given by `disc`
+    fn take_key_seq(disc: u8, mut remain: &[u8]) -> Option<(Self, &[u8])> {
+        let key = match disc & Self::KEY_MASK_BITS {
             Self::KEY_ONE_BITS => {
                 let (keybs, remain2) = remain.split_first()?;
                 remain = remain2;
@@ -511,7 +579,7 @@ impl VarHeader {
             // Impossible: all bits covered
             _ => unreachable!(),
         };
-        let seq_no = match (*disc) & Self::SEQ_MASK_BITS {
+        let seq_no = match disc & Self::SEQ_MASK_BITS {
             Self::SEQ_ONE_BITS => {
                 let (seqbs, remain3) = remain.split_first()?;
                 remain = remain3;
@@ -538,9 +606,45 @@ impl VarHeader {
     }
 }
 
+/// The optional features used by a frame, from the flag bits of its header
+///
+/// Whether the frame carries an [`AuthToken`] is not included, as the token itself
+/// is returned by [`VarHeader::take_from_slice_flagged()`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct VarHeaderFlags {
+    /// The body is compressed, see [`compress`](crate::compress)
+    pub compressed: bool,
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// AUTHTOKEN
+//////////////////////////////////////////////////////////////////////////////
+
+/// A token sent by the client along with its requests, allowing the server to
+/// restrict who may use certain endpoints
+///
+/// The meaning of the token is up to the application, e.g. a shared secret, or a
+/// session id handed out by a login endpoint. It is sent in plain text, so it only
+/// protects against clients that don't know it, not against eavesdroppers.
+///
+/// Tokens are compared in constant time.
+#[derive(Debug, Clone, Copy, Eq)]
+pub struct AuthToken(pub [u8; 8]);
+
+impl PartialEq for AuthToken {
+    fn eq(&self, other: &Self) -> bool {
+        // Don't give away how many leading bytes matched
+        let mut diff = 0;
+        for (a, b) in self.0.iter().zip(other.0.iter()) {
+            diff |= a ^ b;
+        }
+        diff == 0
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{VarHeader, VarKey, VarSeq};
+    use super::{AuthToken, VarHeader, VarHeaderFlags, VarKey, VarSeq};
     use crate::{Key, Key1, Key2};
 
     #[test]
@@ -627,4 +731,66 @@ mod test {
             assert_eq!(val, &deser);
         }
     }
+
+    #[test]
+    fn flags_and_version() {
+        let hdr = VarHeader {
+            key: VarKey::Key1(Key1(1)),
+            seq_no: VarSeq::Seq1(0x02),
+        };
+        let mut frame = hdr.write_to_vec();
+        frame.push(0xEE);
+
+        let (deser, flags, tok, body) = VarHeader::take_from_slice_flagged(&frame).unwrap();
+        assert_eq!(flags, VarHeaderFlags::default());
+        assert_eq!((deser, tok, body), (hdr, None, &[0xEE][..]));
+
+        frame[0] |= VarHeader::COMPRESSED_BITS;
+        let (deser, flags, tok, body) = VarHeader::take_from_slice_flagged(&frame).unwrap();
+        assert!(flags.compressed);
+        assert_eq!((deser, tok, body), (hdr, None, &[0xEE][..]));
+
+        // The flag doesn't change the version, later versions are rejected
+        frame[0] |= 0b01;
+        assert!(VarHeader::take_from_slice_flagged(&frame).is_none());
+    }
+
+    #[test]
+    fn token_frames() {
+        let hdr = VarHeader {
+            key: VarKey::Key2(Key2([0x42, 0xAF])),
+            seq_no: VarSeq::Seq1(0x02),
+        };
+        let token = AuthToken([1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut frame = hdr.write_to_vec_with_token(&token);
+        frame.push(0xEE);
+        assert_eq!(
+            frame[0],
+            VarHeader::KEY_TWO_BITS | VarHeader::SEQ_ONE_BITS | VarHeader::TOKEN_BITS
+        );
+
+        let (deser, flags, tok, body) = VarHeader::take_from_slice_flagged(&frame).unwrap();
+        assert_eq!(deser, hdr);
+        assert!(!flags.compressed);
+        assert_eq!(tok, Some(token));
+        assert_eq!(body, &[0xEE]);
+
+        // Decoders that don't know about tokens reject these frames
+        assert!(VarHeader::take_from_slice(&frame).is_none());
+
+        // Both flags may be set at once
+        frame[0] |= VarHeader::COMPRESSED_BITS;
+        let (deser, flags, tok, body) = VarHeader::take_from_slice_flagged(&frame).unwrap();
+        assert!(flags.compressed);
+        assert_eq!((deser, tok, body), (hdr, Some(token), &[0xEE][..]));
+
+        // Frames without a token are still accepted
+        let plain = hdr.write_to_vec();
+        let (deser, flags, tok, body) = VarHeader::take_from_slice_flagged(&plain).unwrap();
+        assert_eq!(flags, VarHeaderFlags::default());
+        assert_eq!((deser, tok, body), (hdr, None, &[][..]));
+
+        // A truncated token is rejected
+        assert!(VarHeader::take_from_slice_flagged(&frame[..6]).is_none());
+    }
 }
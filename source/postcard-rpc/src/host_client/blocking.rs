@@ -0,0 +1,173 @@
+//! A blocking wrapper around [`HostClient`]
+
+use std::{sync::mpsc, thread::JoinHandle};
+
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{runtime::Handle, sync::oneshot};
+
+use crate::{
+    host_client::{HostClient, HostErr},
+    Endpoint,
+};
+
+/// A [`HostClient`] that can be used without an async runtime
+///
+/// This is intended for simple command line tools and test harnesses. Asynchronous
+/// code should use the [`HostClient`] directly.
+///
+/// ## Thread model
+///
+/// Creating a `BlockingClient` starts a dedicated thread, running a single threaded
+/// tokio runtime. The `HostClient` is created on this thread, so its I/O worker
+/// tasks run there as well.
+///
+/// Methods like [`BlockingClient::call()`] block the calling thread until the
+/// request has completed, while the I/O is driven by the dedicated thread. They may
+/// be called from multiple threads at once, but must NOT be called from within an
+/// async context, as this panics.
+///
+/// ## Shutdown
+///
+/// Dropping the `BlockingClient` closes the `HostClient`, stops the runtime, and
+/// waits for the dedicated thread to exit. Tasks still running on the runtime, such
+/// as the I/O workers, are cancelled.
+pub struct BlockingClient<WireErr> {
+    client: HostClient<WireErr>,
+    handle: Handle,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<WireErr> BlockingClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema + Send + 'static,
+{
+    /// Start the dedicated thread, and create a client on it using `connect`
+    ///
+    /// `connect` is called from within the runtime, and will usually be one of the
+    /// `HostClient` constructors:
+    ///
+    /// ```rust,ignore
+    /// use postcard_rpc::{
+    ///     host_client::{BlockingClient, HostClient},
+    ///     standard_icd::{PingEndpoint, WireError, ERROR_PATH},
+    /// };
+    ///
+    /// let client = BlockingClient::new(|| {
+    ///     HostClient::<WireError>::new_raw_nusb(
+    ///         |d| d.product_string() == Some("Example Device"),
+    ///         ERROR_PATH,
+    ///         8,
+    ///         postcard_rpc::header::VarSeqKind::Seq2,
+    ///     )
+    /// });
+    /// let resp = client.call::<PingEndpoint>(&42).unwrap();
+    /// assert_eq!(resp, 42);
+    /// ```
+    ///
+    /// Panics if the thread or runtime could not be started.
+    pub fn new<F>(connect: F) -> Self
+    where
+        F: FnOnce() -> HostClient<WireErr> + Send + 'static,
+    {
+        match Self::try_new(move || Ok::<_, core::convert::Infallible>(connect())) {
+            Ok(me) => me,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Start the dedicated thread, and create a client on it using a fallible
+    /// `connect` function
+    ///
+    /// If `connect` fails, the thread is stopped, and the error is returned.
+    ///
+    /// Panics if the thread or runtime could not be started.
+    pub fn try_new<F, E>(connect: F) -> Result<Self, E>
+    where
+        F: FnOnce() -> Result<HostClient<WireErr>, E> + Send + 'static,
+        E: Send + 'static,
+    {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("postcard-rpc-client".into())
+            .spawn(move || {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("Failed to start the runtime");
+                let (stop_tx, stop_rx) = oneshot::channel::<()>();
+                let client = match rt.block_on(async { connect() }) {
+                    Ok(client) => client,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok((client, rt.handle().clone(), stop_tx)));
+
+                // Drive the I/O workers until we are stopped
+                rt.block_on(async {
+                    let _ = stop_rx.await;
+                });
+            })
+            .expect("Failed to start the client thread");
+
+        let (client, handle, stop) = match ready_rx.recv() {
+            Ok(Ok(ready)) => ready,
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                return Err(e);
+            }
+            // The thread panicked, pass the panic on
+            Err(_) => match thread.join() {
+                Err(panic) => std::panic::resume_unwind(panic),
+                Ok(()) => unreachable!(),
+            },
+        };
+
+        Ok(Self {
+            client,
+            handle,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Send a request to the [Endpoint] `E`, and wait for the response
+    ///
+    /// See [`HostClient::send_resp()`]. This blocks potentially forever, if the
+    /// device never replies.
+    pub fn call<E: Endpoint>(&self, req: &E::Request) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        self.block_on(self.client.send_resp::<E>(req))
+    }
+
+    /// Run any future to completion, e.g. one of the methods of [`Self::client()`]
+    ///
+    /// Panics if called from within an async context.
+    pub fn block_on<F: core::future::Future>(&self, fut: F) -> F::Output {
+        self.handle.block_on(fut)
+    }
+
+    /// The wrapped [`HostClient`]
+    pub fn client(&self) -> &HostClient<WireErr> {
+        &self.client
+    }
+}
+
+impl<WireErr> Drop for BlockingClient<WireErr> {
+    fn drop(&mut self) {
+        // Same as `HostClient::close()`, which needs more bounds than we have here
+        self.client.stopper.stop();
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
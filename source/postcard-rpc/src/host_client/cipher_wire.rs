@@ -0,0 +1,72 @@
+//! Sealing and opening the body of frames, see the [`cipher`][crate::cipher] module
+
+use crate::{
+    cipher::{open_frame, seal_frame, BodyCipher},
+    host_client::{WireRx, WireTx},
+};
+
+/// A wrapper of a [`WireTx`] or [`WireRx`] that seals the body of each sent frame,
+/// and opens the body of each received frame
+///
+/// Received frames that fail to open are dropped, and the next frame is received
+/// instead. Use this with a server that uses the same cipher, see
+/// [`Server::new_protected()`][crate::server::Server::new_protected]:
+///
+/// ```rust,ignore
+/// static CIPHER: MyCipher = MyCipher::new(KEY);
+///
+/// let client = HostClient::new_with_wire(
+///     CipherWire::new(tx, &CIPHER),
+///     CipherWire::new(rx, &CIPHER),
+///     spawn,
+///     VarSeqKind::Seq2,
+///     ERROR_PATH,
+///     64,
+/// );
+/// ```
+///
+/// With a CRC as well, wrap this in a [`CrcWire`][super::CrcWire], as the CRC covers
+/// the sealed frame.
+pub struct CipherWire<W> {
+    inner: W,
+    cipher: &'static dyn BodyCipher,
+}
+
+impl<W> CipherWire<W> {
+    /// Wrap `inner`, sealing or opening with `cipher`
+    pub fn new(inner: W, cipher: &'static dyn BodyCipher) -> Self {
+        Self { inner, cipher }
+    }
+
+    /// Unwrap the inner [`WireTx`] or [`WireRx`]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: WireTx> WireTx for CipherWire<W> {
+    type Error = W::Error;
+
+    async fn send(&mut self, mut data: Vec<u8>) -> Result<(), Self::Error> {
+        seal_frame(self.cipher, &mut data);
+        self.inner.send(data).await
+    }
+}
+
+impl<W: WireRx> WireRx for CipherWire<W> {
+    type Error = W::Error;
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        loop {
+            let mut frame = self.inner.receive().await?;
+            if let Some(len) = open_frame(self.cipher, &mut frame) {
+                frame.truncate(len);
+                return Ok(frame);
+            }
+            tracing::warn!(
+                "Dropping a frame of {} bytes that failed to open",
+                frame.len()
+            );
+        }
+    }
+}
@@ -0,0 +1,141 @@
+/// Define Client Macro
+///
+/// Creates a typed wrapper around a [`HostClient`][crate::host_client::HostClient],
+/// with one async method per endpoint. Each method sends the
+/// [Request][crate::Endpoint::Request] of its endpoint, and awaits the
+/// [Response][crate::Endpoint::Response], using
+/// [`HostClient::send_resp()`][crate::host_client::HostClient::send_resp].
+///
+/// The methods use the same [`Endpoint`][crate::Endpoint] types as the server, so
+/// the endpoint list of the [`define_dispatch!()`][crate::define_dispatch] macro can
+/// be copied over, replacing the `kind` and `handler` columns with the name of the
+/// method.
+///
+/// # Example
+///
+/// ```rust
+/// # use postcard_schema::Schema;
+/// # use serde::{Serialize, Deserialize};
+/// use postcard_rpc::{define_client, endpoints};
+///
+/// #[derive(Serialize, Deserialize, Schema)]
+/// pub struct AReq(pub u8);
+/// #[derive(Serialize, Deserialize, Schema)]
+/// pub struct AResp(pub u8);
+///
+/// endpoints! {
+///     list = ENDPOINT_LIST;
+///     | EndpointTy        | RequestTy     | ResponseTy    | Path      |
+///     | ----------        | ---------     | ----------    | ----      |
+///     | AlphaEndpoint     | AReq          | AResp         | "alpha"   |
+///     | BetaEndpoint      | u32           | u32           | "beta"    |
+/// }
+///
+/// define_client! {
+///     // This becomes the name of your client
+///     client: MyDeviceClient;
+///     // OPTIONAL: The WireErr type of the HostClient. If omitted,
+///     // `postcard_rpc::standard_icd::WireError` is used.
+///     wire_err: postcard_rpc::standard_icd::WireError;
+///
+///     endpoints: {
+///         | EndpointTy        | method    |
+///         | ----------        | ------    |
+///         | AlphaEndpoint     | alpha     |
+///         | BetaEndpoint      | beta      |
+///     };
+/// }
+///
+/// async fn run(client: &MyDeviceClient) {
+///     let resp: AResp = client.alpha(&AReq(1)).await.unwrap();
+///     let resp: u32 = client.beta(&2).await.unwrap();
+/// }
+/// ```
+///
+/// The client is created from a `HostClient` with `MyDeviceClient::new()` or
+/// `From`, and the `HostClient` is still available with `MyDeviceClient::client()`,
+/// e.g. for subscribing to topics.
+///
+/// `MyDeviceClient::ENDPOINTS` lists the path and keys of each endpoint of the
+/// client. Use [`assert_icd_match!`][crate::assert_icd_match] in a test to check
+/// that they are the same as those handled by the dispatcher of the device.
+#[macro_export]
+macro_rules! define_client {
+    (
+        client: $client_name:ident;
+        endpoints: {
+            | EndpointTy | method |
+            | $(-)* | $(-)* |
+            $( | $endpoint:ty | $method:ident | )*
+        };
+    ) => {
+        define_client! {
+            client: $client_name;
+            wire_err: $crate::standard_icd::WireError;
+            endpoints: {
+                | EndpointTy | method |
+                | ---------- | ------ |
+                $( | $endpoint | $method | )*
+            };
+        }
+    };
+    (
+        client: $client_name:ident;
+        wire_err: $wire_err:ty;
+        endpoints: {
+            | EndpointTy | method |
+            | $(-)* | $(-)* |
+            $( | $endpoint:ty | $method:ident | )*
+        };
+    ) => {
+        /// A typed client, with one method per endpoint
+        ///
+        /// Generated by the `define_client!()` macro.
+        #[derive(Clone)]
+        pub struct $client_name {
+            client: $crate::host_client::HostClient<$wire_err>,
+        }
+
+        impl $client_name {
+            /// The path, request key and response key of each endpoint of this client
+            pub const ENDPOINTS: &'static [(&'static str, $crate::Key, $crate::Key)] = &[
+                $(
+                    (
+                        <$endpoint as $crate::Endpoint>::PATH,
+                        <$endpoint as $crate::Endpoint>::REQ_KEY,
+                        <$endpoint as $crate::Endpoint>::RESP_KEY,
+                    ),
+                )*
+            ];
+
+            /// Create a new client, wrapping the given `HostClient`
+            pub fn new(client: $crate::host_client::HostClient<$wire_err>) -> Self {
+                Self { client }
+            }
+
+            /// Get the wrapped `HostClient`
+            pub fn client(&self) -> &$crate::host_client::HostClient<$wire_err> {
+                &self.client
+            }
+
+            $(
+                #[doc = concat!("Send a request to the `", stringify!($endpoint), "` endpoint, and await the response")]
+                pub async fn $method(
+                    &self,
+                    req: &<$endpoint as $crate::Endpoint>::Request,
+                ) -> Result<
+                    <$endpoint as $crate::Endpoint>::Response,
+                    $crate::host_client::HostErr<$wire_err>,
+                > {
+                    self.client.send_resp::<$endpoint>(req).await
+                }
+            )*
+        }
+
+        impl From<$crate::host_client::HostClient<$wire_err>> for $client_name {
+            fn from(client: $crate::host_client::HostClient<$wire_err>) -> Self {
+                Self::new(client)
+            }
+        }
+    };
+}
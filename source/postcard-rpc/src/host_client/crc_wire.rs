@@ -0,0 +1,65 @@
+//! Appending and checking the CRC of frames, see the [`crc`][crate::crc] module
+
+use crate::{
+    crc::{checked_len, crc32},
+    host_client::{WireRx, WireTx},
+};
+
+/// A wrapper of a [`WireTx`] or [`WireRx`] that appends a CRC to each sent frame, and
+/// checks the CRC of each received frame
+///
+/// Received frames with a wrong CRC are dropped, and the next frame is received
+/// instead. Requests whose response was dropped fail like any lost response, e.g.
+/// by timing out. Use this with a server that has CRCs enabled, see
+/// [`Server::new_protected()`][crate::server::Server::new_protected]:
+///
+/// ```rust,ignore
+/// let client = HostClient::new_with_wire(
+///     CrcWire::new(tx),
+///     CrcWire::new(rx),
+///     spawn,
+///     VarSeqKind::Seq2,
+///     ERROR_PATH,
+///     64,
+/// );
+/// ```
+pub struct CrcWire<W> {
+    inner: W,
+}
+
+impl<W> CrcWire<W> {
+    /// Wrap `inner`
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Unwrap the inner [`WireTx`] or [`WireRx`]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: WireTx> WireTx for CrcWire<W> {
+    type Error = W::Error;
+
+    async fn send(&mut self, mut data: Vec<u8>) -> Result<(), Self::Error> {
+        let crc = crc32(&data);
+        data.extend_from_slice(&crc.to_le_bytes());
+        self.inner.send(data).await
+    }
+}
+
+impl<W: WireRx> WireRx for CrcWire<W> {
+    type Error = W::Error;
+
+    async fn receive(&mut self) -> Result<Vec<u8>, Self::Error> {
+        loop {
+            let mut frame = self.inner.receive().await?;
+            if let Some(len) = checked_len(&frame) {
+                frame.truncate(len);
+                return Ok(frame);
+            }
+            tracing::warn!("Dropping a frame of {} bytes with a wrong CRC", frame.len());
+        }
+    }
+}
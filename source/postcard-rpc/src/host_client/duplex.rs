@@ -0,0 +1,295 @@
+//! The client side of full duplex streams, see [`server::duplex`][crate::server::duplex]
+
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    standard_icd::{DuplexClose, RequestKey, DUPLEX_CLOSE_KEY, KEYED_ERROR_KEY},
+    Endpoint, Key,
+};
+
+use super::{
+    ConnectionState, HostClient, HostContext, HostErr, PendingResponse, RpcFrame, SEQ_NO_ATTEMPTS,
+};
+
+/// # Duplex Streams
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Open a full duplex stream with the endpoint `E`, handled by a `duplex` handler
+    /// on the device, sending `first` as the first message
+    ///
+    /// Returns a [DuplexSink] to send further messages, and a [DuplexStream] of the
+    /// messages sent by the device, which keeps up to `depth` received messages. Both
+    /// can be used from different tasks. The stream ends once the device closes its
+    /// direction of the stream, and yields an error if the device rejects the stream,
+    /// or the connection is lost. See [`server::duplex`][crate::server::duplex].
+    pub async fn duplex<E: Endpoint>(
+        &self,
+        first: &E::Request,
+        depth: usize,
+    ) -> Result<(DuplexSink<E, WireErr>, DuplexStream<E, WireErr>), HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        if self.ctx.draining.load(Ordering::Acquire) {
+            return Err(HostErr::Shutdown);
+        }
+        let (tx, rx) = mpsc::channel(depth);
+        let seq_no = self.register_duplex(tx)?;
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let mut conn = self.ctx.conn.subscribe();
+        let disconnected = Box::pin(async move {
+            let changed = conn.has_changed().unwrap_or(true);
+            if !changed && *conn.borrow() == ConnectionState::Connected {
+                let _ = conn.changed().await;
+            }
+        });
+        let stream = DuplexStream {
+            client: self.clone(),
+            seq_no,
+            rx,
+            closed: closed.clone(),
+            disconnected,
+            done: false,
+            _pd: PhantomData,
+        };
+        let sink = DuplexSink {
+            client: self.clone(),
+            seq_no,
+            closed,
+            half_closed: false,
+            _pd: PhantomData,
+        };
+        sink.send(first).await?;
+        Ok((sink, stream))
+    }
+
+    /// Route all frames with the next unused sequence number to `tx`
+    fn register_duplex(&self, tx: mpsc::Sender<RpcFrame>) -> Result<VarSeq, HostErr<WireErr>> {
+        for _ in 0..SEQ_NO_ATTEMPTS {
+            let mut seq_no = VarSeq::Seq4(self.ctx.seq.read().unwrap().next_seq_no());
+            seq_no.resize(self.seq_kind);
+
+            let in_use = self.ctx.pending.lock().unwrap().contains(&seq_no)
+                || self.ctx.stale.lock().unwrap().contains(&seq_no);
+            let mut duplex = self.ctx.duplex.lock().unwrap();
+            if in_use || duplex.iter().any(|(s, _)| *s == seq_no) {
+                continue;
+            }
+            duplex.push((seq_no, tx));
+            return Ok(seq_no);
+        }
+        Err(HostErr::SeqNoInUse)
+    }
+
+    /// Send a frame of a duplex stream with the full size `key`
+    async fn send_duplex<T: Serialize + ?Sized>(
+        &self,
+        seq_no: VarSeq,
+        key: Key,
+        msg: &T,
+    ) -> Result<(), HostErr<WireErr>> {
+        let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+        let mut key = VarKey::Key8(key);
+        key.shrink_to(kkind);
+        let frame = RpcFrame {
+            header: VarHeader { key, seq_no },
+            body: postcard::to_stdvec(msg).expect("Allocations should not ever fail"),
+        };
+        self.out.send(frame).await.map_err(|_| HostErr::Closed)
+    }
+}
+
+impl HostContext {
+    /// Hand `frame` to the duplex stream with its sequence number, if any
+    ///
+    /// Returns the frame if it doesn't belong to a duplex stream.
+    pub(super) fn route_duplex(&self, frame: RpcFrame) -> Option<RpcFrame> {
+        let duplex = self.duplex.lock().unwrap();
+        let Some((_, tx)) = duplex.iter().find(|(s, _)| *s == frame.header.seq_no) else {
+            return Some(frame);
+        };
+        if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(frame) {
+            tracing::warn!("Duplex stream lagged, a message was lost");
+        }
+        None
+    }
+
+    /// Stop routing frames to the duplex stream with `seq_no`
+    fn remove_duplex(&self, seq_no: VarSeq) {
+        let mut duplex = self.duplex.lock().unwrap();
+        if let Some(i) = duplex.iter().position(|(s, _)| *s == seq_no) {
+            duplex.swap_remove(i);
+        }
+    }
+}
+
+/// Sends messages on a duplex stream, see [HostClient::duplex()]
+pub struct DuplexSink<E, WireErr> {
+    client: HostClient<WireErr>,
+    seq_no: VarSeq,
+    /// Set once the stream is closed in both directions
+    closed: Arc<AtomicBool>,
+    half_closed: bool,
+    _pd: PhantomData<fn() -> E>,
+}
+
+impl<E, WireErr> DuplexSink<E, WireErr>
+where
+    E: Endpoint,
+    E::Request: Serialize,
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Send a message to the device
+    ///
+    /// Returns [HostErr::Closed] if either side closed our direction of the stream.
+    pub async fn send(&self, msg: &E::Request) -> Result<(), HostErr<WireErr>> {
+        if self.half_closed || self.closed.load(Ordering::Acquire) {
+            return Err(HostErr::Closed);
+        }
+        self.client.send_duplex(self.seq_no, E::REQ_KEY, msg).await
+    }
+
+    /// Stop sending messages, while still receiving the messages of the device
+    pub async fn half_close(&mut self) -> Result<(), HostErr<WireErr>> {
+        if self.half_closed || self.closed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        self.half_closed = true;
+        let close = DuplexClose::HalfClose;
+        self.client
+            .send_duplex(self.seq_no, DUPLEX_CLOSE_KEY, &close)
+            .await
+    }
+
+    /// Close the stream in both directions
+    ///
+    /// The [DuplexStream] ends right away, and messages still in flight from the
+    /// device are discarded.
+    pub async fn close(self) -> Result<(), HostErr<WireErr>> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.client.ctx.remove_duplex(self.seq_no);
+        let close = DuplexClose::Close;
+        self.client
+            .send_duplex(self.seq_no, DUPLEX_CLOSE_KEY, &close)
+            .await
+    }
+}
+
+/// Receives the messages of the device on a duplex stream, see [HostClient::duplex()]
+pub struct DuplexStream<E, WireErr> {
+    client: HostClient<WireErr>,
+    seq_no: VarSeq,
+    rx: mpsc::Receiver<RpcFrame>,
+    closed: Arc<AtomicBool>,
+    disconnected: Pin<Box<dyn Future<Output = ()> + Send>>,
+    done: bool,
+    _pd: PhantomData<fn() -> E>,
+}
+
+impl<E, WireErr> DuplexStream<E, WireErr>
+where
+    E: Endpoint,
+    E::Response: DeserializeOwned,
+    WireErr: DeserializeOwned,
+{
+    /// Decode a frame of the stream, `None` if it is not meant for us
+    fn decode(&mut self, frame: RpcFrame) -> Option<Option<Result<E::Response, HostErr<WireErr>>>> {
+        let req_key = VarKey::Key8(E::REQ_KEY);
+        let key = frame.header.key;
+        if key == VarKey::Key8(E::RESP_KEY) {
+            return Some(Some(
+                postcard::from_bytes(&frame.body).map_err(HostErr::Postcard),
+            ));
+        }
+        if key == VarKey::Key8(DUPLEX_CLOSE_KEY) {
+            if let Ok(DuplexClose::Close) = postcard::from_bytes(&frame.body) {
+                self.closed.store(true, Ordering::Release);
+            }
+            self.done = true;
+            return Some(None);
+        }
+        let err = if key == VarKey::Key8(self.client.err_key) {
+            PendingResponse::decode_err(&self.client, &frame.body, req_key)
+        } else if key == VarKey::Key8(KEYED_ERROR_KEY) {
+            let (key, rest) = match postcard::take_from_bytes::<RequestKey>(&frame.body) {
+                Ok(taken) => taken,
+                Err(e) => return Some(Some(Err(HostErr::Postcard(e)))),
+            };
+            // An error for a different request that used the same sequence number
+            if VarKey::from(key) != req_key {
+                return None;
+            }
+            PendingResponse::decode_err(&self.client, rest, req_key)
+        } else {
+            // e.g. an acknowledgement
+            return None;
+        };
+        self.done = true;
+        Some(Some(Err(err.unwrap_or_else(HostErr::Postcard))))
+    }
+}
+
+impl<E, WireErr> Stream for DuplexStream<E, WireErr>
+where
+    E: Endpoint,
+    E::Response: DeserializeOwned,
+    WireErr: DeserializeOwned,
+{
+    type Item = Result<E::Response, HostErr<WireErr>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        loop {
+            match this.rx.poll_recv(cx) {
+                Poll::Ready(Some(frame)) => {
+                    if let Some(item) = this.decode(frame) {
+                        return Poll::Ready(item);
+                    }
+                }
+                Poll::Ready(None) => {
+                    this.done = true;
+                    if this.closed.load(Ordering::Acquire) {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(Err(HostErr::Closed)));
+                }
+                Poll::Pending => break,
+            }
+        }
+        if this.disconnected.as_mut().poll(cx).is_ready() {
+            this.done = true;
+            return Poll::Ready(Some(Err(HostErr::Disconnected)));
+        }
+        Poll::Pending
+    }
+}
+
+impl<E, WireErr> Drop for DuplexStream<E, WireErr> {
+    fn drop(&mut self) {
+        self.client.ctx.remove_duplex(self.seq_no);
+    }
+}
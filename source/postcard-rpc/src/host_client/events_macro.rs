@@ -0,0 +1,114 @@
+use serde::de::DeserializeOwned;
+
+use crate::{Key, Topic};
+
+/// Define Topic Events Macro
+///
+/// Creates an enum with one variant per topic, holding the
+/// [Message][crate::Topic::Message] of that topic, and implements
+/// [`TopicEvents`][crate::host_client::TopicEvents] for it. Messages of all topics
+/// can then be received from a single
+/// [`EventStream`][crate::host_client::EventStream], created with
+/// [`HostClient::subscribe_events()`][crate::host_client::HostClient::subscribe_events],
+/// and handled with a single `match`.
+///
+/// Frames are decoded by their [`TOPIC_KEY`][crate::Topic::TOPIC_KEY]. Frames that
+/// don't match any topic, or that fail to deserialize, become the `Unknown` variant,
+/// which holds the key and the raw body.
+///
+/// # Example
+///
+/// ```rust
+/// # use postcard_schema::Schema;
+/// # use serde::{Serialize, Deserialize};
+/// use postcard_rpc::{define_topic_events, host_client::HostClient, topics};
+///
+/// #[derive(Serialize, Deserialize, Schema)]
+/// pub struct Temperature(pub i16);
+///
+/// topics! {
+///     list = TOPICS_OUT_LIST;
+///     direction = postcard_rpc::TopicDirection::ToClient;
+///     | TopicTy           | MessageTy     | Path              |
+///     | ----------        | ---------     | ----              |
+///     | TemperatureTopic  | Temperature   | "temperature"     |
+///     | ButtonTopic       | u8            | "button"          |
+/// }
+///
+/// define_topic_events! {
+///     // This becomes the name of your enum
+///     events: DeviceEvent;
+///
+///     topics: {
+///         | TopicTy           | variant       |
+///         | ----------        | -------       |
+///         | TemperatureTopic  | Temperature   |
+///         | ButtonTopic       | Button        |
+///     };
+/// }
+///
+/// async fn run(client: &HostClient<postcard_rpc::standard_icd::WireError>) {
+///     let mut events = client.subscribe_events::<DeviceEvent>(8).await.unwrap();
+///     while let Some(event) = events.recv().await {
+///         match event {
+///             DeviceEvent::Temperature(t) => println!("temperature: {}", t.0),
+///             DeviceEvent::Button(b) => println!("button {b} pressed"),
+///             DeviceEvent::Unknown(key, _body) => println!("unknown: {key:?}"),
+///         }
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! define_topic_events {
+    (
+        events: $events_name:ident;
+        topics: {
+            | TopicTy | variant |
+            | $(-)* | $(-)* |
+            $( | $topic:ty | $variant:ident | )*
+        };
+    ) => {
+        /// The messages of several topics, with one variant per topic
+        ///
+        /// Generated by the `define_topic_events!()` macro.
+        pub enum $events_name {
+            $(
+                #[doc = concat!("A message of the `", stringify!($topic), "` topic")]
+                $variant(<$topic as $crate::Topic>::Message),
+            )*
+            /// A message of an unknown topic, or one that could not be deserialized
+            Unknown($crate::Key, ::std::vec::Vec<u8>),
+        }
+
+        impl $crate::host_client::TopicEvents for $events_name {
+            const KEYS: &'static [$crate::Key] = &[
+                $( <$topic as $crate::Topic>::TOPIC_KEY, )*
+            ];
+
+            fn decode(key: $crate::Key, body: &[u8]) -> Self {
+                $(
+                    if let Some(msg) =
+                        $crate::host_client::events_macro::decode_message::<$topic>(key, body)
+                    {
+                        return Self::$variant(msg);
+                    }
+                )*
+                Self::Unknown(key, body.to_vec())
+            }
+        }
+    };
+}
+
+/// Decode `body` as a message of the topic `T`, if `key` is the key of `T`
+///
+/// Used by the `define_topic_events!()` macro.
+pub fn decode_message<T>(key: Key, body: &[u8]) -> Option<T::Message>
+where
+    T: Topic,
+    T::Message: DeserializeOwned,
+{
+    if key != T::TOPIC_KEY {
+        return None;
+    }
+    postcard::from_bytes(body).ok()
+}
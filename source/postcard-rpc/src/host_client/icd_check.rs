@@ -0,0 +1,303 @@
+//! Checking the whole ICD of the device when connecting
+
+use std::fmt::{Display, Formatter};
+
+use postcard_schema::{schema::owned::OwnedNamedType, Schema};
+use serde::de::DeserializeOwned;
+
+use crate::{EndpointMap, Key, TopicMap};
+
+use super::{EndpointReport, HostClient, SchemaError, SchemaReport, TopicReport};
+
+/// The differences between the ICD the host was built with and the one the connected
+/// device reports, see [`HostClient::check_icd()`]
+///
+/// Endpoints and topics of the device that the host doesn't know of are not
+/// mismatches.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IcdMismatch {
+    /// Endpoints that are missing or differ on the device
+    pub endpoints: Vec<EndpointMismatch>,
+    /// Incoming (client to server) topics that are missing or differ on the device
+    pub topics_in: Vec<TopicMismatch>,
+    /// Outgoing (server to client) topics that are missing or differ on the device
+    pub topics_out: Vec<TopicMismatch>,
+}
+
+/// An endpoint of the host that the device doesn't handle the same way
+#[derive(Debug, Clone, PartialEq)]
+pub enum EndpointMismatch {
+    /// The device has no endpoint with this path
+    Missing {
+        /// The path of the endpoint
+        path: String,
+    },
+    /// The device has an endpoint with this path, but with different types
+    Changed {
+        /// The path of the endpoint
+        path: String,
+        /// How the request differs, if it does
+        request: Option<KeyMismatch>,
+        /// How the response differs, if it does
+        response: Option<KeyMismatch>,
+    },
+}
+
+/// A topic of the host that the device doesn't handle or send the same way
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopicMismatch {
+    /// The device has no topic with this path
+    Missing {
+        /// The path of the topic
+        path: String,
+    },
+    /// The device has a topic with this path, but with a different message type
+    Changed {
+        /// The path of the topic
+        path: String,
+        /// How the message differs
+        message: KeyMismatch,
+    },
+}
+
+/// A key, and so the schema of a type, that differs between the host and the device
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMismatch {
+    /// The key the host expected
+    pub expected: Key,
+    /// The key the device reported
+    pub found: Key,
+    /// The schema of the type the host expected, if it is listed in the ICD of the
+    /// host, which is not the case for primitive types
+    pub expected_ty: Option<OwnedNamedType>,
+    /// The schema of the type the device reported
+    pub found_ty: OwnedNamedType,
+}
+
+/// An error returned by [`HostClient::check_icd()`] and [`HostClient::open_checked()`]
+#[derive(Debug)]
+pub enum IcdCheckError<WireErr> {
+    /// The schema report of the device could not be retrieved
+    Schema(SchemaError<WireErr>),
+    /// The ICD of the device differs from the one of the host
+    Mismatch(IcdMismatch),
+}
+
+impl<WireErr> From<SchemaError<WireErr>> for IcdCheckError<WireErr> {
+    fn from(value: SchemaError<WireErr>) -> Self {
+        Self::Schema(value)
+    }
+}
+
+impl IcdMismatch {
+    /// Are there no differences at all?
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty() && self.topics_in.is_empty() && self.topics_out.is_empty()
+    }
+
+    /// Compare the ICD of the host, given by its lists of endpoints and topics, with
+    /// the `report` of the device
+    pub fn compare(
+        report: &SchemaReport,
+        endpoints: &EndpointMap,
+        topics_in: &TopicMap,
+        topics_out: &TopicMap,
+    ) -> Self {
+        let types = endpoints
+            .types
+            .iter()
+            .chain(topics_in.types.iter())
+            .chain(topics_out.types.iter())
+            .map(|ty| OwnedNamedType::from(*ty))
+            .collect::<Vec<_>>();
+
+        IcdMismatch {
+            endpoints: endpoints
+                .endpoints
+                .iter()
+                .filter_map(|ep| compare_endpoint(&report.endpoints, &types, ep))
+                .collect(),
+            topics_in: topics_in
+                .topics
+                .iter()
+                .filter_map(|tp| compare_topic(&report.topics_in, &types, tp))
+                .collect(),
+            topics_out: topics_out
+                .topics
+                .iter()
+                .filter_map(|tp| compare_topic(&report.topics_out, &types, tp))
+                .collect(),
+        }
+    }
+}
+
+/// Find the schema of the type that `key` was calculated from, for `path`
+fn find_ty(types: &[OwnedNamedType], path: &str, key: Key) -> Option<OwnedNamedType> {
+    types
+        .iter()
+        .find(|ty| Key::for_owned_schema_path(path, ty) == key)
+        .cloned()
+}
+
+/// Compare the `expected` key of `path` with the `found` key of the device
+fn compare_key(
+    types: &[OwnedNamedType],
+    path: &str,
+    expected: Key,
+    found: Key,
+    found_ty: &OwnedNamedType,
+) -> Option<KeyMismatch> {
+    (expected != found).then(|| KeyMismatch {
+        expected,
+        found,
+        expected_ty: find_ty(types, path, expected),
+        found_ty: found_ty.clone(),
+    })
+}
+
+fn compare_endpoint(
+    reported: &[EndpointReport],
+    types: &[OwnedNamedType],
+    &(path, req_key, resp_key): &(&str, Key, Key),
+) -> Option<EndpointMismatch> {
+    let mut same_path = reported.iter().filter(|ep| ep.path == path).peekable();
+    let Some(first) = same_path.peek().copied() else {
+        return Some(EndpointMismatch::Missing { path: path.into() });
+    };
+    // The device may list more than one endpoint with the same path
+    if same_path.any(|ep| ep.req_key == req_key && ep.resp_key == resp_key) {
+        return None;
+    }
+    Some(EndpointMismatch::Changed {
+        path: path.into(),
+        request: compare_key(types, path, req_key, first.req_key, &first.req_ty),
+        response: compare_key(types, path, resp_key, first.resp_key, &first.resp_ty),
+    })
+}
+
+fn compare_topic(
+    reported: &[TopicReport],
+    types: &[OwnedNamedType],
+    &(path, key): &(&str, Key),
+) -> Option<TopicMismatch> {
+    let mut same_path = reported.iter().filter(|tp| tp.path == path).peekable();
+    let Some(first) = same_path.peek().copied() else {
+        return Some(TopicMismatch::Missing { path: path.into() });
+    };
+    if same_path.any(|tp| tp.key == key) {
+        return None;
+    }
+    let message = compare_key(types, path, key, first.key, &first.ty)?;
+    Some(TopicMismatch::Changed {
+        path: path.into(),
+        message,
+    })
+}
+
+impl Display for KeyMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.expected_ty {
+            Some(ty) => write!(f, "expected `{}`", ty.name)?,
+            None => write!(f, "expected key {:?}", self.expected)?,
+        }
+        write!(f, ", found `{}`", self.found_ty.name)
+    }
+}
+
+impl Display for IcdMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut sep = "";
+        for ep in &self.endpoints {
+            f.write_str(sep)?;
+            sep = "\n";
+            match ep {
+                EndpointMismatch::Missing { path } => {
+                    write!(f, "endpoint {path:?}: missing on the device")?;
+                }
+                EndpointMismatch::Changed {
+                    path,
+                    request,
+                    response,
+                } => {
+                    write!(f, "endpoint {path:?}:")?;
+                    if let Some(req) = request {
+                        write!(f, " request {req};")?;
+                    }
+                    if let Some(resp) = response {
+                        write!(f, " response {resp};")?;
+                    }
+                }
+            }
+        }
+        let topics = [
+            ("incoming", &self.topics_in),
+            ("outgoing", &self.topics_out),
+        ];
+        for (dir, topics) in topics {
+            for tp in topics {
+                f.write_str(sep)?;
+                sep = "\n";
+                match tp {
+                    TopicMismatch::Missing { path } => {
+                        write!(f, "{dir} topic {path:?}: missing on the device")?;
+                    }
+                    TopicMismatch::Changed { path, message } => {
+                        write!(f, "{dir} topic {path:?}: message {message}")?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for IcdMismatch {}
+
+/// # ICD Checking Methods
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Check every endpoint and topic of the ICD of the host against the schema report
+    /// of the connected device
+    ///
+    /// `endpoints`, `topics_in` and `topics_out` are the lists generated by the
+    /// [`endpoints!`][crate::endpoints] and [`topics!`][crate::topics] macros of the
+    /// ICD. Unlike requests, which fail one by one when the firmware and the host
+    /// disagree, this reports all differences at once, see [`IcdMismatch`].
+    pub async fn check_icd(
+        &self,
+        endpoints: &EndpointMap,
+        topics_in: &TopicMap,
+        topics_out: &TopicMap,
+    ) -> Result<(), IcdCheckError<WireErr>> {
+        let report = self.get_schema_report().await?;
+        let mismatch = IcdMismatch::compare(&report, endpoints, topics_in, topics_out);
+        if mismatch.is_empty() {
+            Ok(())
+        } else {
+            Err(IcdCheckError::Mismatch(mismatch))
+        }
+    }
+
+    /// Check the ICD of the connected device with [`Self::check_icd()`], returning the
+    /// client only if it matches
+    ///
+    /// This is meant to be called right after creating the client, so that a device
+    /// running mismatched firmware is noticed at startup. If the check fails, the
+    /// client is closed.
+    pub async fn open_checked(
+        self,
+        endpoints: &EndpointMap,
+        topics_in: &TopicMap,
+        topics_out: &TopicMap,
+    ) -> Result<Self, IcdCheckError<WireErr>> {
+        match self.check_icd(endpoints, topics_in, topics_out).await {
+            Ok(()) => Ok(self),
+            Err(e) => {
+                self.close();
+                Err(e)
+            }
+        }
+    }
+}
@@ -5,37 +5,76 @@
 
 use core::time::Duration;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     future::Future,
     marker::PhantomData,
+    pin::Pin,
     sync::{
-        atomic::{AtomicU32, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
         Arc, RwLock,
     },
+    task::{Context, Poll},
 };
 
 use maitake_sync::{
-    wait_map::{WaitError, WakeOutcome},
+    wait_map::{Wait, WaitError, WakeOutcome},
     WaitMap,
 };
-use postcard_schema::{schema::owned::OwnedNamedType, Schema};
+use postcard_schema::{
+    schema::owned::{OwnedDataModelType, OwnedNamedType},
+    Schema,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::{
     select,
-    sync::{broadcast, mpsc, Mutex},
+    sync::{broadcast, mpsc, watch, Mutex},
+};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream,
 };
 use util::Subscriptions;
 
 use crate::{
-    header::{VarHeader, VarKey, VarKeyKind, VarSeq, VarSeqKind},
-    standard_icd::{GetAllSchemaDataTopic, GetAllSchemasEndpoint, OwnedSchemaData},
-    Endpoint, Key, Topic, TopicDirection,
+    hash::fnv1a64::hash_icd,
+    header::{AuthToken, VarHeader, VarKey, VarKeyKind, VarSeq, VarSeqKind},
+    standard_icd::{
+        Batch, BatchTopic, Compat, CompatEndpoint, EchoFrameEndpoint, EchoRequest,
+        ErrorLogEndpoint, Fragment, FragmentTopic, GetAllSchemaDataTopic, GetAllSchemasEndpoint,
+        GetGroupsEndpoint, GetStatsEndpoint, HandshakeEndpoint, HasEndpointEndpoint, Heartbeat,
+        HeartbeatTopic, OwnedEchoedFrame, OwnedEndpointGroups, OwnedErrorLogReport,
+        OwnedHandshake, OwnedSchemaData, OwnedStatsReport, RebootEndpoint, RebootMode,
+        RequestKey, ResetEndpoint, SetHeartbeatEndpoint, WireError, ACK_KEY, ERROR_KEY,
+        KEYED_ERROR_KEY, PROTOCOL_VERSION,
+    },
+    Endpoint, EndpointMap, Key, Topic, TopicDirection, TopicMap,
 };
 
-use self::util::Stopper;
+use self::{trace::CallSpan, util::Stopper};
+
+mod blocking;
+mod cipher_wire;
+#[doc(hidden)]
+pub mod client_macro;
+mod crc_wire;
+mod duplex;
+#[doc(hidden)]
+pub mod events_macro;
+mod icd_check;
+mod progress;
+mod topic_router;
+
+pub use blocking::BlockingClient;
+pub use cipher_wire::CipherWire;
+pub use crc_wire::CrcWire;
+pub use duplex::{DuplexSink, DuplexStream};
+pub use icd_check::{EndpointMismatch, IcdCheckError, IcdMismatch, KeyMismatch, TopicMismatch};
+pub use topic_router::TopicRouter;
 
 #[cfg(all(feature = "raw-nusb", not(target_family = "wasm")))]
 mod raw_nusb;
+#[cfg(all(feature = "raw-nusb", not(target_family = "wasm")))]
+pub use raw_nusb::DeviceInfo;
 
 #[cfg(all(feature = "cobs-serial", not(target_family = "wasm")))]
 mod serial;
@@ -43,16 +82,29 @@ mod serial;
 #[cfg(all(feature = "webusb", target_family = "wasm"))]
 pub mod webusb;
 
+mod trace;
 pub(crate) mod util;
 
 #[cfg(feature = "test-utils")]
 pub mod test_channels;
 
 /// Host Error Kind
+///
+/// New kinds of errors may be added in minor releases, so matches on this enum
+/// need a wildcard arm.
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum HostErr<WireErr> {
     /// An error of the user-specified wire error type
     Wire(WireErr),
+    /// The server does not handle the request with this key
+    ///
+    /// This is reported instead of `Wire(WireError::UnknownKey)` when the client uses
+    /// the standard [WireError] type with the standard [ERROR_PATH]. For requests
+    /// made with a typed [Endpoint], this is always the full [VarKey::Key8].
+    ///
+    /// [ERROR_PATH]: crate::standard_icd::ERROR_PATH
+    UnknownKey(VarKey),
     /// We got a response that didn't match the expected value or the
     /// user specified wire error type
     BadResponse,
@@ -60,6 +112,59 @@ pub enum HostErr<WireErr> {
     Postcard(postcard::Error),
     /// The interface has been closed, and no further messages are possible
     Closed,
+    /// The connection was lost before a response was received. If the client was
+    /// created with reconnection enabled, later requests may succeed.
+    Disconnected,
+    /// The sequence number is already in use by another pending request
+    SeqNoInUse,
+    /// No response was received after all attempts allowed by the [RetryPolicy]
+    RetriesExhausted {
+        /// Why the last attempt failed
+        last: AttemptFailure,
+    },
+    /// The client is shutting down, see [HostClient::shutdown()]. New requests are
+    /// rejected, and requests still pending after the deadline resolve with this.
+    Shutdown,
+    /// The connection was reset with [HostClient::reset()] before a response was
+    /// received. Late responses to this request are discarded.
+    Reset,
+    /// The device replied with a different response type than expected, e.g. because
+    /// it runs a newer firmware. Only detected for endpoints listed in the last
+    /// [HostClient::handshake()], or passed to [HostClient::allow_lenient()].
+    SchemaMismatch {
+        /// The response key of the endpoint, as known to the client
+        expected_key: Key,
+        /// The response key used by the device
+        actual_key: Key,
+    },
+    /// No heartbeat was received within the grace period, see
+    /// [HostClient::watch_heartbeat()]
+    HeartbeatLost,
+    /// The device speaks a different protocol version or ICD than this client, see
+    /// [HostClient::check_compat()]. Contains what the device reported.
+    Incompatible(Compat),
+}
+
+impl HostErr<WireError> {
+    /// If the server rejected the request because it was busy, how long to wait
+    /// before retrying
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            HostErr::Wire(WireError::Busy(busy)) => {
+                Some(Duration::from_millis(u64::from(busy.retry_after_ms)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The reason a single attempt of [HostClient::send_resp_with_retry()] failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttemptFailure {
+    /// No response was received within [RetryPolicy::timeout]
+    TimedOut,
+    /// The connection was lost before a response was received
+    Disconnected,
 }
 
 impl<T> From<postcard::Error> for HostErr<T> {
@@ -69,8 +174,11 @@ impl<T> From<postcard::Error> for HostErr<T> {
 }
 
 impl<T> From<WaitError> for HostErr<T> {
-    fn from(_: WaitError) -> Self {
-        Self::Closed
+    fn from(value: WaitError) -> Self {
+        match value {
+            WaitError::Duplicate => Self::SeqNoInUse,
+            _ => Self::Closed,
+        }
     }
 }
 
@@ -158,6 +266,103 @@ pub trait WireSpawn: 'static {
     fn spawn(&mut self, fut: impl Future<Output = ()> + Send + 'static);
 }
 
+/// The state of the connection between a [HostClient] and the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The device is connected
+    Connected,
+    /// The connection to the device was lost
+    Disconnected,
+    /// Waiting for the device to be (re)connected
+    Reconnecting,
+}
+
+/// How [HostClient::send_resp_with_retry()] retransmits requests
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of times the request is sent, including the first attempt
+    pub max_attempts: u32,
+    /// How long to wait for a response to each attempt
+    pub timeout: Duration,
+    /// How long to wait before the first retransmission
+    pub backoff: Duration,
+    /// The factor the backoff is multiplied by after each retransmission
+    pub backoff_factor: u32,
+    /// The upper limit of the backoff
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// The backoff before retransmission number `retry`, starting at zero
+    pub fn backoff_for(&self, retry: u32) -> Duration {
+        let factor = self.backoff_factor.saturating_pow(retry);
+        self.backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            timeout: Duration::from_secs(1),
+            backoff: Duration::from_millis(100),
+            backoff_factor: 2,
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A source of sequence numbers for requests made by a [HostClient]
+///
+/// By default, a [HostClient] uses a [MonotonicSeqNo] counter. A custom source, for
+/// example one that produces random or process-prefixed values, can be set with
+/// [HostClient::set_seq_no_source()]. Any closure returning a `u32` can also be used.
+///
+/// Values are truncated to the [VarSeqKind] of the client before being sent, so only
+/// the lower 8 or 16 bits are used with [VarSeqKind::Seq1] or [VarSeqKind::Seq2].
+/// If a value is already in use by a pending request, it is skipped and the next
+/// value is taken instead.
+pub trait SeqNoSource: Send + Sync + 'static {
+    /// Produce the next sequence number
+    fn next_seq_no(&self) -> u32;
+}
+
+impl<F> SeqNoSource for F
+where
+    F: Fn() -> u32 + Send + Sync + 'static,
+{
+    fn next_seq_no(&self) -> u32 {
+        (self)()
+    }
+}
+
+/// The default [SeqNoSource], counting up from zero and wrapping around
+#[derive(Debug, Default)]
+pub struct MonotonicSeqNo {
+    ctr: AtomicU32,
+}
+
+impl MonotonicSeqNo {
+    /// Create a new counter, starting at `start`
+    pub const fn new(start: u32) -> Self {
+        Self {
+            ctr: AtomicU32::new(start),
+        }
+    }
+}
+
+impl SeqNoSource for MonotonicSeqNo {
+    fn next_seq_no(&self) -> u32 {
+        self.ctr.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// The number of sequence numbers tried before giving up with [HostErr::SeqNoInUse]
+const SEQ_NO_ATTEMPTS: usize = 16;
+
 /// The [HostClient] is the primary PC-side interface.
 ///
 /// It is generic over a single type, `WireErr`, which can be used by the
@@ -170,6 +375,17 @@ pub trait WireSpawn: 'static {
 ///
 /// 1. With raw USB Bulk transfers: [`HostClient::new_raw_nusb()`] (**recommended**)
 /// 2. With cobs CDC-ACM transfers: [`HostClient::new_serial_cobs()`]
+///
+/// With multiple devices attached, [`HostClient::enumerate_raw_nusb()`] lists them, and
+/// [`HostClient::try_open_raw_nusb()`] connects to one of them.
+///
+/// ## Tracing
+///
+/// Each call to [`HostClient::send_resp()`] and its variants runs in a `debug` level
+/// `call` span, with the `endpoint`, `key` and `seq_no` of the request. Events are emitted when the request is sent, when the response is
+/// received, when an attempt times out, and when the call fails. The span is closed
+/// when the call resolves, so the latency and ordering of calls can be inspected
+/// with tools such as `tokio-console`.
 pub struct HostClient<WireErr> {
     ctx: Arc<HostContext>,
     out: mpsc::Sender<RpcFrame>,
@@ -196,7 +412,22 @@ where
         let ctx = Arc::new(HostContext {
             kkind: RwLock::new(VarKeyKind::Key8),
             map: WaitMap::new(),
-            seq: AtomicU32::new(0),
+            seq: RwLock::new(Box::new(MonotonicSeqNo::new(0))),
+            conn: watch::channel(ConnectionState::Connected).0,
+            conn_gen: AtomicU32::new(0),
+            draining: AtomicBool::new(false),
+            inflight: watch::channel(0).0,
+            workers: watch::channel(0).0,
+            epoch: watch::channel(0).0,
+            pending: std::sync::Mutex::new(Vec::new()),
+            stale: std::sync::Mutex::new(Vec::new()),
+            resp_keys: RwLock::new(HashMap::new()),
+            lenient: RwLock::new(HashSet::new()),
+            ordered: std::sync::Mutex::new(HashMap::new()),
+            token: RwLock::new(None),
+            duplex: std::sync::Mutex::new(Vec::new()),
+            progress: std::sync::Mutex::new(Vec::new()),
+            topic_routes: std::sync::Mutex::new(Default::default()),
         });
 
         let err_key = Key::for_path::<WireErr>(err_uri_path);
@@ -319,9 +550,256 @@ where
         }
     }
 
+    /// Obtain the dispatch counters of the connected device, optionally resetting them
+    ///
+    /// The device must have been built with the `metrics` feature of `postcard-rpc`
+    /// enabled, otherwise this will fail with an `UnknownKey` error.
+    pub async fn get_stats(&self, reset: bool) -> Result<OwnedStatsReport, HostErr<WireErr>> {
+        self.send_resp::<GetStatsEndpoint>(&reset).await
+    }
+
+    /// Obtain the protocol version of the connected device, and the keys of its endpoints
+    ///
+    /// Use [`OwnedHandshake::endpoint_status()`] to check whether an endpoint can be
+    /// used with this device, for example to detect that the device has a different
+    /// version of an endpoint than the host.
+    ///
+    /// The response keys of the device are remembered by all clones of this client.
+    /// Afterwards, requests to endpoints that the device answers with a different
+    /// response type fail with [HostErr::SchemaMismatch], instead of waiting for a
+    /// response that never matches. If only the request type differs, the device
+    /// still replies with an `UnknownKey` error.
+    pub async fn handshake(&self) -> Result<OwnedHandshake, HostErr<WireErr>> {
+        let hs = self.send_resp::<HandshakeEndpoint>(&()).await?;
+        let mut resp_keys = self.ctx.resp_keys.write().unwrap();
+        for (_path, req_key, resp_key) in hs.endpoints.iter() {
+            resp_keys.insert(*req_key, *resp_key);
+        }
+        Ok(hs)
+    }
+
+    /// Obtain the protocol version, crate version, ICD hash, and maximum frame length
+    /// of the connected device
+    ///
+    /// See [Self::check_compat()] to compare these with the ICD of this client.
+    pub async fn compat(&self) -> Result<Compat, HostErr<WireErr>> {
+        self.send_resp::<CompatEndpoint>(&()).await
+    }
+
+    /// Obtain the groups that the endpoints of the connected device were put in
+    ///
+    /// Only endpoints that were given a group in the device's `define_dispatch!`
+    /// are listed. Devices with an older version of `postcard-rpc` answer with an
+    /// `UnknownKey` error.
+    pub async fn get_endpoint_groups(&self) -> Result<OwnedEndpointGroups, HostErr<WireErr>> {
+        self.send_resp::<GetGroupsEndpoint>(&()).await
+    }
+
+    /// Send `data` to the connected device, and obtain the request frame as the
+    /// device saw it
+    ///
+    /// The echoed `body` is the serialized [`EchoRequest`] as the device received
+    /// it, and should equal `postcard::to_stdvec(&EchoRequest { data })`. The
+    /// echoed `header` is the header of the request, as the device parsed it. A
+    /// mismatch points at a problem with the framing of the transport.
+    pub async fn echo_frame(&self, data: &[u8]) -> Result<OwnedEchoedFrame, HostErr<WireErr>> {
+        self.send_resp::<EchoFrameEndpoint>(&EchoRequest { data })
+            .await
+    }
+
+    /// Check whether the connected device is compatible with this client at all
+    ///
+    /// `endpoints`, `topics_in` and `topics_out` are the lists generated by the
+    /// [`endpoints!`][crate::endpoints] and [`topics!`][crate::topics] macros of the
+    /// ICD, which must be the same lists that the dispatcher of the device was defined
+    /// with. Fails with [HostErr::Incompatible] if the device has a different
+    /// [PROTOCOL_VERSION], or a different hash of its ICD, see
+    /// [`hash_icd()`][crate::hash::fnv1a64::hash_icd].
+    ///
+    /// This is a single small request, much cheaper than [Self::check_icd()], but it
+    /// only tells THAT something differs, not what.
+    pub async fn check_compat(
+        &self,
+        endpoints: &EndpointMap,
+        topics_in: &TopicMap,
+        topics_out: &TopicMap,
+    ) -> Result<Compat, HostErr<WireErr>> {
+        let compat = self.compat().await?;
+        let icd_hash = hash_icd(endpoints.endpoints, topics_in.topics, topics_out.topics);
+        if compat.protocol_version != PROTOCOL_VERSION || compat.icd_hash != icd_hash {
+            return Err(HostErr::Incompatible(compat));
+        }
+        Ok(compat)
+    }
+
+    /// Check the connected device with [Self::check_compat()], returning the client
+    /// only if it is compatible
+    ///
+    /// This is meant to be called right after creating the client. If the check
+    /// fails, the client is closed.
+    pub async fn open_compatible(
+        self,
+        endpoints: &EndpointMap,
+        topics_in: &TopicMap,
+        topics_out: &TopicMap,
+    ) -> Result<Self, HostErr<WireErr>> {
+        match self.check_compat(endpoints, topics_in, topics_out).await {
+            Ok(_) => Ok(self),
+            Err(e) => {
+                self.close();
+                Err(e)
+            }
+        }
+    }
+
+    /// Accept responses of the [Endpoint] `E` from a device with a newer version of
+    /// the response type, that only appends fields to it
+    ///
+    /// This fetches the [SchemaReport] of the device, and compares the response type
+    /// of the device with [Endpoint::Response][Endpoint]. If the response type of the
+    /// device is a struct that starts with all of our fields, followed by more fields,
+    /// its responses are decoded with our type, ignoring the trailing fields. This
+    /// applies to all clones of this client.
+    ///
+    /// Returns `true` if responses of `E` can be decoded, and `false` if the response
+    /// types are incompatible, in which case requests to `E` fail with
+    /// [HostErr::SchemaMismatch], or if the device does not handle our request type.
+    pub async fn allow_lenient<E: Endpoint>(&self) -> Result<bool, SchemaError<WireErr>> {
+        let report = self.get_schema_report().await?;
+        let Some(ep) = report
+            .endpoints
+            .iter()
+            .find(|ep| ep.path == E::PATH && ep.req_key == E::REQ_KEY)
+        else {
+            return Ok(false);
+        };
+        self.ctx
+            .resp_keys
+            .write()
+            .unwrap()
+            .insert(ep.req_key, ep.resp_key);
+
+        if ep.resp_key == E::RESP_KEY {
+            return Ok(true);
+        }
+        if !extends(&ep.resp_ty, &OwnedNamedType::from(E::Response::SCHEMA)) {
+            return Ok(false);
+        }
+        self.ctx.lenient.write().unwrap().insert(ep.resp_key);
+        Ok(true)
+    }
+
+    /// Check whether the connected device handles the [Endpoint] `E`, without
+    /// sending a request to it
+    ///
+    /// This only compares keys, and is lighter than [Self::handshake()] or
+    /// [Self::get_schema_report()] when only a yes/no answer is needed. Endpoints
+    /// that are listed by the device, but have no handler, are reported as missing.
+    ///
+    /// Devices using an older version of `postcard-rpc` reply with an `UnknownKey`
+    /// error instead.
+    pub async fn has_endpoint<E: Endpoint>(&self) -> Result<bool, HostErr<WireErr>> {
+        self.send_resp::<HasEndpointEndpoint>(&E::REQ_KEY).await
+    }
+
+    /// Reset the connection to the device, e.g. after the host and device got out of
+    /// sync, and return the new epoch of the device
+    ///
+    /// The device drops its cached state, such as the responses cached for `dedup`
+    /// endpoints and partially reassembled requests, and starts a new epoch. All other
+    /// requests that are still pending fail with [HostErr::Reset], and responses to
+    /// them that arrive after the reset are discarded, by matching their sequence
+    /// numbers. These sequence numbers are not reused until the next reset.
+    ///
+    /// Devices using an older version of `postcard-rpc` reply with an `UnknownKey`
+    /// error instead.
+    pub async fn reset(&self) -> Result<u32, HostErr<WireErr>> {
+        let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+        let mut key = VarKey::Key8(ResetEndpoint::REQ_KEY);
+        key.shrink_to(kkind);
+
+        let (seq_no, mut pending) = self
+            .register_next(kkind, ResetEndpoint::REQ_KEY, ResetEndpoint::RESP_KEY)
+            .await?;
+        // Not invalidated by its own response
+        pending.epoch = None;
+
+        let frame = RpcFrame {
+            header: VarHeader { key, seq_no },
+            body: postcard::to_stdvec(&()).expect("Allocations should not ever fail"),
+        };
+        self.send_request(pending.conn_gen, frame).await?;
+        let frame = pending.recv().await?;
+        Ok(postcard::from_bytes::<u32>(&frame.body)?)
+    }
+
+    /// Change the interval at which the device sends heartbeats, or disable them with
+    /// zero
+    ///
+    /// Returns `false` if the dispatcher of the device has no heartbeat config, see
+    /// [`server::heartbeat`][crate::server::heartbeat]. Devices using an older version
+    /// of `postcard-rpc` reply with an `UnknownKey` error instead.
+    pub async fn set_heartbeat_interval(&self, interval_ms: u32) -> Result<bool, HostErr<WireErr>> {
+        self.send_resp::<SetHeartbeatEndpoint>(&interval_ms).await
+    }
+
+    /// Get the most recent errors the device sent, and clear them if `clear` is set
+    ///
+    /// Fails with an `UnknownKey` error if the dispatcher of the device has no error
+    /// log, see [`server::error_log`][crate::server::error_log], or the device uses an
+    /// older version of `postcard-rpc`.
+    pub async fn error_log(&self, clear: bool) -> Result<OwnedErrorLogReport, HostErr<WireErr>> {
+        self.send_resp::<ErrorLogEndpoint>(&clear).await
+    }
+
+    /// Ask the device to reboot into `mode`
+    ///
+    /// Returns `false` if the device doesn't support rebooting into `mode`, see the
+    /// "Rebooting" section of [`define_dispatch!`][crate::define_dispatch]. As the
+    /// device may reboot before its reply reaches us, losing the connection while
+    /// waiting for the reply ([HostErr::Disconnected] or [HostErr::Closed]) counts as
+    /// success, and returns `true`. Devices using an older version of `postcard-rpc`
+    /// reply with an `UnknownKey` error instead.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn reboot(&self, mode: RebootMode) -> Result<bool, HostErr<WireErr>> {
+        match self.send_resp::<RebootEndpoint>(&mode).await {
+            Err(HostErr::Disconnected | HostErr::Closed) => Ok(true),
+            res => res,
+        }
+    }
+
+    /// Watch the heartbeats of the device, see [HeartbeatWatch]
+    ///
+    /// `grace` is how long to wait for each heartbeat before considering the device
+    /// lost, and should be somewhat longer than the heartbeat interval of the device.
+    ///
+    /// Returns an Error if the I/O worker is closed.
+    pub async fn watch_heartbeat(
+        &self,
+        grace: Duration,
+    ) -> Result<HeartbeatWatch<WireErr>, IoClosed> {
+        let sub = self.subscribe_multi::<HeartbeatTopic>(8).await?;
+        Ok(HeartbeatWatch {
+            sub,
+            grace,
+            _pd: PhantomData,
+        })
+    }
+
+    /// The epoch of the device, as returned by the last call to [Self::reset()]
+    ///
+    /// This is `0` until the device has been reset.
+    pub fn epoch(&self) -> u32 {
+        *self.ctx.epoch.borrow()
+    }
+
     /// Send a message of type [Endpoint::Request][Endpoint] to `path`, and await
     /// a response of type [Endpoint::Response][Endpoint] (or WireErr) to `path`.
     ///
+    /// If the server handles this endpoint with a `spawn` handler, the response is
+    /// sent by the spawned task once it has finished, and this function resolves then.
+    ///
     /// This function will wait potentially forever. Consider using with a timeout.
     pub async fn send_resp<E: Endpoint>(
         &self,
@@ -331,68 +809,598 @@ where
         E::Request: Serialize + Schema,
         E::Response: DeserializeOwned + Schema,
     {
-        let seq_no = self.ctx.seq.fetch_add(1, Ordering::Relaxed);
+        let frame = self.send_resp_frame::<E>(t).await?;
+        let r = postcard::from_bytes::<E::Response>(&frame.body)?;
+        Ok(r)
+    }
+
+    /// Send a message of type [Endpoint::Request][Endpoint] to `path`, and await
+    /// a response of type [Endpoint::Response][Endpoint] followed by a binary
+    /// attachment (or WireErr) to `path`.
+    ///
+    /// The server replies with
+    /// [`Sender::reply_with_blob()`][crate::server::Sender::reply_with_blob], which
+    /// appends the length-prefixed attachment to the serialized response.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn send_resp_with_blob<E: Endpoint>(
+        &self,
+        t: &E::Request,
+    ) -> Result<(E::Response, Vec<u8>), HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let frame = self.send_resp_frame::<E>(t).await?;
+        let (r, rest) = postcard::take_from_bytes::<E::Response>(&frame.body)?;
+        let blob = postcard::from_bytes::<&[u8]>(rest)?;
+        Ok((r, blob.to_vec()))
+    }
+
+    /// Send a request, and await the frame of the response
+    async fn send_resp_frame<E: Endpoint>(
+        &self,
+        t: &E::Request,
+    ) -> Result<RpcFrame, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+    {
+        let span = CallSpan::new(E::PATH, VarKey::Key8(E::REQ_KEY));
+        span.run(async {
+            let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+            let mut key = VarKey::Key8(E::REQ_KEY);
+            key.shrink_to(kkind);
+
+            // Make sure we are registered for the response BEFORE sending, otherwise
+            // a fast reply could arrive before anyone is waiting for it
+            let (seq_no, pending) = self.register_next(kkind, E::REQ_KEY, E::RESP_KEY).await?;
+            span.seq_no(seq_no);
+
+            let msg = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
+            let frame = RpcFrame {
+                header: VarHeader { key, seq_no },
+                body: msg,
+            };
+            self.send_request(pending.conn_gen, frame).await?;
+            span.sent();
+            let frame = pending.recv().await?;
+            span.received();
+            Ok(frame)
+        })
+        .await
+    }
+
+    /// Send multiple messages of type [Endpoint::Request][Endpoint] in a single
+    /// transfer, and await all of the responses of type [Endpoint::Response][Endpoint]
+    /// (or WireErr).
+    ///
+    /// The requests are packed into one [`Batch`] frame, which saves the per-transfer
+    /// overhead of sending many small requests. The server handles them in order, as
+    /// if they had been sent one by one, and sends a separate response for each. The
+    /// results are returned in the same order as `reqs`.
+    ///
+    /// The whole batch must fit into the receive buffer of the server, otherwise it is
+    /// discarded. Servers of postcard-rpc 0.10 or older don't support batches, and only
+    /// reply with an error to the first request.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn call_batch<E: Endpoint>(
+        &self,
+        reqs: &[E::Request],
+    ) -> Vec<Result<E::Response, HostErr<WireErr>>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        // First, register interest in all of the responses, so that a fast reply to
+        // an early request can't be dropped while later ones are still registered
+        let mut pending = Vec::with_capacity(reqs.len());
+        for _ in reqs {
+            match self.reserve::<E>().await {
+                Ok((_, p)) => pending.push(p),
+                Err(e) => return fail_all(reqs.len(), e),
+            }
+        }
+        let Some(first) = pending.first() else {
+            return Vec::new();
+        };
+        let (kkind, seq_no, conn_gen) =
+            (first.inner.kkind, first.inner.seq_no, first.inner.conn_gen);
+
+        // Then pack all of the requests, each with the token of the session, if any
+        let token = *self.ctx.token.read().unwrap();
+        let mut frames = Vec::new();
+        for (resp, req) in pending.iter().zip(reqs.iter()) {
+            let mut key = VarKey::Key8(E::REQ_KEY);
+            key.shrink_to(resp.inner.kkind);
+            let frame = RpcFrame {
+                header: VarHeader {
+                    key,
+                    seq_no: resp.inner.seq_no,
+                },
+                body: postcard::to_stdvec(req).expect("Allocations should not ever fail"),
+            };
+            let bytes = match token {
+                Some(token) => frame.to_bytes_with_token(&token),
+                None => frame.to_bytes(),
+            };
+            let prefixed =
+                postcard::to_stdvec(bytes.as_slice()).expect("Allocations should not ever fail");
+            frames.extend_from_slice(&prefixed);
+        }
+
+        // The batch uses the sequence number of the first request, so errors about the
+        // batch itself go there. If the device reconnected after the first request was
+        // registered, sending fails, as some requests may have been failed already.
+        let mut batch_key = VarKey::Key8(BatchTopic::TOPIC_KEY);
+        batch_key.shrink_to(kkind);
+        let frame = RpcFrame {
+            header: VarHeader {
+                key: batch_key,
+                seq_no,
+            },
+            body: postcard::to_stdvec(&Batch { frames: &frames })
+                .expect("Allocations should not ever fail"),
+        };
+        if let Err(e) = self.send_request(conn_gen, frame).await {
+            return fail_all(reqs.len(), e);
+        }
+
+        // Finally, collect the responses in order
+        let mut out = Vec::with_capacity(pending.len());
+        for resp in pending {
+            out.push(resp.recv().await);
+        }
+        out
+    }
+
+    /// Send a message of type [Endpoint::Request][Endpoint], and await a response of type
+    /// [Endpoint::Response][Endpoint] (or WireErr), retransmitting the request according to
+    /// the given [RetryPolicy] if no response is received in time.
+    ///
+    /// All attempts use the same sequence number, so a server using a `dedup` handler for
+    /// this endpoint will not run the handler more than once. A response to ANY of the
+    /// attempts completes the request.
+    ///
+    /// If the connection is lost, the request is sent again once the client has
+    /// reconnected, which counts as an attempt. Errors other than timeouts and lost
+    /// connections, for example a WireErr sent by the server, are returned immediately.
+    ///
+    /// If no response was received after [RetryPolicy::max_attempts], this returns
+    /// [HostErr::RetriesExhausted].
+    pub async fn send_resp_with_retry<E: Endpoint>(
+        &self,
+        t: &E::Request,
+        policy: &RetryPolicy,
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let span = CallSpan::new(E::PATH, VarKey::Key8(E::REQ_KEY));
+        span.run(async {
+            let msg = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
+            let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+            let (seq_no, mut pending) = self.register_next(kkind, E::REQ_KEY, E::RESP_KEY).await?;
+            span.seq_no(seq_no);
+
+            let mut attempts = 0;
+            let last = loop {
+                let conn_gen = pending.conn_gen;
+                let resp = pending.recv();
+                tokio::pin!(resp);
+
+                let res = 'attempts: loop {
+                    if attempts != 0 {
+                        // Keep listening for a late response while backing off
+                        let backoff = tokio::time::sleep(policy.backoff_for(attempts - 1));
+                        select! {
+                            r = &mut resp => break 'attempts r,
+                            _ = backoff => {},
+                        }
+                    }
+                    attempts += 1;
+
+                    let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+                    let mut key = VarKey::Key8(E::REQ_KEY);
+                    key.shrink_to(kkind);
+                    let frame = RpcFrame {
+                        header: VarHeader { key, seq_no },
+                        body: msg.clone(),
+                    };
+                    match self.send_request(conn_gen, frame).await {
+                        // Handled like a lost response, see below
+                        Err(HostErr::Disconnected) => break 'attempts Err(HostErr::Disconnected),
+                        res => res?,
+                    }
+                    span.sent();
+
+                    select! {
+                        r = &mut resp => break 'attempts r,
+                        _ = tokio::time::sleep(policy.timeout) => {},
+                    }
+                    span.timed_out(attempts);
+                    if attempts >= policy.max_attempts {
+                        return Err(HostErr::RetriesExhausted {
+                            last: AttemptFailure::TimedOut,
+                        });
+                    }
+                };
+
+                match res {
+                    Err(HostErr::Disconnected) if attempts < policy.max_attempts => {
+                        // Wait for the connection to come back, and register for the
+                        // response again, keeping the same sequence number
+                        self.wait_connected().await.map_err(|_| HostErr::Closed)?;
+                        let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+                        pending = PendingResponse::register(
+                            self,
+                            kkind,
+                            seq_no,
+                            VarKey::Key8(E::REQ_KEY),
+                            E::RESP_KEY,
+                        )
+                        .await?;
+                    }
+                    Err(HostErr::Disconnected) => break AttemptFailure::Disconnected,
+                    Ok(frame) => {
+                        span.received();
+                        return Ok(postcard::from_bytes::<E::Response>(&frame.body)?);
+                    }
+                    Err(e) => return Err(e),
+                }
+            };
+            Err(HostErr::RetriesExhausted { last })
+        })
+        .await
+    }
+
+    /// Send a message of type [Endpoint::Request][Endpoint] as multiple fragments of up
+    /// to `max_fragment_len` bytes of the original frame each, and await a response of
+    /// type [Endpoint::Response][Endpoint] (or WireErr).
+    ///
+    /// This allows sending requests that are larger than the receive buffer of the
+    /// server. The server must have been created with reassembly enabled, see
+    /// [`Server::new_with_reassembly()`][crate::server::Server::new_with_reassembly].
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn send_resp_fragmented<E: Endpoint>(
+        &self,
+        t: &E::Request,
+        max_fragment_len: usize,
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let span = CallSpan::new(E::PATH, VarKey::Key8(E::REQ_KEY));
+        span.run(async {
+            let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+            let (seq_no, pending) = self.register_next(kkind, E::REQ_KEY, E::RESP_KEY).await?;
+            span.seq_no(seq_no);
+
+            // Serialize the whole frame as it would be sent in one piece
+            let mut key = VarKey::Key8(E::REQ_KEY);
+            key.shrink_to(kkind);
+            let mut whole = VarHeader { key, seq_no }.write_to_vec();
+            let body = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
+            whole.extend_from_slice(&body);
+
+            let mut frag_key = VarKey::Key8(FragmentTopic::TOPIC_KEY);
+            frag_key.shrink_to(kkind);
+            let max_fragment_len = max_fragment_len.max(1);
+            for (i, data) in whole.chunks(max_fragment_len).enumerate() {
+                let frag = Fragment {
+                    total_len: whole.len() as u32,
+                    offset: (i * max_fragment_len) as u32,
+                    data,
+                };
+                let frame = RpcFrame {
+                    header: VarHeader {
+                        key: frag_key,
+                        seq_no,
+                    },
+                    body: postcard::to_stdvec(&frag).expect("Allocations should not ever fail"),
+                };
+                self.send_request(pending.conn_gen, frame).await?;
+            }
+            span.sent();
+
+            let frame = pending.recv().await?;
+            span.received();
+            let r = postcard::from_bytes::<E::Response>(&frame.body)?;
+            Ok(r)
+        })
+        .await
+    }
+
+    /// Reserve a sequence number for a future request to the [Endpoint] `E`, and
+    /// register interest in the response BEFORE the request is sent.
+    ///
+    /// The request should then be sent with [Self::send_reserved()], and the response
+    /// awaited with [ReservedResponse::recv()]. The sequence number is returned to
+    /// correlate the response with events outside of the client. Because
+    /// the response is registered first, a device that replies very quickly can't "beat"
+    /// the registration and have its reply dropped as unknown.
+    pub async fn reserve<E: Endpoint>(
+        &self,
+    ) -> Result<(VarSeq, ReservedResponse<'_, E, WireErr>), HostErr<WireErr>>
+    where
+        E::Response: DeserializeOwned + Schema,
+    {
+        let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+        let (seq_no, inner) = self.register_next(kkind, E::REQ_KEY, E::RESP_KEY).await?;
+        Ok((
+            seq_no,
+            ReservedResponse {
+                inner,
+                _pd: PhantomData,
+            },
+        ))
+    }
+
+    /// Send the request for a response previously reserved with [Self::reserve()].
+    ///
+    /// The request uses the sequence number and [Endpoint] of `resp`, which is
+    /// returned again to await the response.
+    pub async fn send_reserved<'a, E: Endpoint>(
+        &self,
+        resp: ReservedResponse<'a, E, WireErr>,
+        t: &E::Request,
+    ) -> Result<ReservedResponse<'a, E, WireErr>, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+    {
+        let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+        let mut key = VarKey::Key8(E::REQ_KEY);
+        key.shrink_to(kkind);
 
         let msg = postcard::to_stdvec(&t).expect("Allocations should not ever fail");
         let frame = RpcFrame {
-            // NOTE: send_resp_raw automatically shrinks down key and sequence
-            // kinds to the appropriate amount
             header: VarHeader {
-                key: VarKey::Key8(E::REQ_KEY),
-                seq_no: VarSeq::Seq4(seq_no),
+                key,
+                seq_no: resp.inner.seq_no,
             },
             body: msg,
         };
-        let frame = self.send_resp_raw(frame, E::RESP_KEY).await?;
-        let r = postcard::from_bytes::<E::Response>(&frame.body)?;
-        Ok(r)
+        self.send_request(resp.inner.conn_gen, frame).await?;
+        Ok(resp)
+    }
+
+    /// Send a request to the [Endpoint] `E`, and return the acknowledgement and the
+    /// response, which can be awaited separately.
+    ///
+    /// The device acknowledges the request with
+    /// [`Sender::ack()`][crate::server::Sender::ack] once it has received and
+    /// accepted it, which allows reporting progress quickly for slow operations,
+    /// before the response is available. Both are registered before the request is
+    /// sent, so neither can be missed.
+    ///
+    /// A device that rejects the request replies with an error instead, which is
+    /// returned by [ReservedResponse::recv()], and never acknowledges it. Await the
+    /// acknowledgement with a timeout, or concurrently with the response.
+    pub async fn send_resp_with_ack<E: Endpoint>(
+        &self,
+        t: &E::Request,
+    ) -> Result<(PendingAck<'_, WireErr>, ReservedResponse<'_, E, WireErr>), HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let (seq_no, resp) = self.reserve::<E>().await?;
+
+        let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+        let mut key = VarKey::Key8(ACK_KEY);
+        key.shrink_to(kkind);
+        let mut wait = Box::pin(self.ctx.map.wait(VarHeader { seq_no, key }));
+        wait.as_mut().subscribe().await?;
+        let ack = PendingAck {
+            wait,
+            _pd: PhantomData,
+        };
+
+        let resp = self.send_reserved(resp, t).await?;
+        Ok((ack, resp))
+    }
+
+    /// Send a message of type [Endpoint::Request][Endpoint], and await a response of
+    /// type [Endpoint::Response][Endpoint] (or WireErr), keeping the order of requests
+    /// to the same endpoint.
+    ///
+    /// Concurrent calls to [Self::send_resp()] may overtake each other before their
+    /// requests are enqueued, e.g. while one of them waits for a sequence number. Calls
+    /// to this function for the same endpoint wait in a queue instead, shared by all
+    /// clones of this client, so their requests are put on the wire in the order the
+    /// calls were made. The queue is released once the request is enqueued, so later
+    /// requests don't wait for earlier responses, and calls for different endpoints
+    /// don't wait for each other at all.
+    ///
+    /// This only orders the requests on the wire. For the device to also PROCESS them
+    /// in order, the endpoint must be handled serially, e.g. by an `async` or
+    /// `blocking` handler, but not by a `spawn` handler, see
+    /// [`define_dispatch!`][crate::define_dispatch].
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn ordered_call<E: Endpoint>(
+        &self,
+        t: &E::Request,
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let queue = self
+            .ctx
+            .ordered
+            .lock()
+            .unwrap()
+            .entry(E::REQ_KEY)
+            .or_default()
+            .clone();
+        // The tokio mutex is fair, so waiting calls are served first come, first served
+        let guard = queue.lock().await;
+        let (_, resp) = self.reserve::<E>().await?;
+        let resp = self.send_reserved(resp, t).await?;
+        drop(guard);
+        resp.recv().await
+    }
+
+    /// Attach `token` to all frames sent by this client from now on, or stop attaching
+    /// a token with `None`
+    ///
+    /// The device checks the token before handling requests to endpoints that require
+    /// one, and rejects them with `WireError::Unauthorized` otherwise, see the "Access
+    /// control" section of [`define_dispatch!`][crate::define_dispatch]. This applies to
+    /// all clones of this client.
+    ///
+    /// Devices that don't support tokens can't decode frames carrying one, so only set
+    /// a token when talking to a device that does. Frames taken from a [WireContext]
+    /// are not affected, see [RpcFrame::to_bytes_with_token()].
+    pub fn set_auth_token(&self, token: Option<AuthToken>) {
+        *self.ctx.token.write().unwrap() = token;
+    }
+
+    /// Replace the [SeqNoSource] used for requests made by this client
+    ///
+    /// This applies to all clones of this client. Sequence numbers explicitly provided
+    /// by the caller, e.g. with [Self::send_resp_raw()] or [Self::publish()], are not
+    /// affected.
+    pub fn set_seq_no_source(&self, source: impl SeqNoSource) {
+        *self.ctx.seq.write().unwrap() = Box::new(source);
+    }
+
+    /// Register interest in a response, using the next sequence number that is not
+    /// already in use by another pending request
+    async fn register_next(
+        &self,
+        kkind: VarKeyKind,
+        req_key: Key,
+        resp_key: Key,
+    ) -> Result<(VarSeq, PendingResponse<'_, WireErr>), HostErr<WireErr>> {
+        for _ in 0..SEQ_NO_ATTEMPTS {
+            let mut seq_no = VarSeq::Seq4(self.ctx.seq.read().unwrap().next_seq_no());
+            seq_no.resize(self.seq_kind);
+
+            match PendingResponse::register(self, kkind, seq_no, VarKey::Key8(req_key), resp_key)
+                .await
+            {
+                Err(HostErr::SeqNoInUse) => continue,
+                res => return res.map(|pending| (seq_no, pending)),
+            }
+        }
+        Err(HostErr::SeqNoInUse)
+    }
+
+    /// Enqueue a request whose response was registered on the connection `conn_gen`
+    ///
+    /// If that connection has been lost since, e.g. because the response was
+    /// registered while reconnecting, the request is NOT sent, and the same error is
+    /// returned that awaiting the response would return. The connection state is
+    /// checked while holding its lock, so the request is either enqueued before the
+    /// I/O worker notices a lost connection, and discarded along with the rest of the
+    /// queue, or not at all. This way, the device never handles a request whose caller
+    /// was told that it failed.
+    async fn send_request(&self, conn_gen: u32, frame: RpcFrame) -> Result<(), HostErr<WireErr>> {
+        let permit = self.out.reserve().await.map_err(|_| HostErr::Closed)?;
+        let state = self.ctx.conn.borrow();
+        if *state != ConnectionState::Connected
+            || self.ctx.conn_gen.load(Ordering::Acquire) != conn_gen
+        {
+            drop(state);
+            if self.ctx.draining.load(Ordering::Acquire) {
+                return Err(HostErr::Shutdown);
+            }
+            return Err(HostErr::Disconnected);
+        }
+        permit.send(frame);
+        Ok(())
+    }
+
+    /// Perform an endpoint request/response,but without handling the
+    /// Ser/De automatically
+    pub async fn send_resp_raw(
+        &self,
+        mut rqst: RpcFrame,
+        resp_key: Key,
+    ) -> Result<RpcFrame, HostErr<WireErr>> {
+        let span = CallSpan::raw(rqst.header.key);
+        span.run(async {
+            let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
+            rqst.header.key.shrink_to(kkind);
+            rqst.header.seq_no.resize(self.seq_kind);
+            span.seq_no(rqst.header.seq_no);
+
+            // Make sure we are registered for the response BEFORE sending, otherwise
+            // a fast reply could arrive before anyone is waiting for it
+            let pending = PendingResponse::register(
+                self,
+                kkind,
+                rqst.header.seq_no,
+                rqst.header.key,
+                resp_key,
+            )
+            .await?;
+            self.send_request(pending.conn_gen, rqst).await?;
+            span.sent();
+            let frame = pending.recv().await?;
+            span.received();
+            Ok(frame)
+        })
+        .await
+    }
+
+    /// Send a [Request][Endpoint::Request] to an endpoint that does not reply
+    ///
+    /// This is used with endpoints handled by a `notify` handler on the server, which
+    /// must have a [Response][Endpoint::Response] of `()`. Unlike [Self::send_resp], this
+    /// does not wait for (or register for) a response, and returns once the request
+    /// has been handed to the I/O worker. There is no feedback if the server received
+    /// our request. If the I/O worker is closed, an error is returned. With a
+    /// `notify_confirmed` handler, the acknowledgement sent by the server is ignored,
+    /// see [Self::notify_confirmed] to wait for it instead.
+    pub async fn notify<E>(&self, req: &E::Request) -> Result<(), IoClosed>
+    where
+        E: Endpoint<Response = ()>,
+        E::Request: Serialize,
+    {
+        let smsg = postcard::to_stdvec(req).expect("alloc should never fail");
+        let seq_no = VarSeq::Seq4(self.ctx.seq.read().unwrap().next_seq_no());
+        let frame = RpcFrame {
+            header: VarHeader {
+                key: VarKey::Key8(E::REQ_KEY),
+                seq_no,
+            },
+            body: smsg,
+        };
+        self.publish_raw(frame).await
     }
 
-    /// Perform an endpoint request/response,but without handling the
-    /// Ser/De automatically
-    pub async fn send_resp_raw(
-        &self,
-        mut rqst: RpcFrame,
-        resp_key: Key,
-    ) -> Result<RpcFrame, HostErr<WireErr>> {
-        let cancel_fut = self.stopper.wait_stopped();
+    /// Send a [Request][Endpoint::Request] to an endpoint that does not reply, and
+    /// await its acknowledgement
+    ///
+    /// This is used with endpoints handled by a `notify_confirmed` handler on the
+    /// server, which acknowledges each request once the handler has returned, with
+    /// [`Sender::ack()`][crate::server::Sender::ack]. Returns once the request was
+    /// acknowledged, or with the error sent by the server instead, e.g. if the request
+    /// could not be deserialized. A `notify` handler never acknowledges requests.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn notify_confirmed<E>(&self, req: &E::Request) -> Result<(), HostErr<WireErr>>
+    where
+        E: Endpoint<Response = ()>,
+        E::Request: Serialize,
+    {
         let kkind: VarKeyKind = *self.ctx.kkind.read().unwrap();
-        rqst.header.key.shrink_to(kkind);
-        rqst.header.seq_no.resize(self.seq_kind);
-        let mut resp_key = VarKey::Key8(resp_key);
-        let mut err_key = VarKey::Key8(self.err_key);
-        resp_key.shrink_to(kkind);
-        err_key.shrink_to(kkind);
-
-        // TODO: Do I need something like a .subscribe method to ensure this is enqueued?
-        let ok_resp = self.ctx.map.wait(VarHeader {
-            seq_no: rqst.header.seq_no,
-            key: resp_key,
-        });
-        let err_resp = self.ctx.map.wait(VarHeader {
-            seq_no: rqst.header.seq_no,
-            key: err_key,
-        });
-        self.out.send(rqst).await.map_err(|_| HostErr::Closed)?;
-
-        select! {
-            _c = cancel_fut => Err(HostErr::Closed),
-            o = ok_resp => {
-                let (hdr, resp) = o?;
-                if hdr.key.kind() != kkind {
-                    *self.ctx.kkind.write().unwrap() = hdr.key.kind();
-                }
-                Ok(RpcFrame { header: hdr, body: resp })
-            },
-            e = err_resp => {
-                let (hdr, resp) = e?;
-                if hdr.key.kind() != kkind {
-                    *self.ctx.kkind.write().unwrap() = hdr.key.kind();
-                }
-                let r = postcard::from_bytes::<WireErr>(&resp)?;
-                Err(HostErr::Wire(r))
-            },
-        }
+        let (seq_no, pending) = self.register_next(kkind, E::REQ_KEY, ACK_KEY).await?;
+        let mut key = VarKey::Key8(E::REQ_KEY);
+        key.shrink_to(kkind);
+        let frame = RpcFrame {
+            header: VarHeader { key, seq_no },
+            body: postcard::to_stdvec(req).expect("alloc should never fail"),
+        };
+        self.send_request(pending.conn_gen, frame).await?;
+        pending.recv().await?;
+        Ok(())
     }
 
     /// Publish a [Topic] [Message][Topic::Message].
@@ -480,6 +1488,59 @@ where
         })
     }
 
+    /// Begin listening to a [Topic], receiving a [TopicStream] that implements
+    /// [Stream] for the [Message][Topic::Message]s.
+    ///
+    /// Like `subscribe_multi`, multiple subscribers to the same topic each receive a
+    /// copy of every message. Messages that arrive while there are no subscribers are
+    /// dropped. Dropping the stream unregisters the subscription once no other
+    /// subscribers to the topic remain.
+    ///
+    /// If a subscriber falls more than `depth` messages behind, the oldest messages
+    /// are skipped.
+    ///
+    /// Returns an Error if the I/O worker is closed.
+    pub async fn subscribe_stream<T: Topic>(
+        &self,
+        depth: usize,
+    ) -> Result<TopicStream<T::Message>, IoClosed>
+    where
+        T::Message: DeserializeOwned,
+    {
+        let sub = self.subscribe_multi::<T>(depth).await?;
+        Ok(TopicStream {
+            stream: Some(BroadcastStream::new(sub.rx)),
+            key: T::TOPIC_KEY,
+            subscriptions: self.subscriptions.clone(),
+            _pd: PhantomData,
+        })
+    }
+
+    /// Begin listening to all topics of `Ev`, receiving an [EventStream] that
+    /// implements [Stream] for the messages of every topic, decoded into `Ev`.
+    ///
+    /// `Ev` is usually generated with the
+    /// [`define_topic_events!()`][crate::define_topic_events] macro. Each topic is
+    /// subscribed to like with `subscribe_stream`, with the given `depth`.
+    ///
+    /// Returns an Error if the I/O worker is closed.
+    pub async fn subscribe_events<Ev: TopicEvents>(
+        &self,
+        depth: usize,
+    ) -> Result<EventStream<Ev>, IoClosed> {
+        let mut events = EventStream {
+            streams: Vec::with_capacity(Ev::KEYS.len()),
+            next: 0,
+            subscriptions: self.subscriptions.clone(),
+            _pd: PhantomData,
+        };
+        for key in Ev::KEYS {
+            let sub = self.subscribe_multi_raw(*key, depth).await?;
+            events.streams.push((*key, BroadcastStream::new(sub.rx)));
+        }
+        Ok(events)
+    }
+
     /// Begin listening to a [Topic], receiving a [Subscription] that will give a
     /// stream of [Message][Topic::Message]s.
     ///
@@ -624,6 +1685,60 @@ where
     pub async fn wait_closed(&self) {
         self.stopper.wait_stopped().await;
     }
+
+    /// Gracefully close the connection to the client
+    ///
+    /// Unlike [Self::close()], this first stops accepting new requests, which
+    /// fail with [HostErr::Shutdown], and waits up to `deadline` for the requests
+    /// that are already in-flight to resolve. Requests still pending after the
+    /// deadline resolve with [HostErr::Shutdown].
+    ///
+    /// The connection is then closed, and this completes once the I/O worker tasks
+    /// have stopped. This applies to all HostClients sharing the connection.
+    pub async fn shutdown(&self, deadline: Duration) {
+        self.ctx.draining.store(true, Ordering::Release);
+
+        let mut inflight = self.ctx.inflight.subscribe();
+        let _ = tokio::time::timeout(deadline, inflight.wait_for(|n| *n == 0)).await;
+
+        self.close();
+
+        let mut workers = self.ctx.workers.subscribe();
+        let _ = workers.wait_for(|n| *n == 0).await;
+    }
+
+    /// The error for a request that was interrupted by the client being closed
+    fn closed_err(&self) -> HostErr<WireErr> {
+        if self.ctx.draining.load(Ordering::Acquire) {
+            HostErr::Shutdown
+        } else {
+            HostErr::Closed
+        }
+    }
+
+    /// The current state of the connection to the device
+    ///
+    /// A client that has been closed is always [`ConnectionState::Disconnected`].
+    pub fn state(&self) -> ConnectionState {
+        if self.stopper.is_stopped() {
+            return ConnectionState::Disconnected;
+        }
+        *self.ctx.conn.borrow()
+    }
+
+    /// Wait until the device is connected
+    ///
+    /// Completes immediately if the device is already connected. Returns an Error
+    /// if the client is closed before the device is connected.
+    pub async fn wait_connected(&self) -> Result<(), IoClosed> {
+        let mut conn = self.ctx.conn.subscribe();
+        let cancel_fut = self.stopper.wait_stopped();
+        let operate_fut = conn.wait_for(|s| *s == ConnectionState::Connected);
+        select! {
+            _ = cancel_fut => Err(IoClosed),
+            res = operate_fut => res.map(drop).map_err(|_| IoClosed),
+        }
+    }
 }
 
 /// Like Subscription, but receives Raw frames that are not
@@ -721,6 +1836,172 @@ where
     }
 }
 
+/// A watchdog for the heartbeats of a device
+///
+/// Created by [HostClient::watch_heartbeat]
+pub struct HeartbeatWatch<WireErr> {
+    sub: MultiSubscription<Heartbeat>,
+    grace: Duration,
+    _pd: PhantomData<fn() -> WireErr>,
+}
+
+impl<WireErr> HeartbeatWatch<WireErr> {
+    /// Await the next heartbeat
+    ///
+    /// Fails with [HostErr::HeartbeatLost] if none arrives within the grace period,
+    /// and with [HostErr::Closed] if the I/O worker was closed. The watch can still be
+    /// used after a lost heartbeat, e.g. to notice when the device recovers.
+    pub async fn recv(&mut self) -> Result<Heartbeat, HostErr<WireErr>> {
+        let deadline = tokio::time::Instant::now() + self.grace;
+        loop {
+            match tokio::time::timeout_at(deadline, self.sub.recv()).await {
+                Err(_) => return Err(HostErr::HeartbeatLost),
+                Ok(Ok(hb)) => return Ok(hb),
+                Ok(Err(MultiSubRxError::Lagged(_))) => continue,
+                Ok(Err(MultiSubRxError::IoClosed)) => return Err(HostErr::Closed),
+            }
+        }
+    }
+}
+
+/// A [Stream] of messages for the given topic
+///
+/// Created by [HostClient::subscribe_stream]
+pub struct TopicStream<M> {
+    // Only `None` while dropping
+    stream: Option<BroadcastStream<RpcFrame>>,
+    key: Key,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    _pd: PhantomData<fn() -> M>,
+}
+
+impl<M> Stream for TopicStream<M>
+where
+    M: DeserializeOwned,
+{
+    type Item = M;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let Some(stream) = this.stream.as_mut() else {
+            return Poll::Ready(None);
+        };
+        loop {
+            let frame = match Pin::new(&mut *stream).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(Ok(frame))) => frame,
+                Poll::Ready(Some(Err(BroadcastStreamRecvError::Lagged(n)))) => {
+                    tracing::warn!("Topic stream lagged, {n} messages were lost");
+                    continue;
+                }
+            };
+            if let Ok(m) = postcard::from_bytes(&frame.body) {
+                return Poll::Ready(Some(m));
+            }
+        }
+    }
+}
+
+impl<M> Drop for TopicStream<M> {
+    fn drop(&mut self) {
+        // Drop our receiver first, so it is no longer counted
+        drop(self.stream.take());
+
+        // If the lock is busy, the I/O worker will remove the subscription when
+        // the next message for this topic arrives.
+        if let Ok(mut guard) = self.subscriptions.try_lock() {
+            guard
+                .broadcast_list
+                .retain(|(k, tx)| *k != self.key || tx.receiver_count() != 0);
+        }
+    }
+}
+
+/// The messages of several [Topic]s, as a single type
+///
+/// Usually implemented with the [`define_topic_events!()`][crate::define_topic_events]
+/// macro, see [HostClient::subscribe_events].
+pub trait TopicEvents: Sized {
+    /// The keys of all topics
+    const KEYS: &'static [Key];
+
+    /// Decode the `body` of a frame received for `key`
+    fn decode(key: Key, body: &[u8]) -> Self;
+}
+
+/// A [Stream] of the messages of several topics
+///
+/// Created by [HostClient::subscribe_events]
+pub struct EventStream<Ev> {
+    streams: Vec<(Key, BroadcastStream<RpcFrame>)>,
+    // The stream to poll first, so that a busy topic can't starve the others
+    next: usize,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    _pd: PhantomData<fn() -> Ev>,
+}
+
+impl<Ev: TopicEvents> EventStream<Ev> {
+    /// Await the next message of any of the topics
+    ///
+    /// Returns [None] if the I/O worker was closed
+    pub async fn recv(&mut self) -> Option<Ev> {
+        core::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<Ev: TopicEvents> Stream for EventStream<Ev> {
+    type Item = Ev;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let len = this.streams.len();
+        let mut open = false;
+        for i in 0..len {
+            let idx = (this.next + i) % len;
+            let (key, stream) = &mut this.streams[idx];
+            loop {
+                match Pin::new(&mut *stream).poll_next(cx) {
+                    Poll::Pending => {
+                        open = true;
+                        break;
+                    }
+                    Poll::Ready(None) => break,
+                    Poll::Ready(Some(Err(BroadcastStreamRecvError::Lagged(n)))) => {
+                        tracing::warn!("Event stream lagged, {n} messages were lost");
+                    }
+                    Poll::Ready(Some(Ok(frame))) => {
+                        let event = Ev::decode(*key, &frame.body);
+                        this.next = (idx + 1) % len;
+                        return Poll::Ready(Some(event));
+                    }
+                }
+            }
+        }
+        if open {
+            Poll::Pending
+        } else {
+            Poll::Ready(None)
+        }
+    }
+}
+
+impl<Ev> Drop for EventStream<Ev> {
+    fn drop(&mut self) {
+        let keys: Vec<Key> = self.streams.iter().map(|(k, _)| *k).collect();
+        // Drop our receivers first, so they are no longer counted
+        self.streams.clear();
+
+        // If the lock is busy, the I/O worker will remove the subscriptions when
+        // the next message for each topic arrives.
+        if let Ok(mut guard) = self.subscriptions.try_lock() {
+            guard
+                .broadcast_list
+                .retain(|(k, tx)| !keys.contains(k) || tx.receiver_count() != 0);
+        }
+    }
+}
+
 // Manual Clone impl because WireErr may not impl Clone
 impl<WireErr> Clone for HostClient<WireErr> {
     fn clone(&self) -> Self {
@@ -762,13 +2043,409 @@ impl RpcFrame {
         out.extend_from_slice(&self.body);
         out
     }
+
+    /// Serialize the `RpcFrame` into a Vec of bytes, with the given token in the header
+    pub fn to_bytes_with_token(&self, token: &AuthToken) -> Vec<u8> {
+        let mut out = self.header.write_to_vec_with_token(token);
+        out.extend_from_slice(&self.body);
+        out
+    }
+}
+
+/// The results of `n` requests that failed together with `err`
+///
+/// The first result gets `err` itself, the others the same kind of error, or
+/// [HostErr::Closed] if it carries any data.
+fn fail_all<T, WireErr>(n: usize, err: HostErr<WireErr>) -> Vec<Result<T, HostErr<WireErr>>> {
+    let mut out = Vec::with_capacity(n);
+    for _ in 1..n {
+        out.push(Err(match &err {
+            HostErr::Disconnected => HostErr::Disconnected,
+            HostErr::SeqNoInUse => HostErr::SeqNoInUse,
+            HostErr::Shutdown => HostErr::Shutdown,
+            HostErr::Reset => HostErr::Reset,
+            _ => HostErr::Closed,
+        }));
+    }
+    if n != 0 {
+        out.insert(0, Err(err));
+    }
+    out
+}
+
+/// A response that was registered with [HostClient::reserve()]
+///
+/// Use [ReservedResponse::recv()] to await the response, after sending the
+/// request with [HostClient::send_reserved()].
+pub struct ReservedResponse<'a, E, WireErr> {
+    inner: PendingResponse<'a, WireErr>,
+    _pd: PhantomData<fn() -> E>,
+}
+
+impl<E, WireErr> ReservedResponse<'_, E, WireErr>
+where
+    E: Endpoint,
+    E::Response: DeserializeOwned,
+    WireErr: DeserializeOwned,
+{
+    /// The sequence number reserved for this response
+    pub fn seq_no(&self) -> VarSeq {
+        self.inner.seq_no
+    }
+
+    /// Await the response (or WireErr) for the reserved request.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn recv(self) -> Result<E::Response, HostErr<WireErr>> {
+        let frame = self.inner.recv().await?;
+        let r = postcard::from_bytes::<E::Response>(&frame.body)?;
+        Ok(r)
+    }
+}
+
+/// The acknowledgement of a request sent with [HostClient::send_resp_with_ack()]
+pub struct PendingAck<'a, WireErr> {
+    wait: ResponseWait<'a>,
+    _pd: PhantomData<fn() -> WireErr>,
+}
+
+impl<WireErr> PendingAck<'_, WireErr> {
+    /// Await the acknowledgement of the request
+    ///
+    /// This function will wait potentially forever, e.g. if the request is rejected.
+    /// Consider using with a timeout.
+    pub async fn recv(self) -> Result<(), HostErr<WireErr>> {
+        self.wait.await?;
+        Ok(())
+    }
+}
+
+type ResponseWait<'a> = Pin<Box<Wait<'a, VarHeader, (VarHeader, Vec<u8>)>>>;
+
+/// A response (or error) that has been registered in the [HostContext]'s map
+struct PendingResponse<'a, WireErr> {
+    client: &'a HostClient<WireErr>,
+    kkind: VarKeyKind,
+    seq_no: VarSeq,
+    req_key: VarKey,
+    expected_key: Key,
+    ok_resp: ResponseWait<'a>,
+    err_resp: ResponseWait<'a>,
+    keyed_err_resp: ResponseWait<'a>,
+    /// The response key used by the device, if known to differ from ours
+    mismatch: Option<(Key, ResponseWait<'a>)>,
+    conn: watch::Receiver<ConnectionState>,
+    /// The connection the response was registered on, see [HostClient::send_request()]
+    conn_gen: u32,
+    /// `None` for the request that resets the device
+    epoch: Option<watch::Receiver<u32>>,
+    _inflight: InFlight,
+}
+
+impl<'a, WireErr> PendingResponse<'a, WireErr>
+where
+    WireErr: DeserializeOwned,
+{
+    /// Register interest in the response with the given sequence number and key,
+    /// as well as the error responses for the same sequence number.
+    ///
+    /// `req_key` is the key of the request, used to check that a
+    /// [KeyedError][crate::standard_icd::KeyedError] belongs to this request.
+    ///
+    /// Completes once all waiters are enqueued in the map.
+    async fn register(
+        client: &'a HostClient<WireErr>,
+        kkind: VarKeyKind,
+        seq_no: VarSeq,
+        req_key: VarKey,
+        resp_key: Key,
+    ) -> Result<Self, HostErr<WireErr>> {
+        if client.ctx.draining.load(Ordering::Acquire) {
+            return Err(HostErr::Shutdown);
+        }
+        // A late response to a request from before a reset could be mistaken for ours
+        if client.ctx.stale.lock().unwrap().contains(&seq_no) {
+            return Err(HostErr::SeqNoInUse);
+        }
+        // Likewise for the frames of an open duplex stream
+        if client
+            .ctx
+            .duplex
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(s, _)| *s == seq_no)
+        {
+            return Err(HostErr::SeqNoInUse);
+        }
+        let _inflight = InFlight::new(&client.ctx, seq_no);
+
+        // If the device is known to reply with a different key, wait for that too
+        let mismatch = match req_key {
+            VarKey::Key8(req_key) => client.ctx.resp_keys.read().unwrap().get(&req_key).copied(),
+            _ => None,
+        };
+        let mismatch = match mismatch {
+            Some(actual) if actual != resp_key => {
+                let mut key = VarKey::Key8(actual);
+                key.shrink_to(kkind);
+                let mut wait = Box::pin(client.ctx.map.wait(VarHeader { seq_no, key }));
+                wait.as_mut().subscribe().await?;
+                Some((actual, wait))
+            }
+            _ => None,
+        };
+        let expected_key = resp_key;
+
+        let mut resp_key = VarKey::Key8(resp_key);
+        let mut err_key = VarKey::Key8(client.err_key);
+        resp_key.shrink_to(kkind);
+        err_key.shrink_to(kkind);
+
+        let mut ok_resp = Box::pin(client.ctx.map.wait(VarHeader {
+            seq_no,
+            key: resp_key,
+        }));
+        let mut err_resp = Box::pin(client.ctx.map.wait(VarHeader {
+            seq_no,
+            key: err_key,
+        }));
+        ok_resp.as_mut().subscribe().await?;
+        err_resp.as_mut().subscribe().await?;
+        let keyed_err_resp = Self::wait_keyed_err(client, kkind, seq_no).await?;
+
+        // Any change in connection state after this point means the connection
+        // this request was sent on has been lost
+        let conn = client.ctx.conn.subscribe();
+        let conn_gen = {
+            let _state = conn.borrow();
+            client.ctx.conn_gen.load(Ordering::Acquire)
+        };
+        // Likewise, any new epoch means the device has been reset
+        let epoch = Some(client.ctx.epoch.subscribe());
+
+        Ok(Self {
+            client,
+            kkind,
+            seq_no,
+            req_key,
+            expected_key,
+            ok_resp,
+            err_resp,
+            keyed_err_resp,
+            mismatch,
+            conn,
+            conn_gen,
+            epoch,
+            _inflight,
+        })
+    }
+
+    /// Register interest in a [KeyedError][crate::standard_icd::KeyedError] with
+    /// the given sequence number
+    async fn wait_keyed_err(
+        client: &'a HostClient<WireErr>,
+        kkind: VarKeyKind,
+        seq_no: VarSeq,
+    ) -> Result<ResponseWait<'a>, HostErr<WireErr>> {
+        let mut key = VarKey::Key8(KEYED_ERROR_KEY);
+        key.shrink_to(kkind);
+        let mut wait = Box::pin(client.ctx.map.wait(VarHeader { seq_no, key }));
+        wait.as_mut().subscribe().await?;
+        Ok(wait)
+    }
+
+    /// Decode the body of an error reply to the request with `req_key`
+    ///
+    /// Error replies are routed by their key, so this is never confused with the
+    /// response. If the client uses the standard [WireError], an unknown key is
+    /// reported as [HostErr::UnknownKey].
+    fn decode_err(
+        client: &HostClient<WireErr>,
+        body: &[u8],
+        req_key: VarKey,
+    ) -> Result<HostErr<WireErr>, postcard::Error> {
+        if client.err_key == ERROR_KEY {
+            if let Ok(WireError::UnknownKey) = postcard::from_bytes::<WireError>(body) {
+                return Ok(HostErr::UnknownKey(req_key));
+            }
+        }
+        postcard::from_bytes::<WireErr>(body).map(HostErr::Wire)
+    }
+
+    /// Await the response (or WireErr)
+    async fn recv(self) -> Result<RpcFrame, HostErr<WireErr>> {
+        let Self {
+            client,
+            kkind,
+            seq_no,
+            req_key,
+            expected_key,
+            mut ok_resp,
+            mut err_resp,
+            mut keyed_err_resp,
+            mismatch,
+            mut conn,
+            conn_gen: _,
+            epoch,
+            _inflight,
+        } = self;
+
+        let disconnected = async move {
+            let changed = conn.has_changed().unwrap_or(true);
+            if !changed && *conn.borrow() == ConnectionState::Connected {
+                let _ = conn.changed().await;
+            }
+        };
+        tokio::pin!(disconnected);
+
+        let reset = async move {
+            let Some(mut epoch) = epoch else {
+                return core::future::pending().await;
+            };
+            if epoch.changed().await.is_err() {
+                core::future::pending::<()>().await;
+            }
+        };
+        tokio::pin!(reset);
+
+        let mismatch = async move {
+            let Some((actual_key, wait)) = mismatch else {
+                return core::future::pending().await;
+            };
+            (actual_key, wait.await)
+        };
+        tokio::pin!(mismatch);
+
+        loop {
+            select! {
+                _c = client.stopper.wait_stopped() => return Err(client.closed_err()),
+                _d = &mut disconnected => {
+                    if client.ctx.draining.load(Ordering::Acquire) {
+                        return Err(HostErr::Shutdown);
+                    }
+                    return Err(HostErr::Disconnected);
+                },
+                _r = &mut reset => return Err(HostErr::Reset),
+                o = &mut ok_resp => {
+                    let (hdr, resp) = o?;
+                    if hdr.key.kind() != kkind {
+                        *client.ctx.kkind.write().unwrap() = hdr.key.kind();
+                    }
+                    return Ok(RpcFrame { header: hdr, body: resp });
+                },
+                m = &mut mismatch => {
+                    let (actual_key, m) = m;
+                    let (hdr, resp) = m?;
+                    if !client.ctx.lenient.read().unwrap().contains(&actual_key) {
+                        return Err(HostErr::SchemaMismatch { expected_key, actual_key });
+                    }
+                    if hdr.key.kind() != kkind {
+                        *client.ctx.kkind.write().unwrap() = hdr.key.kind();
+                    }
+                    return Ok(RpcFrame { header: hdr, body: resp });
+                },
+                e = &mut err_resp => {
+                    let (hdr, resp) = e?;
+                    if hdr.key.kind() != kkind {
+                        *client.ctx.kkind.write().unwrap() = hdr.key.kind();
+                    }
+                    return Err(Self::decode_err(client, &resp, req_key)?);
+                },
+                e = &mut keyed_err_resp => {
+                    let (hdr, resp) = e?;
+                    // This has the same layout as a `KeyedError`, but allows for
+                    // a user provided `WireErr` type
+                    let (key, rest) = postcard::take_from_bytes::<RequestKey>(&resp)?;
+                    if VarKey::from(key) == req_key {
+                        if hdr.key.kind() != kkind {
+                            *client.ctx.kkind.write().unwrap() = hdr.key.kind();
+                        }
+                        return Err(Self::decode_err(client, rest, req_key)?);
+                    }
+                    // An error for a different request that used the same sequence
+                    // number, keep waiting for ours
+                    keyed_err_resp = Self::wait_keyed_err(client, kkind, seq_no).await?;
+                },
+            }
+        }
+    }
 }
 
 /// Shared context between [HostClient] and the I/O worker task
 pub struct HostContext {
     kkind: RwLock<VarKeyKind>,
     map: WaitMap<VarHeader, (VarHeader, Vec<u8>)>,
-    seq: AtomicU32,
+    seq: RwLock<Box<dyn SeqNoSource>>,
+    conn: watch::Sender<ConnectionState>,
+    /// Incremented whenever the device is (re)connected, only changed while holding
+    /// the lock of `conn`
+    conn_gen: AtomicU32,
+    /// Set by [HostClient::shutdown()], no new requests are accepted
+    draining: AtomicBool,
+    /// The number of pending responses
+    inflight: watch::Sender<usize>,
+    /// The number of running I/O worker tasks
+    workers: watch::Sender<usize>,
+    /// The epoch of the device, as of the last reset, see [HostClient::reset()]
+    epoch: watch::Sender<u32>,
+    /// The sequence numbers of the pending responses
+    pending: std::sync::Mutex<Vec<VarSeq>>,
+    /// The sequence numbers of responses that were pending when the device was reset,
+    /// and that are discarded when received
+    stale: std::sync::Mutex<Vec<VarSeq>>,
+    /// The response key of each request key of the device, as far as known
+    resp_keys: RwLock<HashMap<Key, Key>>,
+    /// The response keys of the device that are accepted for our response types,
+    /// see [HostClient::allow_lenient()]
+    lenient: RwLock<HashSet<Key>>,
+    /// The queue of each request key used with [HostClient::ordered_call()]
+    ordered: std::sync::Mutex<HashMap<Key, Arc<Mutex<()>>>>,
+    /// The token attached to outgoing frames, see [HostClient::set_auth_token()]
+    token: RwLock<Option<AuthToken>>,
+    /// The sequence number of each open duplex stream, and where to send its frames,
+    /// see [HostClient::duplex()]
+    duplex: std::sync::Mutex<Vec<(VarSeq, mpsc::Sender<RpcFrame>)>>,
+    /// The sequence number of each request waiting for progress updates, and where to
+    /// send them, see [HostClient::send_resp_with_progress()]
+    progress: std::sync::Mutex<Vec<(VarSeq, mpsc::Sender<u8>)>>,
+    /// The handlers of incoming topic messages, see [HostClient::topic_router()]
+    topic_routes: std::sync::Mutex<topic_router::TopicRoutes>,
+}
+
+/// Does `theirs` start with all fields of `ours`, followed by more fields?
+fn extends(theirs: &OwnedNamedType, ours: &OwnedNamedType) -> bool {
+    match (&theirs.ty, &ours.ty) {
+        (OwnedDataModelType::Struct(theirs), OwnedDataModelType::Struct(ours)) => {
+            theirs.len() > ours.len() && theirs[..ours.len()] == ours[..]
+        }
+        (OwnedDataModelType::TupleStruct(theirs), OwnedDataModelType::TupleStruct(ours)) => {
+            theirs.len() > ours.len() && theirs[..ours.len()] == ours[..]
+        }
+        _ => false,
+    }
+}
+
+/// Counts a pending response in the [HostContext], until dropped
+struct InFlight(Arc<HostContext>, VarSeq);
+
+impl InFlight {
+    fn new(ctx: &Arc<HostContext>, seq_no: VarSeq) -> Self {
+        ctx.inflight.send_modify(|n| *n += 1);
+        ctx.pending.lock().unwrap().push(seq_no);
+        Self(ctx.clone(), seq_no)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut pending = self.0.pending.lock().unwrap();
+        if let Some(i) = pending.iter().position(|s| *s == self.1) {
+            pending.swap_remove(i);
+        }
+        drop(pending);
+        self.0.inflight.send_modify(|n| *n -= 1);
+    }
 }
 
 /// The I/O worker has closed.
@@ -787,9 +2464,21 @@ impl HostContext {
     /// Like `HostContext::process` but tells you if we processed the message or
     /// nobody wanted it
     pub fn process_did_wake(&self, frame: RpcFrame) -> Result<bool, ProcessError> {
+        if !self.check_epoch(&frame) {
+            return Ok(false);
+        }
+        let Some(frame) = self.route_duplex(frame) else {
+            return Ok(true);
+        };
+        let Some(frame) = self.route_progress(frame) else {
+            return Ok(true);
+        };
         match self.map.wake(&frame.header, (frame.header, frame.body)) {
             WakeOutcome::Woke => Ok(true),
-            WakeOutcome::NoMatch(_) => Ok(false),
+            WakeOutcome::NoMatch((header, body)) => {
+                self.route_unhandled(RpcFrame { header, body });
+                Ok(false)
+            }
             WakeOutcome::Closed(_) => Err(ProcessError::Closed),
         }
     }
@@ -798,11 +2487,35 @@ impl HostContext {
     ///
     /// Returns an Err if the map was closed.
     pub fn process(&self, frame: RpcFrame) -> Result<(), ProcessError> {
-        if let WakeOutcome::Closed(_) = self.map.wake(&frame.header, (frame.header, frame.body)) {
-            Err(ProcessError::Closed)
-        } else {
-            Ok(())
+        self.process_did_wake(frame).map(drop)
+    }
+
+    /// Track the resets of the device, see [HostClient::reset()]
+    ///
+    /// Returns `false` if the frame is a late response to a request from before the
+    /// last reset, which must be discarded.
+    fn check_epoch(&self, frame: &RpcFrame) -> bool {
+        let seq_no = frame.header.seq_no;
+        {
+            let mut stale = self.stale.lock().unwrap();
+            if let Some(i) = stale.iter().position(|s| *s == seq_no) {
+                stale.swap_remove(i);
+                return false;
+            }
+        }
+
+        if frame.header.key == VarKey::Key8(ResetEndpoint::RESP_KEY) {
+            if let Ok(epoch) = postcard::from_bytes::<u32>(&frame.body) {
+                // All other requests that are still pending were sent before the reset,
+                // this is done in order with receiving, so no late response slips by
+                let pending = self.pending.lock().unwrap();
+                let stale = pending.iter().copied().filter(|s| *s != seq_no).collect();
+                *self.stale.lock().unwrap() = stale;
+                drop(pending);
+                self.epoch.send_replace(epoch);
+            }
         }
+        true
     }
 }
 
@@ -0,0 +1,110 @@
+//! Receiving progress updates for long running requests, see
+//! [`Sender::progress()`][crate::server::Sender::progress]
+
+use postcard_schema::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{select, sync::mpsc};
+
+use crate::{
+    header::{VarKey, VarSeq},
+    standard_icd::PROGRESS_KEY,
+    Endpoint,
+};
+
+use super::{HostClient, HostContext, HostErr, RpcFrame};
+
+/// The number of progress updates kept while the caller is busy
+const PROGRESS_DEPTH: usize = 8;
+
+/// # Progress Updates
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Send a request to the [Endpoint] `E`, calling `on_progress` with each progress
+    /// update the device sends before the response, and return the response
+    ///
+    /// The device reports progress with
+    /// [`Sender::progress()`][crate::server::Sender::progress], as the percentage of
+    /// the operation that is done. Updates are registered before the request is sent,
+    /// so none are missed, unless `on_progress` falls behind the device. Other
+    /// requests, e.g. with [Self::send_resp()], ignore progress updates.
+    ///
+    /// This function will wait potentially forever. Consider using with a timeout.
+    pub async fn send_resp_with_progress<E: Endpoint>(
+        &self,
+        t: &E::Request,
+        mut on_progress: impl FnMut(u8),
+    ) -> Result<E::Response, HostErr<WireErr>>
+    where
+        E::Request: Serialize + Schema,
+        E::Response: DeserializeOwned + Schema,
+    {
+        let (seq_no, resp) = self.reserve::<E>().await?;
+        let (tx, mut rx) = mpsc::channel(PROGRESS_DEPTH);
+        let _route = ProgressRoute::new(&self.ctx, seq_no, tx);
+        let resp = self.send_reserved(resp, t).await?;
+
+        let resp = resp.recv();
+        tokio::pin!(resp);
+        loop {
+            select! {
+                biased;
+                Some(percent) = rx.recv() => on_progress(percent),
+                res = &mut resp => {
+                    // Updates sent before the response are delivered before it
+                    while let Ok(percent) = rx.try_recv() {
+                        on_progress(percent);
+                    }
+                    return res;
+                }
+            }
+        }
+    }
+}
+
+impl HostContext {
+    /// Hand the percentage of a progress update to the request with its sequence
+    /// number, if it waits for progress
+    ///
+    /// Returns the frame if it isn't a progress update.
+    pub(super) fn route_progress(&self, frame: RpcFrame) -> Option<RpcFrame> {
+        // Keys of different sizes compare equal if the shorter one matches
+        if VarKey::Key8(PROGRESS_KEY) != frame.header.key {
+            return Some(frame);
+        }
+        let Ok(percent) = postcard::from_bytes::<u8>(&frame.body) else {
+            tracing::warn!("Malformed progress update");
+            return None;
+        };
+        let progress = self.progress.lock().unwrap();
+        if let Some((_, tx)) = progress.iter().find(|(s, _)| *s == frame.header.seq_no) {
+            if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(percent) {
+                tracing::warn!("Progress updates lagged, an update was lost");
+            }
+        }
+        None
+    }
+}
+
+/// Routes the progress updates of a request, until dropped
+struct ProgressRoute<'a> {
+    ctx: &'a HostContext,
+    seq_no: VarSeq,
+}
+
+impl<'a> ProgressRoute<'a> {
+    fn new(ctx: &'a HostContext, seq_no: VarSeq, tx: mpsc::Sender<u8>) -> Self {
+        ctx.progress.lock().unwrap().push((seq_no, tx));
+        Self { ctx, seq_no }
+    }
+}
+
+impl Drop for ProgressRoute<'_> {
+    fn drop(&mut self) {
+        let mut progress = self.ctx.progress.lock().unwrap();
+        if let Some(i) = progress.iter().position(|(s, _)| *s == self.seq_no) {
+            progress.swap_remove(i);
+        }
+    }
+}
@@ -1,10 +1,10 @@
 //! Implementation of transport using nusb
 
-use std::future::Future;
+use std::{future::Future, time::Duration};
 
 use nusb::{
     transfer::{Queue, RequestBuffer, TransferError},
-    DeviceInfo, InterfaceInfo,
+    InterfaceInfo,
 };
 use postcard_schema::Schema;
 use serde::de::DeserializeOwned;
@@ -75,36 +75,17 @@ where
     ///     VarSeqKind::Seq1,
     /// ).unwrap();
     /// ```
-    pub fn try_new_raw_nusb<F: FnMut(&DeviceInfo) -> bool>(
+    pub fn try_new_raw_nusb<F: FnMut(&nusb::DeviceInfo) -> bool>(
         func: F,
         err_uri_path: &str,
         outgoing_depth: usize,
         seq_no_kind: VarSeqKind,
     ) -> Result<Self, String> {
-        let x = nusb::list_devices()
-            .map_err(|e| format!("Error listing devices: {e:?}"))?
-            .find(func)
-            .ok_or_else(|| String::from("Failed to find matching nusb device!"))?;
-        let interface_id = x
-            .interfaces()
-            .position(|i| i.class() == 0xFF)
-            .ok_or_else(|| String::from("Failed to find matching interface!!"))?;
-        let dev = x
-            .open()
-            .map_err(|e| format!("Failed opening device: {e:?}"))?;
-        let interface = dev
-            .claim_interface(interface_id as u8)
-            .map_err(|e| format!("Failed claiming interface: {e:?}"))?;
-
-        let boq = interface.bulk_out_queue(BULK_OUT_EP);
-        let biq = interface.bulk_in_queue(BULK_IN_EP);
+        let (tx, rx) = open_nusb(func, |i| i.class() == 0xFF)?;
 
         Ok(HostClient::new_with_wire(
-            NusbWireTx { boq },
-            NusbWireRx {
-                biq,
-                consecutive_errs: 0,
-            },
+            tx,
+            rx,
             NusbSpawn,
             seq_no_kind,
             err_uri_path,
@@ -151,7 +132,7 @@ where
     /// ).unwrap();
     /// ```
     pub fn try_new_raw_nusb_with_interface<
-        F1: FnMut(&DeviceInfo) -> bool,
+        F1: FnMut(&nusb::DeviceInfo) -> bool,
         F2: FnMut(&InterfaceInfo) -> bool,
     >(
         device_func: F1,
@@ -160,30 +141,11 @@ where
         outgoing_depth: usize,
         seq_no_kind: VarSeqKind,
     ) -> Result<Self, String> {
-        let x = nusb::list_devices()
-            .map_err(|e| format!("Error listing devices: {e:?}"))?
-            .find(device_func)
-            .ok_or_else(|| String::from("Failed to find matching nusb device!"))?;
-        let interface_id = x
-            .interfaces()
-            .position(interface_func)
-            .ok_or_else(|| String::from("Failed to find matching interface!!"))?;
-        let dev = x
-            .open()
-            .map_err(|e| format!("Failed opening device: {e:?}"))?;
-        let interface = dev
-            .claim_interface(interface_id as u8)
-            .map_err(|e| format!("Failed claiming interface: {e:?}"))?;
-
-        let boq = interface.bulk_out_queue(BULK_OUT_EP);
-        let biq = interface.bulk_in_queue(BULK_IN_EP);
+        let (tx, rx) = open_nusb(device_func, interface_func)?;
 
         Ok(HostClient::new_with_wire(
-            NusbWireTx { boq },
-            NusbWireRx {
-                biq,
-                consecutive_errs: 0,
-            },
+            tx,
+            rx,
             NusbSpawn,
             seq_no_kind,
             err_uri_path,
@@ -223,7 +185,7 @@ where
     ///     VarSeqKind::Seq1,
     /// );
     /// ```
-    pub fn new_raw_nusb<F: FnMut(&DeviceInfo) -> bool>(
+    pub fn new_raw_nusb<F: FnMut(&nusb::DeviceInfo) -> bool>(
         func: F,
         err_uri_path: &str,
         outgoing_depth: usize,
@@ -232,6 +194,234 @@ where
         Self::try_new_raw_nusb(func, err_uri_path, outgoing_depth, seq_no_kind)
             .expect("should have found nusb device")
     }
+
+    /// Create a new link using [`nusb`] for connectivity, which automatically
+    /// reconnects to the device
+    ///
+    /// The provided function will be used to find a matching device, for example by
+    /// VID, PID, and/or serial number. The device does not need to be present when
+    /// the client is created: the client will retry every `retry_delay` until a
+    /// matching device is found, and will do the same whenever the device is
+    /// disconnected.
+    ///
+    /// See [`Self::state()`] and [`Self::wait_connected()`] for observing the
+    /// connection state.
+    ///
+    /// This constructor is available when the `raw-nusb` feature is enabled.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use postcard_rpc::host_client::HostClient;
+    /// use postcard_rpc::header::VarSeqKind;
+    /// use serde::{Serialize, Deserialize};
+    /// use postcard_schema::Schema;
+    /// use std::time::Duration;
+    ///
+    /// /// A "wire error" type your server can use to respond to any
+    /// /// kind of request, for example if deserializing a request fails
+    /// #[derive(Debug, PartialEq, Schema, Serialize, Deserialize)]
+    /// pub enum Error {
+    ///    SomethingBad
+    /// }
+    ///
+    /// # async fn example() {
+    /// let client = HostClient::<Error>::new_raw_nusb_reconnecting(
+    ///     // Find the first device with the given VID and PID
+    ///     |d| d.vendor_id() == 0x16c0 && d.product_id() == 0x27DD,
+    ///     // the URI/path for `Error` messages
+    ///     "error",
+    ///     // Outgoing queue depth in messages
+    ///     8,
+    ///     // Use one-byte sequence numbers
+    ///     VarSeqKind::Seq1,
+    ///     // Look for the device every 500ms while disconnected
+    ///     Duration::from_millis(500),
+    /// );
+    /// client.wait_connected().await.unwrap();
+    /// # }
+    /// ```
+    pub fn new_raw_nusb_reconnecting<F: FnMut(&nusb::DeviceInfo) -> bool + Send + 'static>(
+        mut func: F,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+        seq_no_kind: VarSeqKind,
+        retry_delay: Duration,
+    ) -> Self {
+        let connect = move || {
+            let res = open_nusb(&mut func, |i| i.class() == 0xFF);
+            if let Err(e) = &res {
+                tracing::debug!("nusb reconnect failed: {e}");
+            }
+            std::future::ready(res.ok())
+        };
+        HostClient::new_with_wire_reconnecting(
+            connect,
+            NusbSpawn,
+            seq_no_kind,
+            err_uri_path,
+            outgoing_depth,
+            retry_delay,
+        )
+    }
+}
+
+/// A device found by [`HostClient::enumerate_raw_nusb()`]
+///
+/// **Requires feature**: `raw-nusb`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// The USB vendor ID
+    pub vendor_id: u16,
+    /// The USB product ID
+    pub product_id: u16,
+    /// The serial number string, if the device has one
+    pub serial_number: Option<String>,
+    /// The product string, if the device has one
+    pub product: Option<String>,
+    /// The manufacturer string, if the device has one
+    pub manufacturer: Option<String>,
+    /// The number of the bus the device is attached to
+    pub bus_number: u8,
+    /// The address of the device on its bus
+    pub device_address: u8,
+}
+
+impl DeviceInfo {
+    fn from_nusb(d: &nusb::DeviceInfo) -> Self {
+        Self {
+            vendor_id: d.vendor_id(),
+            product_id: d.product_id(),
+            serial_number: d.serial_number().map(String::from),
+            product: d.product_string().map(String::from),
+            manufacturer: d.manufacturer_string().map(String::from),
+            bus_number: d.bus_number(),
+            device_address: d.device_address(),
+        }
+    }
+
+    /// Is `d` the same device that this was created from?
+    ///
+    /// The address is reused once a device is detached, so the IDs are compared too.
+    fn is(&self, d: &nusb::DeviceInfo) -> bool {
+        self.bus_number == d.bus_number()
+            && self.device_address == d.device_address()
+            && self.vendor_id == d.vendor_id()
+            && self.product_id == d.product_id()
+            && self.serial_number.as_deref() == d.serial_number()
+    }
+}
+
+/// # `nusb` Enumeration Methods
+///
+/// These methods are used to list the attached devices, and to connect to one of them.
+///
+/// **Requires feature**: `raw-nusb`
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// List the attached devices that can be connected to with [`nusb`]
+    ///
+    /// These are the devices accepted by the provided function, for example by VID
+    /// and PID, that have a "Vendor Specific" interface, like
+    /// [`Self::try_new_raw_nusb()`] connects to. Connect to one of them with
+    /// [`Self::try_open_raw_nusb()`].
+    ///
+    /// The devices are NOT opened while listing them, so devices that this process
+    /// lacks the permissions to open are listed as well. Opening such a device fails,
+    /// without affecting any other device.
+    ///
+    /// Returns an error if the devices could not be listed at all.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use postcard_rpc::host_client::HostClient;
+    /// use postcard_rpc::header::VarSeqKind;
+    /// use serde::{Serialize, Deserialize};
+    /// use postcard_schema::Schema;
+    ///
+    /// #[derive(Debug, PartialEq, Schema, Serialize, Deserialize)]
+    /// pub enum Error {
+    ///    SomethingBad
+    /// }
+    ///
+    /// let devices = HostClient::<Error>::enumerate_raw_nusb(
+    ///     // All devices with the given VID and PID
+    ///     |d| d.vendor_id() == 0x16c0 && d.product_id() == 0x27DD,
+    /// ).unwrap();
+    /// let mut clients = vec![];
+    /// for info in devices {
+    ///     match HostClient::<Error>::try_open_raw_nusb(&info, "error", 8, VarSeqKind::Seq1) {
+    ///         Ok(client) => clients.push(client),
+    ///         Err(e) => println!("Skipping {:?}: {e}", info.serial_number),
+    ///     }
+    /// }
+    /// ```
+    pub fn enumerate_raw_nusb<F: FnMut(&nusb::DeviceInfo) -> bool>(
+        mut func: F,
+    ) -> Result<Vec<DeviceInfo>, String> {
+        let devices = nusb::list_devices()
+            .map_err(|e| format!("Error listing devices: {e:?}"))?
+            .filter(|d| d.interfaces().any(|i| i.class() == 0xFF))
+            .filter(|d| func(d))
+            .map(|d| DeviceInfo::from_nusb(&d))
+            .collect();
+        Ok(devices)
+    }
+
+    /// Try to create a new link using [`nusb`] to a device found by
+    /// [`Self::enumerate_raw_nusb()`]
+    ///
+    /// `err_uri_path` is the path associated with the `WireErr` message type.
+    ///
+    /// Returns an error if the device is no longer attached, or if there was an error
+    /// connecting to the device, e.g. missing permissions.
+    pub fn try_open_raw_nusb(
+        info: &DeviceInfo,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+        seq_no_kind: VarSeqKind,
+    ) -> Result<Self, String> {
+        Self::try_new_raw_nusb(|d| info.is(d), err_uri_path, outgoing_depth, seq_no_kind)
+    }
+}
+
+/// Find, open, and claim the matching device and interface
+fn open_nusb<F1, F2>(
+    device_func: F1,
+    interface_func: F2,
+) -> Result<(NusbWireTx, NusbWireRx), String>
+where
+    F1: FnMut(&nusb::DeviceInfo) -> bool,
+    F2: FnMut(&InterfaceInfo) -> bool,
+{
+    let x = nusb::list_devices()
+        .map_err(|e| format!("Error listing devices: {e:?}"))?
+        .find(device_func)
+        .ok_or_else(|| String::from("Failed to find matching nusb device!"))?;
+    let interface_id = x
+        .interfaces()
+        .position(interface_func)
+        .ok_or_else(|| String::from("Failed to find matching interface!!"))?;
+    let dev = x
+        .open()
+        .map_err(|e| format!("Failed opening device: {e:?}"))?;
+    let interface = dev
+        .claim_interface(interface_id as u8)
+        .map_err(|e| format!("Failed claiming interface: {e:?}"))?;
+
+    let boq = interface.bulk_out_queue(BULK_OUT_EP);
+    let biq = interface.bulk_in_queue(BULK_IN_EP);
+
+    Ok((
+        NusbWireTx { boq },
+        NusbWireRx {
+            biq,
+            consecutive_errs: 0,
+        },
+    ))
 }
 
 //////////////////////////////////////////////////////////////////////////////
@@ -1,12 +1,14 @@
 //! A Client implementation using channels for testing
 
 use crate::{
+    cipher::BodyCipher,
     header::VarSeqKind,
-    host_client::{HostClient, WireRx, WireSpawn, WireTx},
+    host_client::{CipherWire, CrcWire, HostClient, WireRx, WireSpawn, WireTx},
     standard_icd::WireError,
 };
-use core::fmt::Display;
-use tokio::sync::mpsc;
+use core::{fmt::Display, time::Duration};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
 
 /// Create a new HostClient from the given server channels
 pub fn new_from_channels(
@@ -24,6 +26,71 @@ pub fn new_from_channels(
     )
 }
 
+/// Create a new HostClient from the given server channels, appending and checking
+/// the CRC of each frame
+///
+/// See [`CrcWire`], the server must have CRCs enabled as well.
+pub fn new_from_channels_with_crc(
+    tx: mpsc::Sender<Vec<u8>>,
+    rx: mpsc::Receiver<Vec<u8>>,
+    seq_kind: VarSeqKind,
+) -> HostClient<WireError> {
+    HostClient::new_with_wire(
+        CrcWire::new(ChannelTx { tx }),
+        CrcWire::new(ChannelRx { rx }),
+        TokSpawn,
+        seq_kind,
+        crate::standard_icd::ERROR_PATH,
+        64,
+    )
+}
+
+/// Create a new HostClient from the given server channels, sealing and opening the
+/// body of each frame with `cipher`
+///
+/// See [`CipherWire`], the server must use the same cipher.
+pub fn new_from_channels_with_cipher(
+    tx: mpsc::Sender<Vec<u8>>,
+    rx: mpsc::Receiver<Vec<u8>>,
+    seq_kind: VarSeqKind,
+    cipher: &'static dyn BodyCipher,
+) -> HostClient<WireError> {
+    HostClient::new_with_wire(
+        CipherWire::new(ChannelTx { tx }, cipher),
+        CipherWire::new(ChannelRx { rx }, cipher),
+        TokSpawn,
+        seq_kind,
+        crate::standard_icd::ERROR_PATH,
+        64,
+    )
+}
+
+/// Create a new, automatically reconnecting, HostClient
+///
+/// Each time the client (re)connects, it takes the next pair of server channels
+/// from `connections`.
+pub fn new_from_channels_reconnecting(
+    connections: mpsc::Receiver<(mpsc::Sender<Vec<u8>>, mpsc::Receiver<Vec<u8>>)>,
+    seq_kind: VarSeqKind,
+) -> HostClient<WireError> {
+    let connections = Arc::new(Mutex::new(connections));
+    let connect = move || {
+        let connections = connections.clone();
+        async move {
+            let (tx, rx) = connections.lock().await.recv().await?;
+            Some((ChannelTx { tx }, ChannelRx { rx }))
+        }
+    };
+    HostClient::new_with_wire_reconnecting(
+        connect,
+        TokSpawn,
+        seq_kind,
+        crate::standard_icd::ERROR_PATH,
+        64,
+        Duration::from_millis(10),
+    )
+}
+
 /// Server error kinds
 #[derive(Debug)]
 pub enum ChannelError {
@@ -0,0 +1,144 @@
+//! Handling the messages of topics with typed closures, see
+//! [`HostClient::topic_router()`]
+
+use std::sync::Arc;
+
+use postcard_schema::Schema;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    header::{VarHeader, VarKey},
+    Key, Topic,
+};
+
+use super::{HostClient, HostContext, RpcFrame};
+
+/// The handler of a topic, returning `false` if the message failed to deserialize
+type TopicHandler = Box<dyn FnMut(&[u8]) -> bool + Send>;
+
+/// The handler of frames that nothing else handled
+type DefaultHandler = Box<dyn FnMut(RpcFrame) + Send>;
+
+/// The handlers of a [TopicRouter], once installed
+#[derive(Default)]
+pub(super) struct TopicRoutes {
+    handlers: Vec<(Key, TopicHandler)>,
+    default: Option<DefaultHandler>,
+}
+
+/// A builder of the handlers of incoming topic messages
+///
+/// Created by [HostClient::topic_router()], and takes effect once
+/// [installed][Self::install].
+#[must_use = "the router does nothing until installed"]
+pub struct TopicRouter {
+    ctx: Arc<HostContext>,
+    routes: TopicRoutes,
+}
+
+impl TopicRouter {
+    /// Call `handler` with each message of the topic `T`
+    ///
+    /// Registering the same topic again replaces its handler. Messages that fail to
+    /// deserialize are passed to the [default handler][Self::default], if any.
+    pub fn on<T>(mut self, mut handler: impl FnMut(T::Message) + Send + 'static) -> Self
+    where
+        T: Topic,
+        T::Message: DeserializeOwned,
+    {
+        let erased: TopicHandler = Box::new(move |body| match postcard::from_bytes(body) {
+            Ok(msg) => {
+                handler(msg);
+                true
+            }
+            Err(_) => false,
+        });
+        let handlers = &mut self.routes.handlers;
+        match handlers.iter_mut().find(|(k, _)| *k == T::TOPIC_KEY) {
+            Some(entry) => entry.1 = erased,
+            None => handlers.push((T::TOPIC_KEY, erased)),
+        }
+        self
+    }
+
+    /// Call `handler` with each frame that no topic handler, subscription or pending
+    /// request took, e.g. the messages of topics without a handler
+    pub fn default(mut self, handler: impl FnMut(RpcFrame) + Send + 'static) -> Self {
+        self.routes.default = Some(Box::new(handler));
+        self
+    }
+
+    /// Start calling the handlers from the I/O worker of the client
+    ///
+    /// This replaces the router installed before, if any, for all clones of the
+    /// client. Install an empty router to remove all handlers.
+    pub fn install(self) {
+        *self.ctx.topic_routes.lock().unwrap() = self.routes;
+    }
+}
+
+/// # Topic Routing
+impl<WireErr> HostClient<WireErr>
+where
+    WireErr: DeserializeOwned + Schema,
+{
+    /// Create a [TopicRouter], to handle the messages of each topic with a closure
+    ///
+    /// This is the client side counterpart of the `topics_in` handlers of
+    /// [`define_dispatch!`][crate::define_dispatch]: instead of a subscription per
+    /// topic, each message is decoded and handed to the closure registered for its
+    /// topic, right in the I/O worker of the client. Subscriptions to the same topics
+    /// keep receiving their messages as well.
+    ///
+    /// The handlers are called while the I/O worker holds a lock, so they must not
+    /// block, or install another router. Use a channel or spawn a task for slow work.
+    ///
+    /// ```rust,ignore
+    /// client
+    ///     .topic_router()
+    ///     .on::<TemperatureTopic>(|t| println!("temperature: {}", t.0))
+    ///     .on::<ButtonTopic>(|b| println!("button {b} pressed"))
+    ///     .default(|frame| println!("unhandled: {:?}", frame.header.key))
+    ///     .install();
+    /// ```
+    pub fn topic_router(&self) -> TopicRouter {
+        TopicRouter {
+            ctx: self.ctx.clone(),
+            routes: TopicRoutes::default(),
+        }
+    }
+}
+
+impl HostContext {
+    /// Hand a topic message to the handler of its topic, if there is one
+    ///
+    /// Returns `false` if no handler took the message.
+    pub(super) fn route_topic(&self, hdr: &VarHeader, body: &[u8]) -> bool {
+        let mut routes = self.topic_routes.lock().unwrap();
+        let TopicRoutes { handlers, default } = &mut *routes;
+        // Keys of different sizes compare equal if the shorter one matches
+        let Some((_, handler)) = handlers
+            .iter_mut()
+            .find(|(k, _)| VarKey::Key8(*k) == hdr.key)
+        else {
+            return false;
+        };
+        if !handler(body) {
+            tracing::warn!("Topic message failed to deserialize");
+            if let Some(default) = default {
+                default(RpcFrame {
+                    header: *hdr,
+                    body: body.to_vec(),
+                });
+            }
+        }
+        true
+    }
+
+    /// Hand a frame that nothing else handled to the default handler, if any
+    pub(super) fn route_unhandled(&self, frame: RpcFrame) {
+        if let Some(default) = &mut self.topic_routes.lock().unwrap().default {
+            default(frame);
+        }
+    }
+}
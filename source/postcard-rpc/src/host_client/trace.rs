@@ -0,0 +1,89 @@
+//! Tracing spans of host client calls
+//!
+//! Each call made by the [`HostClient`] runs in a `call` span, with the `endpoint`,
+//! `key` and `seq_no` of the request, and emits events when the request is sent,
+//! when a response is received, when an attempt times out, and when the call fails.
+//! The span is closed when the call resolves, whether it succeeded or not.
+//!
+//! The span and its events are at the `debug` level, so they cost next to nothing
+//! unless a subscriber enables them.
+//!
+//! [`HostClient`]: super::HostClient
+
+use core::future::Future;
+
+use tracing::{field::Empty, Instrument};
+
+use super::HostErr;
+use crate::header::{VarKey, VarSeq};
+
+/// The span of a single call
+pub(crate) struct CallSpan(tracing::Span);
+
+impl CallSpan {
+    /// Create the span of a call to the endpoint at `path`
+    pub(crate) fn new(path: &'static str, key: VarKey) -> Self {
+        Self(tracing::debug_span!("call", endpoint = path, key = ?key, seq_no = Empty))
+    }
+
+    /// Create the span of a call without a known endpoint
+    pub(crate) fn raw(key: VarKey) -> Self {
+        Self(tracing::debug_span!("call", endpoint = Empty, key = ?key, seq_no = Empty))
+    }
+
+    /// Record the sequence number used by the call
+    pub(crate) fn seq_no(&self, seq_no: VarSeq) {
+        let seq_no = match seq_no {
+            VarSeq::Seq1(s) => u32::from(s),
+            VarSeq::Seq2(s) => u32::from(s),
+            VarSeq::Seq4(s) => s,
+        };
+        self.0.record("seq_no", seq_no);
+    }
+
+    /// The request was handed to the I/O worker
+    pub(crate) fn sent(&self) {
+        tracing::debug!(parent: &self.0, "request sent");
+    }
+
+    /// A response was received
+    pub(crate) fn received(&self) {
+        tracing::debug!(parent: &self.0, "response received");
+    }
+
+    /// No response was received in time for the given attempt
+    pub(crate) fn timed_out(&self, attempt: u32) {
+        tracing::debug!(parent: &self.0, attempt, "request timed out");
+    }
+
+    /// Run the call in this span, recording whether it failed
+    pub(crate) async fn run<T, E, F>(&self, call: F) -> Result<T, HostErr<E>>
+    where
+        F: Future<Output = Result<T, HostErr<E>>>,
+    {
+        let res = call.instrument(self.0.clone()).await;
+        if let Err(e) = &res {
+            tracing::debug!(parent: &self.0, error = error_kind(e), "call failed");
+        }
+        res
+    }
+}
+
+/// The name of the kind of `err`, as `WireErr` is not necessarily `Debug`
+fn error_kind<E>(err: &HostErr<E>) -> &'static str {
+    match err {
+        HostErr::Wire(_) => "Wire",
+        HostErr::UnknownKey(_) => "UnknownKey",
+        HostErr::BadResponse => "BadResponse",
+        HostErr::Postcard(_) => "Postcard",
+        HostErr::Closed => "Closed",
+        HostErr::Disconnected => "Disconnected",
+        HostErr::SeqNoInUse => "SeqNoInUse",
+        HostErr::RetriesExhausted { .. } => "RetriesExhausted",
+        HostErr::Shutdown => "Shutdown",
+        HostErr::Reset => "Reset",
+        HostErr::SchemaMismatch { .. } => "SchemaMismatch",
+        HostErr::HeartbeatLost => "HeartbeatLost",
+        HostErr::Incompatible(_) => "Incompatible",
+    }
+}
@@ -1,5 +1,9 @@
 // the contents of this file can probably be moved up to `mod.rs`
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    fmt::Debug,
+    future::Future,
+    sync::{atomic::Ordering, Arc},
+};
 
 use maitake_sync::WaitQueue;
 use postcard_schema::Schema;
@@ -11,9 +15,11 @@ use tokio::{
 use tracing::{debug, trace, warn};
 
 use crate::{
-    header::{VarHeader, VarKey, VarSeqKind},
+    compress,
+    header::{VarHeader, VarKey, VarKeyKind, VarSeqKind},
     host_client::{
-        HostClient, HostContext, ProcessError, RpcFrame, WireContext, WireRx, WireSpawn, WireTx,
+        ConnectionState, HostClient, HostContext, ProcessError, RpcFrame, WireContext, WireRx,
+        WireSpawn, WireTx,
     },
     Key,
 };
@@ -94,26 +100,198 @@ where
 
         let WireContext { outgoing, incoming } = wire_ctx;
 
-        sp.spawn(out_worker(tx, outgoing, me.stopper.clone()));
-        sp.spawn(in_worker(
-            rx,
-            incoming,
-            me.subscriptions.clone(),
-            me.stopper.clone(),
+        sp.spawn(tracked(
+            WorkerGuard::new(&incoming),
+            out_worker(tx, outgoing, incoming.clone(), me.stopper.clone()),
+        ));
+        sp.spawn(tracked(
+            WorkerGuard::new(&incoming),
+            in_worker(rx, incoming, me.subscriptions.clone(), me.stopper.clone()),
+        ));
+
+        me
+    }
+
+    /// Generic HostClient logic with automatic reconnection, using the various
+    /// Wire traits
+    ///
+    /// `connect` is called to open the connection to the device, and is called again
+    /// each time the connection is lost. If it returns `None`, it will be retried
+    /// after `retry_delay`.
+    ///
+    /// The client starts in the [`ConnectionState::Reconnecting`] state, use
+    /// [`HostClient::wait_connected()`] to wait for the first connection. Requests
+    /// that are pending when the connection is lost return
+    /// [`HostErr::Disconnected`][crate::host_client::HostErr::Disconnected].
+    #[cfg(not(target_family = "wasm"))]
+    pub fn new_with_wire_reconnecting<C, Fut, WTX, WRX, WSP>(
+        connect: C,
+        mut sp: WSP,
+        seq_kind: VarSeqKind,
+        err_uri_path: &str,
+        outgoing_depth: usize,
+        retry_delay: std::time::Duration,
+    ) -> Self
+    where
+        C: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Option<(WTX, WRX)>> + Send,
+        WTX: WireTx,
+        WRX: WireRx,
+        WSP: WireSpawn,
+    {
+        let (me, wire_ctx) = Self::new_manual_priv(err_uri_path, outgoing_depth, seq_kind);
+        me.ctx.conn.send_replace(ConnectionState::Reconnecting);
+
+        let WireContext { outgoing, incoming } = wire_ctx;
+
+        sp.spawn(tracked(
+            WorkerGuard::new(&incoming),
+            reconnect_worker(
+                connect,
+                retry_delay,
+                outgoing,
+                incoming,
+                me.subscriptions.clone(),
+                me.stopper.clone(),
+            ),
         ));
 
         me
     }
 }
 
+/// Counts a running I/O worker task in the [HostContext], until dropped
+///
+/// This is created before the task is spawned, so that
+/// [`HostClient::shutdown()`] can't miss a task that has not started yet.
+struct WorkerGuard(Arc<HostContext>);
+
+impl WorkerGuard {
+    fn new(ctx: &Arc<HostContext>) -> Self {
+        ctx.workers.send_modify(|n| *n += 1);
+        Self(ctx.clone())
+    }
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        self.0.workers.send_modify(|n| *n -= 1);
+    }
+}
+
+/// Run an I/O worker, holding `guard` until it completes
+async fn tracked<F: Future<Output = ()>>(guard: WorkerGuard, worker: F) {
+    let _guard = guard;
+    worker.await
+}
+
+/// Combined I/O worker, that re-opens the connection whenever it is lost
+#[cfg(not(target_family = "wasm"))]
+async fn reconnect_worker<C, Fut, WTX, WRX>(
+    connect: C,
+    retry_delay: std::time::Duration,
+    rec: mpsc::Receiver<RpcFrame>,
+    host_ctx: Arc<HostContext>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    stop: Stopper,
+) where
+    C: FnMut() -> Fut,
+    Fut: Future<Output = Option<(WTX, WRX)>>,
+    WTX: WireTx,
+    WRX: WireRx,
+{
+    let cancel_fut = stop.wait_stopped();
+    let operate_fut = reconnect_worker_inner(
+        connect,
+        retry_delay,
+        rec,
+        host_ctx.clone(),
+        subscriptions.clone(),
+    );
+    select! {
+        _ = cancel_fut => {},
+        _ = operate_fut => {
+            // if WE exited, notify everyone else it's stoppin time
+            stop.stop();
+        },
+    }
+    host_ctx.conn.send_replace(ConnectionState::Disconnected);
+    let mut guard = subscriptions.lock().await;
+    guard.stopped = true;
+    guard.exclusive_list.clear();
+    guard.broadcast_list.clear();
+}
+
+#[cfg(not(target_family = "wasm"))]
+async fn reconnect_worker_inner<C, Fut, WTX, WRX>(
+    mut connect: C,
+    retry_delay: std::time::Duration,
+    mut rec: mpsc::Receiver<RpcFrame>,
+    host_ctx: Arc<HostContext>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+) where
+    C: FnMut() -> Fut,
+    Fut: Future<Output = Option<(WTX, WRX)>>,
+    WTX: WireTx,
+    WRX: WireRx,
+{
+    loop {
+        host_ctx.conn.send_replace(ConnectionState::Reconnecting);
+        let (tx, rx) = loop {
+            if let Some(wire) = connect().await {
+                break wire;
+            }
+            tokio::time::sleep(retry_delay).await;
+        };
+
+        // The device may have changed, so start over with full size keys
+        *host_ctx.kkind.write().unwrap() = VarKeyKind::Key8;
+        // Requests registered on an earlier connection are no longer sent, see
+        // `HostClient::send_request()`
+        host_ctx.conn.send_modify(|state| {
+            host_ctx.conn_gen.fetch_add(1, Ordering::AcqRel);
+            *state = ConnectionState::Connected;
+        });
+        debug!("reconnect_worker: connected");
+
+        select! {
+            exit = out_worker_inner(tx, &mut rec, &host_ctx) => {
+                if let OutExit::QueueClosed = exit {
+                    return;
+                }
+            },
+            _ = in_worker_inner(rx, host_ctx.clone(), subscriptions.clone()) => {},
+        }
+
+        warn!("reconnect_worker: connection lost");
+        host_ctx.conn.send_replace(ConnectionState::Disconnected);
+
+        // Don't send requests meant for the old connection, their callers have
+        // already been notified of the disconnection
+        while rec.try_recv().is_ok() {}
+    }
+}
+
+/// Why did [out_worker_inner] exit?
+enum OutExit {
+    /// All senders were dropped
+    QueueClosed,
+    /// The wire returned an error
+    WireError,
+}
+
 /// Output worker, feeding frames to the `Client`.
-async fn out_worker<W>(wire: W, rec: mpsc::Receiver<RpcFrame>, stop: Stopper)
-where
+async fn out_worker<W>(
+    wire: W,
+    mut rec: mpsc::Receiver<RpcFrame>,
+    host_ctx: Arc<HostContext>,
+    stop: Stopper,
+) where
     W: WireTx,
     W::Error: Debug,
 {
     let cancel_fut = stop.wait_stopped();
-    let operate_fut = out_worker_inner(wire, rec);
+    let operate_fut = out_worker_inner(wire, &mut rec, &host_ctx);
     select! {
         _ = cancel_fut => {},
         _ = operate_fut => {
@@ -123,7 +301,11 @@ where
     }
 }
 
-async fn out_worker_inner<W>(mut wire: W, mut rec: mpsc::Receiver<RpcFrame>)
+async fn out_worker_inner<W>(
+    mut wire: W,
+    rec: &mut mpsc::Receiver<RpcFrame>,
+    host_ctx: &HostContext,
+) -> OutExit
 where
     W: WireTx,
     W::Error: Debug,
@@ -131,11 +313,17 @@ where
     loop {
         let Some(msg) = rec.recv().await else {
             tracing::warn!("Receiver Closed, this could be bad");
-            return;
+            return OutExit::QueueClosed;
         };
-        if let Err(e) = wire.send(msg.to_bytes()).await {
+        // Attach the token of the session, if any, see [HostClient::set_auth_token()]
+        let token = *host_ctx.token.read().unwrap();
+        let bytes = match token {
+            Some(token) => msg.to_bytes_with_token(&token),
+            None => msg.to_bytes(),
+        };
+        if let Err(e) = wire.send(bytes).await {
             tracing::error!("Output Queue Error: {e:?}, exiting");
-            return;
+            return OutExit::WireError;
         }
     }
 }
@@ -181,11 +369,24 @@ async fn in_worker_inner<W>(
             return;
         };
 
-        let Some((hdr, body)) = VarHeader::take_from_slice(&res) else {
+        let Some((hdr, flags, _token, body)) = VarHeader::take_from_slice_flagged(&res) else {
             warn!("Header decode error!");
             continue;
         };
 
+        // Decompress the body before anyone gets to see it
+        let decompressed;
+        let body = if flags.compressed {
+            let Some(bytes) = compress::decompress_to_vec(body) else {
+                warn!("Body decompression error!");
+                continue;
+            };
+            decompressed = bytes;
+            &decompressed[..]
+        } else {
+            body
+        };
+
         trace!("in_worker received {hdr:?}");
 
         let mut handled = false;
@@ -262,6 +463,11 @@ async fn in_worker_inner<W>(
             }
         }
 
+        // Topic handlers see the message as well as subscriptions
+        if host_ctx.route_topic(&hdr, body) {
+            handled = true;
+        }
+
         if handled {
             continue;
         }
@@ -119,9 +119,15 @@ use header::{VarKey, VarKeyKind};
 use postcard_schema::{schema::NamedType, Schema};
 use serde::{Deserialize, Serialize};
 
+pub mod cipher;
+pub mod compress;
+pub mod crc;
+pub mod decode;
+pub mod encode;
 pub mod hash;
 pub mod header;
 mod macros;
+pub mod max_size;
 pub mod server;
 pub mod standard_icd;
 pub mod uniques;
@@ -132,6 +138,12 @@ pub mod accumulator;
 #[cfg(feature = "use-std")]
 pub mod host_client;
 
+#[cfg(feature = "embedded-client")]
+pub mod embedded_client;
+
+#[cfg(feature = "codegen")]
+pub mod codegen;
+
 #[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils;
 
@@ -167,6 +179,19 @@ impl core::fmt::Debug for Key {
 
 impl Key {
     /// Create a Key for the given type and path
+    ///
+    /// This is the same key as used by the [`endpoint!`] and [`topic!`] macros, so
+    /// routing tables or gateways can calculate the keys of known paths without
+    /// defining the endpoints:
+    ///
+    /// ```rust
+    /// use postcard_rpc::{endpoint, Endpoint, Key};
+    ///
+    /// endpoint!(PingEndpoint, u32, u32, "ping");
+    ///
+    /// const PING_KEY: Key = Key::for_path::<u32>("ping");
+    /// assert_eq!(PING_KEY, PingEndpoint::REQ_KEY);
+    /// ```
     pub const fn for_path<T>(path: &str) -> Self
     where
         T: Schema + ?Sized,
@@ -181,6 +206,16 @@ impl Key {
     /// This MUST only be used with pre-calculated values. Incorrectly
     /// created keys could lead to the improper deserialization of
     /// messages.
+    ///
+    /// The bytes returned by [`Key::to_bytes()`] are such a value:
+    ///
+    /// ```rust
+    /// use postcard_rpc::Key;
+    ///
+    /// let key = Key::for_path::<u32>("ping");
+    /// let bytes = key.to_bytes();
+    /// assert_eq!(unsafe { Key::from_bytes(bytes) }, key);
+    /// ```
     pub const unsafe fn from_bytes(bytes: [u8; 8]) -> Self {
         Self(bytes)
     }
@@ -417,6 +452,29 @@ impl From<Key> for Key4 {
     }
 }
 
+/// Implemented for every type that implements [Schema]
+///
+/// Used as a bound by the [endpoint], [endpoints], [topic] and [topics] macros, so a
+/// request, response, or message type without `#[derive(Schema)]` is reported at the
+/// macro call with a clear error, rather than deep inside of postcard-rpc.
+///
+/// ```rust,compile_fail
+/// use postcard_rpc::endpoint;
+///
+/// pub struct NotSchema(u8);
+///
+/// // error: `NotSchema` must derive postcard `Schema`
+/// endpoint!(Endpoint1, NotSchema, u32, "endpoint/1");
+/// ```
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` must derive postcard `Schema`",
+    label = "used as a message type here",
+    note = "add `#[derive(Schema)]` to `{Self}`, using `postcard_schema::Schema`"
+)]
+pub trait MustDeriveSchema {}
+
+impl<T: Schema + ?Sized> MustDeriveSchema for T {}
+
 /// A marker trait denoting a single endpoint
 ///
 /// Typically used with the [endpoint] macro.
@@ -443,6 +501,41 @@ pub trait Endpoint {
     const RESP_KEY2: Key2 = Key2::from_key8(Self::RESP_KEY);
     /// The unique [Key1] identifying the Response
     const RESP_KEY1: Key1 = Key1::from_key8(Self::RESP_KEY);
+    /// The maximum serialized size of the Request, or `None` if unbounded
+    const MAX_REQUEST_SIZE: Option<usize> = max_size::max_size_of(Self::Request::SCHEMA);
+    /// The maximum serialized size of the Response, or `None` if unbounded
+    ///
+    /// This can be used to size the buffer used for sending responses. See
+    /// [`max_size::max_frame_size_of`] to include the size of the header.
+    const MAX_RESPONSE_SIZE: Option<usize> = max_size::max_size_of(Self::Response::SCHEMA);
+    /// The schema of the Request
+    ///
+    /// This can be used to inspect the wire format of the Request without a connected
+    /// device, e.g. for generating documentation or decoding captured frames.
+    const REQ_SCHEMA: &'static NamedType = Self::Request::SCHEMA;
+    /// The schema of the Response
+    const RESP_SCHEMA: &'static NamedType = Self::Response::SCHEMA;
+
+    /// The serialized size of `req`, without the header
+    ///
+    /// The size is counted without serializing into a buffer, so this doesn't
+    /// allocate, and can be used to choose a buffer size or whether to send `req` in
+    /// chunks. See [Self::MAX_REQUEST_SIZE] for the size of the largest request.
+    /// Returns `0` if `req` can't be serialized.
+    fn request_len(req: &Self::Request) -> usize
+    where
+        Self::Request: Serialize,
+    {
+        postcard::experimental::serialized_size(req).unwrap_or(0)
+    }
+
+    /// The serialized size of `resp`, without the header, see [Self::request_len()]
+    fn response_len(resp: &Self::Response) -> usize
+    where
+        Self::Response: Serialize,
+    {
+        postcard::experimental::serialized_size(resp).unwrap_or(0)
+    }
 }
 
 /// A marker trait denoting a single topic
@@ -465,6 +558,10 @@ pub trait Topic {
     const TOPIC_KEY2: Key2 = Key2::from_key8(Self::TOPIC_KEY);
     /// The unique [Key2] identifying the Message
     const TOPIC_KEY1: Key1 = Key1::from_key8(Self::TOPIC_KEY);
+    /// The maximum serialized size of the Message, or `None` if unbounded
+    const MAX_MESSAGE_SIZE: Option<usize> = max_size::max_size_of(Self::Message::SCHEMA);
+    /// The schema of the Message
+    const MESSAGE_SCHEMA: &'static NamedType = Self::Message::SCHEMA;
 }
 
 /// The direction of topic messages
@@ -492,6 +589,12 @@ pub struct DeviceMap {
     pub topics_in: &'static [(&'static str, Key)],
     /// The list of topics (server to client) by path string and topic key
     pub topics_out: &'static [(&'static str, Key)],
+    /// The list of endpoint aliases, by the request key of the alias, and the request
+    /// key of the endpoint it stands in for
+    pub aliases: &'static [(Key, Key)],
+    /// The list of grouped endpoints, by group name and request key, see
+    /// [`GetGroupsEndpoint`][crate::standard_icd::GetGroupsEndpoint]
+    pub groups: &'static [(&'static str, Key)],
     /// The minimum key size required to avoid hash collisions
     pub min_key_len: VarKeyKind,
 }
@@ -26,18 +26,75 @@
 /// ```
 ///
 /// If the path is omitted, the type name is used instead.
+///
+/// ## Explicit keys
+///
+/// For bridging to an existing protocol, e.g. the message IDs of a legacy firmware,
+/// the keys may be given explicitly instead of being calculated from the schema and
+/// path. Either use `key` for both the request and the response, or give them
+/// separately with `req_key` and `resp_key`:
+///
+/// ```rust
+/// # use postcard_schema::Schema;
+/// # use serde::{Serialize, Deserialize};
+/// use postcard_rpc::{endpoint, Endpoint};
+///
+/// #[derive(Debug, Serialize, Deserialize, Schema)]
+/// pub struct Req1(u8);
+///
+/// endpoint!(Legacy1, Req1, u32, "legacy/1", key = [0x10, 0, 0, 0, 0, 0, 0, 0]);
+/// endpoint!(
+///     Legacy2,
+///     Req1,
+///     u32,
+///     "legacy/2",
+///     req_key = [0x20, 0, 0, 0, 0, 0, 0, 0],
+///     resp_key = [0x21, 0, 0, 0, 0, 0, 0, 0],
+/// );
+///
+/// assert_eq!(Legacy2::REQ_KEY.to_bytes(), [0x20, 0, 0, 0, 0, 0, 0, 0]);
+/// ```
+///
+/// These keys are used as-is by both the server and the client, and are no longer
+/// tied to the schema of the types: changing the request or response type does NOT
+/// change the key. Make sure that no other endpoint or topic uses the same keys,
+/// including the shortened forms (see [Key1][crate::Key1]), which are calculated
+/// from the given keys as usual.
 #[macro_export]
 macro_rules! endpoint {
     ($tyname:ident, $req:ty, $resp:ty) => {
         endpoint!($tyname, $req, $resp, stringify!($tyname));
     };
+    ($tyname:ident, $req:ty, $resp:ty, $path:expr, key = $key:expr $(,)?) => {
+        endpoint!($tyname, $req, $resp, $path, req_key = $key, resp_key = $key);
+    };
+    ($tyname:ident, $req:ty, $resp:ty, $path:expr, req_key = $req_key:expr, resp_key = $resp_key:expr $(,)?) => {
+        pub struct $tyname;
+
+        impl $crate::Endpoint for $tyname
+        where
+            $req: $crate::MustDeriveSchema,
+            $resp: $crate::MustDeriveSchema,
+        {
+            type Request = $req;
+            type Response = $resp;
+            const PATH: &'static str = $path;
+            // SAFETY: the keys are explicitly given by the user, see the macro docs
+            const REQ_KEY: $crate::Key = unsafe { $crate::Key::from_bytes($req_key) };
+            const RESP_KEY: $crate::Key = unsafe { $crate::Key::from_bytes($resp_key) };
+        }
+    };
     ($tyname:ident, $req:ty, $resp:ty, $path:expr,) => {
         endpoint!($tyname, $req, $resp, $path)
     };
     ($tyname:ident, $req:ty, $resp:ty, $path:expr) => {
         pub struct $tyname;
 
-        impl $crate::Endpoint for $tyname {
+        impl $crate::Endpoint for $tyname
+        where
+            $req: $crate::MustDeriveSchema,
+            $resp: $crate::MustDeriveSchema,
+        {
             type Request = $req;
             type Response = $resp;
             const PATH: &'static str = $path;
@@ -47,6 +104,176 @@ macro_rules! endpoint {
     };
 }
 
+/// ## Endpoint request macro
+///
+/// Used to define a request type, together with the Endpoint marker type that
+/// uses it, keeping the contract next to the data. This produces the same
+/// [Endpoint][crate::Endpoint] impl as the [`endpoint!()`][crate::endpoint] macro.
+///
+/// The `#[endpoint(...)]` attribute must come first, and takes the name of the
+/// marker type, the response type, and optionally the path.
+///
+/// ```rust
+/// # use postcard_schema::Schema;
+/// # use serde::{Serialize, Deserialize};
+/// use postcard_rpc::{endpoint_request, Endpoint, Key};
+///
+/// #[derive(Debug, Serialize, Deserialize, Schema)]
+/// pub struct Resp1 {
+///     c: [u8; 4],
+///     d: i32,
+/// }
+///
+/// endpoint_request! {
+///     #[endpoint(name = Endpoint1, response = Resp1, path = "endpoint/1")]
+///     #[derive(Debug, Serialize, Deserialize, Schema)]
+///     pub struct Req1 {
+///         a: u8,
+///         b: u64,
+///     }
+/// }
+///
+/// assert_eq!(Endpoint1::PATH, "endpoint/1");
+/// assert_eq!(Endpoint1::REQ_KEY, Key::for_path::<Req1>("endpoint/1"));
+/// ```
+///
+/// If the path is omitted, the name of the marker type is used instead. Request
+/// types with generics or lifetimes are not supported, use the
+/// [`endpoints!()`][crate::endpoints] macro for these instead.
+#[macro_export]
+macro_rules! endpoint_request {
+    (
+        #[endpoint(name = $tyname:ident, response = $resp:ty $(,)?)]
+        $(#[$meta:meta])*
+        $vis:vis $kind:ident $req:ident $($body:tt)*
+    ) => {
+        $crate::endpoint_request! {
+            #[endpoint(name = $tyname, response = $resp, path = stringify!($tyname))]
+            $(#[$meta])*
+            $vis $kind $req $($body)*
+        }
+    };
+    (
+        #[endpoint(name = $tyname:ident, response = $resp:ty, path = $path:expr $(,)?)]
+        $(#[$meta:meta])*
+        $vis:vis $kind:ident $req:ident $($body:tt)*
+    ) => {
+        $(#[$meta])*
+        $vis $kind $req $($body)*
+
+        $crate::endpoint!($tyname, $req, $resp, $path);
+    };
+}
+
+/// ## Response enum macro
+///
+/// Used to define an enum for endpoints that respond with one of several types,
+/// together with an accessor method for each variant, so the client doesn't have to
+/// match on the response for every call.
+///
+/// Each variant holds a single value of the given type, and the attributes, e.g. the
+/// derives, are applied to the enum:
+///
+/// ```rust
+/// # use postcard_schema::Schema;
+/// # use serde::{Serialize, Deserialize};
+/// use postcard_rpc::{endpoint, response_enum};
+///
+/// #[derive(Debug, Serialize, Deserialize, Schema)]
+/// pub struct Trace {
+///     samples: Vec<u16>,
+/// }
+///
+/// response_enum! {
+///     #[derive(Debug, Serialize, Deserialize, Schema)]
+///     pub enum Measurement {
+///         | Variant   | Type      | accessor  |
+///         | -------   | ----      | --------  |
+///         | Level     | u8        | level     |
+///         | Average   | f32       | average   |
+///         | Full      | Trace     | full      |
+///     }
+/// }
+///
+/// endpoint!(MeasureEndpoint, u8, Measurement, "measure");
+///
+/// let resp = Measurement::Level(3);
+/// assert_eq!(resp.level(), Some(&3));
+/// assert!(resp.full().is_none());
+/// assert_eq!(resp.variant_name(), "Level");
+/// ```
+///
+/// The enum is serialized like any other enum, as the index of the variant followed
+/// by its value, so the variants may have very different sizes: only the variant that
+/// was sent takes up space in the frame. The schema of the enum is checked at compile
+/// time, to make sure that each variant holds the given type, e.g. that no serde
+/// attribute renamed or skipped a variant.
+#[macro_export]
+macro_rules! response_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            | Variant | Type | accessor |
+            | $(-)* | $(-)* | $(-)* |
+            $( | $variant:ident | $ty:ty | $accessor:ident | )*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $(
+                #[allow(missing_docs)]
+                $variant($ty),
+            )*
+        }
+
+        impl $name {
+            /// The names of all variants, in order
+            pub const VARIANTS: &'static [&'static str] = &[$(stringify!($variant)),*];
+
+            /// The name of this variant
+            pub fn variant_name(&self) -> &'static str {
+                match self {
+                    $($name::$variant(_) => stringify!($variant),)*
+                }
+            }
+
+            $(
+                #[doc = concat!(
+                    "The value, if this is a [`",
+                    stringify!($name), "::", stringify!($variant), "`]"
+                )]
+                pub fn $accessor(&self) -> Option<&$ty> {
+                    match self {
+                        $name::$variant(v) => Some(v),
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    }
+                }
+            )*
+        }
+
+        const _: () = {
+            let mut idx = 0;
+            $(
+                assert!(
+                    $crate::uniques::is_newtype_variant(
+                        <$name as postcard_schema::Schema>::SCHEMA,
+                        idx,
+                        stringify!($variant),
+                        <$ty as postcard_schema::Schema>::SCHEMA,
+                    ),
+                    concat!(
+                        "The schema of `", stringify!($name), "::", stringify!($variant),
+                        "` doesn't match"
+                    ),
+                );
+                idx += 1;
+            )*
+            let _ = idx;
+        };
+    };
+}
+
 /// ## Endpoints macro
 ///
 /// Used to define multiple Endpoint marker types that implements the
@@ -89,8 +316,38 @@ macro_rules! endpoint {
 ///     | Endpoint2      | Req2          | Resp2         | "endpoints/two"   |
 /// }
 /// ```
+///
+/// The keys of an endpoint may be given explicitly after the path, in the same
+/// forms as for the [`endpoint!()`][crate::endpoint] macro, see there for details:
+///
+/// ```rust,ignore
+/// | Legacy1        | Req1          | u32           | "legacy/1" [key = [0x10, 0, 0, 0, 0, 0, 0, 0]]    |
+/// ```
 #[macro_export]
 macro_rules! endpoints {
+    // Keys calculated from the schema and path, or given explicitly
+    (@req_key $ty:tt, $path:literal;) => {
+        $crate::Key::for_path::<$ty>($path)
+    };
+    (@req_key $ty:tt, $path:literal; key = $key:expr) => {
+        // SAFETY: the keys are explicitly given by the user, see the `endpoint!` docs
+        unsafe { $crate::Key::from_bytes($key) }
+    };
+    (@req_key $ty:tt, $path:literal; req_key = $req_key:expr, resp_key = $resp_key:expr $(,)?) => {
+        // SAFETY: the keys are explicitly given by the user, see the `endpoint!` docs
+        unsafe { $crate::Key::from_bytes($req_key) }
+    };
+    (@resp_key $ty:tt, $path:literal;) => {
+        $crate::Key::for_path::<$ty>($path)
+    };
+    (@resp_key $ty:tt, $path:literal; key = $key:expr) => {
+        // SAFETY: the keys are explicitly given by the user, see the `endpoint!` docs
+        unsafe { $crate::Key::from_bytes($key) }
+    };
+    (@resp_key $ty:tt, $path:literal; req_key = $req_key:expr, resp_key = $resp_key:expr $(,)?) => {
+        // SAFETY: the keys are explicitly given by the user, see the `endpoint!` docs
+        unsafe { $crate::Key::from_bytes($resp_key) }
+    };
     (@ep_tys $([[$($meta:meta)?] $ep_name:ident])*) => {
         $crate::endpoints!(@ep_tys omit_std=false; $([[$($meta)?] $ep_name])*)
     };
@@ -162,7 +419,7 @@ macro_rules! endpoints {
            $(omit_std = $omit:tt;)?
            | EndpointTy     | RequestTy                                | ResponseTy                                  | Path              | $( Cfg           |)?
            | $(-)*          | $(-)*                                    | $(-)*                                       | $(-)*             | $($(-)*          |)?
-        $( | $ep_name:ident | $req_ty:tt $(< $($req_lt:lifetime),+ >)? | $resp_ty:tt $(< $($resp_lt:lifetime),+ >)?  | $path_str:literal | $($meta:meta)? $(|)? )*
+        $( | $ep_name:ident | $req_ty:tt $(< $($req_lt:lifetime),+ >)? | $resp_ty:tt $(< $($resp_lt:lifetime),+ >)?  | $path_str:literal $([$($keys:tt)*])? | $($meta:meta)? $(|)? )*
     ) => {
         // struct definitions and trait impls
         $(
@@ -179,12 +436,16 @@ macro_rules! endpoints {
             }
 
             $(#[$meta])?
-            impl < $($($req_lt,)+)? $($($resp_lt,)+)? > $crate::Endpoint for $ep_name < $($($req_lt,)+)? $($($resp_lt,)+)? > {
+            impl < $($($req_lt,)+)? $($($resp_lt,)+)? > $crate::Endpoint for $ep_name < $($($req_lt,)+)? $($($resp_lt,)+)? >
+            where
+                $req_ty $(< $($req_lt,)+ >)?: $crate::MustDeriveSchema,
+                $resp_ty $(< $($resp_lt,)+ >)?: $crate::MustDeriveSchema,
+            {
                 type Request = $req_ty $(< $($req_lt,)+ >)?;
                 type Response = $resp_ty $(< $($resp_lt,)+ >)?;
                 const PATH: &'static str = $path_str;
-                const REQ_KEY: $crate::Key = $crate::Key::for_path::<$req_ty>($path_str);
-                const RESP_KEY: $crate::Key = $crate::Key::for_path::<$resp_ty>($path_str);
+                const REQ_KEY: $crate::Key = $crate::endpoints!(@req_key $req_ty, $path_str; $($($keys)*)?);
+                const RESP_KEY: $crate::Key = $crate::endpoints!(@resp_key $resp_ty, $path_str; $($($keys)*)?);
             }
         )*
 
@@ -232,7 +493,10 @@ macro_rules! topic {
         /// Generated by the `topic!()` macro
         pub struct $tyname;
 
-        impl $crate::Topic for $tyname {
+        impl $crate::Topic for $tyname
+        where
+            $msg: $crate::MustDeriveSchema,
+        {
             type Message = $msg;
             const PATH: &'static str = $path;
             const TOPIC_KEY: $crate::Key = $crate::Key::for_path::<$msg>($path);
@@ -367,7 +631,10 @@ macro_rules! topics {
             }
 
             $(#[$meta])?
-            impl $(< $($msg_lt),+ >)? $crate::Topic for $tp_name $(< $($msg_lt,)+ >)? {
+            impl $(< $($msg_lt),+ >)? $crate::Topic for $tp_name $(< $($msg_lt,)+ >)?
+            where
+                $msg_ty $(< $($msg_lt,)+ >)?: $crate::MustDeriveSchema,
+            {
                 type Message = $msg_ty $(< $($msg_lt,)+ >)?;
                 const PATH: &'static str = $path_str;
                 const TOPIC_KEY: $crate::Key = $crate::Key::for_path::<$msg_ty>($path_str);
@@ -429,6 +696,27 @@ macro_rules! sender_fmt {
     }
 }
 
+/// Send a structured log message with the [Sender][crate::server::Sender]
+///
+/// Publishes a log record on the
+/// [`LogRecordTopic`][crate::standard_icd::LogRecordTopic], without a request from
+/// the client. Messages longer than
+/// [`LOG_RECORD_MAX_LEN`][crate::server::LOG_RECORD_MAX_LEN] are truncated, and the
+/// record is dropped if the transport has no room for it, see
+/// [`Sender::log_record()`][crate::server::Sender::log_record].
+///
+/// ```rust,ignore
+/// use postcard_rpc::{rpc_log, standard_icd::LogLevel};
+///
+/// let _ = rpc_log!(sender, LogLevel::Warn, "temperature high: {}C", temp);
+/// ```
+#[macro_export]
+macro_rules! rpc_log {
+    ($sender:expr, $level:expr, $($arg:tt)*) => {
+        $sender.log_record($level, format_args!($($arg)*))
+    };
+}
+
 #[cfg(test)]
 mod endpoints_test {
     use postcard_schema::{schema::owned::OwnedNamedType, Schema};
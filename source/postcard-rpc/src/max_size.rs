@@ -0,0 +1,221 @@
+//! Schema-based maximum serialized size calculation
+//!
+//! These const functions walk the [`NamedType`] of a message, and calculate the
+//! largest number of bytes the message could take up when serialized by `postcard`.
+//!
+//! Types that have no upper bound, such as `String`s, `Vec`s, slices, or maps, result
+//! in `None`.
+
+use postcard_schema::{
+    schema::{DataModelType, DataModelVariant, NamedType, NamedValue},
+    Schema,
+};
+
+use crate::standard_icd::KeyedError;
+
+/// The maximum size of a [`VarHeader`][crate::header::VarHeader] on the wire
+///
+/// This is one discriminant byte, an eight byte key, and a four byte sequence number.
+pub const MAX_HEADER_SIZE: usize = 1 + 8 + 4;
+
+/// The maximum size of the body of an error reply
+///
+/// This is the size of a [`KeyedError`], which is larger than the plain
+/// [`WireError`][crate::standard_icd::WireError] it contains.
+pub const MAX_ERROR_SIZE: usize = match max_size_of(KeyedError::SCHEMA) {
+    Some(sz) => sz,
+    None => panic!("error replies must be bounded in size"),
+};
+
+/// Calculate the maximum serialized size of the given type, if it is bounded
+pub const fn max_size_of(nt: &NamedType) -> Option<usize> {
+    max_size_sdm_type(nt.ty)
+}
+
+/// Calculate the maximum serialized size of a full frame (header and body) carrying
+/// the given type, if it is bounded
+pub const fn max_frame_size_of(nt: &NamedType) -> Option<usize> {
+    match max_size_of(nt) {
+        Some(sz) => Some(MAX_HEADER_SIZE + sz),
+        None => None,
+    }
+}
+
+/// Does a message with the given maximum size fit in a buffer of `buf_len` bytes?
+///
+/// Unbounded messages never fit. This is used for the compile time check of the
+/// `tx_buf_size` of [`define_dispatch!`][crate::define_dispatch], and may be used for
+/// similar checks, for example:
+///
+/// ```rust,ignore
+/// const TX_BUF_SZ: usize = 1024;
+/// const _: () = assert!(postcard_rpc::max_size::fits_in(
+///     MyApp::MAX_RESPONSE_FRAME_SIZE,
+///     TX_BUF_SZ,
+/// ));
+/// ```
+pub const fn fits_in(max_size: Option<usize>, buf_len: usize) -> bool {
+    match max_size {
+        Some(sz) => sz <= buf_len,
+        None => false,
+    }
+}
+
+/// The number of bytes needed to encode `n` as a varint
+const fn varint_size(mut n: u128) -> usize {
+    let mut used = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        used += 1;
+    }
+    used
+}
+
+/// The maximum number of bytes needed to varint-encode a value with `bits` bits
+const fn varint_max_bits(bits: usize) -> usize {
+    (bits + 6) / 7
+}
+
+const fn add(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        _ => None,
+    }
+}
+
+const fn max(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) if a > b => Some(a),
+        (Some(_), Some(b)) => Some(b),
+        _ => None,
+    }
+}
+
+const fn max_size_sdm_type(sdmty: &DataModelType) -> Option<usize> {
+    match sdmty {
+        DataModelType::Bool => Some(1),
+        DataModelType::I8 => Some(1),
+        DataModelType::U8 => Some(1),
+        DataModelType::I16 => Some(varint_max_bits(16)),
+        DataModelType::I32 => Some(varint_max_bits(32)),
+        DataModelType::I64 => Some(varint_max_bits(64)),
+        DataModelType::I128 => Some(varint_max_bits(128)),
+        DataModelType::U16 => Some(varint_max_bits(16)),
+        DataModelType::U32 => Some(varint_max_bits(32)),
+        DataModelType::U64 => Some(varint_max_bits(64)),
+        DataModelType::U128 => Some(varint_max_bits(128)),
+        DataModelType::Usize => Some(varint_max_bits(core::mem::size_of::<usize>() * 8)),
+        DataModelType::Isize => Some(varint_max_bits(core::mem::size_of::<isize>() * 8)),
+        DataModelType::F32 => Some(4),
+        DataModelType::F64 => Some(8),
+        // One length byte, and up to four bytes of UTF-8
+        DataModelType::Char => Some(1 + 4),
+        DataModelType::String => None,
+        DataModelType::ByteArray => None,
+        DataModelType::Option(nt) => add(Some(1), max_size_of(nt)),
+        DataModelType::Unit => Some(0),
+        DataModelType::UnitStruct => Some(0),
+        DataModelType::NewtypeStruct(nt) => max_size_of(nt),
+        DataModelType::Seq(_) => None,
+        DataModelType::Tuple(nts) => max_size_named_types(nts),
+        DataModelType::TupleStruct(nts) => max_size_named_types(nts),
+        DataModelType::Map { .. } => None,
+        DataModelType::Struct(nvs) => max_size_named_values(nvs),
+        DataModelType::Enum(nvs) => {
+            if nvs.is_empty() {
+                return Some(0);
+            }
+            let mut largest = Some(0);
+            let mut idx = 0;
+            while idx < nvs.len() {
+                let var_sz = match nvs[idx].ty {
+                    DataModelVariant::UnitVariant => Some(0),
+                    DataModelVariant::NewtypeVariant(nt) => max_size_of(nt),
+                    DataModelVariant::TupleVariant(nts) => max_size_named_types(nts),
+                    DataModelVariant::StructVariant(nvs) => max_size_named_values(nvs),
+                };
+                largest = max(largest, var_sz);
+                idx += 1;
+            }
+            // The discriminant is encoded as a varint of the variant index
+            add(Some(varint_size((nvs.len() - 1) as u128)), largest)
+        }
+        DataModelType::Schema => None,
+    }
+}
+
+const fn max_size_named_types(nts: &[&NamedType]) -> Option<usize> {
+    let mut ttl = Some(0);
+    let mut idx = 0;
+    while idx < nts.len() {
+        ttl = add(ttl, max_size_of(nts[idx]));
+        idx += 1;
+    }
+    ttl
+}
+
+const fn max_size_named_values(nvs: &[&NamedValue]) -> Option<usize> {
+    let mut ttl = Some(0);
+    let mut idx = 0;
+    while idx < nvs.len() {
+        ttl = add(ttl, max_size_of(nvs[idx].ty));
+        idx += 1;
+    }
+    ttl
+}
+
+#[cfg(test)]
+mod test {
+    use super::max_size_of;
+    use postcard_schema::Schema;
+    use serde::Serialize;
+
+    #[derive(Schema, Serialize)]
+    struct Bounded {
+        a: u8,
+        b: u32,
+        c: [u16; 4],
+        d: Option<i64>,
+    }
+
+    #[derive(Schema, Serialize)]
+    enum BoundedEnum {
+        Alpha,
+        Beta(u32),
+        Gamma { a: u8, b: u8 },
+    }
+
+    #[derive(Schema, Serialize)]
+    struct Unbounded {
+        a: u8,
+        b: Vec<u8>,
+    }
+
+    #[test]
+    fn bounded_sizes() {
+        assert_eq!(max_size_of(<u8 as Schema>::SCHEMA), Some(1));
+        assert_eq!(max_size_of(<u32 as Schema>::SCHEMA), Some(5));
+        assert_eq!(max_size_of(<() as Schema>::SCHEMA), Some(0));
+        assert_eq!(
+            max_size_of(Bounded::SCHEMA),
+            Some(1 + 5 + (4 * 3) + (1 + 10))
+        );
+        assert_eq!(max_size_of(BoundedEnum::SCHEMA), Some(1 + 5));
+
+        let val = Bounded {
+            a: u8::MAX,
+            b: u32::MAX,
+            c: [u16::MAX; 4],
+            d: Some(i64::MIN),
+        };
+        let ser = postcard::to_stdvec(&val).unwrap();
+        assert_eq!(Some(ser.len()), max_size_of(Bounded::SCHEMA));
+    }
+
+    #[test]
+    fn unbounded_sizes() {
+        assert_eq!(max_size_of(<String as Schema>::SCHEMA), None);
+        assert_eq!(max_size_of(<Vec<u32> as Schema>::SCHEMA), None);
+        assert_eq!(max_size_of(Unbounded::SCHEMA), None);
+    }
+}
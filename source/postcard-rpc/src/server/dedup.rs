@@ -0,0 +1,194 @@
+//! Request deduplication for idempotent endpoints
+//!
+//! On unreliable links, a client may retransmit a request using the same sequence
+//! number. For endpoints that are idempotent, the dispatcher can remember the
+//! serialized response for a given request, and send it again instead of running
+//! the handler a second time.
+//!
+//! This is used by the `dedup` handler flavor of [`define_dispatch!`][crate::define_dispatch].
+//! The cache is cleared when the client resets the connection with the
+//! [`ResetEndpoint`][crate::standard_icd::ResetEndpoint].
+
+use serde::Serialize;
+
+use crate::{hash::Fnv1a64Hasher, header::VarSeq, Key};
+
+/// A single cached response
+struct Entry<const M: usize> {
+    key: Key,
+    seq_no: VarSeq,
+    req_hash: u64,
+    resp: heapless::Vec<u8, M>,
+}
+
+/// A bounded cache of recently sent responses
+///
+/// Holds up to `N` responses, each of which may be up to `M` bytes when serialized.
+/// When the cache is full, the oldest response is evicted. Responses that are larger
+/// than `M` bytes are not cached.
+///
+/// Entries are matched by the request key, the sequence number, AND a hash of the
+/// request body. This means that when sequence numbers wrap around, a different
+/// request that reuses the sequence number of a cached request will NOT receive the
+/// stale response, and will replace it in the cache instead.
+///
+/// An identical request that is sent again after the sequence numbers have wrapped
+/// around WILL receive the cached response, if it has not been evicted yet. If this is
+/// a concern, keep `N` small, or use a larger [`VarSeqKind`][crate::header::VarSeqKind].
+pub struct DedupCache<const N: usize, const M: usize> {
+    entries: [Option<Entry<M>>; N],
+    next: usize,
+}
+
+impl<const N: usize, const M: usize> DedupCache<N, M> {
+    /// Create a new, empty, cache
+    pub const fn new() -> Self {
+        Self {
+            entries: [const { None }; N],
+            next: 0,
+        }
+    }
+
+    /// Look up the serialized response for a previously handled request
+    pub fn get(&self, key: Key, seq_no: VarSeq, req_body: &[u8]) -> Option<&[u8]> {
+        let req_hash = Self::hash(req_body);
+        self.entries.iter().flatten().find_map(|e| {
+            let hit = e.key == key && e.seq_no == seq_no && e.req_hash == req_hash;
+            hit.then_some(e.resp.as_slice())
+        })
+    }
+
+    /// Store the response for a handled request
+    ///
+    /// Any existing response with the same key and sequence number is replaced.
+    /// Returns `false` if the response did not fit in `M` bytes, and was not cached.
+    pub fn insert<T: Serialize + ?Sized>(
+        &mut self,
+        key: Key,
+        seq_no: VarSeq,
+        req_body: &[u8],
+        resp: &T,
+    ) -> bool {
+        if N == 0 {
+            return false;
+        }
+
+        let mut buf = heapless::Vec::<u8, M>::new();
+        // Vec<u8, M> can always be filled with M bytes
+        let _ = buf.resize_default(M);
+        let Ok(used) = postcard::to_slice(resp, &mut buf).map(|used| used.len()) else {
+            return false;
+        };
+        buf.truncate(used);
+
+        let entry = Entry {
+            key,
+            seq_no,
+            req_hash: Self::hash(req_body),
+            resp: buf,
+        };
+
+        // Replace any existing entry for this request, e.g. after a wraparound
+        let existing = self
+            .entries
+            .iter_mut()
+            .find(|e| matches!(e, Some(e) if e.key == key && e.seq_no == seq_no));
+        match existing {
+            Some(slot) => *slot = Some(entry),
+            None => {
+                self.entries[self.next] = Some(entry);
+                self.next = (self.next + 1) % N;
+            }
+        }
+        true
+    }
+
+    /// Remove all cached responses
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|e| *e = None);
+        self.next = 0;
+    }
+
+    /// The number of currently cached responses
+    pub fn len(&self) -> usize {
+        self.entries.iter().flatten().count()
+    }
+
+    /// Are there no currently cached responses?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn hash(req_body: &[u8]) -> u64 {
+        let mut hasher = Fnv1a64Hasher::new();
+        hasher.update(req_body);
+        hasher.digest()
+    }
+}
+
+impl<const N: usize, const M: usize> Default for DedupCache<N, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DedupCache;
+    use crate::{header::VarSeq, Key};
+
+    const KEY_A: Key = unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) };
+    const KEY_B: Key = unsafe { Key::from_bytes([8, 7, 6, 5, 4, 3, 2, 1]) };
+
+    #[test]
+    fn hit_and_miss() {
+        let mut cache = DedupCache::<4, 16>::new();
+        assert!(cache.insert(KEY_A, VarSeq::Seq1(1), &[10], &42u32));
+
+        let hit = cache.get(KEY_A, VarSeq::Seq1(1), &[10]).unwrap();
+        assert_eq!(postcard::from_bytes::<u32>(hit).unwrap(), 42);
+
+        // Different key, seq, or body
+        assert!(cache.get(KEY_B, VarSeq::Seq1(1), &[10]).is_none());
+        assert!(cache.get(KEY_A, VarSeq::Seq1(2), &[10]).is_none());
+        assert!(cache.get(KEY_A, VarSeq::Seq1(1), &[11]).is_none());
+    }
+
+    #[test]
+    fn bounded() {
+        let mut cache = DedupCache::<2, 16>::new();
+        assert!(cache.insert(KEY_A, VarSeq::Seq1(1), &[], &1u8));
+        assert!(cache.insert(KEY_A, VarSeq::Seq1(2), &[], &2u8));
+        assert!(cache.insert(KEY_A, VarSeq::Seq1(3), &[], &3u8));
+        assert_eq!(cache.len(), 2);
+
+        // Oldest is evicted
+        assert!(cache.get(KEY_A, VarSeq::Seq1(1), &[]).is_none());
+        assert!(cache.get(KEY_A, VarSeq::Seq1(2), &[]).is_some());
+        assert!(cache.get(KEY_A, VarSeq::Seq1(3), &[]).is_some());
+
+        // Too large to cache
+        assert!(!cache.insert(KEY_A, VarSeq::Seq1(4), &[], &[0xFFu8; 32]));
+        assert!(cache.get(KEY_A, VarSeq::Seq1(4), &[]).is_none());
+
+        let mut empty = DedupCache::<0, 0>::new();
+        assert!(!empty.insert(KEY_A, VarSeq::Seq1(1), &[], &()));
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn wraparound_replaces() {
+        let mut cache = DedupCache::<4, 16>::new();
+        assert!(cache.insert(KEY_A, VarSeq::Seq1(255), &[1], &1u8));
+        assert!(cache.insert(KEY_A, VarSeq::Seq1(0), &[2], &2u8));
+
+        // After wrapping, a new request reuses seq 255 with a different body
+        assert!(cache.get(KEY_A, VarSeq::Seq1(255), &[3]).is_none());
+        assert!(cache.insert(KEY_A, VarSeq::Seq1(255), &[3], &3u8));
+        assert_eq!(cache.len(), 2);
+
+        let hit = cache.get(KEY_A, VarSeq::Seq1(255), &[3]).unwrap();
+        assert_eq!(postcard::from_bytes::<u8>(hit).unwrap(), 3);
+        assert!(cache.get(KEY_A, VarSeq::Seq1(255), &[1]).is_none());
+    }
+}
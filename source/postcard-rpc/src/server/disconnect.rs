@@ -0,0 +1,272 @@
+//! Aborting handlers when the connection is lost
+//!
+//! When the client goes away in the middle of a request, e.g. because the USB cable
+//! was pulled, a `spawn` handler keeps running, and hands its reply to a transport
+//! that may never send it. A [`DisconnectSignal`] tells handlers that the connection
+//! they were started on is gone, so they can stop early.
+//!
+//! The signal is a `static`, passed to [`Server::set_disconnect_signal()`]:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::disconnect::DisconnectSignal;
+//!
+//! // Up to 4 tasks may wait for the signal at the same time
+//! static LINK: DisconnectSignal<4> = DisconnectSignal::new();
+//!
+//! server.set_disconnect_signal(Some(&LINK));
+//! ```
+//!
+//! [`Server::run()`] marks the signal as connected once the connection is ready, and
+//! trips it once receiving fails with [`WireRxErrorKind::ConnectionClosed`], before
+//! calling [`Dispatch::on_disconnected()`]. A transport may trip it earlier: with the
+//! `embassy-usb-0_3-server` feature, registering a `DisconnectHandler` with the USB
+//! builder trips it as soon as the bus is reset or the device is deconfigured, even
+//! while the server is busy with a handler.
+//!
+//! ## In handlers
+//!
+//! The [`Sender`] passed to a handler, and all of its clones, belong to the
+//! connection the request was received on. A handler can check
+//! [`Sender::is_disconnected()`] between the steps of a long operation, or race its
+//! work against [`Sender::disconnected()`], e.g. with
+//! [`with_timeout()`][super::timeout::with_timeout]:
+//!
+//! ```rust,ignore
+//! async fn flash_handler(ctx: SpawnCtx, hdr: VarHeader, req: Flash, tx: Sender<AppTx>) {
+//!     let Some(res) = with_timeout(tx.disconnected(), write_pages(ctx, req)).await else {
+//!         // The client is gone, nobody would receive the reply
+//!         return;
+//!     };
+//!     let _ = tx.reply::<FlashEndpoint>(hdr.seq_no, &res).await;
+//! }
+//! ```
+//!
+//! The work is dropped at its current `.await` point, so it must be cancellation
+//! safe. Handlers without a [`Sender`], like `async` handlers, can keep the `static`
+//! in their context, and use [`DisconnectSignal::connection()`] instead.
+//!
+//! ## In-flight replies
+//!
+//! Once its connection is lost, each frame sent with a [`Sender`] fails right away
+//! with the error of [`WireTx::closed_error()`], which is
+//! [`WireTxErrorKind::ConnectionClosed`] for the transports of this crate. A frame
+//! that is being written when the connection is lost is abandoned, instead of
+//! waiting for a transport that may never complete it. This way, the reply of a
+//! handler that outlives its connection never blocks, and never reaches the client
+//! of a later connection. Transports without a `closed_error()` are still handed
+//! each frame.
+//!
+//! [`Sender`]s obtained with [`Server::sender()`], e.g. for tasks publishing topics,
+//! don't belong to a single connection: they fail while no connection is
+//! established, i.e. before the first one, and from when the signal trips until
+//! [`Server::run()`] is called again.
+//!
+//! [`Server::set_disconnect_signal()`]: super::Server::set_disconnect_signal
+//! [`Server::run()`]: super::Server::run
+//! [`Server::sender()`]: super::Server::sender
+//! [`WireRxErrorKind::ConnectionClosed`]: super::WireRxErrorKind::ConnectionClosed
+//! [`WireTxErrorKind::ConnectionClosed`]: super::WireTxErrorKind::ConnectionClosed
+//! [`Dispatch::on_disconnected()`]: super::Dispatch::on_disconnected
+//! [`WireTx::closed_error()`]: super::WireTx::closed_error
+//! [`Sender`]: super::Sender
+//! [`Sender::is_disconnected()`]: super::Sender::is_disconnected
+//! [`Sender::disconnected()`]: super::Sender::disconnected
+
+use core::{
+    cell::UnsafeCell,
+    future::poll_fn,
+    task::{Context, Poll, Waker},
+};
+
+use portable_atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Something that tells handlers whether their connection was lost, see the
+/// [module docs][self]
+///
+/// The state is a counter that is odd while connected, and is incremented on each
+/// connect and disconnect.
+pub trait ConnectionSignal: Sync {
+    /// The current state, which is odd while connected
+    fn state(&self) -> u32;
+
+    /// Mark the start of a new connection, unless connected already
+    fn connect(&self);
+
+    /// Mark the connection as lost, if connected, and wake all tasks waiting for it
+    fn disconnect(&self);
+
+    /// Register the waker of `cx` to be woken once the state is no longer `seen`
+    ///
+    /// Returns [`Poll::Ready`] if it changed already.
+    fn poll_change(&self, seen: u32, cx: &mut Context<'_>) -> Poll<()>;
+}
+
+/// A single connection, which may be lost
+///
+/// Obtained with [`Sender::connection()`][super::Sender::connection] or
+/// [`DisconnectSignal::connection()`].
+#[derive(Clone, Copy)]
+pub struct Connection {
+    signal: &'static dyn ConnectionSignal,
+    state: u32,
+}
+
+impl Connection {
+    /// The current connection of `signal`
+    ///
+    /// If there is no connection, this is lost already.
+    pub fn current(signal: &'static dyn ConnectionSignal) -> Self {
+        Self::with_state(signal, signal.state())
+    }
+
+    /// The connection of `signal` that started with `state`
+    pub(super) fn with_state(signal: &'static dyn ConnectionSignal, state: u32) -> Self {
+        Self { signal, state }
+    }
+
+    /// Whether this connection was lost, or never established
+    pub fn is_lost(&self) -> bool {
+        self.state & 1 == 0 || self.signal.state() != self.state
+    }
+
+    /// Wait until this connection is lost
+    ///
+    /// Completes right away if it was lost already. This is cancellation safe.
+    pub async fn lost(&self) {
+        poll_fn(|cx| {
+            if self.is_lost() {
+                Poll::Ready(())
+            } else {
+                self.signal.poll_change(self.state, cx)
+            }
+        })
+        .await
+    }
+}
+
+/// A [`ConnectionSignal`] that up to `N` tasks can wait for at the same time
+///
+/// If more tasks wait at once, all waiting tasks are woken each time another one
+/// starts waiting, so that none of them is missed. This works, but wastes time, so
+/// `N` should be at least the number of tasks that wait at the same time, e.g. the
+/// `spawn_limit` of the dispatcher.
+///
+/// Waiting never blocks: if the signal is used by another task at the same time,
+/// e.g. from another core, the waiting task is polled again.
+pub struct DisconnectSignal<const N: usize> {
+    state: AtomicU32,
+    locked: AtomicBool,
+    wakers: UnsafeCell<[Option<Waker>; N]>,
+}
+
+// SAFETY: the wakers are only accessed while holding `locked`
+unsafe impl<const N: usize> Sync for DisconnectSignal<N> {}
+
+impl<const N: usize> DisconnectSignal<N> {
+    /// Create a new signal, which is not connected
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            locked: AtomicBool::new(false),
+            wakers: UnsafeCell::new([const { None }; N]),
+        }
+    }
+
+    /// The current connection of this signal, see [`Connection::current()`]
+    pub fn connection(&'static self) -> Connection {
+        Connection::current(self)
+    }
+
+    /// Wake all waiting tasks
+    ///
+    /// If the wakers are in use, the task holding them wakes them instead, see
+    /// [`ConnectionSignal::poll_change()`].
+    fn wake_all(&self) {
+        if self.locked.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // SAFETY: we hold `locked`
+        let wakers = unsafe { &mut *self.wakers.get() };
+        let woken = core::mem::replace(wakers, [const { None }; N]);
+        self.locked.store(false, Ordering::SeqCst);
+        woken.into_iter().flatten().for_each(Waker::wake);
+    }
+}
+
+impl<const N: usize> Default for DisconnectSignal<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ConnectionSignal for DisconnectSignal<N> {
+    fn state(&self) -> u32 {
+        self.state.load(Ordering::SeqCst)
+    }
+
+    fn connect(&self) {
+        let _ = self
+            .state
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |s| {
+                (s & 1 == 0).then_some(s.wrapping_add(1))
+            });
+    }
+
+    fn disconnect(&self) {
+        let tripped = self
+            .state
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |s| {
+                (s & 1 == 1).then_some(s.wrapping_add(1))
+            });
+        if tripped.is_ok() {
+            self.wake_all();
+        }
+    }
+
+    fn poll_change(&self, seen: u32, cx: &mut Context<'_>) -> Poll<()> {
+        if self.state() != seen {
+            return Poll::Ready(());
+        }
+        if self.locked.swap(true, Ordering::SeqCst) {
+            // Someone else is using the wakers, try again soon
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        // SAFETY: we hold `locked`
+        let wakers = unsafe { &mut *self.wakers.get() };
+        // Replace our waker from an earlier poll, or take a free slot
+        let slot = wakers
+            .iter()
+            .position(|w| w.as_ref().is_some_and(|w| w.will_wake(cx.waker())))
+            .or_else(|| wakers.iter().position(Option::is_none));
+        let evicted = match slot {
+            Some(i) => {
+                wakers[i] = Some(cx.waker().clone());
+                None
+            }
+            // All slots are taken, possibly by tasks that stopped waiting: make room
+            // by waking all of them, the others will register again
+            None => {
+                let evicted = core::mem::replace(wakers, [const { None }; N]);
+                match wakers.first_mut() {
+                    Some(first) => *first = Some(cx.waker().clone()),
+                    None => cx.waker().wake_by_ref(),
+                }
+                Some(evicted)
+            }
+        };
+        self.locked.store(false, Ordering::SeqCst);
+        evicted
+            .into_iter()
+            .flatten()
+            .flatten()
+            .for_each(Waker::wake);
+
+        // A disconnect while we held the wakers could not wake anyone, so do it now
+        if self.state() != seen {
+            self.wake_all();
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,130 @@
+//! Key lookup used by [`define_dispatch!`][crate::define_dispatch]
+//!
+//! Keys are not contiguous, so matching directly on the key of an incoming frame
+//! compiles to a chain of comparisons, one per handler. Instead, the dispatcher
+//! sorts all keys it handles at compile time, and finds the index ("slot") of the
+//! incoming key with a binary search. It then matches on the slot, which can be
+//! compiled to a jump table.
+//!
+//! Keys of all sizes are converted to a `u64` for sorting and searching, using the
+//! `keyN_index` functions.
+
+use crate::{Key, Key1, Key2, Key4};
+
+/// Convert a [`Key1`] to a sortable integer
+pub const fn key1_index(key: Key1) -> u64 {
+    key.to_bytes() as u64
+}
+
+/// Convert a [`Key2`] to a sortable integer
+pub const fn key2_index(key: Key2) -> u64 {
+    u16::from_be_bytes(key.to_bytes()) as u64
+}
+
+/// Convert a [`Key4`] to a sortable integer
+pub const fn key4_index(key: Key4) -> u64 {
+    u32::from_be_bytes(key.to_bytes()) as u64
+}
+
+/// Convert a [`Key`] to a sortable integer
+pub const fn key8_index(key: Key) -> u64 {
+    u64::from_be_bytes(key.to_bytes())
+}
+
+/// Sort the given keys, at compile time
+///
+/// Panics if `keys` does not contain exactly `N` keys.
+pub const fn sorted<const N: usize>(keys: &[u64]) -> [u64; N] {
+    assert!(keys.len() == N);
+    let mut out = [0u64; N];
+    let mut i = 0;
+    while i < N {
+        out[i] = keys[i];
+        i += 1;
+    }
+
+    // Insertion sort, the lists are short and this only runs at compile time
+    let mut i = 1;
+    while i < N {
+        let mut j = i;
+        while j > 0 && out[j - 1] > out[j] {
+            let tmp = out[j - 1];
+            out[j - 1] = out[j];
+            out[j] = tmp;
+            j -= 1;
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Find the slot of `key` in the sorted list of `keys`, at compile time
+///
+/// Panics if `key` is not in the list.
+pub const fn slot_of(keys: &[u64], key: u64) -> usize {
+    let slot = find(keys, key);
+    assert!(slot < keys.len(), "key is not handled by this dispatcher");
+    slot
+}
+
+/// Find the slot of `key` in the sorted list of `keys`
+///
+/// Returns `keys.len()`, which is not a valid slot, if the key is unknown.
+#[inline]
+pub const fn find(keys: &[u64], key: u64) -> usize {
+    let mut lo = 0;
+    let mut hi = keys.len();
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if keys[mid] < key {
+            lo = mid + 1;
+        } else if keys[mid] > key {
+            hi = mid;
+        } else {
+            return mid;
+        }
+    }
+    keys.len()
+}
+
+/// Find the position of the key of each slot in `list`, at compile time
+///
+/// `N` is the number of slots plus one, so the invalid slot of unknown keys can be
+/// looked up as well. Slots whose key is not in `list` get `usize::MAX`.
+pub const fn positions<const N: usize>(keys: &[u64], list: &[u64]) -> [usize; N] {
+    assert!(keys.len() + 1 == N);
+    let mut out = [usize::MAX; N];
+    let mut i = 0;
+    while i < list.len() {
+        let slot = find(keys, list[i]);
+        if slot < keys.len() {
+            out[slot] = i;
+        }
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find, positions, slot_of, sorted};
+
+    #[test]
+    fn sort_and_find() {
+        const KEYS: [u64; 5] = sorted(&[40, 10, 30, 50, 20]);
+        assert_eq!(KEYS, [10, 20, 30, 40, 50]);
+
+        const SLOT: usize = slot_of(&KEYS, 30);
+        assert_eq!(SLOT, 2);
+        for (idx, key) in KEYS.iter().enumerate() {
+            assert_eq!(find(&KEYS, *key), idx);
+        }
+        assert_eq!(find(&KEYS, 0), KEYS.len());
+        assert_eq!(find(&KEYS, 35), KEYS.len());
+        assert_eq!(find(&KEYS, 99), KEYS.len());
+        assert_eq!(find(&[], 1), 0);
+
+        const POS: [usize; 6] = positions(&KEYS, &[50, 20]);
+        assert_eq!(POS, [usize::MAX, 1, usize::MAX, usize::MAX, 0, usize::MAX]);
+    }
+}
@@ -19,6 +19,53 @@
 ///     spawn_impl: WireSpawnImpl;
 ///     // This is the TestContext you define to be passed to all handlers
 ///     context: TestContext;
+///     // OPTIONAL: The size of the TX buffer of the server, checked at compile time
+///     // to fit every response and error frame. See "TX buffer size" below.
+///     tx_buf_size: 1024;
+///     // OPTIONAL: The cache used by `dedup` endpoints, holding up to 8 responses
+///     // of up to 64 bytes each. If omitted, no responses are cached.
+///     dedup: postcard_rpc::server::dedup::DedupCache<8, 64>;
+///     // OPTIONAL: A function called before handling each endpoint request. If it
+///     // returns `Some`, the request is rejected with `WireError::Busy`.
+///     busy: check_busy;
+///     // OPTIONAL: The maximum number of live `spawn` handler tasks. Further
+///     // requests to `spawn` endpoints are rejected with `WireError::Busy`.
+///     max_spawned: 4;
+///     // OPTIONAL: The maximum number of live tasks of each `spawn` endpoint, so
+///     // that a flood of requests to one endpoint doesn't take all the tasks.
+///     max_spawned_per_endpoint: 2;
+///     // OPTIONAL: A `static` `RequestPool`, the buffers requests are deserialized
+///     // from. See the `server::request_pool` module.
+///     request_pool: REQUESTS;
+///     // OPTIONAL: A function returning a future that completes after the given
+///     // number of milliseconds, used by endpoints with a `timeout_ms`.
+///     timer: sleep_ms;
+///     // OPTIONAL: The interval of the `heartbeat_task`, which the client may change
+///     // with the `SetHeartbeatEndpoint`. See the `server::heartbeat` module.
+///     heartbeat: HEARTBEAT;
+///     // OPTIONAL: A `static` `ErrorLog` recording the most recent errors sent,
+///     // read with the `ErrorLogEndpoint`. See the `server::error_log` module.
+///     error_log: ERRORS;
+///     // OPTIONAL: A function preparing to reboot the device, when requested with
+///     // the `RebootEndpoint`. See the "Rebooting" section below.
+///     reboot: prepare_reboot;
+///     // OPTIONAL: Functions called when the connection is ready, and when it
+///     // is lost. See the "Connection events" section below.
+///     on_connected: reset_state;
+///     on_disconnected: stop_motors;
+///     // OPTIONAL: Functions called before and after each received frame is
+///     // dispatched. See the "Dispatch hooks" section below.
+///     on_dispatch_start: start_watch;
+///     on_dispatch_end: stop_watch;
+///     // OPTIONAL: A handler for frames with unknown keys, instead of replying
+///     // with `WireError::UnknownKey`. See the `server::fallback` module.
+///     fallback: forward_handler;
+///     // OPTIONAL: A function deciding whether to accept the token of a request to
+///     // an endpoint marked with `auth`. See the "Access control" section below.
+///     auth: check_token;
+///     // OPTIONAL: Middleware run around every frame, in order. See the
+///     // `server::middleware` module.
+///     middleware: [SESSION_CHECK, Tracer];
 ///
 ///     endpoints: {
 ///         // This is the list you get from the `endpoints()` macro
@@ -29,6 +76,7 @@
 ///         | ----------        | ----      | -------               |
 ///         | AlphaEndpoint     | async     | test_alpha_handler    |
 ///         | BetaEndpoint      | spawn     | test_beta_handler     |
+///         | GammaEndpoint     | dedup     | test_gamma_handler    |
 ///     };
 ///     topics_in: {
 ///         // This is the list you get from the `topics!()` macro
@@ -49,6 +97,576 @@
 ///     };
 /// }
 /// ```
+///
+/// ## Borrowed requests
+///
+/// Requests and topic messages are deserialized directly from the receive buffer,
+/// so types containing `&str` or `&[u8]` do not need to be copied. `blocking`,
+/// `async`, and `dedup` handlers may take these types with a lifetime tied to the
+/// receive buffer, and may also return responses that borrow from the request or
+/// the context:
+///
+/// ```rust,ignore
+/// async fn borrow_handler<'a>(
+///     _context: &mut TestContext,
+///     _header: VarHeader,
+///     body: DoubleMessage<'a, 'a>,
+/// ) -> DoubleMessage<'a, 'a> {
+///     DoubleMessage { data1: body.data2, data2: body.data1 }
+/// }
+/// ```
+///
+/// The borrow only lasts until the handler returns (for `async` handlers, until the
+/// response has been sent), as the receive buffer is reused for the next frame.
+/// `spawn` handlers outlive the receive buffer, and must take owned types.
+///
+/// ## Borrowed responses
+///
+/// `ref` handlers are run like `blocking` handlers, but return a reference to the
+/// response instead of an owned value. The response is serialized directly from
+/// the reference, which avoids building a copy of large, read-only responses, such
+/// as constant tables stored in flash:
+///
+/// ```rust,ignore
+/// static CALIBRATION: Curve = Curve { points: [/* ... */] };
+///
+/// fn get_calibration(_context: &mut TestContext, _header: VarHeader, _body: ()) -> &'static Curve {
+///     &CALIBRATION
+/// }
+/// ```
+///
+/// The reference may also borrow from the context.
+///
+/// ## TX buffer size
+///
+/// Each response is serialized into the TX buffer of the server before it is sent.
+/// The dispatcher calculates the largest response frame any of its endpoint handlers,
+/// or an error reply, could need from their schemas, see
+/// [`max_size`][crate::max_size]. With `tx_buf_size`, compilation fails unless that
+/// frame fits into a buffer of the given size, instead of failing with `SerFailed`
+/// or `ResponseTooLarge` errors once a large response is sent:
+///
+/// ```rust,ignore
+/// define_dispatch! {
+///     // ...
+///     context: TestContext;
+///     tx_buf_size: TX_BUF_SZ;
+///     // ...
+/// }
+/// ```
+///
+/// Responses containing types without an upper bound on their size, such as
+/// `String`s or `Vec`s, never fit, so `tx_buf_size` can only be used if all
+/// responses are bounded.
+///
+/// ## Spawned handlers
+///
+/// `spawn` handlers are run in a separate task, and are given a `Sender` instead of
+/// returning a response. The dispatcher does NOT send anything to the client when the
+/// task is spawned successfully (only a `FailedToSpawn` error if spawning failed), so
+/// the spawned task is responsible for sending the response, using the `seq_no` of the
+/// original request:
+///
+/// ```rust,ignore
+/// async fn spawn_handler(
+///     context: TestSpawnContext,
+///     header: VarHeader,
+///     body: BReq,
+///     sender: Sender<WireTxImpl>,
+/// ) {
+///     // Do some long running work...
+///     let resp = do_work(context, body).await;
+///     let _ = sender.reply::<BetaEndpoint>(header.seq_no, &resp).await;
+/// }
+/// ```
+///
+/// On the client, the request resolves when this response is received, which may be
+/// long after the task was spawned.
+///
+/// ## Concurrency
+///
+/// The server dispatches one frame at a time. `blocking`, `async`, `dedup`, `notify`,
+/// `notify_confirmed`, `transaction`, and `duplex` handlers have exclusive access to
+/// the context, so while one of them is running (including while it awaits), no
+/// other frame is received. Requests to these handlers are therefore handled
+/// strictly in order, even when the client sends them concurrently.
+///
+/// To handle requests concurrently, use `spawn` handlers. The dispatcher returns to
+/// receiving as soon as the task has been spawned, so a slow `spawn` handler does not
+/// stall unrelated frames, and multiple requests to the same `spawn` endpoint run
+/// concurrently, each in its own task. Each task receives its own copy of the
+/// `SpawnCtxt`, and is identified by the `seq_no` of its request.
+///
+/// The number of concurrent tasks is bounded by the spawner, e.g. the `pool_size` of
+/// an embassy task. When the pool is exhausted, the client receives `FailedToSpawn`.
+/// To avoid this, set `max_spawned` to the size of the pool: the dispatcher then
+/// counts the live tasks of `spawn` endpoints, and rejects requests with
+/// `WireError::Busy` once the limit is reached, see the `server::spawn_limit` module.
+/// A task is counted until it completes. `spawn` topic handlers are not counted.
+///
+/// To keep one busy endpoint from taking all the tasks, e.g. a flood of requests to a
+/// slow `spawn` endpoint delaying an unrelated one, also set
+/// `max_spawned_per_endpoint`: requests to an endpoint with that many live tasks are
+/// rejected with `WireError::Busy`, while the remaining tasks are left to the other
+/// endpoints. As tasks complete in any order, so may their replies, which each carry
+/// the `seq_no` of their own request.
+///
+/// To also bound the memory used by the requests in flight, set `request_pool` to a
+/// `static` `RequestPool`: each request to an endpoint is then copied into one of its
+/// buffers, and rejected with `WireError::Busy` when all of them are taken. The
+/// buffer is taken until the handler completes, for `spawn` handlers until the task
+/// completes. See the `server::request_pool` module.
+///
+/// ## Notify handlers
+///
+/// `notify` handlers are used for commands that don't need a response, such as
+/// resetting the device. They are run like `async` handlers, but return `()`, and no
+/// reply is sent to the client. The `Response` type of the endpoint must be `()`.
+/// On the client, use `HostClient::notify()` to send these commands:
+///
+/// ```rust,ignore
+/// async fn set_led(context: &mut TestContext, _header: VarHeader, on: bool) {
+///     context.led.set(on);
+/// }
+/// ```
+///
+/// Errors, such as a request that could not be deserialized, are still sent to the
+/// client as `WireError`s.
+///
+/// To let the client know that a command was handled, without making up a response,
+/// use the `notify_confirmed` kind instead. These handlers are the same as `notify`
+/// handlers, but once the handler returns, the request is acknowledged like with
+/// `Sender::ack()`. On the client, use `HostClient::notify_confirmed()` to wait for
+/// the acknowledgement, or `HostClient::notify()` to ignore it.
+///
+/// ## Transactions
+///
+/// `transaction` handlers take over the connection until they return, e.g. to send a
+/// prompt to the client and wait for its answer before replying. They are run like
+/// `async` handlers, and are also given the `Sender` and a `Transaction`, which
+/// receives the next frames from the client directly, without dispatching them:
+///
+/// ```rust,ignore
+/// async fn confirm_handler<Rx: WireRx>(
+///     context: &mut TestContext,
+///     header: VarHeader,
+///     body: AReq,
+///     sender: &Sender<WireTxImpl>,
+///     mut txn: Transaction<'_, Rx>,
+/// ) -> AResp {
+///     let _ = sender.publish::<PromptTopic>(header.seq_no, &body.0).await;
+///     let mut buf = [0u8; 64];
+///     match txn.recv(&mut buf).await {
+///         Ok((hdr, body)) => { /* ... */ }
+///         Err(_) => { /* ... */ }
+///     }
+/// }
+/// ```
+///
+/// The handler must be generic over the `WireRx`, as the dispatcher may also be used
+/// without a receiver, in which case receiving always fails.
+///
+/// **Beware**: while a transaction is running, no other frame is dispatched, so
+/// requests to ALL other endpoints (including `spawn` endpoints and the standard
+/// endpoints such as ping) are stalled, and may time out on the client. Frames that
+/// are not part of the transaction are received by the handler, and must be
+/// answered by it, or they are lost. Keep transactions short, and give up after a
+/// timeout if the client does not answer. See the `server::transaction` module.
+///
+/// ## Duplex streams
+///
+/// `duplex` handlers exchange a stream of messages with the client in both
+/// directions, e.g. for an interactive shell. The request that opens the stream is
+/// passed to the handler, which is also given the `Sender` and a `Duplex`, to
+/// receive further requests of the client and send responses to it, until either
+/// side closes the stream:
+///
+/// ```rust,ignore
+/// async fn shell_handler<Rx: WireRx>(
+///     context: &mut TestContext,
+///     header: VarHeader,
+///     _first: ShellInput,
+///     sender: &Sender<WireTxImpl>,
+///     duplex: &mut Duplex<'_, ShellEndpoint, WireTxImpl, Rx>,
+/// ) {
+///     let mut buf = [0u8; 64];
+///     while let Ok(Some(input)) = duplex.recv(&mut buf).await {
+///         let _ = duplex.send(&context.shell.run(&input)).await;
+///     }
+/// }
+/// ```
+///
+/// The stream is closed once the handler returns. Like a transaction, a duplex
+/// stream takes over the connection while it is open, and the handler must be
+/// generic over the `WireRx`. On the client, open the stream with
+/// `HostClient::duplex()`. See the `server::duplex` module.
+///
+/// ## Custom replies
+///
+/// `custom` handlers are run like `async` handlers, and are also given the `Sender`.
+/// Instead of the response, they return an `Outcome`, which replies with a
+/// response, with an error, or not at all, for handlers that reply later:
+///
+/// ```rust,ignore
+/// async fn measure_handler(
+///     context: &mut TestContext,
+///     header: VarHeader,
+///     body: AReq,
+///     sender: &Sender<WireTxImpl>,
+/// ) -> Outcome<AResp> {
+///     match context.adc.start(body.0) {
+///         Ok(()) => Outcome::Deferred,
+///         Err(_) => Outcome::Error(WireError::Busy(Busy { retry_after_ms: 10 })),
+///     }
+/// }
+/// ```
+///
+/// A handler may also reply with the `Sender` before it completes, and then return
+/// `Outcome::AlreadyReplied`. Replying AND returning a response is counted as an
+/// error, and the response is not sent.
+///
+/// This allows building other flavors of handlers on top of the macro. See the
+/// `server::outcome` module.
+///
+/// ## Rejecting requests when busy
+///
+/// The optional `busy` function is called with the context and header of each
+/// endpoint request, before the request is handled:
+///
+/// ```rust,ignore
+/// fn check_busy(context: &mut TestContext, _header: &VarHeader) -> Option<Busy> {
+///     if context.workers_in_use >= MAX_WORKERS {
+///         Some(Busy { retry_after_ms: 50 })
+///     } else {
+///         None
+///     }
+/// }
+/// ```
+///
+/// If it returns `Some`, the handler is not called, and the client receives a
+/// `WireError::Busy` error instead, which tells it when to retry. This allows
+/// rejecting requests cleanly, e.g. before a `spawn` handler would fail to spawn.
+/// Topic messages and the standard endpoints are not affected.
+///
+/// ## Connection events
+///
+/// The optional `on_connected` and `on_disconnected` functions are called with the
+/// context, by `Server::run()`:
+///
+/// ```rust,ignore
+/// fn reset_state(context: &mut TestContext) {
+///     context.session = None;
+/// }
+/// ```
+///
+/// `on_connected` is called once the transport is ready, e.g. once the USB host has
+/// configured the device, before the first frame is received. `on_disconnected` is
+/// called when `run` returns because of a fatal error, e.g. because the connection
+/// was lost, so each `on_connected` is followed by exactly one `on_disconnected`.
+/// Tasks that send topic messages on their own can use `Sender::wait_connected()`
+/// to wait for the connection instead.
+///
+/// These hooks run in the dispatch loop, after the handler of the current frame
+/// has returned. To stop `spawn` handlers (or a long `async` handler) as soon as the
+/// connection is lost, use a disconnect signal, see the `server::disconnect`
+/// module.
+///
+/// ## Heartbeats
+///
+/// A device can send periodic heartbeats with `server::heartbeat::heartbeat_task()`,
+/// so the client notices when it hangs. If the dispatcher is given the same
+/// `HeartbeatConfig` with the optional `heartbeat` config item, the client can
+/// change the interval with the standard `SetHeartbeatEndpoint`, and the dispatcher
+/// replies `true`. Without it, the dispatcher replies `false` and nothing changes.
+///
+/// ## Error log
+///
+/// With the optional `error_log` config item, each error sent to the client is
+/// recorded in the given `static` `server::error_log::ErrorLog`, which keeps the
+/// most recent ones. The client reads them with the standard `ErrorLogEndpoint`,
+/// and clears the log by sending `true`. Without it, the dispatcher replies to the
+/// `ErrorLogEndpoint` with `WireError::UnknownKey`.
+///
+/// ## Rebooting
+///
+/// The client can ask the device to reboot with the standard `RebootEndpoint`, e.g.
+/// into its bootloader to update the firmware. How to reboot is specific to the
+/// chip, so this is only supported with the optional `reboot` config item, a
+/// function that is given the requested `RebootMode`, and returns a function that
+/// reboots, or `None` if the mode is not supported:
+///
+/// ```rust,ignore
+/// fn prepare_reboot(context: &mut TestContext, mode: RebootMode) -> Option<impl FnOnce()> {
+///     context.motors.stop();
+///     match mode {
+///         RebootMode::Application => Some(|| {
+///             cortex_m::peripheral::SCB::sys_reset();
+///         }),
+///         RebootMode::Bootloader => None,
+///     }
+/// }
+/// ```
+///
+/// The dispatcher replies `true`, waits until the reply is sent with
+/// `Sender::flush()`, and then calls the returned function, which usually doesn't
+/// return. It replies `false` if the mode is not supported, or without the config
+/// item. See `HostClient::reboot()`.
+///
+/// ## Dispatch hooks
+///
+/// The optional `on_dispatch_start` and `on_dispatch_end` functions are called with
+/// the context and the header of each received frame, by `Server::run()`, right
+/// before and after the frame is dispatched:
+///
+/// ```rust,ignore
+/// fn start_watch(context: &mut TestContext, _header: &VarHeader) {
+///     context.dispatch_since.store(now_ms(), Ordering::Relaxed);
+/// }
+///
+/// fn stop_watch(context: &mut TestContext, _header: &VarHeader) {
+///     context.dispatch_since.store(0, Ordering::Relaxed);
+/// }
+/// ```
+///
+/// This can be used to keep firmware with a hardware watchdog from hanging in a
+/// handler: a separate task feeds the watchdog periodically, unless a dispatch has
+/// been running for too long, in which case it may also publish a message with its
+/// own `Sender`, before the watchdog resets the device. The hooks themselves can't
+/// feed the watchdog reliably, as no hooks are called while waiting for a frame.
+///
+/// The hooks are never called concurrently or nested, and each `on_dispatch_start`
+/// is followed by exactly one `on_dispatch_end`, unless the future of `run` is
+/// dropped. The measured time covers the handler, and sending its reply, but not
+/// `spawn` handlers, which only start their task. Frames received by a
+/// `transaction` handler are part of its dispatch. As the hooks delay receiving
+/// the next frame, they should return quickly.
+///
+/// ## Request length limits
+///
+/// An endpoint may limit the length of the requests it accepts, by adding
+/// `[max_len = N]` after the endpoint type:
+///
+/// ```rust,ignore
+/// | EndpointTy                        | kind      | handler           |
+/// | ----------                        | ----      | -------           |
+/// | UploadEndpoint [max_len = 256]    | async     | upload_handler    |
+/// ```
+///
+/// Requests with a body longer than `N` bytes are rejected with a
+/// `WireError::FrameTooLong` error, BEFORE being deserialized, so a malicious or
+/// malformed request can't cause large collections to be built. Without a limit,
+/// requests are only limited by the size of the receive buffer.
+///
+/// ## Handler timeouts
+///
+/// An `async`, `custom`, `dedup`, `notify`, `notify_confirmed` or `transaction` endpoint
+/// may limit how long its handler runs, by adding `[timeout_ms = N]` after the endpoint
+/// type (before any `compress` modifier). This requires the `timer` config item:
+///
+/// ```rust,ignore
+/// | EndpointTy                        | kind      | handler           |
+/// | ----------                        | ----      | -------           |
+/// | FooEndpoint [timeout_ms = 500]    | async     | foo_handler       |
+/// ```
+///
+/// A handler still running after `N` milliseconds is dropped, and the client
+/// receives a `WireError::HandlerTimeout` error. See the `server::timeout` module.
+///
+/// ## Compressed responses
+///
+/// Responses of an endpoint may be compressed, by adding `[compress = true]` after
+/// the endpoint type (after any `max_len` or `timeout_ms` modifier):
+///
+/// ```rust,ignore
+/// | EndpointTy                        | kind      | handler           |
+/// | ----------                        | ----      | -------           |
+/// | ReadLogEndpoint [compress = true] | async     | read_log_handler  |
+/// ```
+///
+/// The body of each response is then compressed with a simple run-length encoding,
+/// if this makes it shorter, and the `HostClient` decompresses it before it is
+/// deserialized. This is worthwhile for large responses with repetitive contents,
+/// but only adds overhead for small ones, so it is opt-in per endpoint. The
+/// `Sender` given to `spawn` handlers compresses their replies as well. See the
+/// `compress` module for details.
+///
+/// ## Access control
+///
+/// Privileged endpoints may require the client to send an `AuthToken` along with the
+/// request, by adding `[auth = true]` after the endpoint type (after any other
+/// modifiers). This requires the `auth` config item, a function that decides whether
+/// to accept the token:
+///
+/// ```rust,ignore
+/// fn check_token(context: &mut TestContext, _header: &VarHeader, token: &AuthToken) -> bool {
+///     context.session == Some(*token)
+/// }
+///
+/// // ...
+///
+/// | EndpointTy                        | kind      | handler           |
+/// | ----------                        | ----      | -------           |
+/// | EraseEndpoint [auth = true]       | async     | erase_handler     |
+/// ```
+///
+/// Requests to these endpoints without a token, or with a token that isn't
+/// accepted, are rejected with `WireError::Unauthorized` before the handler runs.
+/// Clients attach a token to all of their frames with `HostClient::set_auth_token()`.
+/// The token is carried in the frame header, see the `header` module, and only the
+/// token of the request itself is checked, e.g. not that of a later frame received by
+/// a `transaction` handler. Standard endpoints, topics, and the `fallback` handler
+/// never require a token.
+///
+/// ## Unknown keys
+///
+/// Frames with a key that doesn't match any endpoint or topic are answered with
+/// `WireError::UnknownKey`. With the optional `fallback` handler, they are passed to
+/// it instead, along with their raw body, which allows forwarding them elsewhere:
+///
+/// ```rust,ignore
+/// async fn forward_handler(
+///     context: &mut TestContext,
+///     header: VarHeader,
+///     body: &[u8],
+///     sender: &Sender<WireTxImpl>,
+/// ) -> Outcome {
+///     // ...
+/// }
+/// ```
+///
+/// See the `server::fallback` module for details.
+///
+/// ## Conditional endpoints
+///
+/// Like the `endpoints!` and `topics!` macros, the `endpoints` and `topics_in`
+/// tables take an optional `Cfg` column, containing a condition for each row:
+///
+/// ```rust,ignore
+/// | EndpointTy        | kind      | handler           | Cfg                           |
+/// | ----------        | ----      | -------           | ---                           |
+/// | PingEndpoint      | blocking  | ping_handler      |                               |
+/// | MotorEndpoint     | async     | motor_handler     | cfg(feature = "motor")        |
+/// ```
+///
+/// When the condition does not hold, the row is removed entirely: there is no match
+/// arm, the handler is not referenced (and may be cfg'd out as well), and the key is
+/// not part of the dispatch table. Requests to the excluded endpoint are answered
+/// with `WireError::UnknownKey`, as for any other unknown endpoint. The same
+/// condition should usually be used in the `endpoints!` list.
+///
+/// ## Endpoint aliases
+///
+/// Renaming the path of an endpoint changes its keys, so hosts built with the old
+/// path receive `UnknownKey` errors. To keep them working, define an endpoint with
+/// the old path and the same request and response types, list it in the
+/// `endpoints!` list, and mark it as an alias of the renamed endpoint, using the
+/// same handler:
+///
+/// ```rust,ignore
+/// // endpoints!: | FooEndpoint       | FooReq | FooResp | "foo"     |
+/// //             | OldFooEndpoint    | FooReq | FooResp | "old_foo" |
+///
+/// | EndpointTy                                | kind      | handler       |
+/// | ----------                                | ----      | -------       |
+/// | FooEndpoint                               | async     | foo_handler   |
+/// | OldFooEndpoint [alias_of = FooEndpoint]   | async     | foo_handler   |
+/// ```
+///
+/// Requests to the alias are handled like those to any other endpoint, and the
+/// response is sent with the key of the alias, as expected by the old host. The
+/// `aliases` of the dispatcher's `DeviceMap` list the request keys of each alias,
+/// and of the endpoint it stands in for. It is a compile time error for the alias to
+/// have different request or response types than the endpoint.
+///
+/// ## Endpoint groups
+///
+/// Endpoints can be put into a named group, e.g. by the subsystem they belong to,
+/// so that a client listing the device's endpoints can organize them. The group is
+/// given as the first option after the endpoint type:
+///
+/// ```rust,ignore
+/// | EndpointTy                                | kind      | handler       |
+/// | ----------                                | ----      | -------       |
+/// | MotorSetEndpoint [group = "motor"]        | async     | motor_set     |
+/// | MotorStopEndpoint [group = "motor"]       | blocking  | motor_stop    |
+/// | LedSetEndpoint [group = "led"]            | blocking  | led_set       |
+/// ```
+///
+/// Groups don't change how requests are dispatched. The `groups` of the
+/// dispatcher's `DeviceMap` list the group name and request key of each grouped
+/// endpoint, and clients can fetch them with the standard `GetGroupsEndpoint`.
+///
+/// ## Middleware
+///
+/// The optional `middleware` list contains paths to `static`s or unit structs that
+/// implement `server::middleware::Middleware`. Their `before` hook is called for
+/// every frame, before it is dispatched, and may reject the frame with a
+/// `WireError`. This allows e.g. checking a session token without changing every
+/// handler:
+///
+/// ```rust,ignore
+/// struct SessionCheck {
+///     unlocked: AtomicBool,
+/// }
+///
+/// impl Middleware for SessionCheck {
+///     fn before(&self, hdr: &VarHeader, _body: &[u8]) -> ControlFlow<WireError, ()> {
+///         if hdr.key == UNLOCK_KEY || self.unlocked.load(Ordering::Relaxed) {
+///             ControlFlow::Continue(())
+///         } else {
+///             ControlFlow::Break(WireError::UnknownKey)
+///         }
+///     }
+/// }
+/// ```
+///
+/// Unlike the `busy` hook, middleware sees all frames, including topic messages
+/// and the standard endpoints.
+///
+/// ## Testing the routing
+///
+/// The generated `try_dispatch` method returns a `server::DispatchDecision`, telling
+/// which endpoint or topic a frame would go to, and whether its body can be
+/// deserialized, without running any handler or sending anything:
+///
+/// ```rust,ignore
+/// let app = MyApp::new(context, spawn);
+/// let hdr = VarHeader { key: VarKey::Key8(AlphaEndpoint::REQ_KEY), seq_no: VarSeq::Seq1(0) };
+/// assert_eq!(app.try_dispatch(&hdr, &[1]), DispatchDecision::Handle(AlphaEndpoint::REQ_KEY));
+/// ```
+///
+/// Middleware, the `auth`, `busy` and `fallback` hooks, length limits and the
+/// `request_pool` are not consulted, as they depend on the state of the dispatcher.
+///
+/// ## Handler signatures
+///
+/// Each endpoint handler is checked against the `Request` and `Response` types of
+/// its endpoint, so a handler assigned to the wrong endpoint results in an error
+/// like "`beta_handler` is not a valid `async` handler for the endpoint
+/// `AlphaEndpoint`". See the `server::handler_check` module for the expected
+/// signature of each kind of handler.
+///
+/// ## Inline and generic handlers
+///
+/// The `handler` column accepts any expression, as long as it is a single token
+/// tree. Plain function names can be used as-is, while closures and generic paths
+/// must be wrapped in braces or parentheses. The argument types of a closure are
+/// inferred from the endpoint, so they do not need to be annotated:
+///
+/// ```rust,ignore
+/// endpoints: {
+///     list: ENDPOINT_LIST;
+///
+///     | EndpointTy     | kind      | handler                                              |
+///     | ----------     | ----      | -------                                              |
+///     | AlphaEndpoint  | async     | { |_ctx, _hdr, req| async move { AResp(req.0) } }    |
+///     | BetaEndpoint   | blocking  | { |ctx, _hdr, req| ctx.handle_beta(req) }            |
+///     | GammaEndpoint  | async     | (generic_handler::<Periph>)                          |
+/// };
+/// ```
+///
+/// On Rust 1.85 and newer, `async` closures (`{ async |ctx, hdr, req| { ... } }`)
+/// may be used as well.
 #[macro_export]
 macro_rules! define_dispatch {
     //////////////////////////////////////////////////////////////////////////////
@@ -56,62 +674,432 @@ macro_rules! define_dispatch {
     //////////////////////////////////////////////////////////////////////////////
 
     // This is the "blocking execution" arm for defining an endpoint
-    (@ep_arm blocking ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@ep_arm blocking ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
         {
-            let reply = $handler($context, $header.clone(), $req);
-            if $outputter.reply::<$endpoint>($header.seq_no, &reply).await.is_err() {
-                let err = $crate::standard_icd::WireError::SerFailed;
-                $outputter.error($header.seq_no, err).await
-            } else {
-                Ok(())
-            }
+            $crate::define_dispatch!(@no_timeout blocking $timeout);
+            let handler = $crate::server::handler_check::blocking_endpoint::<$endpoint, _, _>($handler, &$context);
+            let reply = handler($context, $header.clone(), $req);
+            $crate::define_dispatch!(@reply ($endpoint) $outputter $header $stats &reply)
+        }
+    };
+    // This is the "blocking execution, borrowed response" arm for defining an endpoint
+    (@ep_arm ref ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
+        {
+            $crate::define_dispatch!(@no_timeout ref $timeout);
+            let handler = $crate::server::handler_check::ref_endpoint::<$endpoint, _, _>($handler, &$context);
+            let reply = handler($context, $header.clone(), $req);
+            $crate::define_dispatch!(@reply ($endpoint) $outputter $header $stats reply)
         }
     };
     // This is the "async execution" arm for defining an endpoint
-    (@ep_arm async ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@ep_arm async ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
+        {
+            let handler = $crate::server::handler_check::async_endpoint::<$endpoint, _, _, _>($handler, &$context);
+            let fut = handler($context, $header.clone(), $req);
+            let Some(reply) = $crate::define_dispatch!(@timed $timeout $timer fut) else {
+                $stats.record_error();
+                let err = $crate::standard_icd::WireError::HandlerTimeout;
+                return $outputter.error_for(&$header, err).await;
+            };
+            $crate::define_dispatch!(@reply ($endpoint) $outputter $header $stats &reply)
+        }
+    };
+    // This is the "async execution, no reply" arm for defining an endpoint
+    (@ep_arm notify ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
+        {
+            let handler = $crate::server::handler_check::notify_endpoint::<$endpoint, _, _, _>($handler, &$context);
+            let fut = handler($context, $header.clone(), $req);
+            if $crate::define_dispatch!(@timed $timeout $timer fut).is_none() {
+                $stats.record_error();
+                let err = $crate::standard_icd::WireError::HandlerTimeout;
+                return $outputter.error_for(&$header, err).await;
+            }
+            Ok(())
+        }
+    };
+    // This is the "async execution, acknowledged once handled" arm for defining an endpoint
+    (@ep_arm notify_confirmed ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
         {
-            let reply = $handler($context, $header.clone(), $req).await;
-            if $outputter.reply::<$endpoint>($header.seq_no, &reply).await.is_err() {
-                let err = $crate::standard_icd::WireError::SerFailed;
-                $outputter.error($header.seq_no, err).await
+            let handler = $crate::server::handler_check::notify_endpoint::<$endpoint, _, _, _>($handler, &$context);
+            let fut = handler($context, $header.clone(), $req);
+            if $crate::define_dispatch!(@timed $timeout $timer fut).is_none() {
+                $stats.record_error();
+                let err = $crate::standard_icd::WireError::HandlerTimeout;
+                return $outputter.error_for(&$header, err).await;
+            }
+            if let Err(e) = $outputter.ack($header.seq_no).await {
+                $stats.record_error();
+                let kind = $crate::server::AsWireTxErrorKind::as_kind(&e);
+                $outputter.reply_failed(&$header, kind).await
             } else {
                 Ok(())
             }
         }
     };
+    // This is the "async execution, handler decides how to reply" arm for defining an endpoint
+    (@ep_arm custom ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
+        {
+            let handler = $crate::server::handler_check::custom_endpoint::<$endpoint, _, _, _, _>($handler, &$context, &$outputter);
+            let replies = $outputter.replies();
+            let fut = handler($context, $header.clone(), $req, $outputter);
+            let Some(outcome) = $crate::define_dispatch!(@timed $timeout $timer fut) else {
+                $stats.record_error();
+                let err = $crate::standard_icd::WireError::HandlerTimeout;
+                return $outputter.error_for(&$header, err).await;
+            };
+            // Did the handler reply early, and does it say so?
+            let Some(outcome) = outcome.check_replied($outputter.replies() != replies) else {
+                $stats.record_error();
+                return Ok(());
+            };
+            if outcome.is_error() {
+                $stats.record_error();
+            }
+            outcome.send::<$endpoint, _>($outputter, &$header).await
+        }
+    };
+    // This is the "async execution, receiving follow-up frames" arm for defining an endpoint
+    (@ep_arm transaction ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
+        {
+            let txn = $crate::server::transaction::Transaction::new($rx);
+            let handler = $crate::server::handler_check::transaction_endpoint::<$endpoint, _, _, _, _, _>($handler, &$context, &$outputter, &txn);
+            let fut = handler($context, $header.clone(), $req, $outputter, txn);
+            let Some(reply) = $crate::define_dispatch!(@timed $timeout $timer fut) else {
+                $stats.record_error();
+                let err = $crate::standard_icd::WireError::HandlerTimeout;
+                return $outputter.error_for(&$header, err).await;
+            };
+            $crate::define_dispatch!(@reply ($endpoint) $outputter $header $stats &reply)
+        }
+    };
+    // This is the "async execution, full duplex stream" arm for defining an endpoint
+    (@ep_arm duplex ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
+        {
+            $crate::define_dispatch!(@no_timeout duplex $timeout);
+            let mut duplex = $crate::server::duplex::Duplex::<$endpoint, _, _>::new($header.clone(), $outputter, $rx);
+            let duplex_ref = &mut duplex;
+            let handler = $crate::server::handler_check::duplex_endpoint::<$endpoint, _, _, _, _, _>($handler, &$context, &$outputter, &duplex_ref);
+            handler($context, $header.clone(), $req, $outputter, duplex_ref).await;
+            // Close the stream, unless the handler or the client already did
+            match duplex.close().await {
+                Err($crate::server::duplex::DuplexError::Tx(kind)) => {
+                    $stats.record_error();
+                    $outputter.reply_failed(&$header, kind).await
+                }
+                _ => Ok(()),
+            }
+        }
+    };
     // This is the "spawn an embassy task" arm for defining an endpoint
-    (@ep_arm spawn ($endpoint:ty) $handler:ident $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@ep_arm spawn ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
         {
-            let context = $crate::server::SpawnContext::spawn_ctxt($context);
-            if $spawn_fn($spawner, $handler(context, $header.clone(), $req, $outputter.clone())).is_err() {
-                let err = $crate::standard_icd::WireError::FailedToSpawn;
-                $outputter.error($header.seq_no, err).await
+            $crate::define_dispatch!(@no_timeout spawn $timeout);
+            // The live tasks of this endpoint, for `max_spawned_per_endpoint`
+            static LIVE: $crate::server::spawn_limit::KeyCount = $crate::server::spawn_limit::KeyCount::new();
+            // Are there too many live tasks already?
+            if let Some(permit) = Self::spawn_permit(&LIVE) {
+                let context = $crate::server::SpawnContext::spawn_ctxt($context);
+                let handler = $crate::server::handler_check::spawn_endpoint::<$endpoint, _, _, _, _>($handler, &context, $outputter);
+                // The task holds the permit and the request buffer until it completes and
+                // drops its sender
+                let sender = $outputter.clone().with_permit(permit).with_request_buf($pooled);
+                if $spawn_fn($spawner, handler(context, $header.clone(), $req, sender)).is_err() {
+                    $stats.record_error();
+                    let err = $crate::standard_icd::WireError::FailedToSpawn;
+                    $outputter.error_for(&$header, err).await
+                } else {
+                    Ok(())
+                }
             } else {
-                Ok(())
+                $stats.record_error();
+                let busy = $crate::standard_icd::Busy { retry_after_ms: 0 };
+                let err = $crate::standard_icd::WireError::Busy(busy);
+                $outputter.error_for(&$header, err).await
             }
         }
     };
 
+    // This is the "async execution, with deduplication" arm for defining an endpoint
+    (@ep_arm dedup ($endpoint:ty) $handler:tt $context:ident $header:ident $req:ident $outputter:ident ($spawn_fn:path) $spawner:ident $dedup:ident $stats:ident $body:ident $rx:ident $pooled:ident $timeout:tt $timer:tt) => {
+        {
+            let key = <$endpoint as $crate::Endpoint>::REQ_KEY;
+            // Is this a retransmission of a request we've already handled?
+            if let Some(cached) = $dedup.get(key, $header.seq_no, $body) {
+                match postcard::from_bytes::<<$endpoint as $crate::Endpoint>::Response>(cached) {
+                    Ok(reply) => {
+                        return $crate::define_dispatch!(@reply ($endpoint) $outputter $header $stats &reply);
+                    }
+                    // The cached response is unusable, count it and run the handler again
+                    Err(_) => $stats.record_error(),
+                }
+            }
+            let handler = $crate::server::handler_check::async_endpoint::<$endpoint, _, _, _>($handler, &$context);
+            let fut = handler($context, $header.clone(), $req);
+            let Some(reply) = $crate::define_dispatch!(@timed $timeout $timer fut) else {
+                $stats.record_error();
+                let err = $crate::standard_icd::WireError::HandlerTimeout;
+                return $outputter.error_for(&$header, err).await;
+            };
+            $dedup.insert(key, $header.seq_no, $body, &reply);
+            $crate::define_dispatch!(@reply ($endpoint) $outputter $header $stats &reply)
+        }
+    };
+
+    // Send the reply of a handler, handling a failure to send it
+    (@reply ($endpoint:ty) $outputter:ident $header:ident $stats:ident $reply:expr) => {
+        if let Err(e) = $outputter.reply::<$endpoint>($header.seq_no, $reply).await {
+            $stats.record_error();
+            let kind = $crate::server::AsWireTxErrorKind::as_kind(&e);
+            $outputter.reply_failed(&$header, kind).await
+        } else {
+            Ok(())
+        }
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // DEDUP CACHE TYPE
+    //////////////////////////////////////////////////////////////////////////////
+
+    // No cache configured, use a zero sized cache
+    (@dedup_ty) => {
+        $crate::server::dedup::DedupCache<0, 0>
+    };
+    (@dedup_ty $dedup_ty:ty) => {
+        $dedup_ty
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // REQUEST LENGTH LIMIT
+    //////////////////////////////////////////////////////////////////////////////
+
+    // No limit, other than the size of the receive buffer
+    (@max_len ()) => {
+        None::<usize>
+    };
+    (@max_len ($max_len:expr)) => {
+        Some::<usize>($max_len)
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // RESPONSE COMPRESSION
+    //////////////////////////////////////////////////////////////////////////////
+
+    // Not compressed, use the sender as-is
+    (@compress $tx:ident ()) => {};
+    (@compress $tx:ident ($compress:literal)) => {
+        let $tx = &$tx.clone().with_compression($compress);
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // HANDLER TIMEOUTS
+    //////////////////////////////////////////////////////////////////////////////
+
+    // No timeout, run the handler to completion
+    (@timed () $timer:tt $fut:expr) => {
+        Some($fut.await)
+    };
+    (@timed ($timeout:expr) ($timer_fn:path) $fut:expr) => {
+        $crate::server::timeout::with_timeout($timer_fn($timeout), $fut).await
+    };
+    (@timed ($timeout:expr) () $fut:expr) => {
+        compile_error!("Endpoints with a `timeout_ms` require a `timer` function")
+    };
+    // Handlers that can't time out
+    (@no_timeout $flavor:tt ()) => {};
+    (@no_timeout $flavor:tt ($timeout:expr)) => {
+        compile_error!(concat!(
+            "`timeout_ms` is not supported for `", stringify!($flavor), "` handlers"
+        ))
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // ACCESS CONTROL
+    //////////////////////////////////////////////////////////////////////////////
+
+    // No token required
+    (@auth_check () $auth:tt $context:tt $header:ident $token:ident) => {
+        true
+    };
+    (@auth_check (false) $auth:tt $context:tt $header:ident $token:ident) => {
+        true
+    };
+    (@auth_check (true) ($auth_fn:path) $context:tt $header:ident $token:ident) => {
+        match $token.as_ref() {
+            Some(token) => $auth_fn($context, $header, token),
+            None => false,
+        }
+    };
+    (@auth_check (true) () $context:tt $header:ident $token:ident) => {
+        compile_error!("Endpoints with `auth = true` require an `auth` function")
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // ALIASES
+    //////////////////////////////////////////////////////////////////////////////
+
+    // Not an alias
+    (@alias_of $endpoint:ty) => {
+        None::<($crate::Key, $crate::Key)>
+    };
+    (@alias_of $endpoint:ty, $target:ty) => {
+        Some($crate::server::handler_check::alias_of::<$endpoint, $target>())
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // GROUPS
+    //////////////////////////////////////////////////////////////////////////////
+
+    // Not in a group
+    (@group $endpoint:ty) => {
+        None::<(&'static str, $crate::Key)>
+    };
+    (@group $endpoint:ty, $group:literal) => {
+        Some(($group, <$endpoint as $crate::Endpoint>::REQ_KEY))
+    };
+
+    //////////////////////////////////////////////////////////////////////////////
+    // BUSY HOOK
+    //////////////////////////////////////////////////////////////////////////////
+
+    // No hook configured, never busy
+    (@busy_check $context:ident $header:ident) => {
+        {
+            let _ = ($context, $header);
+            None
+        }
+    };
+    (@busy_check $context:ident $header:ident $busy_fn:path) => {
+        $busy_fn($context, $header)
+    };
+
+    // No hook configured, nothing to do
+    (@lifecycle $context:ident) => {
+        {
+            let _ = $context;
+        }
+    };
+    (@lifecycle $context:ident $hook_fn:path) => {
+        $hook_fn($context)
+    };
+    (@dispatch_hook $context:ident $header:ident) => {
+        {
+            let _ = ($context, $header);
+        }
+    };
+    (@dispatch_hook $context:ident $header:ident $hook_fn:path) => {
+        $hook_fn($context, $header)
+    };
+
+    // No fallback configured, unknown keys are an error
+    (@fallback $dispatch:ident $tx:ident $header:ident $body:ident ()) => {
+        {
+            $dispatch.stats.record_error();
+            let err = $crate::standard_icd::WireError::UnknownKey;
+            $tx.error_for($header, err).await
+        }
+    };
+    (@fallback $dispatch:ident $tx:ident $header:ident $body:ident ($fallback_fn:path)) => {
+        match $fallback_fn(&mut $dispatch.context, $header.clone(), $body, $tx).await {
+            $crate::server::outcome::Outcome::Reply(never) => match never {},
+            $crate::server::outcome::Outcome::Deferred => Ok(()),
+            $crate::server::outcome::Outcome::AlreadyReplied => Ok(()),
+            $crate::server::outcome::Outcome::Error(err) => {
+                $dispatch.stats.record_error();
+                $tx.error_for($header, err).await
+            }
+        }
+    };
+
+    // No heartbeat config, so no heartbeats to configure
+    (@set_heartbeat () $interval:ident) => {
+        {
+            let _ = $interval;
+            false
+        }
+    };
+    (@set_heartbeat ($heartbeat_cfg:path) $interval:ident) => {
+        {
+            $heartbeat_cfg.set_interval_ms($interval);
+            true
+        }
+    };
+
+    // No error log configured, errors are only counted in the stats
+    (@error_log ()) => {
+        None
+    };
+    (@error_log ($error_log_cfg:path)) => {
+        Some(&$error_log_cfg)
+    };
+    (@send_error_log () $tx:ident $hdr:ident $clear:ident) => {
+        {
+            let _ = $clear;
+            $tx.error_for($hdr, $crate::standard_icd::WireError::UnknownKey).await
+        }
+    };
+    (@send_error_log ($error_log_cfg:path) $tx:ident $hdr:ident $clear:ident) => {
+        $tx.send_error_log($hdr, &$error_log_cfg, $clear).await
+    };
+
+    // No reboot function, so rebooting is not supported
+    (@reboot () $context:expr, $tx:ident $hdr:ident $mode:ident) => {
+        {
+            let _ = $mode;
+            $tx.reply::<$crate::standard_icd::RebootEndpoint>($hdr.seq_no, &false).await
+        }
+    };
+    (@reboot ($reboot_fn:path) $context:expr, $tx:ident $hdr:ident $mode:ident) => {
+        match $reboot_fn($context, $mode) {
+            Some(reboot) => {
+                // Reboot even if the reply is lost, the client is told so by the disconnect
+                let res = $tx.reply::<$crate::standard_icd::RebootEndpoint>($hdr.seq_no, &true).await;
+                let _ = $tx.flush().await;
+                reboot();
+                res
+            }
+            None => $tx.reply::<$crate::standard_icd::RebootEndpoint>($hdr.seq_no, &false).await,
+        }
+    };
+
+    // No limit configured, spawn until the spawner fails
+    (@max_spawned) => {
+        usize::MAX
+    };
+    (@max_spawned $max_spawned:expr) => {
+        $max_spawned
+    };
+
+    // No pool configured, deserialize requests from the receive buffer
+    (@request_buf ()) => {
+        Some(None::<$crate::server::request_pool::PooledBuf>)
+    };
+    (@request_buf ($request_pool:path)) => {
+        $request_pool.try_take().map(Some)
+    };
+
     //////////////////////////////////////////////////////////////////////////////
     // TOPIC HANDLER EXPANSION ARMS
     //////////////////////////////////////////////////////////////////////////////
 
     // This is the "blocking execution" arm for defining a topic
-    (@tp_arm blocking $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@tp_arm blocking $handler:tt $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
-            $handler($context, $header.clone(), $msg, $outputter);
+            let handler = $handler;
+            handler($context, $header.clone(), $msg, $outputter);
         }
     };
     // This is the "async execution" arm for defining a topic
-    (@tp_arm async $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@tp_arm async $handler:tt $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
-            $handler($context, $header.clone(), $msg, $outputter).await;
+            let handler = $handler;
+            handler($context, $header.clone(), $msg, $outputter).await;
         }
     };
-    (@tp_arm spawn $handler:ident $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
+    (@tp_arm spawn $handler:tt $context:ident $header:ident $msg:ident $outputter:ident ($spawn_fn:path) $spawner:ident) => {
         {
             let context = $crate::server::SpawnContext::spawn_ctxt($context);
-            let _ = $spawn_fn($spawner, $handler(context, $header.clone(), $msg, $outputter.clone()));
+            let handler = $handler;
+            let _ = $spawn_fn($spawner, handler(context, $header.clone(), $msg, $outputter.clone()));
         }
     };
 
@@ -123,93 +1111,469 @@ macro_rules! define_dispatch {
     //////////////////////////////////////////////////////////////////////////////
     (@matcher
         $n:literal $app_name:ident $tx_impl:ty; $spawn_fn:ident $key_ty:ty; $key_kind:expr;
-        $req_key_name:ident / $topic_key_name:ident = $bytes_ty:ty;
-        ($($endpoint:ty | $ep_flavor:tt | $ep_handler:ident)*)
-        ($($topic_in:ty | $tp_flavor:tt | $tp_handler:ident)*)
+        $req_key_name:ident / $topic_key_name:ident = $to_index:path;
+        middleware: [$($mw:path),*];
+        request_pool: $request_pool:tt;
+        timer: $timer:tt;
+        heartbeat: $heartbeat:tt;
+        error_log: $error_log:tt;
+        reboot: $reboot:tt;
+        fallback: $fallback:tt;
+        auth: $auth:tt;
+        ($($endpoint:ty | $ep_flavor:tt | $ep_handler:tt | $ep_max_len:tt | $ep_timeout:tt | $ep_compress:tt | $ep_auth:tt | [$($ep_meta:meta)?])*)
+        ($($topic_in:ty | $tp_flavor:tt | $tp_handler:tt | [$($tp_meta:meta)?])*)
     ) => {
-        impl $crate::server::Dispatch for $app_name<$n> {
-            type Tx = $tx_impl;
+        const _: () = {
+            // All keys handled by this dispatcher, sorted so we can binary search for the
+            // slot of an incoming key, see `server::dispatch_index`
+            const UNSORTED_KEYS: &[u64] = &[
+                $to_index(<$crate::standard_icd::PingEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::GetAllSchemasEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::HandshakeEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::CompatEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::GetGroupsEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::EchoFrameEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::GetStatsEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::HasEndpointEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::ResetEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::SetHeartbeatEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::ErrorLogEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::RebootEndpoint as $crate::Endpoint>::$req_key_name),
+                $($(#[$ep_meta])? $to_index(<$endpoint as $crate::Endpoint>::$req_key_name),)*
+                $($(#[$tp_meta])? $to_index(<$topic_in as $crate::Topic>::$topic_key_name),)*
+            ];
+            const KEYS: [u64; UNSORTED_KEYS.len()] = $crate::server::dispatch_index::sorted(UNSORTED_KEYS);
 
-            fn min_key_len(&self) -> $crate::header::VarKeyKind {
-                $key_kind
+            // The index of the counter of each slot in the `DispatchStats`, in the same
+            // order as the handler keys of the dispatcher
+            const STATS_INDEX: [usize; KEYS.len() + 1] = $crate::server::dispatch_index::positions(&KEYS, &[
+                $($(#[$ep_meta])? $to_index(<$endpoint as $crate::Endpoint>::$req_key_name),)*
+                $($(#[$tp_meta])? $to_index(<$topic_in as $crate::Topic>::$topic_key_name),)*
+            ]);
+
+            // Only the keys of endpoints, used to answer `HasEndpointEndpoint` requests
+            const UNSORTED_EP_KEYS: &[u64] = &[
+                $to_index(<$crate::standard_icd::PingEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::GetAllSchemasEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::HandshakeEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::CompatEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::GetGroupsEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::EchoFrameEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::GetStatsEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::HasEndpointEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::ResetEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::SetHeartbeatEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::ErrorLogEndpoint as $crate::Endpoint>::$req_key_name),
+                $to_index(<$crate::standard_icd::RebootEndpoint as $crate::Endpoint>::$req_key_name),
+                $($(#[$ep_meta])? $to_index(<$endpoint as $crate::Endpoint>::$req_key_name),)*
+            ];
+            const EP_KEYS: [u64; UNSORTED_EP_KEYS.len()] = $crate::server::dispatch_index::sorted(UNSORTED_EP_KEYS);
+
+            // The slot of each endpoint and topic, usable as a pattern
+            struct EpSlot<E>(core::marker::PhantomData<E>);
+            impl<E: $crate::Endpoint> EpSlot<E> {
+                const SLOT: usize = $crate::server::dispatch_index::slot_of(&KEYS, $to_index(E::$req_key_name));
             }
+            struct TpSlot<T>(core::marker::PhantomData<T>);
+            impl<T: $crate::Topic> TpSlot<T> {
+                const SLOT: usize = $crate::server::dispatch_index::slot_of(&KEYS, $to_index(T::$topic_key_name));
+            }
+
+            impl $crate::server::Dispatch for $app_name<$n> {
+                type Tx = $tx_impl;
+
+                fn min_key_len(&self) -> $crate::header::VarKeyKind {
+                    $key_kind
+                }
 
-            /// Handle dispatching of a single frame
-            async fn handle(
-                &mut self,
-                tx: &$crate::server::Sender<Self::Tx>,
-                hdr: &$crate::header::VarHeader,
-                body: &[u8],
-            ) -> Result<(), <Self::Tx as $crate::server::WireTx>::Error> {
-                let key = hdr.key;
-                let Some(keyb) = <$key_ty>::try_from_varkey(&key) else {
-                    let err = $crate::standard_icd::WireError::KeyTooSmall;
-                    return tx.error(hdr.seq_no, err).await;
-                };
-                match keyb {
-                    // Standard ICD endpoints
-                    <$crate::standard_icd::PingEndpoint as $crate::Endpoint>::$req_key_name => {
-                        // Can we deserialize the request?
-                        let Ok(req) = postcard::from_bytes::<<$crate::standard_icd::PingEndpoint as $crate::Endpoint>::Request>(body) else {
-                            let err = $crate::standard_icd::WireError::DeserFailed;
-                            return tx.error(hdr.seq_no, err).await;
-                        };
-
-                        tx.reply::<$crate::standard_icd::PingEndpoint>(hdr.seq_no, &req).await
-                    },
-                    <$crate::standard_icd::GetAllSchemasEndpoint as $crate::Endpoint>::$req_key_name => {
-                        tx.send_all_schemas(hdr, self.device_map).await
+                fn error_log(&self) -> Option<&'static dyn $crate::server::error_log::RecordError> {
+                    $crate::define_dispatch!(@error_log $error_log)
+                }
+
+                fn on_connected(&mut self) {
+                    Self::connected_hook(&mut self.context)
+                }
+
+                fn on_disconnected(&mut self) {
+                    Self::disconnected_hook(&mut self.context)
+                }
+
+                fn on_dispatch_start(&mut self, hdr: &$crate::header::VarHeader) {
+                    Self::dispatch_start_hook(&mut self.context, hdr)
+                }
+
+                fn on_dispatch_end(&mut self, hdr: &$crate::header::VarHeader) {
+                    Self::dispatch_end_hook(&mut self.context, hdr)
+                }
+
+                fn set_token(&mut self, token: Option<$crate::header::AuthToken>) {
+                    self.token = token;
+                }
+
+                fn set_max_frame_len(&mut self, len: usize) {
+                    self.max_frame_len = u32::try_from(len).unwrap_or(u32::MAX);
+                }
+
+                /// Handle dispatching of a single frame
+                async fn handle(
+                    &mut self,
+                    tx: &$crate::server::Sender<Self::Tx>,
+                    hdr: &$crate::header::VarHeader,
+                    body: &[u8],
+                ) -> Result<(), <Self::Tx as $crate::server::WireTx>::Error> {
+                    self.handle_with_middleware(tx, hdr, body, None::<&mut $crate::server::transaction::NoRx>).await
+                }
+
+                /// Handle dispatching of a single frame, with a receiver for transactions
+                async fn handle_with_rx<Rx: $crate::server::WireRx>(
+                    &mut self,
+                    tx: &$crate::server::Sender<Self::Tx>,
+                    hdr: &$crate::header::VarHeader,
+                    body: &[u8],
+                    rx: &mut Rx,
+                ) -> Result<(), <Self::Tx as $crate::server::WireTx>::Error> {
+                    self.handle_with_middleware(tx, hdr, body, Some(rx)).await
+                }
+            }
+
+            impl $app_name<$n> {
+                /// Find out how a frame would be dispatched, without running any handler or
+                /// sending anything, see [`DispatchDecision`][$crate::server::DispatchDecision]
+                pub fn try_dispatch(
+                    &self,
+                    hdr: &$crate::header::VarHeader,
+                    body: &[u8],
+                ) -> $crate::server::DispatchDecision {
+                    use $crate::server::DispatchDecision::*;
+
+                    let Some(keyb) = <$key_ty>::try_from_varkey(&hdr.key) else {
+                        return KeyTooSmall;
+                    };
+                    let slot = $crate::server::dispatch_index::find(&KEYS, $to_index(keyb));
+                    match slot {
+                        <EpSlot<$crate::standard_icd::PingEndpoint>>::SLOT => {
+                            $crate::define_dispatch!(@decide_ep ($crate::standard_icd::PingEndpoint) body)
+                        }
+                        <EpSlot<$crate::standard_icd::GetAllSchemasEndpoint>>::SLOT => {
+                            // The body of this request is ignored
+                            Handle(<$crate::standard_icd::GetAllSchemasEndpoint as $crate::Endpoint>::REQ_KEY)
+                        }
+                        <EpSlot<$crate::standard_icd::HandshakeEndpoint>>::SLOT => {
+                            // The body of this request is ignored
+                            Handle(<$crate::standard_icd::HandshakeEndpoint as $crate::Endpoint>::REQ_KEY)
+                        }
+                        <EpSlot<$crate::standard_icd::CompatEndpoint>>::SLOT => {
+                            // The body of this request is ignored
+                            Handle(<$crate::standard_icd::CompatEndpoint as $crate::Endpoint>::REQ_KEY)
+                        }
+                        <EpSlot<$crate::standard_icd::GetGroupsEndpoint>>::SLOT => {
+                            // The body of this request is ignored
+                            Handle(<$crate::standard_icd::GetGroupsEndpoint as $crate::Endpoint>::REQ_KEY)
+                        }
+                        <EpSlot<$crate::standard_icd::EchoFrameEndpoint>>::SLOT => {
+                            // The body of this request is echoed without deserializing it
+                            Handle(<$crate::standard_icd::EchoFrameEndpoint as $crate::Endpoint>::REQ_KEY)
+                        }
+                        <EpSlot<$crate::standard_icd::GetStatsEndpoint>>::SLOT => {
+                            $crate::define_dispatch!(@decide_ep ($crate::standard_icd::GetStatsEndpoint) body)
+                        }
+                        <EpSlot<$crate::standard_icd::HasEndpointEndpoint>>::SLOT => {
+                            $crate::define_dispatch!(@decide_ep ($crate::standard_icd::HasEndpointEndpoint) body)
+                        }
+                        <EpSlot<$crate::standard_icd::ResetEndpoint>>::SLOT => {
+                            // The body of this request is ignored
+                            Handle(<$crate::standard_icd::ResetEndpoint as $crate::Endpoint>::REQ_KEY)
+                        }
+                        <EpSlot<$crate::standard_icd::SetHeartbeatEndpoint>>::SLOT => {
+                            $crate::define_dispatch!(@decide_ep ($crate::standard_icd::SetHeartbeatEndpoint) body)
+                        }
+                        <EpSlot<$crate::standard_icd::ErrorLogEndpoint>>::SLOT => {
+                            $crate::define_dispatch!(@decide_ep ($crate::standard_icd::ErrorLogEndpoint) body)
+                        }
+                        <EpSlot<$crate::standard_icd::RebootEndpoint>>::SLOT => {
+                            $crate::define_dispatch!(@decide_ep ($crate::standard_icd::RebootEndpoint) body)
+                        }
+                        $(
+                            $(#[$ep_meta])?
+                            <EpSlot<$endpoint>>::SLOT => {
+                                $crate::define_dispatch!(@decide_ep ($endpoint) body)
+                            }
+                        )*
+                        $(
+                            $(#[$tp_meta])?
+                            <TpSlot<$topic_in>>::SLOT => {
+                                let key = <$topic_in as $crate::Topic>::TOPIC_KEY;
+                                match postcard::from_bytes::<<$topic_in as $crate::Topic>::Message>(body) {
+                                    Ok(_) => Handle(key),
+                                    Err(_) => DeserFailed(key),
+                                }
+                            }
+                        )*
+                        _other => Unknown,
                     }
-                    // end
-                    $(
-                        <$endpoint as $crate::Endpoint>::$req_key_name => {
+                }
+
+                // Run the middleware around dispatching a single frame
+                async fn handle_with_middleware<Rx: $crate::server::WireRx>(
+                    &mut self,
+                    tx: &$crate::server::Sender<$tx_impl>,
+                    hdr: &$crate::header::VarHeader,
+                    body: &[u8],
+                    rx: Option<&mut Rx>,
+                ) -> Result<(), <$tx_impl as $crate::server::WireTx>::Error> {
+                    let slot = match <$key_ty>::try_from_varkey(&hdr.key) {
+                        Some(keyb) => $crate::server::dispatch_index::find(&KEYS, $to_index(keyb)),
+                        None => KEYS.len(),
+                    };
+                    self.stats.record_frame(STATS_INDEX[slot]);
+
+                    // Should any middleware reject this frame?
+                    let middleware: &[&dyn $crate::server::middleware::Middleware] = &[$(&$mw),*];
+                    if let Err((ran, err)) = $crate::server::middleware::run_before(middleware, hdr, body) {
+                        self.stats.record_error();
+                        let res = tx.error_for(hdr, err).await;
+                        $crate::server::middleware::run_after(middleware, ran, hdr);
+                        return res;
+                    }
+
+                    let res = self.handle_frame(tx, hdr, body, rx).await;
+                    $crate::server::middleware::run_after(middleware, middleware.len(), hdr);
+                    res
+                }
+
+                // Dispatch a single frame to its handler. Only `transaction` handlers use `rx`
+                #[allow(unused_variables)]
+                async fn handle_frame<Rx: $crate::server::WireRx>(
+                    &mut self,
+                    tx: &$crate::server::Sender<$tx_impl>,
+                    hdr: &$crate::header::VarHeader,
+                    body: &[u8],
+                    rx: Option<&mut Rx>,
+                ) -> Result<(), <$tx_impl as $crate::server::WireTx>::Error> {
+                    // The token only applies to this frame
+                    let token = self.token.take();
+                    let key = hdr.key;
+                    let Some(keyb) = <$key_ty>::try_from_varkey(&key) else {
+                        self.stats.record_error();
+                        let err = $crate::standard_icd::WireError::KeyTooSmall;
+                        return tx.error_for(hdr, err).await;
+                    };
+                    // Unknown keys get an invalid slot, and end up in the fallthrough below
+                    let slot = $crate::server::dispatch_index::find(&KEYS, $to_index(keyb));
+                    match slot {
+                        // Standard ICD endpoints
+                        <EpSlot<$crate::standard_icd::PingEndpoint>>::SLOT => {
                             // Can we deserialize the request?
-                            let Ok(req) = postcard::from_bytes::<<$endpoint as $crate::Endpoint>::Request>(body) else {
+                            let Ok(req) = postcard::from_bytes::<<$crate::standard_icd::PingEndpoint as $crate::Endpoint>::Request>(body) else {
+                                self.stats.record_error();
                                 let err = $crate::standard_icd::WireError::DeserFailed;
-                                return tx.error(hdr.seq_no, err).await;
+                                return tx.error_for(hdr, err).await;
                             };
 
-                            // Store some items as named bindings, so we can use `ident` in the
-                            // recursive macro expansion. Load bearing order: we borrow `context`
-                            // from `dispatch` because we need `dispatch` AFTER `context`, so NLL
-                            // allows this to still borrowck
-                            let dispatch = self;
-                            let context = &mut dispatch.context;
-                            #[allow(unused)]
-                            let spawninfo = &dispatch.spawn;
+                            tx.reply::<$crate::standard_icd::PingEndpoint>(hdr.seq_no, &req).await
+                        },
+                        <EpSlot<$crate::standard_icd::GetAllSchemasEndpoint>>::SLOT => {
+                            tx.send_all_schemas(hdr, self.device_map).await
+                        }
+                        <EpSlot<$crate::standard_icd::HandshakeEndpoint>>::SLOT => {
+                            tx.send_handshake(hdr, self.device_map).await
+                        }
+                        <EpSlot<$crate::standard_icd::CompatEndpoint>>::SLOT => {
+                            tx.send_compat(hdr, self.device_map, self.max_frame_len).await
+                        }
+                        <EpSlot<$crate::standard_icd::GetGroupsEndpoint>>::SLOT => {
+                            tx.send_groups(hdr, self.device_map).await
+                        }
+                        <EpSlot<$crate::standard_icd::EchoFrameEndpoint>>::SLOT => {
+                            tx.send_echo_frame(hdr, body).await
+                        }
+                        <EpSlot<$crate::standard_icd::GetStatsEndpoint>>::SLOT => {
+                            // Can we deserialize the request?
+                            let Ok(reset) = postcard::from_bytes::<<$crate::standard_icd::GetStatsEndpoint as $crate::Endpoint>::Request>(body) else {
+                                self.stats.record_error();
+                                let err = $crate::standard_icd::WireError::DeserFailed;
+                                return tx.error_for(hdr, err).await;
+                            };
 
-                            // This will expand to the right "flavor" of handler
-                            $crate::define_dispatch!(@ep_arm $ep_flavor ($endpoint) $ep_handler context hdr req tx ($spawn_fn) spawninfo)
+                            tx.send_stats(hdr, &mut self.stats, reset).await
                         }
-                    )*
-                    $(
-                        <$topic_in as $crate::Topic>::$topic_key_name => {
+                        <EpSlot<$crate::standard_icd::HasEndpointEndpoint>>::SLOT => {
                             // Can we deserialize the request?
-                            let Ok(msg) = postcard::from_bytes::<<$topic_in as $crate::Topic>::Message>(body) else {
-                                // This is a topic, not much to be done
-                                return Ok(());
+                            let Ok(key) = postcard::from_bytes::<<$crate::standard_icd::HasEndpointEndpoint as $crate::Endpoint>::Request>(body) else {
+                                self.stats.record_error();
+                                let err = $crate::standard_icd::WireError::DeserFailed;
+                                return tx.error_for(hdr, err).await;
                             };
 
-                            // Store some items as named bindings, so we can use `ident` in the
-                            // recursive macro expansion. Load bearing order: we borrow `context`
-                            // from `dispatch` because we need `dispatch` AFTER `context`, so NLL
-                            // allows this to still borrowck
-                            let dispatch = self;
-                            let context = &mut dispatch.context;
-                            #[allow(unused)]
-                            let spawninfo = &dispatch.spawn;
+                            let key = $crate::header::VarKey::Key8(key);
+                            let found = match <$key_ty>::try_from_varkey(&key) {
+                                Some(keyb) => {
+                                    let idx = $crate::server::dispatch_index::find(&EP_KEYS, $to_index(keyb));
+                                    idx < EP_KEYS.len()
+                                }
+                                None => false,
+                            };
+                            tx.reply::<$crate::standard_icd::HasEndpointEndpoint>(hdr.seq_no, &found).await
+                        }
+                        <EpSlot<$crate::standard_icd::ResetEndpoint>>::SLOT => {
+                            // Forget the responses cached before the reset, and start a new epoch
+                            self.dedup.clear();
+                            self.epoch = self.epoch.wrapping_add(1);
+                            tx.reply::<$crate::standard_icd::ResetEndpoint>(hdr.seq_no, &self.epoch).await
+                        }
+                        <EpSlot<$crate::standard_icd::SetHeartbeatEndpoint>>::SLOT => {
+                            // Can we deserialize the request?
+                            let Ok(interval_ms) = postcard::from_bytes::<<$crate::standard_icd::SetHeartbeatEndpoint as $crate::Endpoint>::Request>(body) else {
+                                self.stats.record_error();
+                                let err = $crate::standard_icd::WireError::DeserFailed;
+                                return tx.error_for(hdr, err).await;
+                            };
 
-                            $crate::define_dispatch!(@tp_arm $tp_flavor $tp_handler context hdr msg tx ($spawn_fn) spawninfo);
-                            Ok(())
+                            let supported = $crate::define_dispatch!(@set_heartbeat $heartbeat interval_ms);
+                            tx.reply::<$crate::standard_icd::SetHeartbeatEndpoint>(hdr.seq_no, &supported).await
                         }
-                    )*
-                    _other => {
-                        // huh! We have no idea what this key is supposed to be!
-                        let err = $crate::standard_icd::WireError::UnknownKey;
-                        tx.error(hdr.seq_no, err).await
-                    },
+                        <EpSlot<$crate::standard_icd::ErrorLogEndpoint>>::SLOT => {
+                            // Can we deserialize the request?
+                            let Ok(clear) = postcard::from_bytes::<<$crate::standard_icd::ErrorLogEndpoint as $crate::Endpoint>::Request>(body) else {
+                                self.stats.record_error();
+                                let err = $crate::standard_icd::WireError::DeserFailed;
+                                return tx.error_for(hdr, err).await;
+                            };
+
+                            $crate::define_dispatch!(@send_error_log $error_log tx hdr clear)
+                        }
+                        <EpSlot<$crate::standard_icd::RebootEndpoint>>::SLOT => {
+                            // Can we deserialize the request?
+                            let Ok(mode) = postcard::from_bytes::<<$crate::standard_icd::RebootEndpoint as $crate::Endpoint>::Request>(body) else {
+                                self.stats.record_error();
+                                let err = $crate::standard_icd::WireError::DeserFailed;
+                                return tx.error_for(hdr, err).await;
+                            };
+
+                            $crate::define_dispatch!(@reboot $reboot (&mut self.context), tx hdr mode)
+                        }
+                        // end
+                        $(
+                            $(#[$ep_meta])?
+                            <EpSlot<$endpoint>>::SLOT => {
+                                // Is the client allowed to use this endpoint?
+                                if !$crate::define_dispatch!(@auth_check $ep_auth $auth (&mut self.context) hdr token) {
+                                    self.stats.record_error();
+                                    let err = $crate::standard_icd::WireError::Unauthorized;
+                                    return tx.error_for(hdr, err).await;
+                                }
+
+                                // Should we reject this request, without handling it?
+                                if let Some(busy) = Self::check_busy(&mut self.context, hdr) {
+                                    self.stats.record_error();
+                                    let err = $crate::standard_icd::WireError::Busy(busy);
+                                    return tx.error_for(hdr, err).await;
+                                }
+
+                                // Is the request longer than this endpoint accepts?
+                                if let Some(max) = $crate::define_dispatch!(@max_len $ep_max_len) {
+                                    if body.len() > max {
+                                        self.stats.record_error();
+                                        let err = $crate::standard_icd::WireError::FrameTooLong($crate::standard_icd::FrameTooLong {
+                                            len: body.len() as u32,
+                                            max: max as u32,
+                                        });
+                                        return tx.error_for(hdr, err).await;
+                                    }
+                                }
+
+                                // Is there a buffer left for the request?
+                                let Some(mut pooled) = $crate::define_dispatch!(@request_buf $request_pool) else {
+                                    self.stats.record_error();
+                                    let busy = $crate::standard_icd::Busy { retry_after_ms: 0 };
+                                    let err = $crate::standard_icd::WireError::Busy(busy);
+                                    return tx.error_for(hdr, err).await;
+                                };
+                                let req_bytes = match pooled.as_mut() {
+                                    Some(buf) if body.len() > buf.len() => {
+                                        self.stats.record_error();
+                                        let err = $crate::standard_icd::WireError::FrameTooLong($crate::standard_icd::FrameTooLong {
+                                            len: body.len() as u32,
+                                            max: buf.len() as u32,
+                                        });
+                                        return tx.error_for(hdr, err).await;
+                                    }
+                                    Some(buf) => {
+                                        buf[..body.len()].copy_from_slice(body);
+                                        &buf[..body.len()]
+                                    }
+                                    None => body,
+                                };
+
+                                // Can we deserialize the request?
+                                let Ok(req) = postcard::from_bytes::<<$endpoint as $crate::Endpoint>::Request>(req_bytes) else {
+                                    self.stats.record_error();
+                                    let err = $crate::standard_icd::WireError::DeserFailed;
+                                    return tx.error_for(hdr, err).await;
+                                };
+
+                                // Store some items as named bindings, so we can use `ident` in the
+                                // recursive macro expansion. Load bearing order: we borrow `context`
+                                // from `dispatch` because we need `dispatch` AFTER `context`, so NLL
+                                // allows this to still borrowck
+                                let dispatch = self;
+                                let context = &mut dispatch.context;
+                                #[allow(unused)]
+                                let spawninfo = &dispatch.spawn;
+                                #[allow(unused)]
+                                let dedup = &mut dispatch.dedup;
+                                #[allow(unused)]
+                                let stats = &mut dispatch.stats;
+
+                                // Should the reply be compressed?
+                                $crate::define_dispatch!(@compress tx $ep_compress);
+
+                                // This will expand to the right "flavor" of handler
+                                $crate::define_dispatch!(@ep_arm $ep_flavor ($endpoint) $ep_handler context hdr req tx ($spawn_fn) spawninfo dedup stats body rx pooled $ep_timeout $timer)
+                            }
+                        )*
+                        $(
+                            $(#[$tp_meta])?
+                            <TpSlot<$topic_in>>::SLOT => {
+                                // Can we deserialize the request?
+                                let Ok(msg) = postcard::from_bytes::<<$topic_in as $crate::Topic>::Message>(body) else {
+                                    // This is a topic, not much to be done
+                                    self.stats.record_error();
+                                    return Ok(());
+                                };
+
+                                // Store some items as named bindings, so we can use `ident` in the
+                                // recursive macro expansion. Load bearing order: we borrow `context`
+                                // from `dispatch` because we need `dispatch` AFTER `context`, so NLL
+                                // allows this to still borrowck
+                                let dispatch = self;
+                                let context = &mut dispatch.context;
+                                #[allow(unused)]
+                                let spawninfo = &dispatch.spawn;
+
+                                $crate::define_dispatch!(@tp_arm $tp_flavor $tp_handler context hdr msg tx ($spawn_fn) spawninfo);
+                                Ok(())
+                            }
+                        )*
+                        _other => {
+                            // huh! We have no idea what this key is supposed to be!
+                            let dispatch = self;
+                            $crate::define_dispatch!(@fallback dispatch tx hdr body $fallback)
+                        },
+                    }
                 }
             }
+        };
+    };
+
+    // Can the body be deserialized as the request of the endpoint, see `try_dispatch`
+    (@decide_ep ($endpoint:ty) $body:ident) => {
+        {
+            let key = <$endpoint as $crate::Endpoint>::REQ_KEY;
+            match postcard::from_bytes::<<$endpoint as $crate::Endpoint>::Request>($body) {
+                Ok(_) => $crate::server::DispatchDecision::Handle(key),
+                Err(_) => $crate::server::DispatchDecision::DeserFailed(key),
+            }
         }
     };
 
@@ -223,20 +1587,37 @@ macro_rules! define_dispatch {
         tx_impl: $tx_impl:ty;
         spawn_impl: $spawn_impl:ty;
         context: $context_ty:ty;
+        $(tx_buf_size: $tx_buf_size:expr;)?
+        $(dedup: $dedup_ty:ty;)?
+        $(busy: $busy_fn:path;)?
+        $(max_spawned: $max_spawned:expr;)?
+        $(max_spawned_per_endpoint: $max_spawned_per_ep:expr;)?
+        $(request_pool: $request_pool:path;)?
+        $(timer: $timer_fn:path;)?
+        $(heartbeat: $heartbeat_cfg:path;)?
+        $(error_log: $error_log_cfg:path;)?
+        $(reboot: $reboot_fn:path;)?
+        $(on_connected: $connected_fn:path;)?
+        $(on_disconnected: $disconnected_fn:path;)?
+        $(on_dispatch_start: $dispatch_start_fn:path;)?
+        $(on_dispatch_end: $dispatch_end_fn:path;)?
+        $(fallback: $fallback_fn:path;)?
+        $(auth: $auth_fn:path;)?
+        $(middleware: [$($mw:path),* $(,)?];)?
 
         endpoints: {
             list: $endpoint_list:ident;
 
-               | EndpointTy     | kind          | handler           |
-               | $(-)*          | $(-)*         | $(-)*             |
-            $( | $endpoint:ty   | $ep_flavor:tt | $ep_handler:ident | )*
+               | EndpointTy     | kind          | handler           | $( Cfg |)?
+               | $(-)*          | $(-)*         | $(-)*             | $($(-)* |)?
+            $( | $endpoint:ty $([group = $ep_group:literal])? $([alias_of = $ep_target:ty])? $([max_len = $ep_max_len:expr])? $([timeout_ms = $ep_timeout:expr])? $([compress = $ep_compress:literal])? $([auth = $ep_auth:literal])? | $ep_flavor:tt | $ep_handler:tt | $($ep_meta:meta)? $(|)? )*
         };
         topics_in: {
             list: $topic_in_list:ident;
 
-               | TopicTy        | kind          | handler           |
-               | $(-)*          | $(-)*         | $(-)*             |
-            $( | $topic_in:ty   | $tp_flavor:tt | $tp_handler:ident | )*
+               | TopicTy        | kind          | handler           | $( Cfg |)?
+               | $(-)*          | $(-)*         | $(-)*             | $($(-)* |)?
+            $( | $topic_in:ty   | $tp_flavor:tt | $tp_handler:tt | $($tp_meta:meta)? $(|)? )*
         };
         topics_out: {
             list: $topic_out_list:ident;
@@ -305,13 +1686,13 @@ macro_rules! define_dispatch {
             //
             // This should be a SUBSET of the REQUEST KEYS in the Endpoint report
             const EP_HANDLER_IN_KEYS: &[Key] = &[
-                $(<$endpoint as $crate::Endpoint>::REQ_KEY,)*
+                $($(#[$ep_meta])? <$endpoint as $crate::Endpoint>::REQ_KEY,)*
             ];
             // This is a list of all RESPONSE KEYS in the actual handlers
             //
             // This should be a SUBSET of the RESPONSE KEYS in the Endpoint report
             const EP_HANDLER_OUT_KEYS: &[Key] = &[
-                $(<$endpoint as $crate::Endpoint>::RESP_KEY,)*
+                $($(#[$ep_meta])? <$endpoint as $crate::Endpoint>::RESP_KEY,)*
             ];
             // This is a list of all TOPIC KEYS in the actual handlers
             //
@@ -319,7 +1700,7 @@ macro_rules! define_dispatch {
             // (we can't check the out, we have no way of enumerating that yet,
             // which would require linkme-like crimes I think)
             const TP_HANDLER_IN_KEYS: &[Key] = &[
-                $(<$topic_in as $crate::Topic>::TOPIC_KEY,)*
+                $($(#[$tp_meta])? <$topic_in as $crate::Topic>::TOPIC_KEY,)*
             ];
 
             const fn a_is_subset_of_b(a: &[Key], b: &[Key]) -> bool {
@@ -372,6 +1753,109 @@ macro_rules! define_dispatch {
                     NEEDED_SZ_OUT
                 }
             };
+
+            // This is a list of all REQUEST and TOPIC KEYS in the actual handlers,
+            // used for per-key counters
+            pub const HANDLER_KEYS_SZ: usize = EP_HANDLER_IN_KEYS.len() + TP_HANDLER_IN_KEYS.len();
+            pub const HANDLER_KEYS: [Key; HANDLER_KEYS_SZ] = const {
+                let mut keys = [unsafe { Key::from_bytes([0; 8]) }; HANDLER_KEYS_SZ];
+                let mut i = 0;
+                while i < EP_HANDLER_IN_KEYS.len() {
+                    keys[i] = EP_HANDLER_IN_KEYS[i];
+                    i += 1;
+                }
+                let mut j = 0;
+                while j < TP_HANDLER_IN_KEYS.len() {
+                    keys[i + j] = TP_HANDLER_IN_KEYS[j];
+                    j += 1;
+                }
+                keys
+            };
+
+            // This is a list of the aliases, and the endpoints they stand in for
+            const EP_HANDLER_ALIASES: &[Option<(Key, Key)>] = &[
+                $($(#[$ep_meta])? $crate::define_dispatch!(@alias_of $endpoint $(, $ep_target)?),)*
+            ];
+            pub const ALIASES_SZ: usize = const {
+                let mut count = 0;
+                let mut i = 0;
+                while i < EP_HANDLER_ALIASES.len() {
+                    if EP_HANDLER_ALIASES[i].is_some() {
+                        count += 1;
+                    }
+                    i += 1;
+                }
+                count
+            };
+            pub const ALIASES: [(Key, Key); ALIASES_SZ] = const {
+                let mut aliases = [unsafe { (Key::from_bytes([0; 8]), Key::from_bytes([0; 8])) }; ALIASES_SZ];
+                let mut i = 0;
+                let mut j = 0;
+                while i < EP_HANDLER_ALIASES.len() {
+                    if let Some(alias) = EP_HANDLER_ALIASES[i] {
+                        aliases[j] = alias;
+                        j += 1;
+                    }
+                    i += 1;
+                }
+                aliases
+            };
+
+            // This is a list of the groups of all endpoints
+            const EP_HANDLER_GROUPS: &[Option<(&'static str, Key)>] = &[
+                $($(#[$ep_meta])? $crate::define_dispatch!(@group $endpoint $(, $ep_group)?),)*
+            ];
+            pub const GROUPS_SZ: usize = const {
+                let mut count = 0;
+                let mut i = 0;
+                while i < EP_HANDLER_GROUPS.len() {
+                    if EP_HANDLER_GROUPS[i].is_some() {
+                        count += 1;
+                    }
+                    i += 1;
+                }
+                count
+            };
+            pub const GROUPS: [(&'static str, Key); GROUPS_SZ] = const {
+                let mut groups = [("", unsafe { Key::from_bytes([0; 8]) }); GROUPS_SZ];
+                let mut i = 0;
+                let mut j = 0;
+                while i < EP_HANDLER_GROUPS.len() {
+                    if let Some(group) = EP_HANDLER_GROUPS[i] {
+                        groups[j] = group;
+                        j += 1;
+                    }
+                    i += 1;
+                }
+                groups
+            };
+
+            // This is a list of the maximum response sizes of all handlers
+            const EP_HANDLER_RESP_SIZES: &[Option<usize>] = &[
+                $($(#[$ep_meta])? <$endpoint as $crate::Endpoint>::MAX_RESPONSE_SIZE,)*
+            ];
+
+            // The largest response frame (including header) any handler could send,
+            // or `None` if any of the handlers has an unbounded response. Any request
+            // may get an error reply instead, so this is at least an error frame.
+            pub const MAX_RESP_FRAME_SZ: Option<usize> = const {
+                let mut largest = $crate::max_size::MAX_ERROR_SIZE;
+                let mut i = 0;
+                let mut bounded = true;
+                while i < EP_HANDLER_RESP_SIZES.len() {
+                    match EP_HANDLER_RESP_SIZES[i] {
+                        Some(sz) if sz > largest => largest = sz,
+                        Some(_) => {}
+                        None => bounded = false,
+                    }
+                    i += 1;
+                }
+                if bounded {
+                    Some($crate::max_size::MAX_HEADER_SIZE + largest)
+                } else {
+                    None
+                }
+            };
         }
 
         // This is the fun part.
@@ -389,16 +1873,59 @@ macro_rules! define_dispatch {
         // same outcome.
         pub type $app_name = impls::$app_name<{ sizer::NEEDED_SZ }>;
 
+        // Does every response fit into the TX buffer?
+        $(
+            const _: () = assert!(
+                $crate::max_size::fits_in(sizer::MAX_RESP_FRAME_SZ, $tx_buf_size),
+                "a response or error frame of this dispatcher may not fit into `tx_buf_size`",
+            );
+        )?
+
         mod impls {
             use super::*;
 
+            // The live tasks of `spawn` handlers, shared by all instances
+            static SPAWN_LIMIT: $crate::server::spawn_limit::SpawnLimit =
+                $crate::server::spawn_limit::SpawnLimit::with_max_per_key(
+                    $crate::define_dispatch!(@max_spawned $($max_spawned)?),
+                    $crate::define_dispatch!(@max_spawned $($max_spawned_per_ep)?),
+                );
+
             pub struct $app_name<const N: usize> {
                 pub context: $context_ty,
                 pub spawn: $spawn_impl,
                 pub device_map: &'static $crate::DeviceMap,
+                pub dedup: $crate::define_dispatch!(@dedup_ty $($dedup_ty)?),
+                pub stats: $crate::server::metrics::DispatchStats<{ sizer::HANDLER_KEYS_SZ }>,
+                pub epoch: u32,
+                pub token: Option<$crate::header::AuthToken>,
+                pub max_frame_len: u32,
             }
 
             impl<const N: usize> $app_name<N> {
+                /// The largest response frame (including the header) that any endpoint
+                /// handler, or an error reply, could need, or `None` if any response is
+                /// unbounded in size
+                ///
+                /// This is checked against the `tx_buf_size` of the dispatcher at
+                /// compile time, if given.
+                pub const MAX_RESPONSE_FRAME_SIZE: Option<usize> = sizer::MAX_RESP_FRAME_SZ;
+
+                /// The path, request key and response key of each endpoint with a handler
+                ///
+                /// Standard endpoints are not listed. See `test_utils::assert_icd_match!`
+                /// to compare these with the endpoints of a `define_client!` client.
+                pub const ENDPOINTS: &'static [(&'static str, $crate::Key, $crate::Key)] = &[
+                    $(
+                        $(#[$ep_meta])?
+                        (
+                            <$endpoint as $crate::Endpoint>::PATH,
+                            <$endpoint as $crate::Endpoint>::REQ_KEY,
+                            <$endpoint as $crate::Endpoint>::RESP_KEY,
+                        ),
+                    )*
+                ];
+
                 /// Create a new instance of the dispatcher
                 pub fn new(
                     context: $context_ty,
@@ -420,6 +1947,8 @@ macro_rules! define_dispatch {
                         endpoints: &$endpoint_list.endpoints,
                         topics_in: &$topic_in_list.topics,
                         topics_out: &$topic_out_list.topics,
+                        aliases: &sizer::ALIASES,
+                        groups: &sizer::GROUPS,
                         min_key_len: const {
                             match sizer::NEEDED_SZ {
                                 1 => $crate::header::VarKeyKind::Key1,
@@ -434,33 +1963,123 @@ macro_rules! define_dispatch {
                         context,
                         spawn,
                         device_map: MAP,
+                        dedup: Default::default(),
+                        stats: $crate::server::metrics::DispatchStats::new(sizer::HANDLER_KEYS),
+                        epoch: 0,
+                        token: None,
+                        max_frame_len: 0,
                     }
                 }
+
+                // Ask the `busy` hook, if any, whether to reject a request
+                #[inline(always)]
+                fn check_busy(
+                    context: &mut $context_ty,
+                    header: &$crate::header::VarHeader,
+                ) -> Option<$crate::standard_icd::Busy> {
+                    $crate::define_dispatch!(@busy_check context header $($busy_fn)?)
+                }
+
+                // Call the `on_connected` hook, if any
+                #[inline(always)]
+                fn connected_hook(context: &mut $context_ty) {
+                    $crate::define_dispatch!(@lifecycle context $($connected_fn)?)
+                }
+
+                // Call the `on_disconnected` hook, if any
+                #[inline(always)]
+                fn disconnected_hook(context: &mut $context_ty) {
+                    $crate::define_dispatch!(@lifecycle context $($disconnected_fn)?)
+                }
+
+                // Call the `on_dispatch_start` hook, if any
+                #[inline(always)]
+                fn dispatch_start_hook(
+                    context: &mut $context_ty,
+                    header: &$crate::header::VarHeader,
+                ) {
+                    $crate::define_dispatch!(@dispatch_hook context header $($dispatch_start_fn)?)
+                }
+
+                // Call the `on_dispatch_end` hook, if any
+                #[inline(always)]
+                fn dispatch_end_hook(
+                    context: &mut $context_ty,
+                    header: &$crate::header::VarHeader,
+                ) {
+                    $crate::define_dispatch!(@dispatch_hook context header $($dispatch_end_fn)?)
+                }
+
+                // Count a new `spawn` handler task, unless `max_spawned` are live, or
+                // `max_spawned_per_endpoint` are live for its endpoint
+                #[inline(always)]
+                fn spawn_permit(
+                    endpoint: &'static $crate::server::spawn_limit::KeyCount,
+                ) -> Option<$crate::server::spawn_limit::SpawnPermit> {
+                    SPAWN_LIMIT.try_acquire_for(endpoint)
+                }
+
+                /// The number of `spawn` handler tasks that have not completed yet
+                pub fn live_spawned(&self) -> usize {
+                    SPAWN_LIMIT.live()
+                }
             }
 
             $crate::define_dispatch! {
                 @matcher 1 $app_name $tx_impl; $spawn_fn $crate::Key1; $crate::header::VarKeyKind::Key1;
-                REQ_KEY1 / TOPIC_KEY1 = u8;
-                ($($endpoint | $ep_flavor | $ep_handler)*)
-                ($($topic_in | $tp_flavor | $tp_handler)*)
+                REQ_KEY1 / TOPIC_KEY1 = $crate::server::dispatch_index::key1_index;
+                middleware: [$($($mw),*)?];
+                request_pool: ($($request_pool)?);
+                timer: ($($timer_fn)?);
+                heartbeat: ($($heartbeat_cfg)?);
+                error_log: ($($error_log_cfg)?);
+                reboot: ($($reboot_fn)?);
+                fallback: ($($fallback_fn)?);
+                auth: ($($auth_fn)?);
+                ($($endpoint | $ep_flavor | $ep_handler | ($($ep_max_len)?) | ($($ep_timeout)?) | ($($ep_compress)?) | ($($ep_auth)?) | [$($ep_meta)?])*)
+                ($($topic_in | $tp_flavor | $tp_handler | [$($tp_meta)?])*)
             }
             $crate::define_dispatch! {
                 @matcher 2 $app_name $tx_impl; $spawn_fn $crate::Key2; $crate::header::VarKeyKind::Key2;
-                REQ_KEY2 / TOPIC_KEY2 = [u8; 2];
-                ($($endpoint | $ep_flavor | $ep_handler)*)
-                ($($topic_in | $tp_flavor | $tp_handler)*)
+                REQ_KEY2 / TOPIC_KEY2 = $crate::server::dispatch_index::key2_index;
+                middleware: [$($($mw),*)?];
+                request_pool: ($($request_pool)?);
+                timer: ($($timer_fn)?);
+                heartbeat: ($($heartbeat_cfg)?);
+                error_log: ($($error_log_cfg)?);
+                reboot: ($($reboot_fn)?);
+                fallback: ($($fallback_fn)?);
+                auth: ($($auth_fn)?);
+                ($($endpoint | $ep_flavor | $ep_handler | ($($ep_max_len)?) | ($($ep_timeout)?) | ($($ep_compress)?) | ($($ep_auth)?) | [$($ep_meta)?])*)
+                ($($topic_in | $tp_flavor | $tp_handler | [$($tp_meta)?])*)
             }
             $crate::define_dispatch! {
                 @matcher 4 $app_name $tx_impl; $spawn_fn $crate::Key4; $crate::header::VarKeyKind::Key4;
-                REQ_KEY4 / TOPIC_KEY4 = [u8; 4];
-                ($($endpoint | $ep_flavor | $ep_handler)*)
-                ($($topic_in | $tp_flavor | $tp_handler)*)
+                REQ_KEY4 / TOPIC_KEY4 = $crate::server::dispatch_index::key4_index;
+                middleware: [$($($mw),*)?];
+                request_pool: ($($request_pool)?);
+                timer: ($($timer_fn)?);
+                heartbeat: ($($heartbeat_cfg)?);
+                error_log: ($($error_log_cfg)?);
+                reboot: ($($reboot_fn)?);
+                fallback: ($($fallback_fn)?);
+                auth: ($($auth_fn)?);
+                ($($endpoint | $ep_flavor | $ep_handler | ($($ep_max_len)?) | ($($ep_timeout)?) | ($($ep_compress)?) | ($($ep_auth)?) | [$($ep_meta)?])*)
+                ($($topic_in | $tp_flavor | $tp_handler | [$($tp_meta)?])*)
             }
             $crate::define_dispatch! {
                 @matcher 8 $app_name $tx_impl; $spawn_fn $crate::Key; $crate::header::VarKeyKind::Key8;
-                REQ_KEY / TOPIC_KEY = [u8; 8];
-                ($($endpoint | $ep_flavor | $ep_handler)*)
-                ($($topic_in | $tp_flavor | $tp_handler)*)
+                REQ_KEY / TOPIC_KEY = $crate::server::dispatch_index::key8_index;
+                middleware: [$($($mw),*)?];
+                request_pool: ($($request_pool)?);
+                timer: ($($timer_fn)?);
+                heartbeat: ($($heartbeat_cfg)?);
+                error_log: ($($error_log_cfg)?);
+                reboot: ($($reboot_fn)?);
+                fallback: ($($fallback_fn)?);
+                auth: ($($auth_fn)?);
+                ($($endpoint | $ep_flavor | $ep_handler | ($($ep_max_len)?) | ($($ep_timeout)?) | ($($ep_compress)?) | ($($ep_auth)?) | [$($ep_meta)?])*)
+                ($($topic_in | $tp_flavor | $tp_handler | [$($tp_meta)?])*)
             }
         }
 
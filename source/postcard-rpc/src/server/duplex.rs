@@ -0,0 +1,221 @@
+//! Full duplex streams of messages
+//!
+//! Some protocols are not a single request and response, but a conversation, e.g. an
+//! interactive shell, where both the client and the server send messages whenever
+//! they have something to say. Handlers of the `duplex` kind of
+//! [`define_dispatch!`][crate::define_dispatch] are given a [`Duplex`], which
+//! receives the messages sent by the client, and sends messages to it, until either
+//! side closes the stream:
+//!
+//! ```rust,ignore
+//! async fn shell_handler<Rx: WireRx>(
+//!     context: &mut TestContext,
+//!     header: VarHeader,
+//!     first: ShellInput,
+//!     sender: &Sender<WireTxImpl>,
+//!     duplex: &mut Duplex<'_, ShellEndpoint, WireTxImpl, Rx>,
+//! ) {
+//!     let mut input = first;
+//!     let mut buf = [0u8; 64];
+//!     loop {
+//!         let output = context.shell.run(&input);
+//!         if duplex.send(&output).await.is_err() {
+//!             return;
+//!         }
+//!         match duplex.recv(&mut buf).await {
+//!             Ok(Some(next)) => input = next,
+//!             // The client closed the stream, or the connection was lost
+//!             Ok(None) | Err(_) => return,
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! ## On the wire
+//!
+//! A stream is identified by the sequence number of the request that opened it:
+//!
+//! * The client opens the stream with a request to the endpoint, which is passed to
+//!   the handler as the first message.
+//! * The client sends further messages as requests to the endpoint, with the same
+//!   sequence number. Each message is NOT answered by a response.
+//! * The server sends messages as responses of the endpoint, with the same
+//!   sequence number.
+//! * Either side sends a [`DuplexClose`] with the [`DUPLEX_CLOSE_KEY`] and the same
+//!   sequence number to end one ([`DuplexClose::HalfClose`]) or both
+//!   ([`DuplexClose::Close`]) directions of the stream.
+//!
+//! When the handler returns, the stream is closed in both directions, unless the
+//! client already closed it. Messages that can't be deserialized are answered with
+//! [`WireError::DeserFailed`], and the stream stays open.
+//!
+//! Like a [`Transaction`][super::transaction::Transaction], a duplex stream takes
+//! over the connection while the handler runs: no other frame is dispatched, and
+//! frames that are not part of the stream are answered with [`WireError::Busy`].
+//! Fragmented frames are not reassembled.
+//!
+//! [`DuplexClose`]: crate::standard_icd::DuplexClose
+//! [`DuplexClose::HalfClose`]: crate::standard_icd::DuplexClose::HalfClose
+//! [`DuplexClose::Close`]: crate::standard_icd::DuplexClose::Close
+//! [`DUPLEX_CLOSE_KEY`]: crate::standard_icd::DUPLEX_CLOSE_KEY
+//! [`WireError::DeserFailed`]: crate::standard_icd::WireError::DeserFailed
+//! [`WireError::Busy`]: crate::standard_icd::WireError::Busy
+
+use core::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    header::{VarHeader, VarKey},
+    standard_icd::{Busy, DuplexClose, WireError, DUPLEX_CLOSE_KEY},
+    Endpoint,
+};
+
+use super::{
+    AsWireRxErrorKind, AsWireTxErrorKind, Sender, WireRx, WireRxErrorKind, WireTx, WireTxErrorKind,
+};
+
+/// The error of sending or receiving on a [`Duplex`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplexError {
+    /// This direction of the stream has been closed
+    Closed,
+    /// Receiving a frame failed
+    Rx(WireRxErrorKind),
+    /// Sending a frame failed
+    Tx(WireTxErrorKind),
+}
+
+/// A full duplex stream of messages of the endpoint `E`, see the [module docs][self]
+///
+/// Messages from the client are [`E::Request`][Endpoint::Request]s, and messages to
+/// the client are [`E::Response`][Endpoint::Response]s.
+pub struct Duplex<'a, E: Endpoint, Tx: WireTx, Rx: WireRx> {
+    rx: Option<&'a mut Rx>,
+    sender: &'a Sender<Tx>,
+    header: VarHeader,
+    /// Can the client still send messages?
+    incoming: bool,
+    /// Can we still send messages?
+    outgoing: bool,
+    _pd: PhantomData<fn() -> E>,
+}
+
+impl<'a, E: Endpoint, Tx: WireTx, Rx: WireRx> Duplex<'a, E, Tx, Rx> {
+    /// Create a new stream, opened by the request with `header`
+    ///
+    /// Without a receiver, e.g. when frames are dispatched with
+    /// [`Dispatch::handle()`][super::Dispatch::handle], all calls to
+    /// [`recv()`][Self::recv] fail.
+    pub fn new(header: VarHeader, sender: &'a Sender<Tx>, rx: Option<&'a mut Rx>) -> Self {
+        Self {
+            rx,
+            sender,
+            header,
+            incoming: true,
+            outgoing: true,
+            _pd: PhantomData,
+        }
+    }
+
+    /// The header of the request that opened the stream
+    pub fn header(&self) -> &VarHeader {
+        &self.header
+    }
+
+    /// Receive the next message from the client, using `buf` as storage
+    ///
+    /// Returns `None` once the client has closed its direction of the stream. Frames
+    /// that are not part of the stream are answered with an error, and skipped.
+    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<Option<E::Request>, DuplexError>
+    where
+        E::Request: DeserializeOwned,
+    {
+        if !self.incoming {
+            return Ok(None);
+        }
+        let Some(rx) = self.rx.as_mut() else {
+            return Err(DuplexError::Rx(WireRxErrorKind::ConnectionClosed));
+        };
+
+        loop {
+            let used = rx
+                .receive(buf)
+                .await
+                .map_err(|e| DuplexError::Rx(e.as_kind()))?;
+            let Some((hdr, body)) = VarHeader::take_from_slice(used) else {
+                continue;
+            };
+
+            let err = if hdr.seq_no != self.header.seq_no {
+                WireError::Busy(Busy { retry_after_ms: 0 })
+            } else if hdr.key == VarKey::Key8(DUPLEX_CLOSE_KEY) {
+                match postcard::from_bytes::<DuplexClose>(body) {
+                    Ok(DuplexClose::HalfClose) => {
+                        self.incoming = false;
+                        return Ok(None);
+                    }
+                    Ok(DuplexClose::Close) => {
+                        self.incoming = false;
+                        self.outgoing = false;
+                        return Ok(None);
+                    }
+                    Err(_) => WireError::DeserFailed,
+                }
+            } else if hdr.key == VarKey::Key8(E::REQ_KEY) {
+                match postcard::from_bytes::<E::Request>(body) {
+                    Ok(msg) => return Ok(Some(msg)),
+                    Err(_) => WireError::DeserFailed,
+                }
+            } else {
+                WireError::Busy(Busy { retry_after_ms: 0 })
+            };
+            self.sender.error_for(&hdr, err).await.map_err(tx_err)?;
+        }
+    }
+
+    /// Send a message to the client
+    ///
+    /// Fails with [`DuplexError::Closed`] once either side closed our direction of the
+    /// stream.
+    pub async fn send(&mut self, msg: &E::Response) -> Result<(), DuplexError>
+    where
+        E::Response: Serialize,
+    {
+        if !self.outgoing {
+            return Err(DuplexError::Closed);
+        }
+        self.sender
+            .reply::<E>(self.header.seq_no, msg)
+            .await
+            .map_err(tx_err)
+    }
+
+    /// Stop sending messages, while still receiving the messages of the client
+    pub async fn half_close(&mut self) -> Result<(), DuplexError> {
+        self.close_with(DuplexClose::HalfClose).await
+    }
+
+    /// Close the stream in both directions
+    ///
+    /// This is done automatically once the handler returns.
+    pub async fn close(&mut self) -> Result<(), DuplexError> {
+        self.incoming = false;
+        self.close_with(DuplexClose::Close).await
+    }
+
+    async fn close_with(&mut self, close: DuplexClose) -> Result<(), DuplexError> {
+        if !self.outgoing {
+            return Ok(());
+        }
+        self.outgoing = false;
+        self.sender
+            .reply_keyed(self.header.seq_no, DUPLEX_CLOSE_KEY, &close)
+            .await
+            .map_err(tx_err)
+    }
+}
+
+fn tx_err<E: AsWireTxErrorKind>(e: E) -> DuplexError {
+    DuplexError::Tx(e.as_kind())
+}
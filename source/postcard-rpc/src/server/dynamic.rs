@@ -0,0 +1,206 @@
+//! A dispatcher with handlers registered at runtime
+//!
+//! [`define_dispatch!`][crate::define_dispatch] requires the set of endpoints to be
+//! known at compile time. When endpoints are only known at runtime, e.g. because
+//! they are provided by plugins, a [`DynDispatch`] can be built with a
+//! [`DispatchBuilder`] instead:
+//!
+//! ```rust,ignore
+//! let dispatch = DispatchBuilder::new(MyContext::default())
+//!     .register::<AlphaEndpoint>(|context, _header, req| {
+//!         Box::pin(async move {
+//!             context.count += 1;
+//!             AResp(req.0)
+//!         })
+//!     })
+//!     .build();
+//! let kkind = dispatch.min_key_len();
+//! let server = Server::new(&tx, rx, buf, dispatch, kkind);
+//! ```
+//!
+//! Each handler returns a boxed future, and is stored in a map keyed by the
+//! [`Key`] of its request, which trades some performance for flexibility. The same
+//! [`WireError`]s are sent as by `define_dispatch!`, e.g.
+//! [`WireError::UnknownKey`] for keys without a handler, and
+//! [`WireError::DeserFailed`] for requests that fail to deserialize. Besides the
+//! registered endpoints, only the standard [`PingEndpoint`] is handled.
+//!
+//! The key length is chosen when building the dispatcher, see
+//! [`DynDispatch::min_key_len()`], and does not change afterwards.
+//!
+//! **Requires feature**: `use-std`
+
+use core::{future::Future, pin::Pin};
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{dispatch_index, min_key_needed, AsWireTxErrorKind, Dispatch, Sender, WireTx};
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind},
+    standard_icd::{PingEndpoint, WireError},
+    Endpoint, Key, Key1, Key2, Key4,
+};
+
+/// A boxed future, as returned by the handlers of a [`DynDispatch`]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// A handler with the endpoint type erased, which replies on its own
+type Handler<Ctx, Tx> = Box<
+    dyn for<'a> FnMut(
+        &'a mut Ctx,
+        &'a Sender<Tx>,
+        &'a VarHeader,
+        &'a [u8],
+    ) -> BoxFuture<'a, Result<(), <Tx as WireTx>::Error>>,
+>;
+
+/// Box `handler`, with its signature checked, as closures don't infer it on their own
+fn erase<Ctx, Tx, F>(handler: F) -> Handler<Ctx, Tx>
+where
+    Tx: WireTx,
+    F: for<'a> FnMut(
+            &'a mut Ctx,
+            &'a Sender<Tx>,
+            &'a VarHeader,
+            &'a [u8],
+        ) -> BoxFuture<'a, Result<(), <Tx as WireTx>::Error>>
+        + 'static,
+{
+    Box::new(handler)
+}
+
+/// A builder for a [`DynDispatch`], see the [module docs][self]
+pub struct DispatchBuilder<Ctx, Tx: WireTx> {
+    context: Ctx,
+    handlers: HashMap<Key, Handler<Ctx, Tx>>,
+}
+
+impl<Ctx: 'static, Tx: WireTx + 'static> DispatchBuilder<Ctx, Tx> {
+    /// Create a new builder, with the context passed to all handlers
+    pub fn new(context: Ctx) -> Self {
+        Self {
+            context,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Handle requests to the endpoint `E` with `handler`
+    ///
+    /// The handler is called with the context, the header, and the request, and
+    /// returns a boxed future of the response. Registering another handler for the
+    /// same endpoint replaces the previous one.
+    pub fn register<E>(
+        mut self,
+        mut handler: impl for<'a> FnMut(&'a mut Ctx, VarHeader, E::Request) -> BoxFuture<'a, E::Response>
+            + 'static,
+    ) -> Self
+    where
+        E: Endpoint + 'static,
+        E::Request: DeserializeOwned,
+        E::Response: Serialize,
+    {
+        let erased = erase(move |context, tx, hdr, body| {
+            // Call the handler right away, so the future doesn't borrow it
+            let fut = postcard::from_bytes::<E::Request>(body)
+                .ok()
+                .map(|req| handler(context, *hdr, req));
+            Box::pin(async move {
+                let Some(fut) = fut else {
+                    return tx.error_for(hdr, WireError::DeserFailed).await;
+                };
+                let resp = fut.await;
+                if let Err(e) = tx.reply::<E>(hdr.seq_no, &resp).await {
+                    tx.reply_failed(hdr, e.as_kind()).await
+                } else {
+                    Ok(())
+                }
+            })
+        });
+        self.handlers.insert(E::REQ_KEY, erased);
+        self
+    }
+
+    /// Build the dispatcher, choosing the shortest key length without collisions
+    pub fn build(self) -> DynDispatch<Ctx, Tx> {
+        let mut keys: Vec<Key> = self.handlers.keys().copied().collect();
+        keys.push(PingEndpoint::REQ_KEY);
+        let kkind = match min_key_needed(&[keys.as_slice()]) {
+            1 => VarKeyKind::Key1,
+            2 => VarKeyKind::Key2,
+            4 => VarKeyKind::Key4,
+            _ => VarKeyKind::Key8,
+        };
+        let slots = keys
+            .iter()
+            .filter_map(|key| Some((key_index(&VarKey::Key8(*key), kkind)?, *key)))
+            .collect();
+        DynDispatch {
+            context: self.context,
+            handlers: self.handlers,
+            slots,
+            kkind,
+        }
+    }
+}
+
+/// A dispatcher with handlers registered at runtime, see the [module docs][self]
+pub struct DynDispatch<Ctx, Tx: WireTx> {
+    context: Ctx,
+    handlers: HashMap<Key, Handler<Ctx, Tx>>,
+    // The full key of each handled key, by its index at the chosen key length
+    slots: HashMap<u64, Key>,
+    kkind: VarKeyKind,
+}
+
+impl<Ctx, Tx: WireTx> DynDispatch<Ctx, Tx> {
+    /// The context passed to all handlers
+    pub fn context(&mut self) -> &mut Ctx {
+        &mut self.context
+    }
+}
+
+impl<Ctx: 'static, Tx: WireTx + 'static> Dispatch for DynDispatch<Ctx, Tx> {
+    type Tx = Tx;
+
+    fn min_key_len(&self) -> VarKeyKind {
+        self.kkind
+    }
+
+    async fn handle(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        let Some(index) = key_index(&hdr.key, self.kkind) else {
+            return tx.error_for(hdr, WireError::KeyTooSmall).await;
+        };
+        let Some(key) = self.slots.get(&index).copied() else {
+            return tx.error_for(hdr, WireError::UnknownKey).await;
+        };
+        if key == PingEndpoint::REQ_KEY {
+            let Ok(req) = postcard::from_bytes::<u32>(body) else {
+                return tx.error_for(hdr, WireError::DeserFailed).await;
+            };
+            return tx.reply::<PingEndpoint>(hdr.seq_no, &req).await;
+        }
+        let Self {
+            context, handlers, ..
+        } = self;
+        match handlers.get_mut(&key) {
+            Some(handler) => handler(context, tx, hdr, body).await,
+            None => tx.error_for(hdr, WireError::UnknownKey).await,
+        }
+    }
+}
+
+/// The index of `key` at the length `kkind`, or `None` if `key` is shorter
+fn key_index(key: &VarKey, kkind: VarKeyKind) -> Option<u64> {
+    match kkind {
+        VarKeyKind::Key1 => Key1::try_from_varkey(key).map(dispatch_index::key1_index),
+        VarKeyKind::Key2 => Key2::try_from_varkey(key).map(dispatch_index::key2_index),
+        VarKeyKind::Key4 => Key4::try_from_varkey(key).map(dispatch_index::key4_index),
+        VarKeyKind::Key8 => Key::try_from_varkey(key).map(dispatch_index::key8_index),
+    }
+}
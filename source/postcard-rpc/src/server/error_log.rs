@@ -0,0 +1,185 @@
+//! Recording the most recent errors sent to the client
+//!
+//! A device that intermittently rejects requests is hard to debug in the field. An
+//! [`ErrorLog`] keeps the most recent errors sent by the server in a ring buffer,
+//! with the key and sequence number of the request that caused each of them, and
+//! the client can read them with the standard
+//! [`ErrorLogEndpoint`][crate::standard_icd::ErrorLogEndpoint].
+//!
+//! The log is a `static`, passed to [`define_dispatch!`][crate::define_dispatch] with
+//! the optional `error_log` config item:
+//!
+//! ```rust,ignore
+//! use postcard_rpc::server::error_log::ErrorLog;
+//!
+//! // Keep the 8 most recent errors
+//! static ERRORS: ErrorLog<8> = ErrorLog::new();
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     error_log: ERRORS;
+//!     // ...
+//! }
+//! ```
+//!
+//! Every error sent with [`Sender::error()`][super::Sender::error] or
+//! [`Sender::error_for()`][super::Sender::error_for] is recorded, including those
+//! sent by `spawn` handlers and by the [`Server`][super::Server] itself, as long as
+//! the sender was obtained from a server with this dispatcher. Without the config
+//! item, nothing is recorded, and requests to the `ErrorLogEndpoint` are answered
+//! with [`WireError::UnknownKey`].
+//!
+//! Recording never waits: if the log is being recorded to or read at the same time,
+//! e.g. from an interrupt, the error is only counted in
+//! [`total`][crate::standard_icd::ErrorRecord], and not kept in the log.
+
+use core::cell::UnsafeCell;
+
+use portable_atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::{
+    header::{VarKey, VarSeq},
+    standard_icd::{ErrorRecord, WireError},
+};
+
+/// Something that records the errors sent by a [`Sender`][super::Sender]
+pub trait RecordError: Sync {
+    /// Record `error`, sent in reply to the request with `seq_no`, and `key` if known
+    fn record(&self, key: Option<VarKey>, seq_no: VarSeq, error: &WireError);
+}
+
+/// A ring buffer of the `N` most recent errors, see the [module docs][self]
+pub struct ErrorLog<const N: usize> {
+    locked: AtomicBool,
+    total: AtomicU32,
+    ring: UnsafeCell<Ring<N>>,
+}
+
+struct Ring<const N: usize> {
+    records: [Option<ErrorRecord>; N],
+    // The position of the next record, which is also the oldest record once full
+    next: usize,
+}
+
+// SAFETY: the ring is only accessed while holding `locked`
+unsafe impl<const N: usize> Sync for ErrorLog<N> {}
+
+impl<const N: usize> ErrorLog<N> {
+    /// Create a new, empty log
+    pub const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            total: AtomicU32::new(0),
+            ring: UnsafeCell::new(Ring {
+                records: [const { None }; N],
+                next: 0,
+            }),
+        }
+    }
+
+    /// Run `f` with the ring, unless it is already in use
+    fn try_with<R>(&self, f: impl FnOnce(&mut Ring<N>) -> R) -> Option<R> {
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        // SAFETY: we hold `locked`, so nobody else accesses the ring
+        let res = f(unsafe { &mut *self.ring.get() });
+        self.locked.store(false, Ordering::Release);
+        Some(res)
+    }
+
+    /// The total number of errors recorded, including those no longer in the log
+    pub fn total(&self) -> u32 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// The errors in the log, oldest first
+    ///
+    /// Returns an empty list if the log is being recorded to at the same time.
+    pub fn records(&self) -> heapless::Vec<ErrorRecord, N> {
+        let mut out = heapless::Vec::new();
+        self.try_with(|ring| {
+            let (newer, older) = ring.records.split_at(ring.next);
+            for rec in older.iter().chain(newer).flatten() {
+                // Can't fail, there are at most N records
+                let _ = out.push(*rec);
+            }
+        });
+        out
+    }
+
+    /// Remove all errors from the log, and reset the total
+    pub fn clear(&self) {
+        self.try_with(|ring| {
+            ring.records = [const { None }; N];
+            ring.next = 0;
+        });
+        self.total.store(0, Ordering::Relaxed);
+    }
+}
+
+impl<const N: usize> Default for ErrorLog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RecordError for ErrorLog<N> {
+    fn record(&self, key: Option<VarKey>, seq_no: VarSeq, error: &WireError) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        if N == 0 {
+            return;
+        }
+        let seq_no = match seq_no {
+            VarSeq::Seq1(s) => s.into(),
+            VarSeq::Seq2(s) => s.into(),
+            VarSeq::Seq4(s) => s,
+        };
+        let rec = ErrorRecord {
+            key: key.map(Into::into),
+            seq_no,
+            error: *error,
+        };
+        self.try_with(|ring| {
+            ring.records[ring.next] = Some(rec);
+            ring.next = (ring.next + 1) % N;
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ErrorLog, RecordError};
+    use crate::{
+        header::{VarKey, VarSeq},
+        standard_icd::WireError,
+        Key,
+    };
+
+    #[test]
+    fn keeps_most_recent() {
+        let log = ErrorLog::<2>::new();
+        let key = VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) });
+        log.record(Some(key), VarSeq::Seq1(1), &WireError::UnknownKey);
+        log.record(None, VarSeq::Seq2(2), &WireError::DeserFailed);
+        log.record(Some(key), VarSeq::Seq4(3), &WireError::KeyTooSmall);
+
+        assert_eq!(log.total(), 3);
+        let recs = log.records();
+        assert_eq!(recs.len(), 2);
+        assert_eq!(recs[0].seq_no, 2);
+        assert_eq!(recs[0].key, None);
+        assert_eq!(recs[0].error, WireError::DeserFailed);
+        assert_eq!(recs[1].seq_no, 3);
+        assert_eq!(recs[1].key, Some(key.into()));
+
+        log.clear();
+        assert_eq!(log.total(), 0);
+        assert!(log.records().is_empty());
+    }
+}
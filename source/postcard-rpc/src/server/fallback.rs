@@ -0,0 +1,53 @@
+//! Handling frames with unknown keys
+//!
+//! By default, [`define_dispatch!`][crate::define_dispatch] replies to a frame whose
+//! key doesn't match any endpoint or topic with [`WireError::UnknownKey`]. A
+//! gateway or proxy may want to forward these frames elsewhere instead, e.g. to a
+//! device behind it. This is done with the optional `fallback` config item:
+//!
+//! ```rust,ignore
+//! async fn forward(
+//!     context: &mut Context,
+//!     header: VarHeader,
+//!     body: &[u8],
+//!     sender: &Sender<WireTxImpl>,
+//! ) -> Outcome {
+//!     match context.downstream.forward(header, body).await {
+//!         Some(reply) => {
+//!             let _ = sender.reply_keyed(header.seq_no, reply.key, &reply.body).await;
+//!             Outcome::Deferred
+//!         }
+//!         None => Outcome::Error(WireError::UnknownKey),
+//!     }
+//! }
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     fallback: forward;
+//!     // ...
+//! }
+//! ```
+//!
+//! The fallback handler is called with the context, the header, and the raw body
+//! of every frame that isn't handled by the dispatcher, including topic messages,
+//! and returns an [`Outcome`]. Frames whose key is shorter than the keys used by
+//! the dispatcher are still rejected with [`WireError::KeyTooSmall`].
+//!
+//! The key in the header may have been shortened by the client, to the key length
+//! used by this dispatcher, see [`Dispatch::min_key_len()`]. A gateway that
+//! forwards frames to devices with longer keys should make sure that clients use
+//! long enough keys, e.g. by listing the forwarded endpoints in its own schema.
+//!
+//! [`WireError::UnknownKey`]: crate::standard_icd::WireError::UnknownKey
+//! [`WireError::KeyTooSmall`]: crate::standard_icd::WireError::KeyTooSmall
+//! [`Dispatch::min_key_len()`]: crate::server::Dispatch::min_key_len
+
+use core::convert::Infallible;
+
+/// The result of the `fallback` handler of a dispatcher, see the [module docs][self]
+///
+/// As there is no response type for unknown keys, this is either
+/// [`Outcome::Deferred`][super::outcome::Outcome::Deferred], once the frame has been
+/// handled and any reply sent, or [`Outcome::Error`][super::outcome::Outcome::Error].
+pub type Outcome = super::outcome::Outcome<Infallible>;
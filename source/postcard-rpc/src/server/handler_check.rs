@@ -0,0 +1,318 @@
+//! Compile time checks of endpoint handler signatures
+//!
+//! These are used by [`define_dispatch!`][crate::define_dispatch] to make sure that
+//! each handler takes the `Request` type, and returns the `Response` type, of the
+//! [`Endpoint`] it is assigned to. Without these checks, a handler assigned to the
+//! wrong endpoint results in a type mismatch deep inside of the macro expansion.
+//!
+//! If one of these checks fails, make sure that the handler in the `handler` column
+//! matches the endpoint in the `EndpointTy` column, and the signature expected by the
+//! `kind` of the handler.
+
+use core::future::Future;
+
+use crate::{header::VarHeader, Endpoint, Key};
+
+use super::{duplex::Duplex, outcome::Outcome, transaction::Transaction, Sender, WireRx, WireTx};
+
+/// A handler usable with the `blocking` kind for the endpoint `E`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a valid `blocking` handler for the endpoint `{E}`",
+    label = "this handler does not match the endpoint",
+    note = "expected `fn(&mut {Ctx}, VarHeader, <{E} as Endpoint>::Request) -> <{E} as Endpoint>::Response`"
+)]
+pub trait BlockingEndpointHandler<'c, E: Endpoint, Ctx: 'c> {}
+
+impl<'c, E, Ctx, F> BlockingEndpointHandler<'c, E, Ctx> for F
+where
+    E: Endpoint,
+    Ctx: 'c,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request) -> E::Response,
+{
+}
+
+/// A handler usable with the `ref` kind for the endpoint `E`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a valid `ref` handler for the endpoint `{E}`",
+    label = "this handler does not match the endpoint",
+    note = "expected `fn(&mut {Ctx}, VarHeader, <{E} as Endpoint>::Request) -> &<{E} as Endpoint>::Response`"
+)]
+pub trait RefEndpointHandler<'c, 'r, E: Endpoint, Ctx: 'c> {}
+
+impl<'c, 'r, E, Ctx, F> RefEndpointHandler<'c, 'r, E, Ctx> for F
+where
+    E: Endpoint,
+    E::Response: 'r,
+    Ctx: 'c,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request) -> &'r E::Response,
+{
+}
+
+/// A handler usable with the `async` or `dedup` kinds for the endpoint `E`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a valid `async` handler for the endpoint `{E}`",
+    label = "this handler does not match the endpoint",
+    note = "expected `async fn(&mut {Ctx}, VarHeader, <{E} as Endpoint>::Request) -> <{E} as Endpoint>::Response`"
+)]
+pub trait AsyncEndpointHandler<'c, E: Endpoint, Ctx: 'c, Fut> {}
+
+impl<'c, E, Ctx, F, Fut> AsyncEndpointHandler<'c, E, Ctx, Fut> for F
+where
+    E: Endpoint,
+    Ctx: 'c,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request) -> Fut,
+    Fut: Future<Output = E::Response>,
+{
+}
+
+/// A handler usable with the `notify` and `notify_confirmed` kinds for the endpoint `E`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a valid `notify` handler for the endpoint `{E}`",
+    label = "this handler does not match the endpoint",
+    note = "expected `async fn(&mut {Ctx}, VarHeader, <{E} as Endpoint>::Request)`"
+)]
+pub trait NotifyEndpointHandler<'c, E: Endpoint, Ctx: 'c, Fut> {}
+
+impl<'c, E, Ctx, F, Fut> NotifyEndpointHandler<'c, E, Ctx, Fut> for F
+where
+    E: Endpoint,
+    Ctx: 'c,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request) -> Fut,
+    Fut: Future<Output = ()>,
+{
+}
+
+/// A handler usable with the `spawn` kind for the endpoint `E`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a valid `spawn` handler for the endpoint `{E}`",
+    label = "this handler does not match the endpoint",
+    note = "expected `async fn({Ctx}, VarHeader, <{E} as Endpoint>::Request, Sender<{Tx}>)`"
+)]
+pub trait SpawnEndpointHandler<E: Endpoint, Ctx, Tx: WireTx, Fut> {}
+
+impl<E, Ctx, Tx, F, Fut> SpawnEndpointHandler<E, Ctx, Tx, Fut> for F
+where
+    E: Endpoint,
+    Tx: WireTx,
+    F: FnOnce(Ctx, VarHeader, E::Request, Sender<Tx>) -> Fut,
+{
+}
+
+/// A handler usable with the `transaction` kind for the endpoint `E`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a valid `transaction` handler for the endpoint `{E}`",
+    label = "this handler does not match the endpoint",
+    note = "expected `async fn(&mut {Ctx}, VarHeader, <{E} as Endpoint>::Request, &Sender<{Tx}>, Transaction<'_, Rx>) -> <{E} as Endpoint>::Response`"
+)]
+pub trait TransactionEndpointHandler<'c, 's, 't, E: Endpoint, Ctx: 'c, Tx: WireTx, Rx: WireRx, Fut>
+{
+}
+
+impl<'c, 's, 't, E, Ctx, Tx, Rx, F, Fut> TransactionEndpointHandler<'c, 's, 't, E, Ctx, Tx, Rx, Fut>
+    for F
+where
+    E: Endpoint,
+    Ctx: 'c,
+    Tx: WireTx,
+    Rx: WireRx,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request, &'s Sender<Tx>, Transaction<'t, Rx>) -> Fut,
+    Fut: Future<Output = E::Response>,
+{
+}
+
+/// A handler usable with the `duplex` kind for the endpoint `E`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a valid `duplex` handler for the endpoint `{E}`",
+    label = "this handler does not match the endpoint",
+    note = "expected `async fn(&mut {Ctx}, VarHeader, <{E} as Endpoint>::Request, &Sender<{Tx}>, &mut Duplex<'_, {E}, {Tx}, Rx>)`"
+)]
+pub trait DuplexEndpointHandler<'c, 's, 'd, 't, E: Endpoint, Ctx: 'c, Tx: WireTx, Rx: WireRx, Fut> {}
+
+impl<'c, 's, 'd, 't, E, Ctx, Tx, Rx, F, Fut>
+    DuplexEndpointHandler<'c, 's, 'd, 't, E, Ctx, Tx, Rx, Fut> for F
+where
+    E: Endpoint,
+    Ctx: 'c,
+    Tx: WireTx,
+    Rx: WireRx,
+    F: FnOnce(
+        &'c mut Ctx,
+        VarHeader,
+        E::Request,
+        &'s Sender<Tx>,
+        &'d mut Duplex<'t, E, Tx, Rx>,
+    ) -> Fut,
+    Fut: Future<Output = ()>,
+{
+}
+
+/// A handler usable with the `custom` kind for the endpoint `E`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a valid `custom` handler for the endpoint `{E}`",
+    label = "this handler does not match the endpoint",
+    note = "expected `async fn(&mut {Ctx}, VarHeader, <{E} as Endpoint>::Request, &Sender<{Tx}>) -> Outcome<<{E} as Endpoint>::Response>`"
+)]
+pub trait CustomEndpointHandler<'c, 's, E: Endpoint, Ctx: 'c, Tx: WireTx, Fut> {}
+
+impl<'c, 's, E, Ctx, Tx, F, Fut> CustomEndpointHandler<'c, 's, E, Ctx, Tx, Fut> for F
+where
+    E: Endpoint,
+    Ctx: 'c,
+    Tx: WireTx,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request, &'s Sender<Tx>) -> Fut,
+    Fut: Future<Output = Outcome<E::Response>>,
+{
+}
+
+/// Check that `handler` is a `blocking` handler for the endpoint `E`
+///
+/// Returns the handler, so that closures have their argument types inferred from
+/// the endpoint.
+#[inline(always)]
+pub fn blocking_endpoint<'c, E, Ctx, F>(handler: F, _context: &&'c mut Ctx) -> F
+where
+    E: Endpoint,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request) -> E::Response,
+    F: BlockingEndpointHandler<'c, E, Ctx>,
+{
+    handler
+}
+
+/// Check that `handler` is a `ref` handler for the endpoint `E`
+///
+/// Returns the handler, so that closures have their argument types inferred from
+/// the endpoint.
+#[inline(always)]
+pub fn ref_endpoint<'c, 'r, E, Ctx, F>(handler: F, _context: &&'c mut Ctx) -> F
+where
+    E: Endpoint,
+    E::Response: 'r,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request) -> &'r E::Response,
+    F: RefEndpointHandler<'c, 'r, E, Ctx>,
+{
+    handler
+}
+
+/// Check that `handler` is an `async` or `dedup` handler for the endpoint `E`
+///
+/// Returns the handler, so that closures have their argument types inferred from
+/// the endpoint.
+#[inline(always)]
+pub fn async_endpoint<'c, E, Ctx, F, Fut>(handler: F, _context: &&'c mut Ctx) -> F
+where
+    E: Endpoint,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request) -> Fut,
+    F: AsyncEndpointHandler<'c, E, Ctx, Fut>,
+{
+    handler
+}
+
+/// Check that `handler` is a `notify` or `notify_confirmed` handler for the endpoint `E`
+///
+/// The `Response` of `E` must be `()`, as no response is sent.
+///
+/// Returns the handler, so that closures have their argument types inferred from
+/// the endpoint.
+#[inline(always)]
+pub fn notify_endpoint<'c, E, Ctx, F, Fut>(handler: F, _context: &&'c mut Ctx) -> F
+where
+    E: Endpoint<Response = ()>,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request) -> Fut,
+    F: NotifyEndpointHandler<'c, E, Ctx, Fut>,
+{
+    handler
+}
+
+/// Check that `handler` is a `spawn` handler for the endpoint `E`
+///
+/// Returns the handler, so that closures have their argument types inferred from
+/// the endpoint.
+#[inline(always)]
+pub fn spawn_endpoint<E, Ctx, Tx, F, Fut>(handler: F, _context: &Ctx, _sender: &Sender<Tx>) -> F
+where
+    E: Endpoint,
+    Tx: WireTx,
+    F: FnOnce(Ctx, VarHeader, E::Request, Sender<Tx>) -> Fut,
+    F: SpawnEndpointHandler<E, Ctx, Tx, Fut>,
+{
+    handler
+}
+
+/// Check that `handler` is a `transaction` handler for the endpoint `E`
+///
+/// Returns the handler, so that closures have their argument types inferred from
+/// the endpoint.
+#[inline(always)]
+pub fn transaction_endpoint<'c, 's, 't, E, Ctx, Tx, Rx, F, Fut>(
+    handler: F,
+    _context: &&'c mut Ctx,
+    _sender: &&'s Sender<Tx>,
+    _txn: &Transaction<'t, Rx>,
+) -> F
+where
+    E: Endpoint,
+    Tx: WireTx,
+    Rx: WireRx,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request, &'s Sender<Tx>, Transaction<'t, Rx>) -> Fut,
+    F: TransactionEndpointHandler<'c, 's, 't, E, Ctx, Tx, Rx, Fut>,
+{
+    handler
+}
+
+/// Check that `handler` is a `duplex` handler for the endpoint `E`
+///
+/// Returns the handler, so that closures have their argument types inferred from
+/// the endpoint.
+#[inline(always)]
+pub fn duplex_endpoint<'c, 's, 'd, 't, E, Ctx, Tx, Rx, F, Fut>(
+    handler: F,
+    _context: &&'c mut Ctx,
+    _sender: &&'s Sender<Tx>,
+    _duplex: &&'d mut Duplex<'t, E, Tx, Rx>,
+) -> F
+where
+    E: Endpoint,
+    Tx: WireTx,
+    Rx: WireRx,
+    F: FnOnce(
+        &'c mut Ctx,
+        VarHeader,
+        E::Request,
+        &'s Sender<Tx>,
+        &'d mut Duplex<'t, E, Tx, Rx>,
+    ) -> Fut,
+    F: DuplexEndpointHandler<'c, 's, 'd, 't, E, Ctx, Tx, Rx, Fut>,
+{
+    handler
+}
+
+/// Check that `handler` is a `custom` handler for the endpoint `E`
+///
+/// Returns the handler, so that closures have their argument types inferred from
+/// the endpoint.
+#[inline(always)]
+pub fn custom_endpoint<'c, 's, E, Ctx, Tx, F, Fut>(
+    handler: F,
+    _context: &&'c mut Ctx,
+    _sender: &&'s Sender<Tx>,
+) -> F
+where
+    E: Endpoint,
+    Tx: WireTx,
+    F: FnOnce(&'c mut Ctx, VarHeader, E::Request, &'s Sender<Tx>) -> Fut,
+    F: CustomEndpointHandler<'c, 's, E, Ctx, Tx, Fut>,
+{
+    handler
+}
+
+/// Check that the endpoint `A` can be used as an alias of the endpoint `E`
+///
+/// Both must have the same `Request` and `Response` types, so that requests to `A`
+/// can be handled by the handler of `E`. Returns the request keys of `A` and `E`.
+pub const fn alias_of<A, E>() -> (Key, Key)
+where
+    A: Endpoint<Request = E::Request, Response = E::Response>,
+    E: Endpoint,
+{
+    (A::REQ_KEY, E::REQ_KEY)
+}
@@ -0,0 +1,106 @@
+//! Sending periodic heartbeats, so the client can detect a hung device
+//!
+//! A client that only sends requests now and then can't tell whether an idle device
+//! is still alive. [`heartbeat_task()`] publishes a [`Heartbeat`] on the
+//! [`HeartbeatTopic`] at an interval, alongside the normal traffic. The client
+//! watches for these with `HostClient::watch_heartbeat()`, which fails with
+//! `HostErr::HeartbeatLost` if none arrives in time.
+//!
+//! The interval is stored in a [`HeartbeatConfig`], which is shared by the task and
+//! the dispatcher, and can be changed by the client at runtime with the
+//! [`SetHeartbeatEndpoint`], if the dispatcher was given the config:
+//!
+//! ```rust,ignore
+//! static HEARTBEAT: HeartbeatConfig = HeartbeatConfig::new(1000);
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     heartbeat: HEARTBEAT;
+//!     // ...
+//! }
+//!
+//! #[embassy_executor::task]
+//! async fn heartbeat(sender: Sender<WireTxImpl>) {
+//!     heartbeat_task(&sender, &HEARTBEAT, sleep_ms).await
+//! }
+//! ```
+//!
+//! Without the config, the dispatcher answers [`SetHeartbeatEndpoint`] requests
+//! with `false`.
+//!
+//! [`Heartbeat`]: crate::standard_icd::Heartbeat
+//! [`HeartbeatTopic`]: crate::standard_icd::HeartbeatTopic
+//! [`SetHeartbeatEndpoint`]: crate::standard_icd::SetHeartbeatEndpoint
+
+use core::future::Future;
+
+use portable_atomic::{AtomicU32, Ordering};
+
+use super::{Sender, WireTx};
+use crate::{
+    header::VarSeq,
+    standard_icd::{Heartbeat, HeartbeatTopic},
+};
+
+/// How often a disabled [`heartbeat_task()`] checks whether it was enabled again
+pub const DISABLED_POLL_MS: u32 = 1000;
+
+/// The heartbeat interval, shared by [`heartbeat_task()`] and the dispatcher
+pub struct HeartbeatConfig {
+    interval_ms: AtomicU32,
+}
+
+impl HeartbeatConfig {
+    /// Create a new config, with the given interval in milliseconds
+    ///
+    /// An interval of zero disables heartbeats.
+    pub const fn new(interval_ms: u32) -> Self {
+        Self {
+            interval_ms: AtomicU32::new(interval_ms),
+        }
+    }
+
+    /// The current interval in milliseconds, or zero if disabled
+    pub fn interval_ms(&self) -> u32 {
+        self.interval_ms.load(Ordering::Relaxed)
+    }
+
+    /// Change the interval, or disable heartbeats with zero
+    ///
+    /// The new interval takes effect once the current one has elapsed.
+    pub fn set_interval_ms(&self, interval_ms: u32) {
+        self.interval_ms.store(interval_ms, Ordering::Relaxed);
+    }
+}
+
+/// Publish a [`Heartbeat`] every interval of `config`, forever
+///
+/// `timer` is a function returning a future that completes after the given number
+/// of milliseconds, like the `sleep_ms` function of the `dispatch_impl` modules.
+/// While heartbeats are disabled, the task checks the config every
+/// [`DISABLED_POLL_MS`]. No heartbeats are sent while the client isn't connected, and
+/// failing to send one is not an error, the next one is sent as usual.
+pub async fn heartbeat_task<Tx, T, F>(sender: &Sender<Tx>, config: &HeartbeatConfig, timer: T)
+where
+    Tx: WireTx,
+    T: Fn(u32) -> F,
+    F: Future<Output = ()>,
+{
+    let mut seq = 0u32;
+    loop {
+        let interval_ms = config.interval_ms();
+        if interval_ms == 0 {
+            timer(DISABLED_POLL_MS).await;
+            continue;
+        }
+        timer(interval_ms).await;
+
+        sender.wait_connected().await;
+        let msg = Heartbeat { seq, interval_ms };
+        let _ = sender
+            .publish::<HeartbeatTopic>(VarSeq::Seq4(seq), &msg)
+            .await;
+        seq = seq.wrapping_add(1);
+    }
+}
@@ -0,0 +1,714 @@
+//! A [`WireTx`] implementation that hands frames off to a central TX task
+//!
+//! Normally, every handler holding a [`Sender`] writes to the transport directly,
+//! and the [`WireTx`] impl serializes concurrent senders using a mutex. When many
+//! handlers reply at once, whichever handler happens to win the mutex goes first,
+//! and each handler is blocked until its frame has been written to the transport.
+//!
+//! With this module, handlers instead serialize each frame into a [`TxQueue`], an
+//! [`embassy_sync::channel::Channel`] of frames. A single task, running
+//! [`TxQueue::run()`], drains the queue in FIFO order and writes each frame to the
+//! "real" [`WireTx`] of the transport. A [`ChannelSender`] has the same API as any
+//! other [`Sender`], so handlers do not need to change.
+//!
+//! ```rust,ignore
+//! static QUEUE: TxQueue<ThreadModeRawMutex, 256, 8> = TxQueue::new();
+//!
+//! // `usb_tx` is the `WireTx` of the transport, e.g. from `WireStorage::init`
+//! spawner.must_spawn(tx_task(usb_tx));
+//! let server = Server::new(QUEUE.wire_tx(), usb_rx, rx_buf, dispatcher, kkind);
+//!
+//! #[embassy_executor::task]
+//! async fn tx_task(tx: WireTxImpl<ThreadModeRawMutex, Driver>) {
+//!     QUEUE.run(tx).await;
+//! }
+//! ```
+//!
+//! ## Sizing the queue
+//!
+//! A [`TxQueue`] has two parameters: `SZ`, the largest frame (header and body) that
+//! can be sent, and `DEPTH`, the number of frames that can be queued at once. The
+//! queue is statically allocated, and uses at least `SZ * DEPTH` bytes of RAM. Each
+//! frame is also serialized on the stack of the sending handler, which needs `SZ`
+//! bytes of stack while sending.
+//!
+//! * A larger `DEPTH` lets more handlers reply without waiting for the transport, at
+//!   the cost of RAM. Once the queue is full, handlers wait for room in the queue,
+//!   which behaves like the shared mutex of other [`WireTx`] impls, but in FIFO order.
+//! * `SZ` must fit the largest response or topic message, including the header.
+//!   Frames that are too large fail to send with [`WireTxErrorKind::TooLarge`]. Sizing
+//!   `SZ` for the rare large message wastes RAM in every slot of the queue, so prefer
+//!   keeping large messages rare, or sending them from a single handler.
+//!
+//! Sending returns once the frame has been queued, NOT once it has been written to
+//! the transport. Errors of the transport (including a closed connection) are
+//! therefore not reported to the sender, and frames that fail to be written are
+//! dropped by the TX task. Flushing a [`ChannelSender`] waits until the TX task has
+//! taken all queued frames, but not for the last frame to be written.
+//!
+//! Sending waits for room in the queue. To drop frames instead, e.g. telemetry that
+//! is superseded by the next message anyway, use [`Sender::try_publish()`], which
+//! returns [`TrySendError::Full`] right away when the lane is full.
+//!
+//! ## Latest values
+//!
+//! Messages published with [`Sender::publish_latest()`] are not queued in a lane, but
+//! in a slot for their topic: publishing again before the TX task has taken the
+//! message replaces it, so a slow client receives the latest value of the topic
+//! instead of a backlog. Each [`TxQueue`] has `DEPTH` slots, one for each topic
+//! with a message waiting to be sent, and `publish_latest()` returns
+//! [`TrySendError::Full`] when they are all taken by other topics.
+//!
+//! The TX task writes the waiting latest values after the queued bulk frames, in the
+//! order their slots were taken. Intermediate values of a topic are dropped, and
+//! the latest one is written once, so the client never receives an older value of a
+//! topic after a newer one. See [`Sender::publish_latest()`].
+//!
+//! ## Urgent frames
+//!
+//! Each [`TxQueue`] has a second, urgent lane, with its own `DEPTH` slots. Frames
+//! sent with a sender obtained from [`ChannelSender::urgent()`] are queued in the
+//! urgent lane, and the TX task writes them before any queued bulk frames, e.g. to
+//! report a fault while a large response is still being streamed:
+//!
+//! ```rust,ignore
+//! sender.urgent().publish::<FaultTopic>(seq_no, &fault).await?;
+//! ```
+//!
+//! Frames within each lane are still written in FIFO order. A frame that is already
+//! being written is not interrupted, so an urgent frame may still wait for one bulk
+//! frame.
+//!
+//! By default, the urgent lane has strict priority: as long as urgent frames keep
+//! coming, NO bulk frames are written, which can starve the bulk lane and stall all
+//! other replies. Use [`TxQueue::with_urgent_burst()`] to let a bulk frame through
+//! after a number of urgent frames in a row.
+//!
+//! ## Connections
+//!
+//! A transport that accepts a new connection after the last one was closed, e.g. a
+//! TCP socket, should call [`TxQueue::reset()`] before serving the new connection.
+//! Frames still queued for the old connection, and frames sent later by handlers of
+//! the old connection that are still running, are then dropped instead of being
+//! written, so the new client doesn't receive replies to sequence numbers it never
+//! used.
+
+use core::{
+    cell::{Cell, RefCell},
+    fmt::Arguments,
+};
+
+use embassy_futures::{
+    select::{select3, Either3},
+    yield_now,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::RawMutex, Mutex},
+    channel::Channel,
+    signal::Signal,
+};
+use heapless::Vec;
+use portable_atomic::{AtomicU32, Ordering};
+use serde::Serialize;
+
+use crate::{
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::{Sender, TrySendError, WireTx, WireTxErrorKind},
+    standard_icd::LoggingTopic,
+    Topic,
+};
+
+/// The slots for the latest frame of each key, each tagged with its connection
+type LatestSlots<const SZ: usize, const DEPTH: usize> = Vec<(u32, VarKey, Vec<u8, SZ>), DEPTH>;
+
+/// A [`Sender`] that enqueues frames into a [`TxQueue`]
+pub type ChannelSender<M, const SZ: usize, const DEPTH: usize> = Sender<QueuedWireTx<M, SZ, DEPTH>>;
+
+/// A queue of serialized frames, drained by a single TX task
+///
+/// See the [module level docs][self] for how to size `SZ` and `DEPTH`.
+pub struct TxQueue<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> {
+    // Each frame is tagged with the connection it was sent for
+    frames: Channel<M, (u32, Vec<u8, SZ>), DEPTH>,
+    urgent: Channel<M, (u32, Vec<u8, SZ>), DEPTH>,
+    // The latest frame of each key, see `Sender::publish_latest()`
+    latest: Mutex<M, RefCell<LatestSlots<SZ, DEPTH>>>,
+    latest_ready: Signal<M, ()>,
+    urgent_burst: usize,
+    log_seq: Mutex<M, Cell<u16>>,
+    connection: AtomicU32,
+}
+
+impl<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> TxQueue<M, SZ, DEPTH> {
+    /// Create a new, empty queue, where urgent frames have strict priority
+    pub const fn new() -> Self {
+        Self::with_urgent_burst(usize::MAX)
+    }
+
+    /// Create a new, empty queue, which writes a bulk frame (if any are queued) after
+    /// at most `burst` urgent frames in a row
+    ///
+    /// With a `burst` of `0`, urgent frames never go ahead of queued bulk frames. See
+    /// the [module level docs][self] for more details.
+    pub const fn with_urgent_burst(burst: usize) -> Self {
+        Self {
+            frames: Channel::new(),
+            urgent: Channel::new(),
+            latest: Mutex::new(RefCell::new(Vec::new())),
+            latest_ready: Signal::new(),
+            urgent_burst: burst,
+            log_seq: Mutex::new(Cell::new(0)),
+            connection: AtomicU32::new(0),
+        }
+    }
+
+    /// Start a new connection, dropping all frames sent for earlier connections
+    ///
+    /// Only [`WireTx`]s obtained with [`Self::wire_tx()`] after this call send frames
+    /// for the new connection. See the [module level docs][self].
+    pub fn reset(&self) {
+        self.connection.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Obtain a [`WireTx`] that sends frames through this queue
+    ///
+    /// This is usually passed to the `Server` instead of the [`WireTx`] of the transport.
+    pub fn wire_tx(&'static self) -> QueuedWireTx<M, SZ, DEPTH> {
+        QueuedWireTx {
+            queue: self,
+            urgent: false,
+            connection: self.connection.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Write all queued frames to `tx`, urgent frames first, then bulk frames, then
+    /// latest values, and in the order they were queued within each lane
+    ///
+    /// This should be run in a dedicated task, and never returns. Frames that fail to
+    /// be written, e.g. because the connection is closed, are dropped.
+    pub async fn run<Tx: WireTx>(&'static self, tx: Tx) {
+        let mut burst = 0;
+        loop {
+            let frame = self.next_frame(&mut burst).await;
+            let _ = tx.send_raw(&frame).await;
+        }
+    }
+
+    /// Take the next frame of the current connection to write, `burst` counts the
+    /// urgent frames taken in a row
+    pub(crate) async fn next_frame(&self, burst: &mut usize) -> Vec<u8, SZ> {
+        loop {
+            let (connection, frame) = self.next_tagged(burst).await;
+            if connection == self.connection.load(Ordering::Relaxed) {
+                return frame;
+            }
+        }
+    }
+
+    /// Take the next frame of any connection
+    async fn next_tagged(&self, burst: &mut usize) -> (u32, Vec<u8, SZ>) {
+        loop {
+            if *burst < self.urgent_burst {
+                if let Ok(frame) = self.urgent.try_receive() {
+                    *burst += 1;
+                    return frame;
+                }
+            }
+            // It's the turn of the bulk lane, or there are no urgent frames
+            if let Ok(frame) = self.frames.try_receive() {
+                *burst = 0;
+                return frame;
+            }
+            if let Some(frame) = self.take_latest() {
+                *burst = 0;
+                return frame;
+            }
+            if let Ok(frame) = self.urgent.try_receive() {
+                *burst = burst.saturating_add(1);
+                return frame;
+            }
+            match select3(
+                self.urgent.receive(),
+                self.frames.receive(),
+                self.latest_ready.wait(),
+            )
+            .await
+            {
+                Either3::First(frame) => {
+                    *burst = 1;
+                    return frame;
+                }
+                Either3::Second(frame) => {
+                    *burst = 0;
+                    return frame;
+                }
+                // Take the latest value, unless other frames came first
+                Either3::Third(()) => {}
+            }
+        }
+    }
+
+    /// Take the latest value that has waited the longest, if any
+    fn take_latest(&self) -> Option<(u32, Vec<u8, SZ>)> {
+        self.latest.lock(|latest| {
+            let mut latest = latest.borrow_mut();
+            if latest.is_empty() {
+                return None;
+            }
+            let (connection, _key, frame) = latest.remove(0);
+            Some((connection, frame))
+        })
+    }
+
+    /// Are there no latest values waiting?
+    fn latest_is_empty(&self) -> bool {
+        self.latest.lock(|latest| latest.borrow().is_empty())
+    }
+
+    fn next_log_seq(&self) -> u16 {
+        self.log_seq.lock(|seq| {
+            let ctr = seq.get();
+            seq.set(ctr.wrapping_add(1));
+            ctr
+        })
+    }
+}
+
+impl<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> Default for TxQueue<M, SZ, DEPTH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// TX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireTx`] implementation that enqueues frames into a [`TxQueue`]
+pub struct QueuedWireTx<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> {
+    queue: &'static TxQueue<M, SZ, DEPTH>,
+    urgent: bool,
+    connection: u32,
+}
+
+impl<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> Clone
+    for QueuedWireTx<M, SZ, DEPTH>
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> Copy
+    for QueuedWireTx<M, SZ, DEPTH>
+{
+}
+
+impl<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> QueuedWireTx<M, SZ, DEPTH> {
+    /// Obtain a [`WireTx`] that sends through the urgent lane of the same queue
+    pub fn urgent(&self) -> Self {
+        Self {
+            queue: self.queue,
+            urgent: true,
+            connection: self.connection,
+        }
+    }
+
+    /// Queue a serialized frame in the lane of this [`WireTx`]
+    ///
+    /// The frame is dropped if the queue has been reset since this was created.
+    async fn enqueue(&self, frame: Vec<u8, SZ>) {
+        if self.connection != self.queue.connection.load(Ordering::Relaxed) {
+            return;
+        }
+        if self.urgent {
+            self.queue.urgent.send((self.connection, frame)).await;
+        } else {
+            self.queue.frames.send((self.connection, frame)).await;
+        }
+    }
+
+    /// Queue a serialized frame in the lane of this [`WireTx`], if there is room
+    ///
+    /// Like [`Self::enqueue()`], the frame is dropped if the queue has been reset.
+    fn try_enqueue(&self, frame: Vec<u8, SZ>) -> Result<(), TrySendError<WireTxErrorKind>> {
+        if self.connection != self.queue.connection.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let lane = if self.urgent {
+            &self.queue.urgent
+        } else {
+            &self.queue.frames
+        };
+        lane.try_send((self.connection, frame))
+            .map_err(|_| TrySendError::Full)
+    }
+
+    /// Put a serialized frame in the slot of `key`, replacing the frame in it, if any
+    ///
+    /// Like [`Self::enqueue()`], the frame is dropped if the queue has been reset.
+    fn replace_latest(
+        &self,
+        key: VarKey,
+        frame: Vec<u8, SZ>,
+    ) -> Result<(), TrySendError<WireTxErrorKind>> {
+        if self.connection != self.queue.connection.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.queue.latest.lock(|latest| {
+            let mut latest = latest.borrow_mut();
+            // Slots of earlier connections are dropped by the TX task anyway
+            match latest.iter_mut().find(|(_, k, _)| *k == key) {
+                Some(slot) => *slot = (self.connection, key, frame),
+                None => latest
+                    .push((self.connection, key, frame))
+                    .map_err(|_| TrySendError::Full)?,
+            }
+            Ok::<_, TrySendError<WireTxErrorKind>>(())
+        })?;
+        self.queue.latest_ready.signal(());
+        Ok(())
+    }
+
+    fn log_header(&self, kkind: VarKeyKind) -> VarHeader {
+        let key = match kkind {
+            VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
+            VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
+            VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
+            VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
+        };
+        VarHeader {
+            key,
+            seq_no: VarSeq::Seq2(self.queue.next_log_seq()),
+        }
+    }
+}
+
+impl<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> WireTx
+    for QueuedWireTx<M, SZ, DEPTH>
+{
+    type Error = WireTxErrorKind;
+
+    fn closed_error() -> Option<Self::Error> {
+        Some(WireTxErrorKind::ConnectionClosed)
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let frame = serialize_frame::<SZ, T>(hdr, msg)?;
+        self.enqueue(frame).await;
+        Ok(())
+    }
+
+    fn try_send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), TrySendError<Self::Error>> {
+        let frame = serialize_frame::<SZ, T>(hdr, msg).map_err(TrySendError::Tx)?;
+        self.try_enqueue(frame)
+    }
+
+    fn try_send_latest<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), TrySendError<Self::Error>> {
+        let frame = serialize_frame::<SZ, T>(hdr, msg).map_err(TrySendError::Tx)?;
+        self.replace_latest(hdr.key, frame)
+    }
+
+    async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        let frame = Vec::from_slice(buf).map_err(|_| WireTxErrorKind::Other)?;
+        self.enqueue(frame).await;
+        Ok(())
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        self.send::<str>(self.log_header(kkind), s).await
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        args: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let mut frame = full_frame::<SZ>();
+        let wh = self.log_header(kkind);
+        let Some((hdr_used, remaining)) = wh.write_to_slice(&mut frame) else {
+            return Err(WireTxErrorKind::Other);
+        };
+        let hdr_len = hdr_used.len();
+
+        // Format the message after space reserved for the longest possible length
+        // field, then encode the real length and move the message down to follow it
+        let max_len_len = varint_len(remaining.len());
+        if remaining.len() < max_len_len {
+            return Err(WireTxErrorKind::Other);
+        }
+        let (len_field, body) = remaining.split_at_mut(max_len_len);
+        let body_len = body.len();
+        let mut sw = SliceWriter(body);
+        let res = core::fmt::write(&mut sw, args);
+        let used = body_len - sw.0.len();
+
+        // If we ran out of room, mark the message as truncated
+        if res.is_err() && (used >= 3) {
+            body[used - 3..used].iter_mut().for_each(|b| *b = b'.');
+        }
+
+        let len_used = postcard::to_slice(&used, len_field)
+            .map_err(|_| WireTxErrorKind::Other)?
+            .len();
+        let body_start = hdr_len + max_len_len;
+        frame.copy_within(body_start..body_start + used, hdr_len + len_used);
+        frame.truncate(hdr_len + len_used + used);
+
+        self.enqueue(frame).await;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        // The TX task doesn't notify us, so poll until it has taken all frames
+        while !self.queue.frames.is_empty()
+            || !self.queue.urgent.is_empty()
+            || !self.queue.latest_is_empty()
+        {
+            yield_now().await;
+        }
+        Ok(())
+    }
+}
+
+impl<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize> ChannelSender<M, SZ, DEPTH> {
+    /// Obtain a sender that queues its frames in the urgent lane of the [`TxQueue`]
+    ///
+    /// Like [`Sender::clone()`], the new sender does NOT hold the spawn permit of this
+    /// one. See the [module level docs][self] for the risk of starving other replies.
+    pub fn urgent(&self) -> Self {
+        let mut sender = self.clone();
+        sender.tx = self.tx.urgent();
+        sender
+    }
+}
+
+/// A frame buffer, filled with zeroes up to its capacity
+fn full_frame<const SZ: usize>() -> Vec<u8, SZ> {
+    let mut frame = Vec::new();
+    // Can't fail, we resize to exactly the capacity
+    let _ = frame.resize_default(SZ);
+    frame
+}
+
+/// Serialize a frame of `hdr` and `msg`
+fn serialize_frame<const SZ: usize, T: Serialize + ?Sized>(
+    hdr: VarHeader,
+    msg: &T,
+) -> Result<Vec<u8, SZ>, WireTxErrorKind> {
+    let mut frame = full_frame::<SZ>();
+    let (hdr_used, remain) = hdr
+        .write_to_slice(&mut frame)
+        .ok_or(WireTxErrorKind::Other)?;
+    let hdr_len = hdr_used.len();
+    let bdy_len = postcard::to_slice(msg, remain)
+        .map_err(|_| WireTxErrorKind::too_large(&hdr, msg, SZ))?
+        .len();
+    frame.truncate(hdr_len + bdy_len);
+    Ok(frame)
+}
+
+/// The number of bytes needed to varint-encode `n`
+fn varint_len(mut n: usize) -> usize {
+    let mut used = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        used += 1;
+    }
+    used
+}
+
+struct SliceWriter<'a>(&'a mut [u8]);
+
+impl<'a> core::fmt::Write for SliceWriter<'a> {
+    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
+        let sli = core::mem::take(&mut self.0);
+
+        // If this write would overflow us, note that, but still take
+        // as much as we possibly can here
+        let bad = s.len() > sli.len();
+        let to_write = s.len().min(sli.len());
+        let (now, later) = sli.split_at_mut(to_write);
+        now.copy_from_slice(&s.as_bytes()[..to_write]);
+        self.0 = later;
+
+        // Now, report whether we overflowed or not
+        if bad {
+            Err(core::fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TxQueue;
+    use crate::{
+        header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+        server::{TrySendError, WireTx},
+        Key,
+    };
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    #[test]
+    fn frames_in_order() {
+        let queue: &'static TxQueue<NoopRawMutex, 32, 4> = Box::leak(Box::new(TxQueue::new()));
+        let tx = queue.wire_tx();
+
+        let hdr = VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) }),
+            seq_no: VarSeq::Seq4(123),
+        };
+        block_on(async {
+            tx.send(hdr, &0x1234u16).await.unwrap();
+            tx.send_log_fmt(VarKeyKind::Key8, format_args!("hello {}", 42))
+                .await
+                .unwrap();
+            // Too large for a single frame
+            assert!(tx.send::<[u8]>(hdr, &[0u8; 64]).await.is_err());
+
+            let (_, frame) = queue.frames.receive().await;
+            let (rhdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            assert_eq!(rhdr, hdr);
+            assert_eq!(postcard::from_bytes::<u16>(body).unwrap(), 0x1234);
+
+            let (_, frame) = queue.frames.receive().await;
+            let (_rhdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            assert_eq!(postcard::from_bytes::<&str>(body).unwrap(), "hello 42");
+
+            assert!(queue.frames.try_receive().is_err());
+            // Everything has been taken from the queue
+            tx.flush().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn urgent_lane() {
+        let hdr = VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) }),
+            seq_no: VarSeq::Seq4(123),
+        };
+        let take = |queue: &'static TxQueue<NoopRawMutex, 32, 4>, burst: &mut usize| {
+            let frame = block_on(queue.next_frame(burst));
+            let (_rhdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            postcard::from_bytes::<u8>(body).unwrap()
+        };
+
+        for (queue, order) in [
+            (TxQueue::new(), [10, 11, 0, 1]),
+            (TxQueue::with_urgent_burst(1), [10, 0, 11, 1]),
+        ] {
+            let queue: &'static TxQueue<NoopRawMutex, 32, 4> = Box::leak(Box::new(queue));
+            let tx = queue.wire_tx();
+            let urgent = tx.urgent();
+            block_on(async {
+                tx.send(hdr, &0u8).await.unwrap();
+                tx.send(hdr, &1u8).await.unwrap();
+                urgent.send(hdr, &10u8).await.unwrap();
+                urgent.send(hdr, &11u8).await.unwrap();
+            });
+
+            let mut burst = 0;
+            let taken = [(); 4].map(|_| take(queue, &mut burst));
+            assert_eq!(taken, order);
+            block_on(tx.flush()).unwrap();
+        }
+    }
+
+    #[test]
+    fn reset_drops_old_frames() {
+        let queue: &'static TxQueue<NoopRawMutex, 32, 4> = Box::leak(Box::new(TxQueue::new()));
+        let hdr = VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) }),
+            seq_no: VarSeq::Seq4(123),
+        };
+        let old_tx = queue.wire_tx();
+        block_on(old_tx.send(hdr, &1u8)).unwrap();
+
+        queue.reset();
+        let new_tx = queue.wire_tx();
+        block_on(async {
+            // Sent by a handler of the old connection after the reset
+            old_tx.send(hdr, &2u8).await.unwrap();
+            new_tx.send(hdr, &3u8).await.unwrap();
+        });
+
+        let mut burst = 0;
+        let frame = block_on(queue.next_frame(&mut burst));
+        let (_rhdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+        assert_eq!(postcard::from_bytes::<u8>(body).unwrap(), 3);
+        assert!(queue.frames.try_receive().is_err());
+    }
+
+    #[test]
+    fn try_send_when_full() {
+        let queue: &'static TxQueue<NoopRawMutex, 32, 2> = Box::leak(Box::new(TxQueue::new()));
+        let tx = queue.wire_tx();
+        let hdr = VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) }),
+            seq_no: VarSeq::Seq4(123),
+        };
+        tx.try_send(hdr, &1u8).unwrap();
+        tx.try_send(hdr, &2u8).unwrap();
+        assert!(matches!(tx.try_send(hdr, &3u8), Err(TrySendError::Full)));
+        // The urgent lane has its own room
+        tx.urgent().try_send(hdr, &10u8).unwrap();
+        assert!(matches!(
+            tx.try_send::<[u8]>(hdr, &[0u8; 64]),
+            Err(TrySendError::Tx(_))
+        ));
+
+        let mut burst = 0;
+        let taken = [(); 3].map(|_| {
+            let frame = block_on(queue.next_frame(&mut burst));
+            let (_rhdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            postcard::from_bytes::<u8>(body).unwrap()
+        });
+        assert_eq!(taken, [10, 1, 2]);
+        tx.try_send(hdr, &4u8).unwrap();
+    }
+
+    #[test]
+    fn latest_values_replace() {
+        let queue: &'static TxQueue<NoopRawMutex, 32, 2> = Box::leak(Box::new(TxQueue::new()));
+        let tx = queue.wire_tx();
+        let hdr = |key: u8| VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([key; 8]) }),
+            seq_no: VarSeq::Seq4(123),
+        };
+        tx.try_send_latest(hdr(1), &1u8).unwrap();
+        tx.try_send_latest(hdr(2), &2u8).unwrap();
+        tx.try_send_latest(hdr(1), &3u8).unwrap();
+        // All slots are taken by other keys
+        assert!(matches!(
+            tx.try_send_latest(hdr(3), &4u8),
+            Err(TrySendError::Full)
+        ));
+        block_on(tx.send(hdr(1), &5u8)).unwrap();
+
+        // Bulk frames first, then the latest value of each key, in slot order
+        let mut burst = 0;
+        let taken = [(); 3].map(|_| {
+            let frame = block_on(queue.next_frame(&mut burst));
+            let (_rhdr, body) = VarHeader::take_from_slice(&frame).unwrap();
+            postcard::from_bytes::<u8>(body).unwrap()
+        });
+        assert_eq!(taken, [5, 3, 2]);
+        block_on(tx.flush()).unwrap();
+        tx.try_send_latest(hdr(3), &4u8).unwrap();
+    }
+}
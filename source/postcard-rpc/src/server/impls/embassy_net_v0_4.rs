@@ -0,0 +1,193 @@
+//! Implementation using `embassy-net` TCP sockets
+//!
+//! This allows running the same dispatcher over Ethernet or Wi-Fi, instead of USB.
+//! As TCP is a byte stream, each frame is prefixed by its length as a little endian
+//! `u32`, like with the [`spsc`][super::spsc] impl.
+//!
+//! The reading and writing halves of a [`TcpSocket`] only borrow the socket for one
+//! connection, while `spawn` handlers need a `'static` sender. Frames are therefore
+//! sent through a static [`TxQueue`] (see the [`channel_sender`][super::channel_sender]
+//! module), which [`serve()`] drains into the socket while the connection is open.
+//! Each connection gets a new dispatcher, and the queue is reset before serving it,
+//! so replies of the previous connection, e.g. from `spawn` handlers that are still
+//! running, never reach the new client.
+//!
+//! ```rust,ignore
+//! use embassy_net::{tcp::TcpSocket, Stack};
+//! use postcard_rpc::server::impls::{
+//!     channel_sender::TxQueue,
+//!     embassy_net_v0_4::{dispatch_impl::WireTxImpl, serve},
+//!     embassy_usb_v0_3::dispatch_impl::{spawn_fn, WireSpawnImpl},
+//! };
+//!
+//! static QUEUE: TxQueue<ThreadModeRawMutex, 512, 4> = TxQueue::new();
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     spawn_fn: spawn_fn;
+//!     tx_impl: WireTxImpl<ThreadModeRawMutex, 512, 4>;
+//!     spawn_impl: WireSpawnImpl;
+//!     context: MyContext;
+//!     // ...
+//! }
+//!
+//! #[embassy_executor::task]
+//! async fn tcp_server(stack: &'static Stack<Device>, spawner: Spawner) {
+//!     let mut socket_rx = [0u8; 1024];
+//!     let mut socket_tx = [0u8; 1024];
+//!     let mut rx_buf = [0u8; 512];
+//!     let mut socket = TcpSocket::new(stack, &mut socket_rx, &mut socket_tx);
+//!     loop {
+//!         if socket.accept(1234).await.is_err() {
+//!             continue;
+//!         }
+//!         // A new dispatcher for each connection, so no state carries over
+//!         let app = MyApp::new(MyContext::default(), spawner.into());
+//!         serve(&mut socket, &QUEUE, &mut rx_buf, app, VarKeyKind::Key8).await;
+//!     }
+//! }
+//! ```
+//!
+//! Only one connection is served at a time. This module does not provide a
+//! [`WireSpawn`][crate::server::WireSpawn] impl, as that depends on the executor
+//! rather than the transport. Any impl may be used, for example the one from the
+//! `embassy-usb-0_3-server` feature.
+
+use embassy_futures::select::select;
+use embassy_net::tcp::{TcpReader, TcpSocket, TcpWriter};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+
+use crate::{
+    header::VarKeyKind,
+    server::{
+        impls::channel_sender::{QueuedWireTx, TxQueue},
+        Dispatch, Server, WireRx, WireRxErrorKind,
+    },
+};
+
+/// The size of the length prefix of each frame
+const LEN_SZ: usize = 4;
+
+/// A collection of types and aliases useful for importing the correct types
+pub mod dispatch_impl {
+    /// Type alias for `WireTx` impl
+    pub type WireTxImpl<M, const SZ: usize, const DEPTH: usize> =
+        crate::server::impls::channel_sender::QueuedWireTx<M, SZ, DEPTH>;
+    /// Type alias for `WireRx` impl
+    pub type WireRxImpl<'a> = super::TcpWireRx<'a>;
+}
+
+/// Serve a single, already accepted connection of `socket` with `dispatch`
+///
+/// `queue` must be the queue of the `WireTx` type of the dispatcher, and `rx_buf`
+/// must fit the largest request. This returns once the connection is closed by the
+/// client or fails, after aborting the connection, so `socket` can accept the next
+/// one. See the [module level docs][self] for an example.
+pub async fn serve<D, M, const SZ: usize, const DEPTH: usize>(
+    socket: &mut TcpSocket<'_>,
+    queue: &'static TxQueue<M, SZ, DEPTH>,
+    rx_buf: &mut [u8],
+    dispatch: D,
+    kkind: VarKeyKind,
+) where
+    D: Dispatch<Tx = QueuedWireTx<M, SZ, DEPTH>>,
+    M: RawMutex + 'static,
+{
+    // Drop anything still sent for the previous connection
+    queue.reset();
+    {
+        let tx = queue.wire_tx();
+        let (reader, mut writer) = socket.split();
+        let mut server = Server::new(&tx, TcpWireRx::new(reader), rx_buf, dispatch, kkind);
+        select(server.run(), run_tx(queue, &mut writer)).await;
+    }
+    socket.abort();
+    let _ = socket.flush().await;
+}
+
+/// Write the frames of `queue` to `writer`, until writing fails
+async fn run_tx<M: RawMutex + 'static, const SZ: usize, const DEPTH: usize>(
+    queue: &'static TxQueue<M, SZ, DEPTH>,
+    writer: &mut TcpWriter<'_>,
+) {
+    let mut burst = 0;
+    loop {
+        let frame = queue.next_frame(&mut burst).await;
+        let Ok(len) = u32::try_from(frame.len()) else {
+            continue;
+        };
+        if write_all(writer, &len.to_le_bytes()).await.is_err() {
+            return;
+        }
+        if write_all(writer, &frame).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn write_all(writer: &mut TcpWriter<'_>, mut buf: &[u8]) -> Result<(), ()> {
+    while !buf.is_empty() {
+        match writer.write(buf).await {
+            Ok(0) | Err(_) => return Err(()),
+            Ok(n) => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireRx`] implementation for the reading half of a [`TcpSocket`]
+///
+/// Receiving is not cancel safe: if a call to [`WireRx::receive()`] is dropped while
+/// a frame is partially read, the rest of the stream can't be decoded, and the
+/// connection should be closed.
+pub struct TcpWireRx<'a> {
+    reader: TcpReader<'a>,
+}
+
+impl<'a> TcpWireRx<'a> {
+    /// Create a new [`TcpWireRx`]
+    pub fn new(reader: TcpReader<'a>) -> Self {
+        Self { reader }
+    }
+
+    /// Fill all of `buf`, failing if the connection is closed first
+    async fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), WireRxErrorKind> {
+        while !buf.is_empty() {
+            match self.reader.read(buf).await {
+                Ok(0) | Err(_) => return Err(WireRxErrorKind::ConnectionClosed),
+                Ok(n) => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WireRx for TcpWireRx<'_> {
+    type Error = WireRxErrorKind;
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        let mut len_bytes = [0u8; LEN_SZ];
+        self.read_exact(&mut len_bytes).await?;
+        let len = u32::from_le_bytes(len_bytes) as usize;
+
+        if len > buf.len() {
+            // Discard the frame, so the next one can be read
+            let mut scratch = [0u8; 64];
+            let mut remain = len;
+            while remain > 0 {
+                let chunk = remain.min(scratch.len());
+                self.read_exact(&mut scratch[..chunk]).await?;
+                remain -= chunk;
+            }
+            return Err(WireRxErrorKind::ReceivedMessageTooLarge);
+        }
+
+        let out = &mut buf[..len];
+        self.read_exact(out).await?;
+        Ok(out)
+    }
+}
@@ -1,8 +1,13 @@
 //! Implementation using `embassy-usb` and bulk interfaces
 
 use crate::{
+    compress,
     header::{VarHeader, VarKey, VarKeyKind, VarSeq},
-    server::{WireRx, WireRxErrorKind, WireSpawn, WireTx, WireTxErrorKind},
+    server::{
+        disconnect::ConnectionSignal,
+        packets::{PacketAccumulator, Progress},
+        WireRx, WireRxErrorKind, WireSpawn, WireTx, WireTxErrorKind,
+    },
     standard_icd::LoggingTopic,
     Topic,
 };
@@ -10,9 +15,12 @@ use core::fmt::Arguments;
 use core::sync::atomic::{AtomicU8, Ordering};
 use embassy_executor::{SpawnError, SpawnToken, Spawner};
 use embassy_futures::select::{select, Either};
-use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use embassy_sync::{
+    blocking_mutex::raw::RawMutex,
+    mutex::{Mutex, MutexGuard},
+};
 use embassy_time::Timer;
-use embassy_usb_driver::{Driver, EndpointError, EndpointIn, EndpointOut};
+use embassy_usb_driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
 use serde::Serialize;
 use static_cell::ConstStaticCell;
 
@@ -42,12 +50,19 @@ pub mod dispatch_impl {
     pub use super::embassy_spawn as spawn_fn;
     use super::{EUsbWireRx, EUsbWireTx, EUsbWireTxInner, UsbDeviceBuffers};
 
+    /// Wait for `ms` milliseconds, usable as the `timer` of `define_dispatch!`
+    pub async fn sleep_ms(ms: u32) {
+        embassy_time::Timer::after_millis(ms.into()).await;
+    }
+
     /// Used for defining the USB interface
     pub const DEVICE_INTERFACE_GUIDS: &[&str] = &["{AFB9A6FB-30BA-44BC-9232-806CFC875321}"];
 
     use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
     use embassy_usb::{
+        builder::InterfaceAltBuilder,
         msos::{self, windows_version},
+        types::InterfaceNumber,
         Builder, Config, UsbDevice,
     };
     use embassy_usb_driver::Driver;
@@ -138,21 +153,13 @@ pub mod dispatch_impl {
             let stindx = interface.string();
             super::STINDX.store(stindx.0, core::sync::atomic::Ordering::Relaxed);
             let mut alt = interface.alt_setting(0xFF, 0xCA, 0x7D, Some(stindx));
-            let ep_out = alt.endpoint_bulk_out(64);
-            let ep_in = alt.endpoint_bulk_in(64);
+            let (wtx, wrx) = self.init_in_alt_setting(&mut alt, 64, tx_buf);
             drop(function);
 
-            let wtx = self.cell.init(Mutex::new(EUsbWireTxInner {
-                ep_in,
-                log_seq: 0,
-                tx_buf,
-                pending_frame: false,
-            }));
-
             // Build the builder.
             let usb = builder.build();
 
-            (usb, EUsbWireTx { inner: wtx }, EUsbWireRx { ep_out })
+            (usb, wtx, wrx)
         }
 
         /// Initialize the static storage.
@@ -201,15 +208,61 @@ pub mod dispatch_impl {
                 msos::PropertyData::RegMultiSz(DEVICE_INTERFACE_GUIDS),
             ));
 
-            // Add a vendor-specific function (class 0xFF), and corresponding interface,
-            // that uses our custom handler.
+            let (_, wtx, wrx) = self.init_in_builder(&mut builder, tx_buf);
+            (builder, wtx, wrx)
+        }
+
+        /// Initialize the static storage, adding a vendor-specific function to a
+        /// `Builder` owned by the caller
+        ///
+        /// Unlike [`Self::init_without_build()`], this doesn't create the `Builder`,
+        /// and doesn't add the Microsoft OS descriptors, so postcard-rpc can be one
+        /// function of a composite device. The function, with a single interface
+        /// (class 0xFF), is added where this is called, after the functions added
+        /// before. Returns the number of its interface, e.g. to handle control
+        /// requests to it.
+        ///
+        /// For Windows to load the WinUSB driver for the interface without an INF file,
+        /// add the "WINUSB" compatible ID and the [`DEVICE_INTERFACE_GUIDS`] as
+        /// Microsoft OS descriptors, see [`Self::init_without_build()`].
+        ///
+        /// This must only be called once.
+        pub fn init_in_builder(
+            &'static self,
+            builder: &mut Builder<'static, D>,
+            tx_buf: &'static mut [u8],
+        ) -> (InterfaceNumber, WireTxImpl<M, D>, WireRxImpl<D>) {
+            // Add a vendor-specific function (class 0xFF), and corresponding interface
             let mut function = builder.function(0xFF, 0, 0);
             let mut interface = function.interface();
+            let iface = interface.interface_number();
             let mut alt = interface.alt_setting(0xFF, 0, 0, None);
-            let ep_out = alt.endpoint_bulk_out(64);
-            let ep_in = alt.endpoint_bulk_in(64);
+            let (wtx, wrx) = self.init_in_alt_setting(&mut alt, 64, tx_buf);
             drop(function);
 
+            (iface, wtx, wrx)
+        }
+
+        /// Initialize the static storage, allocating the bulk endpoints in an
+        /// alternate setting of an interface created by the caller
+        ///
+        /// This gives full control over the function, interface and alternate setting
+        /// that postcard-rpc runs on, e.g. a vendor interface with a specific class,
+        /// subclass and protocol, or an alternate setting other than the first. The
+        /// OUT endpoint is allocated before the IN endpoint, both with
+        /// `max_packet_size`, which is usually 64 for full speed, and 512 for high
+        /// speed devices.
+        ///
+        /// This must only be called once.
+        pub fn init_in_alt_setting(
+            &'static self,
+            alt: &mut InterfaceAltBuilder<'_, 'static, D>,
+            max_packet_size: u16,
+            tx_buf: &'static mut [u8],
+        ) -> (WireTxImpl<M, D>, WireRxImpl<D>) {
+            let ep_out = alt.endpoint_bulk_out(max_packet_size);
+            let ep_in = alt.endpoint_bulk_in(max_packet_size);
+
             let wtx = self.cell.init(Mutex::new(EUsbWireTxInner {
                 ep_in,
                 log_seq: 0,
@@ -217,7 +270,7 @@ pub mod dispatch_impl {
                 pending_frame: false,
             }));
 
-            (builder, EUsbWireTx { inner: wtx }, EUsbWireRx { ep_out })
+            (EUsbWireTx { inner: wtx }, EUsbWireRx { ep_out })
         }
     }
 }
@@ -246,15 +299,34 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> Clone for EUsbWireTx<M
     }
 }
 
+impl<M: RawMutex + 'static, D: Driver<'static> + 'static> EUsbWireTx<M, D> {
+    /// Lock the sending half
+    ///
+    /// In the common case, the handlers reply one at a time, and the lock is free.
+    /// Try to take it without going through the async lock first. The lock is then
+    /// held for all packets of the frame, so it is only taken once per frame.
+    #[inline]
+    async fn lock(&self) -> MutexGuard<'static, M, EUsbWireTxInner<D>> {
+        match self.inner.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => self.inner.lock().await,
+        }
+    }
+}
+
 impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<M, D> {
     type Error = WireTxErrorKind;
 
+    fn closed_error() -> Option<Self::Error> {
+        Some(WireTxErrorKind::ConnectionClosed)
+    }
+
     async fn send<T: Serialize + ?Sized>(
         &self,
         hdr: VarHeader,
         msg: &T,
     ) -> Result<(), Self::Error> {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.lock().await;
 
         let EUsbWireTxInner {
             ep_in,
@@ -263,8 +335,10 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
             pending_frame,
         }: &mut EUsbWireTxInner<D> = &mut inner;
 
+        let have = tx_buf.len();
         let (hdr_used, remain) = hdr.write_to_slice(tx_buf).ok_or(WireTxErrorKind::Other)?;
-        let bdy_used = postcard::to_slice(msg, remain).map_err(|_| WireTxErrorKind::Other)?;
+        let bdy_used = postcard::to_slice(msg, remain)
+            .map_err(|_| WireTxErrorKind::too_large(&hdr, msg, have))?;
         let used_ttl = hdr_used.len() + bdy_used.len();
 
         if let Some(used) = tx_buf.get(..used_ttl) {
@@ -275,7 +349,7 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
     }
 
     async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.lock().await;
         let EUsbWireTxInner {
             ep_in,
             pending_frame,
@@ -284,8 +358,28 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
         send_all::<D>(ep_in, buf, pending_frame).await
     }
 
+    async fn send_compressed<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut inner = self.lock().await;
+
+        let EUsbWireTxInner {
+            ep_in,
+            log_seq: _,
+            tx_buf,
+            pending_frame,
+        }: &mut EUsbWireTxInner<D> = &mut inner;
+
+        let have = tx_buf.len();
+        let used_ttl = compress::write_to_slice(hdr, msg, tx_buf)
+            .ok_or_else(|| WireTxErrorKind::too_large(&hdr, msg, have))?;
+        send_all::<D>(ep_in, &tx_buf[..used_ttl], pending_frame).await
+    }
+
     async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.lock().await;
 
         let EUsbWireTxInner {
             ep_in,
@@ -323,7 +417,7 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
         kkind: VarKeyKind,
         args: Arguments<'a>,
     ) -> Result<(), Self::Error> {
-        let mut inner = self.inner.lock().await;
+        let mut inner = self.lock().await;
 
         let EUsbWireTxInner {
             ep_in,
@@ -402,6 +496,35 @@ impl<M: RawMutex + 'static, D: Driver<'static> + 'static> WireTx for EUsbWireTx<
 
         send_all::<D>(ep_in, &tx_buf[..act_used], pending_frame).await
     }
+
+    async fn flush(&self) -> Result<(), Self::Error> {
+        // Taking the lock waits for any write in progress to complete
+        let mut inner = self.lock().await;
+        let EUsbWireTxInner {
+            ep_in,
+            pending_frame,
+            ..
+        }: &mut EUsbWireTxInner<D> = &mut inner;
+
+        if !*pending_frame {
+            return Ok(());
+        }
+
+        // The last frame was interrupted, terminate it with an empty packet
+        match select(ep_in.write(&[]), Timer::after_millis(2)).await {
+            Either::First(Ok(())) => {
+                *pending_frame = false;
+                Ok(())
+            }
+            Either::First(Err(_)) => Err(WireTxErrorKind::ConnectionClosed),
+            Either::Second(()) => Err(WireTxErrorKind::Timeout),
+        }
+    }
+
+    async fn wait_connection(&self) {
+        let mut inner = self.lock().await;
+        inner.ep_in.wait_enabled().await;
+    }
 }
 
 #[inline]
@@ -538,39 +661,81 @@ impl<D: Driver<'static>> WireRx for EUsbWireRx<D> {
 
     async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
         let buflen = buf.len();
-        let mut window = &mut buf[..];
-        while !window.is_empty() {
-            let n = match self.ep_out.read(window).await {
-                Ok(n) => n,
+        let max_packet = self.ep_out.info().max_packet_size as usize;
+        let mut acc = PacketAccumulator::new(max_packet);
+        loop {
+            let progress = match self.ep_out.read(acc.window(buf)).await {
+                Ok(n) => acc.push(n, buflen),
                 Err(EndpointError::BufferOverflow) => {
-                    return Err(WireRxErrorKind::ReceivedMessageTooLarge)
+                    // Keep reading until the end of this frame, so the next
+                    // frame starts cleanly
+                    acc.overflow();
+                    Progress::Pending
                 }
                 Err(EndpointError::Disabled) => return Err(WireRxErrorKind::ConnectionClosed),
             };
 
-            let (_now, later) = window.split_at_mut(n);
-            window = later;
-            if n != 64 {
-                // We now have a full frame! Great!
-                let wlen = window.len();
-                let len = buflen - wlen;
-                let frame = &mut buf[..len];
-
-                return Ok(frame);
+            match progress {
+                Progress::Pending => {}
+                Progress::Frame(len) => return Ok(&mut buf[..len]),
+                Progress::TooLong => return Err(WireRxErrorKind::ReceivedMessageTooLarge),
             }
         }
+    }
 
-        // If we got here, we've run out of space. That's disappointing. Accumulate to the
-        // end of this packet
-        loop {
-            match self.ep_out.read(buf).await {
-                Ok(64) => {}
-                Ok(_) => return Err(WireRxErrorKind::ReceivedMessageTooLarge),
-                Err(EndpointError::BufferOverflow) => {
-                    return Err(WireRxErrorKind::ReceivedMessageTooLarge)
-                }
-                Err(EndpointError::Disabled) => return Err(WireRxErrorKind::ConnectionClosed),
-            };
+    async fn wait_connection(&mut self) {
+        self.ep_out.wait_enabled().await;
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// DISCONNECT
+//////////////////////////////////////////////////////////////////////////////
+
+/// An [`embassy_usb::Handler`] that trips a disconnect signal when the USB link
+/// goes down
+///
+/// The signal is tripped when the bus is reset, and when the device is disabled or
+/// deconfigured, e.g. because the cable was pulled. This happens right away, even
+/// while the server is busy with a handler, while the server itself only notices
+/// once receiving fails. See the [`disconnect`][crate::server::disconnect] module.
+///
+/// Register it with the USB [`Builder`][embassy_usb::Builder], and pass the same
+/// signal to the server:
+///
+/// ```rust,ignore
+/// static LINK: DisconnectSignal<4> = DisconnectSignal::new();
+/// static LINK_HANDLER: StaticCell<DisconnectHandler> = StaticCell::new();
+///
+/// builder.handler(LINK_HANDLER.init(DisconnectHandler::new(&LINK)));
+/// // ...
+/// server.set_disconnect_signal(Some(&LINK));
+/// ```
+pub struct DisconnectHandler {
+    signal: &'static dyn ConnectionSignal,
+}
+
+impl DisconnectHandler {
+    /// Create a handler that trips `signal`
+    pub const fn new(signal: &'static dyn ConnectionSignal) -> Self {
+        Self { signal }
+    }
+}
+
+impl embassy_usb::Handler for DisconnectHandler {
+    fn enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.signal.disconnect();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.signal.disconnect();
+    }
+
+    fn configured(&mut self, configured: bool) {
+        if !configured {
+            self.signal.disconnect();
         }
     }
 }
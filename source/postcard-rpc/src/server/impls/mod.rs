@@ -2,8 +2,50 @@
 //!
 //! The implementations in this module typically require feature flags to be set.
 
+#[cfg(feature = "channel-sender")]
+pub mod channel_sender;
+
+#[cfg(feature = "embassy-net-0_4-server")]
+pub mod embassy_net_v0_4;
+
 #[cfg(feature = "embassy-usb-0_3-server")]
 pub mod embassy_usb_v0_3;
 
+#[cfg(feature = "spsc-server")]
+pub mod spsc;
+
 #[cfg(feature = "test-utils")]
 pub mod test_channels;
+
+#[doc(hidden)]
+#[cfg(any(feature = "embassy-usb-0_3-server", feature = "spsc-server"))]
+pub use static_cell;
+
+/// Initialize a `WireStorage`, with a statically allocated TX buffer
+///
+/// The `init` methods of the `WireStorage` types take the buffer used for
+/// serializing outgoing frames as a `&'static mut [u8]`, which can't be a
+/// buffer on the stack. This macro declares a static buffer of `$len` bytes,
+/// and passes it as the last argument to `$storage.init(..)`:
+///
+/// ```rust,ignore
+/// use postcard_rpc::make_sender;
+///
+/// static STORAGE: AppStorage = AppStorage::new();
+///
+/// // Same as `STORAGE.init(driver, config, tx_buf)`
+/// let (device, tx_impl, rx_impl) = make_sender!(STORAGE, 1024, driver, config);
+/// ```
+///
+/// Like `init`, this may only be called once: the buffer is taken the first
+/// time the expanded code runs, and it panics if it runs again. Use `init`
+/// directly to provide the buffer in some other way.
+#[cfg(any(feature = "embassy-usb-0_3-server", feature = "spsc-server"))]
+#[macro_export]
+macro_rules! make_sender {
+    ($storage:expr, $len:expr $(, $arg:expr)* $(,)?) => {{
+        static TX_BUF: $crate::server::impls::static_cell::ConstStaticCell<[u8; $len]> =
+            $crate::server::impls::static_cell::ConstStaticCell::new([0u8; $len]);
+        $storage.init($($arg,)* TX_BUF.take())
+    }};
+}
@@ -0,0 +1,484 @@
+//! Implementation using a lock-free single producer, single consumer ring buffer
+//!
+//! This is intended for multi-core systems, where one core acts as the "server",
+//! and another core acts as the "client", communicating through a [`heapless::spsc::Queue`]
+//! placed in memory shared by both cores.
+//!
+//! Two queues are needed, one for each direction. The [`SpscWireTx`] and [`SpscWireRx`]
+//! types are symmetric, so the same types may be used by the client to send requests
+//! and receive responses.
+//!
+//! On the wire (or rather, in the ring), each frame is prefixed by its length as a
+//! little endian `u32`. A frame is only placed in the ring once there is room for the
+//! whole frame, and only taken from the ring once the whole frame has arrived, so
+//! sending and receiving may be safely cancelled. This means that frames must fit
+//! in the capacity of the queue, which is `N - 1` bytes, minus four bytes for the length.
+//!
+//! As the other core has no way to wake our executor, both sending and receiving
+//! poll the queue, yielding to the executor between each attempt.
+//!
+//! This module does not provide a [`WireSpawn`][crate::server::WireSpawn] impl, as
+//! that depends on the executor rather than the transport. Any impl may be used, for
+//! example the one from the `embassy-usb-0_3-server` feature.
+
+use crate::{
+    compress,
+    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    server::{WireRx, WireRxErrorKind, WireTx, WireTxErrorKind},
+    standard_icd::LoggingTopic,
+    Topic,
+};
+use core::fmt::Arguments;
+use embassy_futures::yield_now;
+use embassy_sync::{
+    blocking_mutex::raw::RawMutex,
+    mutex::{Mutex, MutexGuard},
+};
+use heapless::spsc::{Consumer, Producer};
+use serde::Serialize;
+
+/// The size of the length prefix of each frame
+const LEN_SZ: usize = 4;
+
+/// A collection of types and aliases useful for importing the correct types
+pub mod dispatch_impl {
+    use super::{SpscWireRx, SpscWireTx, SpscWireTxInner};
+
+    use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+    use heapless::spsc::{Consumer, Producer};
+    use static_cell::StaticCell;
+
+    /// Type alias for `WireTx` impl
+    pub type WireTxImpl<M, const N: usize> = super::SpscWireTx<M, N>;
+    /// Type alias for `WireRx` impl
+    pub type WireRxImpl<const N: usize> = super::SpscWireRx<N>;
+    /// Type alias for the receive buffer
+    pub type WireRxBuf = &'static mut [u8];
+
+    /// A static storage container for the sending half of the transport
+    pub struct WireStorage<M: RawMutex + 'static, const N: usize> {
+        /// The mutex protecting the sending half
+        pub cell: StaticCell<Mutex<M, SpscWireTxInner<N>>>,
+    }
+
+    impl<M: RawMutex + 'static, const N: usize> WireStorage<M, N> {
+        /// Create a new, uninitialized storage
+        pub const fn new() -> Self {
+            Self {
+                cell: StaticCell::new(),
+            }
+        }
+
+        /// Initialize the transport
+        ///
+        /// `producer` is the sending half of the queue towards the client, and `consumer`
+        /// is the receiving half of the queue from the client. `tx_buf` is used as scratch
+        /// space when serializing outgoing frames.
+        ///
+        /// Panics if called more than once.
+        pub fn init(
+            &'static self,
+            producer: Producer<'static, u8, N>,
+            consumer: Consumer<'static, u8, N>,
+            tx_buf: &'static mut [u8],
+        ) -> (WireTxImpl<M, N>, WireRxImpl<N>) {
+            let wtx = self.cell.init(Mutex::new(SpscWireTxInner {
+                producer,
+                log_seq: 0,
+                tx_buf,
+            }));
+            (SpscWireTx { inner: wtx }, SpscWireRx::new(consumer))
+        }
+    }
+
+    impl<M: RawMutex + 'static, const N: usize> Default for WireStorage<M, N> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// TX
+//////////////////////////////////////////////////////////////////////////////
+
+/// Implementation detail, holding the producer and scratch buffer used for sending
+pub struct SpscWireTxInner<const N: usize> {
+    producer: Producer<'static, u8, N>,
+    log_seq: u16,
+    tx_buf: &'static mut [u8],
+}
+
+/// A [`WireTx`] implementation for a [`heapless::spsc::Queue`] of bytes
+#[derive(Copy)]
+pub struct SpscWireTx<M: RawMutex + 'static, const N: usize> {
+    inner: &'static Mutex<M, SpscWireTxInner<N>>,
+}
+
+impl<M: RawMutex + 'static, const N: usize> SpscWireTx<M, N> {
+    /// Create a new [`SpscWireTx`] from the mutex protecting the sending half
+    ///
+    /// See also [`dispatch_impl::WireStorage`] for creating this with static storage.
+    pub fn new(inner: &'static Mutex<M, SpscWireTxInner<N>>) -> Self {
+        Self { inner }
+    }
+
+    /// Lock the sending half, without going through the async lock if it is free
+    #[inline]
+    async fn lock(&self) -> MutexGuard<'static, M, SpscWireTxInner<N>> {
+        match self.inner.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => self.inner.lock().await,
+        }
+    }
+}
+
+impl<const N: usize> SpscWireTxInner<N> {
+    /// Create the sending half, using `tx_buf` as scratch space when serializing
+    pub fn new(producer: Producer<'static, u8, N>, tx_buf: &'static mut [u8]) -> Self {
+        Self {
+            producer,
+            log_seq: 0,
+            tx_buf,
+        }
+    }
+}
+
+impl<M: RawMutex + 'static, const N: usize> Clone for SpscWireTx<M, N> {
+    fn clone(&self) -> Self {
+        SpscWireTx { inner: self.inner }
+    }
+}
+
+impl<M: RawMutex + 'static, const N: usize> WireTx for SpscWireTx<M, N> {
+    type Error = WireTxErrorKind;
+
+    fn closed_error() -> Option<Self::Error> {
+        Some(WireTxErrorKind::ConnectionClosed)
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut inner = self.lock().await;
+
+        let SpscWireTxInner {
+            producer,
+            log_seq: _,
+            tx_buf,
+        }: &mut SpscWireTxInner<N> = &mut inner;
+
+        let have = tx_buf.len();
+        let (hdr_used, remain) = hdr.write_to_slice(tx_buf).ok_or(WireTxErrorKind::Other)?;
+        let bdy_used = postcard::to_slice(msg, remain)
+            .map_err(|_| WireTxErrorKind::too_large(&hdr, msg, have))?;
+        let used_ttl = hdr_used.len() + bdy_used.len();
+
+        if let Some(used) = tx_buf.get(..used_ttl) {
+            send_frame(producer, used).await
+        } else {
+            Err(WireTxErrorKind::Other)
+        }
+    }
+
+    async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        let mut inner = self.lock().await;
+        send_frame(&mut inner.producer, buf).await
+    }
+
+    async fn send_compressed<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut inner = self.lock().await;
+
+        let SpscWireTxInner {
+            producer,
+            log_seq: _,
+            tx_buf,
+        }: &mut SpscWireTxInner<N> = &mut inner;
+
+        let have = tx_buf.len();
+        let used = compress::write_to_slice(hdr, msg, tx_buf)
+            .ok_or_else(|| WireTxErrorKind::too_large(&hdr, msg, have))?;
+        send_frame(producer, &tx_buf[..used]).await
+    }
+
+    async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
+        let mut inner = self.lock().await;
+
+        let SpscWireTxInner {
+            producer,
+            log_seq,
+            tx_buf,
+        }: &mut SpscWireTxInner<N> = &mut inner;
+
+        let wh = log_header(kkind, log_seq);
+        let (hdr_used, remain) = wh.write_to_slice(tx_buf).ok_or(WireTxErrorKind::Other)?;
+        let bdy_used = postcard::to_slice::<str>(s, remain).map_err(|_| WireTxErrorKind::Other)?;
+        let used_ttl = hdr_used.len() + bdy_used.len();
+
+        if let Some(used) = tx_buf.get(..used_ttl) {
+            send_frame(producer, used).await
+        } else {
+            Err(WireTxErrorKind::Other)
+        }
+    }
+
+    async fn send_log_fmt<'a>(
+        &self,
+        kkind: VarKeyKind,
+        args: Arguments<'a>,
+    ) -> Result<(), Self::Error> {
+        let mut inner = self.lock().await;
+
+        let SpscWireTxInner {
+            producer,
+            log_seq,
+            tx_buf,
+        }: &mut SpscWireTxInner<N> = &mut inner;
+
+        let wh = log_header(kkind, log_seq);
+        let Some((hdr_used, remaining)) = wh.write_to_slice(tx_buf) else {
+            return Err(WireTxErrorKind::Other);
+        };
+        let hdr_len = hdr_used.len();
+
+        // Format the message after space reserved for the longest possible length
+        // field, then encode the real length and move the message down to follow it
+        let max_len_len = varint_len(remaining.len());
+        if remaining.len() < max_len_len {
+            return Err(WireTxErrorKind::Other);
+        }
+        let (len_field, body) = remaining.split_at_mut(max_len_len);
+        let body_len = body.len();
+        let mut sw = SliceWriter(body);
+        let res = core::fmt::write(&mut sw, args);
+        let used = body_len - sw.0.len();
+
+        // If we ran out of room, mark the message as truncated
+        if res.is_err() && (used >= 3) {
+            body[used - 3..used].iter_mut().for_each(|b| *b = b'.');
+        }
+
+        let len_used = postcard::to_slice(&used, len_field)
+            .map_err(|_| WireTxErrorKind::Other)?
+            .len();
+        let body_start = hdr_len + max_len_len;
+        tx_buf.copy_within(body_start..body_start + used, hdr_len + len_used);
+
+        send_frame(producer, &tx_buf[..hdr_len + len_used + used]).await
+    }
+}
+
+fn log_header(kkind: VarKeyKind, log_seq: &mut u16) -> VarHeader {
+    let key = match kkind {
+        VarKeyKind::Key1 => VarKey::Key1(LoggingTopic::TOPIC_KEY1),
+        VarKeyKind::Key2 => VarKey::Key2(LoggingTopic::TOPIC_KEY2),
+        VarKeyKind::Key4 => VarKey::Key4(LoggingTopic::TOPIC_KEY4),
+        VarKeyKind::Key8 => VarKey::Key8(LoggingTopic::TOPIC_KEY),
+    };
+    let ctr = *log_seq;
+    *log_seq = log_seq.wrapping_add(1);
+    VarHeader {
+        key,
+        seq_no: VarSeq::Seq2(ctr),
+    }
+}
+
+/// The number of bytes needed to varint-encode `n`
+fn varint_len(mut n: usize) -> usize {
+    let mut used = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        used += 1;
+    }
+    used
+}
+
+/// Place a single frame in the queue, waiting until there is room for the whole frame
+async fn send_frame<const N: usize>(
+    producer: &mut Producer<'static, u8, N>,
+    frame: &[u8],
+) -> Result<(), WireTxErrorKind> {
+    let needed = LEN_SZ + frame.len();
+    let Ok(len) = u32::try_from(frame.len()) else {
+        return Err(WireTxErrorKind::Other);
+    };
+    if needed > producer.capacity() {
+        // This will never fit
+        return Err(WireTxErrorKind::Other);
+    }
+
+    while producer.capacity() - producer.len() < needed {
+        yield_now().await;
+    }
+
+    for b in len.to_le_bytes().into_iter().chain(frame.iter().copied()) {
+        // We checked above that there is room for the whole frame, and we are the
+        // only producer, so this can't fail
+        if producer.enqueue(b).is_err() {
+            return Err(WireTxErrorKind::Other);
+        }
+    }
+    Ok(())
+}
+
+struct SliceWriter<'a>(&'a mut [u8]);
+
+impl<'a> core::fmt::Write for SliceWriter<'a> {
+    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
+        let sli = core::mem::take(&mut self.0);
+
+        // If this write would overflow us, note that, but still take
+        // as much as we possibly can here
+        let bad = s.len() > sli.len();
+        let to_write = s.len().min(sli.len());
+        let (now, later) = sli.split_at_mut(to_write);
+        now.copy_from_slice(&s.as_bytes()[..to_write]);
+        self.0 = later;
+
+        // Now, report whether we overflowed or not
+        if bad {
+            Err(core::fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// RX
+//////////////////////////////////////////////////////////////////////////////
+
+/// A [`WireRx`] implementation for a [`heapless::spsc::Queue`] of bytes
+pub struct SpscWireRx<const N: usize> {
+    consumer: Consumer<'static, u8, N>,
+    pending_len: Option<usize>,
+}
+
+impl<const N: usize> SpscWireRx<N> {
+    /// Create a new [`SpscWireRx`] from the receiving half of a queue
+    pub fn new(consumer: Consumer<'static, u8, N>) -> Self {
+        Self {
+            consumer,
+            pending_len: None,
+        }
+    }
+}
+
+impl<const N: usize> WireRx for SpscWireRx<N> {
+    type Error = WireRxErrorKind;
+
+    async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        // Wait for the length of the next frame, unless we already took it
+        // before being cancelled
+        let len = match self.pending_len {
+            Some(len) => len,
+            None => {
+                while self.consumer.len() < LEN_SZ {
+                    yield_now().await;
+                }
+                let mut len_bytes = [0u8; LEN_SZ];
+                len_bytes
+                    .iter_mut()
+                    .for_each(|b| *b = self.consumer.dequeue().unwrap_or(0));
+                let len = u32::from_le_bytes(len_bytes) as usize;
+                self.pending_len = Some(len);
+                len
+            }
+        };
+
+        // Then wait for the whole frame to arrive
+        while self.consumer.len() < len {
+            yield_now().await;
+        }
+        self.pending_len = None;
+
+        let Some(out) = buf.get_mut(..len) else {
+            // Discard the frame
+            (0..len).for_each(|_| {
+                let _ = self.consumer.dequeue();
+            });
+            return Err(WireRxErrorKind::ReceivedMessageTooLarge);
+        };
+        out.iter_mut()
+            .for_each(|b| *b = self.consumer.dequeue().unwrap_or(0));
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dispatch_impl::WireStorage, SpscWireRx, SpscWireTx, SpscWireTxInner};
+    use crate::{
+        header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+        server::{WireRx, WireTx},
+        Key,
+    };
+    use embassy_futures::block_on;
+    use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+    use heapless::spsc::Queue;
+
+    #[test]
+    fn round_trip() {
+        let queue: &'static mut Queue<u8, 64> = Box::leak(Box::new(Queue::new()));
+        let (producer, consumer) = queue.split();
+        let tx_buf: &'static mut [u8] = Box::leak(Box::new([0u8; 32]));
+        let inner = Box::leak(Box::new(Mutex::<NoopRawMutex, _>::new(
+            SpscWireTxInner::new(producer, tx_buf),
+        )));
+        let tx = SpscWireTx::new(inner);
+        let mut rx = SpscWireRx::new(consumer);
+
+        let hdr = VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) }),
+            seq_no: VarSeq::Seq4(123),
+        };
+        block_on(async {
+            tx.send(hdr, &0x1234u16).await.unwrap();
+            tx.send_log_fmt(VarKeyKind::Key8, format_args!("hello {}", 42))
+                .await
+                .unwrap();
+
+            let mut buf = [0u8; 32];
+            let frame = rx.receive(&mut buf).await.unwrap();
+            let (rhdr, body) = VarHeader::take_from_slice(frame).unwrap();
+            assert_eq!(rhdr, hdr);
+            assert_eq!(postcard::from_bytes::<u16>(body).unwrap(), 0x1234);
+
+            let frame = rx.receive(&mut buf).await.unwrap();
+            let (_rhdr, body) = VarHeader::take_from_slice(frame).unwrap();
+            assert_eq!(postcard::from_bytes::<&str>(body).unwrap(), "hello 42");
+
+            // Too large for the ring
+            assert!(tx.send_raw(&[0u8; 64]).await.is_err());
+        });
+    }
+
+    #[test]
+    fn make_sender_static_buf() {
+        static STORAGE: WireStorage<NoopRawMutex, 64> = WireStorage::new();
+
+        let queue: &'static mut Queue<u8, 64> = Box::leak(Box::new(Queue::new()));
+        let (producer, consumer) = queue.split();
+        let (tx, mut rx) = crate::make_sender!(STORAGE, 32, producer, consumer);
+
+        let hdr = VarHeader {
+            key: VarKey::Key8(unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) }),
+            seq_no: VarSeq::Seq1(7),
+        };
+        block_on(async {
+            // Serialized into the static TX buffer
+            tx.send(hdr, &0x1234u16).await.unwrap();
+            let mut buf = [0u8; 32];
+            let frame = rx.receive(&mut buf).await.unwrap();
+            let (rhdr, body) = VarHeader::take_from_slice(frame).unwrap();
+            assert_eq!(rhdr, hdr);
+            assert_eq!(postcard::from_bytes::<u16>(body).unwrap(), 0x1234);
+        });
+    }
+}
@@ -1,4 +1,32 @@
 //! Implementation that uses channels for local testing
+//!
+//! ## Simulating firmware
+//!
+//! The same [`define_dispatch!`][crate::define_dispatch] used by the firmware can run
+//! on tokio, e.g. to test the interactions of its handlers in a host-side simulator.
+//! All executor specific parts are named in the macro, as `spawn_fn`, `tx_impl` and
+//! `spawn_impl`, and the `dispatch_impl` modules of the server implementations use
+//! the same names, so only the import needs to be switched:
+//!
+//! ```rust,ignore
+//! #[cfg(not(feature = "sim"))]
+//! use postcard_rpc::server::impls::embassy_usb_v0_3::dispatch_impl::{
+//!     spawn_fn, WireSpawnImpl, WireTxImpl,
+//! };
+//! #[cfg(feature = "sim")]
+//! use postcard_rpc::server::impls::test_channels::dispatch_impl::{
+//!     spawn_fn_local as spawn_fn, WireSpawnImpl, WireTxImpl,
+//! };
+//! ```
+//!
+//! Embassy spawns `spawn` handlers as tasks, so they are declared with
+//! `#[cfg_attr(not(feature = "sim"), embassy_executor::task)]`, and tokio spawns the
+//! future they return. Handlers written for a single threaded executor are usually not
+//! `Send`, which [`tokio_spawn`] requires: [`tokio_spawn_local`] spawns them with
+//! [`tokio::task::spawn_local`] instead, so the server must run in a
+//! [`LocalSet`][tokio::task::LocalSet]. The server is then created with
+//! [`ChannelWireTx`] and [`ChannelWireRx`], and a client connected with
+//! `host_client::test_channels`.
 
 use core::{
     convert::Infallible,
@@ -11,8 +39,8 @@ use crate::{
     header::{VarHeader, VarKey, VarKeyKind, VarSeq},
     host_client::util::Stopper,
     server::{
-        AsWireRxErrorKind, AsWireTxErrorKind, WireRx, WireRxErrorKind, WireSpawn, WireTx,
-        WireTxErrorKind,
+        AsWireRxErrorKind, AsWireTxErrorKind, TrySendError, WireRx, WireRxErrorKind, WireSpawn,
+        WireTx, WireTxErrorKind,
     },
     standard_icd::LoggingTopic,
     Topic,
@@ -29,10 +57,16 @@ pub mod dispatch_impl {
     pub use crate::host_client::util::Stopper;
     use crate::{
         header::VarKeyKind,
-        server::{Dispatch, Server},
+        server::{Dispatch, LinkProtection, Server},
     };
 
     pub use super::tokio_spawn as spawn_fn;
+    pub use super::tokio_spawn_local as spawn_fn_local;
+
+    /// Wait for `ms` milliseconds, usable as the `timer` of `define_dispatch!`
+    pub async fn sleep_ms(ms: u32) {
+        tokio::time::sleep(core::time::Duration::from_millis(ms.into())).await;
+    }
 
     /// The settings necessary for creating a new channel server
     pub struct Settings {
@@ -73,6 +107,49 @@ pub mod dispatch_impl {
         )
     }
 
+    /// Create a new server using the [`Settings`] and [`Dispatch`] implementation,
+    /// that protects the frames on the link as given by `protection`
+    pub fn new_server_protected<D>(
+        dispatch: D,
+        settings: Settings,
+        protection: LinkProtection,
+    ) -> crate::server::Server<WireTxImpl, WireRxImpl, WireRxBuf, D>
+    where
+        D: Dispatch<Tx = WireTxImpl>,
+    {
+        let buf = vec![0; settings.buf];
+        Server::new_protected(
+            &settings.tx,
+            settings.rx,
+            buf.into_boxed_slice(),
+            dispatch,
+            settings.kkind,
+            protection,
+        )
+    }
+
+    /// Create a new server using the [`Settings`] and [`Dispatch`] implementation,
+    /// that also reassembles fragmented frames of up to `reassembly_buf` bytes
+    pub fn new_server_reassembling<D>(
+        dispatch: D,
+        settings: Settings,
+        reassembly_buf: usize,
+    ) -> crate::server::Server<WireTxImpl, WireRxImpl, WireRxBuf, D>
+    where
+        D: Dispatch<Tx = WireTxImpl>,
+    {
+        let buf = vec![0; settings.buf];
+        let reassembly_buf = vec![0; reassembly_buf];
+        Server::new_with_reassembly(
+            &settings.tx,
+            settings.rx,
+            buf.into_boxed_slice(),
+            reassembly_buf.into_boxed_slice(),
+            dispatch,
+            settings.kkind,
+        )
+    }
+
     /// Create a new server using the [`Settings`] and [`Dispatch`] implementation
     ///
     /// Also returns a [`Stopper`] that can be used to halt the server's operation
@@ -99,6 +176,86 @@ pub mod dispatch_impl {
         );
         (me, stopper)
     }
+
+    /// Feed recorded frames through `dispatch`, and capture all frames sent in reply
+    ///
+    /// Each frame in `frames` must contain the header and body, as they were sent
+    /// on the wire, e.g. captured from a USB session. Frames with a malformed header
+    /// are skipped, like a [`Server`] would. The replies are returned in the order
+    /// they were sent, including replies sent by `spawn` handlers, which allows
+    /// comparing them against known good ("golden") replies.
+    ///
+    /// This waits until all spawned handlers holding a copy of the [`Sender`] have
+    /// completed.
+    ///
+    /// [`Sender`]: crate::server::Sender
+    pub async fn replay<D>(dispatch: &mut D, frames: &[&[u8]]) -> Vec<Vec<u8>>
+    where
+        D: Dispatch<Tx = WireTxImpl>,
+    {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let sender =
+            crate::server::Sender::new(super::ChannelWireTx::new(tx), dispatch.min_key_len());
+
+        let feed = async move {
+            for frame in frames {
+                let Some((hdr, body)) = crate::header::VarHeader::take_from_slice(frame) else {
+                    continue;
+                };
+                let _ = dispatch.handle(&sender, &hdr, body).await;
+            }
+        };
+        let capture = async {
+            let mut replies = Vec::new();
+            while let Some(reply) = rx.recv().await {
+                replies.push(reply);
+            }
+            replies
+        };
+        let ((), replies) = tokio::join!(feed, capture);
+        replies
+    }
+
+    /// Interpret `data` as a single frame, and dispatch it, discarding any replies
+    ///
+    /// This is intended as the body of a fuzz target, e.g. with `cargo fuzz`, to find
+    /// panics in the handlers, or in the decoding of frames:
+    ///
+    /// ```rust,ignore
+    /// fuzz_target!(|data: &[u8]| {
+    ///     let mut app = MyApp::new(MyContext::default(), ChannelWireSpawn {});
+    ///     fuzz_dispatch(&mut app, data);
+    /// });
+    /// ```
+    ///
+    /// The frame is dispatched like by [`replay()`], on a single threaded tokio
+    /// runtime that is kept for each thread, and this returns once the handler, and
+    /// any `spawn` handler it started, has completed. No other tasks run on the
+    /// runtime, so dispatching the same frame to the same dispatcher behaves the
+    /// same each time, as long as the handlers don't depend on the time or other
+    /// outside state. As this blocks on the runtime, it must not be called from
+    /// within an async context.
+    ///
+    /// Data that doesn't start with a valid header is ignored, and bodies that
+    /// don't deserialize are answered with errors, so a panic in postcard-rpc while
+    /// decoding the frame is always a bug. Panics in handlers are real bugs as well,
+    /// unless they are intended, e.g. a `todo!()` in an unfinished handler, or an
+    /// `assert!` on a precondition the client is trusted to uphold. Such handlers
+    /// can be left out of the fuzzed dispatcher.
+    pub fn fuzz_dispatch<D>(dispatch: &mut D, data: &[u8])
+    where
+        D: Dispatch<Tx = WireTxImpl>,
+    {
+        std::thread_local! {
+            static RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .expect("Creating a runtime should not fail");
+        }
+        RUNTIME.with(|rt| {
+            rt.block_on(replay(dispatch, &[data]));
+        });
+    }
 }
 
 //////////////////////////////////////////////////////////////////////////////
@@ -111,6 +268,8 @@ pub struct ChannelWireTx {
     tx: mpsc::Sender<Vec<u8>>,
     log_ctr: Arc<AtomicU32>,
     stopper: Option<Stopper>,
+    max_frame_len: Option<usize>,
+    fail_sends: Arc<AtomicU32>,
 }
 
 impl ChannelWireTx {
@@ -120,6 +279,8 @@ impl ChannelWireTx {
             tx,
             log_ctr: Arc::new(AtomicU32::new(0)),
             stopper: None,
+            max_frame_len: None,
+            fail_sends: Arc::new(AtomicU32::new(0)),
         }
     }
 
@@ -128,7 +289,38 @@ impl ChannelWireTx {
         self.stopper = Some(stopper);
     }
 
+    /// Reject frames longer than `len`, like a transport with a fixed size send
+    /// buffer would
+    pub fn set_max_frame_len(&mut self, len: usize) {
+        self.max_frame_len = Some(len);
+    }
+
+    /// Fail the next `n` frames sent with this or any clone of it, like a transport
+    /// with a transient write error would, see [`ChannelWireTxError::Failed`]
+    pub fn fail_next_sends(&self, n: u32) {
+        self.fail_sends.store(n, Ordering::Relaxed);
+    }
+
+    fn check_len(&self, msg: &[u8]) -> Result<(), ChannelWireTxError> {
+        if let Some(max) = self.max_frame_len {
+            if msg.len() > max {
+                return Err(ChannelWireTxError::TooLarge {
+                    needed: msg.len() as u32,
+                    have: max as u32,
+                });
+            }
+        }
+        Ok(())
+    }
+
     async fn inner_send(&self, msg: Vec<u8>) -> Result<(), ChannelWireTxError> {
+        self.check_len(&msg)?;
+        let failing = self
+            .fail_sends
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+        if failing.is_ok() {
+            return Err(ChannelWireTxError::Failed);
+        }
         let stop_fut = async {
             if let Some(s) = self.stopper.as_ref() {
                 s.wait_stopped().await;
@@ -153,6 +345,10 @@ impl ChannelWireTx {
 impl WireTx for ChannelWireTx {
     type Error = ChannelWireTxError;
 
+    fn closed_error() -> Option<Self::Error> {
+        Some(ChannelWireTxError::ChannelClosed)
+    }
+
     async fn send<T: serde::Serialize + ?Sized>(
         &self,
         hdr: crate::header::VarHeader,
@@ -169,6 +365,44 @@ impl WireTx for ChannelWireTx {
         self.inner_send(buf).await
     }
 
+    fn try_send<T: serde::Serialize + ?Sized>(
+        &self,
+        hdr: crate::header::VarHeader,
+        msg: &T,
+    ) -> Result<(), TrySendError<Self::Error>> {
+        let mut hdr_ser = hdr.write_to_vec();
+        let bdy_ser = postcard::to_stdvec(msg).unwrap();
+        hdr_ser.extend_from_slice(&bdy_ser);
+        self.check_len(&hdr_ser).map_err(TrySendError::Tx)?;
+        if self.stopper.as_ref().is_some_and(|s| s.is_stopped()) {
+            return Err(TrySendError::Tx(ChannelWireTxError::ChannelClosed));
+        }
+        self.tx.try_send(hdr_ser).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => TrySendError::Full,
+            mpsc::error::TrySendError::Closed(_) => {
+                TrySendError::Tx(ChannelWireTxError::ChannelClosed)
+            }
+        })
+    }
+
+    async fn send_compressed<T: serde::Serialize + ?Sized>(
+        &self,
+        hdr: crate::header::VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        let mut hdr_ser = hdr.write_to_vec();
+        let bdy_ser = postcard::to_stdvec(msg).unwrap();
+        let mut compressed = vec![0u8; bdy_ser.len()];
+        match crate::compress::compress(&bdy_ser, &mut compressed) {
+            Some(used) => {
+                hdr_ser[0] |= VarHeader::COMPRESSED_BITS;
+                hdr_ser.extend_from_slice(&compressed[..used]);
+            }
+            None => hdr_ser.extend_from_slice(&bdy_ser),
+        }
+        self.inner_send(hdr_ser).await
+    }
+
     async fn send_log_str(&self, kkind: VarKeyKind, s: &str) -> Result<(), Self::Error> {
         let ctr = self.log_ctr.fetch_add(1, Ordering::Relaxed);
         let key = match kkind {
@@ -216,12 +450,26 @@ impl WireTx for ChannelWireTx {
 pub enum ChannelWireTxError {
     /// The receiver closed the channel
     ChannelClosed,
+    /// The frame was longer than the limit set with
+    /// [`ChannelWireTx::set_max_frame_len()`]
+    TooLarge {
+        /// The length of the frame
+        needed: u32,
+        /// The maximum frame length
+        have: u32,
+    },
+    /// The frame was failed on purpose, see [`ChannelWireTx::fail_next_sends()`]
+    Failed,
 }
 
 impl AsWireTxErrorKind for ChannelWireTxError {
     fn as_kind(&self) -> WireTxErrorKind {
         match self {
             ChannelWireTxError::ChannelClosed => WireTxErrorKind::ConnectionClosed,
+            ChannelWireTxError::Failed => WireTxErrorKind::Other,
+            ChannelWireTxError::TooLarge { needed, have } => {
+                WireTxErrorKind::TooLarge { needed, have }
+            }
         }
     }
 }
@@ -323,3 +571,16 @@ where
     tokio::task::spawn(fut);
     Ok(())
 }
+
+/// Spawn a task that is not `Send` using tokio, on the current
+/// [`LocalSet`][tokio::task::LocalSet]
+///
+/// Panics if called outside of a `LocalSet`, see [`tokio::task::spawn_local`].
+pub fn tokio_spawn_local<Sp, F>(_sp: &Sp, fut: F) -> Result<(), Sp::Error>
+where
+    Sp: WireSpawn<Error = Infallible, Info = ()>,
+    F: Future<Output = ()> + 'static,
+{
+    tokio::task::spawn_local(fut);
+    Ok(())
+}
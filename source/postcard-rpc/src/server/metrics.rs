@@ -0,0 +1,140 @@
+//! Dispatcher throughput counters
+//!
+//! When the `metrics` feature is enabled, dispatchers generated by
+//! [`define_dispatch!`][crate::define_dispatch] count the number of frames they
+//! have dispatched (in total, and for each handler's key), as well as the number
+//! of errors that occurred. These counters can be retrieved by the client with the
+//! [`GetStatsEndpoint`][crate::standard_icd::GetStatsEndpoint].
+//!
+//! When the `metrics` feature is disabled, [`DispatchStats`] is zero sized and does
+//! nothing, and the client will receive an [`UnknownKey`] error when requesting stats.
+//!
+//! [`UnknownKey`]: crate::standard_icd::WireError::UnknownKey
+
+#[cfg(feature = "metrics")]
+use crate::standard_icd::KeyCount;
+use crate::Key;
+
+/// Counters for frames handled by a dispatcher, with `K` handler keys
+#[cfg(feature = "metrics")]
+pub struct DispatchStats<const K: usize> {
+    frames: u32,
+    errors: u32,
+    keys: [KeyCount; K],
+}
+
+#[cfg(feature = "metrics")]
+impl<const K: usize> DispatchStats<K> {
+    /// Create a new set of counters, for the given handler keys
+    pub const fn new(keys: [Key; K]) -> Self {
+        let mut counts = [KeyCount {
+            key: unsafe { Key::from_bytes([0; 8]) },
+            count: 0,
+        }; K];
+        let mut i = 0;
+        while i < K {
+            counts[i].key = keys[i];
+            i += 1;
+        }
+        Self {
+            frames: 0,
+            errors: 0,
+            keys: counts,
+        }
+    }
+
+    /// Record a single frame for the handler at index `handler` of the keys given
+    /// to [`Self::new()`]
+    ///
+    /// An index that is out of range, e.g. for a standard endpoint or an unknown
+    /// key, is only counted in the total.
+    #[inline]
+    pub fn record_frame(&mut self, handler: usize) {
+        self.frames = self.frames.wrapping_add(1);
+        if let Some(kc) = self.keys.get_mut(handler) {
+            kc.count = kc.count.wrapping_add(1);
+        }
+    }
+
+    /// Record a single error
+    #[inline]
+    pub fn record_error(&mut self) {
+        self.errors = self.errors.wrapping_add(1);
+    }
+
+    /// The total number of frames dispatched
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// The total number of errors
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    /// The number of frames dispatched for each handler key
+    pub fn keys(&self) -> &[KeyCount] {
+        &self.keys
+    }
+
+    /// Reset all counters to zero
+    pub fn reset(&mut self) {
+        self.frames = 0;
+        self.errors = 0;
+        self.keys.iter_mut().for_each(|kc| kc.count = 0);
+    }
+}
+
+/// Counters for frames handled by a dispatcher, with `K` handler keys
+///
+/// The `metrics` feature is disabled, so this does nothing.
+#[cfg(not(feature = "metrics"))]
+pub struct DispatchStats<const K: usize> {
+    _priv: (),
+}
+
+#[cfg(not(feature = "metrics"))]
+impl<const K: usize> DispatchStats<K> {
+    /// Create a new set of counters, for the given handler keys
+    pub const fn new(_keys: [Key; K]) -> Self {
+        Self { _priv: () }
+    }
+
+    /// Record a single frame for the handler at index `handler`
+    #[inline(always)]
+    pub fn record_frame(&mut self, _handler: usize) {}
+
+    /// Record a single error
+    #[inline(always)]
+    pub fn record_error(&mut self) {}
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod test {
+    use super::DispatchStats;
+    use crate::Key;
+
+    const KEY_A: Key = unsafe { Key::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]) };
+    const KEY_B: Key = unsafe { Key::from_bytes([8, 7, 6, 5, 4, 3, 2, 1]) };
+
+    #[test]
+    fn counts() {
+        let mut stats = DispatchStats::new([KEY_A, KEY_B]);
+        stats.record_frame(0);
+        stats.record_frame(1);
+        stats.record_frame(1);
+        // Not a handler, only counted in total
+        stats.record_frame(usize::MAX);
+        stats.record_error();
+
+        assert_eq!(stats.frames(), 4);
+        assert_eq!(stats.errors(), 1);
+        assert_eq!(stats.keys()[0].count, 1);
+        assert_eq!(stats.keys()[1].count, 2);
+
+        stats.reset();
+        assert_eq!(stats.frames(), 0);
+        assert_eq!(stats.errors(), 0);
+        assert!(stats.keys().iter().all(|kc| kc.count == 0));
+    }
+}
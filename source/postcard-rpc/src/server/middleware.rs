@@ -0,0 +1,83 @@
+//! Hooks run around the dispatching of every frame
+//!
+//! A [`Middleware`] is used for concerns that apply to all handlers, such as
+//! checking a session token, rate limiting, or tracing. Middleware is listed in the
+//! optional `middleware` section of [`define_dispatch!`][crate::define_dispatch],
+//! as paths to `static`s (or unit structs) implementing [`Middleware`]:
+//!
+//! ```rust,ignore
+//! static SESSION: SessionCheck = SessionCheck::new();
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     middleware: [SESSION, Tracer];
+//!     // ...
+//! }
+//! ```
+//!
+//! For each received frame, [`Middleware::before()`] is called for each middleware,
+//! in the listed order. If one of them returns [`ControlFlow::Break`], the frame is
+//! not handled, and the error is sent to the client instead. Otherwise, the frame is
+//! handled as usual.
+//!
+//! Afterwards, [`Middleware::after()`] is called, in reverse order, for each
+//! middleware whose `before` returned [`ControlFlow::Continue`].
+//!
+//! Middleware is called for all frames, including topic messages and the standard
+//! endpoints, and only has shared access to itself. Use atomics or a blocking mutex
+//! for any state.
+//!
+//! See [`rate_limit`][super::rate_limit] for a middleware limiting the rate of frames.
+
+use core::ops::ControlFlow;
+
+use crate::{header::VarHeader, standard_icd::WireError};
+
+/// A hook run around the dispatching of every frame, see the [module docs][self]
+pub trait Middleware {
+    /// Called before the frame with the given header and body is handled
+    ///
+    /// Return [`ControlFlow::Break`] to reject the frame, sending the given error to
+    /// the client instead.
+    fn before(&self, hdr: &VarHeader, body: &[u8]) -> ControlFlow<WireError, ()> {
+        let _ = (hdr, body);
+        ControlFlow::Continue(())
+    }
+
+    /// Called after the frame with the given header has been handled
+    ///
+    /// For `spawn` handlers, this is called once the handler has been spawned, not
+    /// when it completes.
+    fn after(&self, hdr: &VarHeader) {
+        let _ = hdr;
+    }
+}
+
+/// Run [`Middleware::before()`] for each of `middleware`, in order
+///
+/// On [`ControlFlow::Break`], returns the number of middleware whose `before`
+/// continued, along with the error. Used by [`define_dispatch!`][crate::define_dispatch].
+#[inline]
+pub fn run_before(
+    middleware: &[&dyn Middleware],
+    hdr: &VarHeader,
+    body: &[u8],
+) -> Result<(), (usize, WireError)> {
+    for (i, mw) in middleware.iter().enumerate() {
+        if let ControlFlow::Break(err) = mw.before(hdr, body) {
+            return Err((i, err));
+        }
+    }
+    Ok(())
+}
+
+/// Run [`Middleware::after()`] for the first `ran` of `middleware`, in reverse order
+///
+/// Used by [`define_dispatch!`][crate::define_dispatch].
+#[inline]
+pub fn run_after(middleware: &[&dyn Middleware], ran: usize, hdr: &VarHeader) {
+    for mw in middleware[..ran].iter().rev() {
+        mw.after(hdr);
+    }
+}
@@ -22,25 +22,83 @@
 
 #![allow(async_fn_in_trait)]
 
+pub mod dedup;
+pub mod disconnect;
+pub mod dispatch_index;
 #[doc(hidden)]
 pub mod dispatch_macro;
-
+pub mod duplex;
+#[cfg(feature = "use-std")]
+pub mod dynamic;
+pub mod error_log;
+pub mod fallback;
+
+pub mod handler_check;
+pub mod heartbeat;
 pub mod impls;
+pub mod metrics;
+pub mod middleware;
+pub mod outcome;
+pub mod packets;
+pub mod rate_limit;
+pub mod reassembly;
+pub mod request_pool;
+pub mod spawn_limit;
+pub mod timeout;
+pub mod transaction;
 
 use core::{fmt::Arguments, ops::DerefMut};
 
+use portable_atomic::{AtomicU32, Ordering};
 use postcard_schema::Schema;
 use serde::Serialize;
 
 use crate::{
-    header::{VarHeader, VarKey, VarKeyKind, VarSeq},
+    cipher::{self, BodyCipher},
+    crc::{self, CrcMode},
+    header::{AuthToken, VarHeader, VarKey, VarKeyKind, VarSeq},
+    standard_icd::{ResponseTooLarge, WireError},
     DeviceMap, Key, TopicDirection,
 };
 
+use self::{
+    disconnect::{Connection, ConnectionSignal},
+    error_log::RecordError,
+    request_pool::PooledBuf,
+    spawn_limit::SpawnPermit,
+};
+
 //////////////////////////////////////////////////////////////////////////////
 // TX
 //////////////////////////////////////////////////////////////////////////////
 
+/// The maximum length of a message sent with [`Sender::log_record()`]
+///
+/// Longer messages are truncated.
+pub const LOG_RECORD_MAX_LEN: usize = 128;
+
+/// A buffer for formatting a message, which truncates the message at a char boundary
+/// once it is full
+struct Truncating<const N: usize>(heapless::String<N>);
+
+impl<const N: usize> core::fmt::Write for Truncating<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let room = N - self.0.len();
+        if s.len() <= room {
+            // Can't fail, as it fits
+            let _ = self.0.push_str(s);
+            return Ok(());
+        }
+        let mut end = room;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        let _ = self.0.push_str(&s[..end]);
+        // Stop formatting, the rest doesn't fit anyway
+        Err(core::fmt::Error)
+    }
+}
+
 /// This trait defines how the server sends frames to the client
 pub trait WireTx: Clone {
     /// The error type of this connection.
@@ -50,12 +108,58 @@ pub trait WireTx: Clone {
     type Error: AsWireTxErrorKind;
 
     /// Send a single frame to the client, returning when send is complete.
+    ///
+    /// Impls that lock their buffer or endpoint must release the lock before
+    /// returning, and must not hold it while awaiting anything but the transport, so
+    /// that the [`Sender`] can guarantee that sending never deadlocks, see its
+    /// "Locking" section. The same applies to all other methods of this trait.
     async fn send<T: Serialize + ?Sized>(&self, hdr: VarHeader, msg: &T)
         -> Result<(), Self::Error>;
 
     /// Send a single frame to the client, without handling serialization
     async fn send_raw(&self, buf: &[u8]) -> Result<(), Self::Error>;
 
+    /// Send a single frame to the client, compressing the body if this makes it
+    /// shorter, see [`compress`][crate::compress].
+    ///
+    /// The default implementation sends the frame uncompressed, which the client
+    /// handles as well.
+    async fn send_compressed<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), Self::Error> {
+        self.send(hdr, msg).await
+    }
+
+    /// Send a single frame to the client, without waiting for room to send it
+    ///
+    /// Returns [`TrySendError::Full`] if the frame can't be taken right away, in which
+    /// case it is dropped. The default implementation always returns
+    /// [`TrySendError::Full`], which is correct for transports that can only send by
+    /// waiting for the transport, see [`Sender::try_publish()`].
+    fn try_send<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), TrySendError<Self::Error>> {
+        let _ = (hdr, msg);
+        Err(TrySendError::Full)
+    }
+
+    /// Send a single frame to the client, replacing any frame with the same key that
+    /// is still waiting to be sent, see [`Sender::publish_latest()`]
+    ///
+    /// The default implementation is [`Self::try_send()`], which is correct for
+    /// transports that don't queue frames.
+    fn try_send_latest<T: Serialize + ?Sized>(
+        &self,
+        hdr: VarHeader,
+        msg: &T,
+    ) -> Result<(), TrySendError<Self::Error>> {
+        self.try_send(hdr, msg)
+    }
+
     /// Send a logging message on the [`LoggingTopic`][crate::standard_icd::LoggingTopic]
     ///
     /// This message is simpler as it does not do any formatting
@@ -69,6 +173,33 @@ pub trait WireTx: Clone {
         kkind: VarKeyKind,
         a: Arguments<'a>,
     ) -> Result<(), Self::Error>;
+
+    /// Wait until all frames sent so far have been handed to the transport
+    ///
+    /// This includes terminating any frame that was left unterminated, e.g. because
+    /// sending it was cancelled, so that the client receives it without waiting for
+    /// the next frame. The default implementation does nothing, which is correct for
+    /// transports where sending completes only once the frame has been handed off.
+    async fn flush(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Wait until the connection to the client is able to send frames
+    ///
+    /// For example, this waits until the USB host has configured the device. The
+    /// default implementation returns immediately, which is correct for transports
+    /// that are always connected.
+    async fn wait_connection(&self) {}
+
+    /// The error returned for frames sent with a [`Sender`] whose connection was lost,
+    /// see the [`disconnect`] module
+    ///
+    /// This lets the [`Sender`] fail right away, instead of handing the frame to the
+    /// transport. The default implementation returns `None`, in which case the frame
+    /// is sent anyway.
+    fn closed_error() -> Option<Self::Error> {
+        None
+    }
 }
 
 /// The base [`WireTx`] Error Kind
@@ -83,6 +214,81 @@ pub enum WireTxErrorKind {
     Other,
     /// Timeout (WireTx impl specific) reached
     Timeout,
+    /// The frame didn't fit into the buffer of the connection, and was not sent
+    TooLarge {
+        /// The length of the whole frame, including the header
+        needed: u32,
+        /// The length of the buffer
+        have: u32,
+    },
+    /// The message could not be serialized, for another reason than the lack of
+    /// space in the buffer, and was not sent
+    SerFailed,
+}
+
+impl WireTxErrorKind {
+    /// The error for a frame of `hdr` and `msg` that didn't fit into a buffer of
+    /// `have` bytes
+    ///
+    /// The length of the frame is counted without serializing it again into a
+    /// buffer. Returns [`WireTxErrorKind::SerFailed`] if `msg` can't be serialized at
+    /// all.
+    pub fn too_large<T: Serialize + ?Sized>(hdr: &VarHeader, msg: &T, have: usize) -> Self {
+        let mut hdr_buf = [0u8; crate::max_size::MAX_HEADER_SIZE];
+        let Some((hdr_used, _)) = hdr.write_to_slice(&mut hdr_buf) else {
+            return WireTxErrorKind::Other;
+        };
+        let Ok(body_len) = postcard::experimental::serialized_size(msg) else {
+            return WireTxErrorKind::SerFailed;
+        };
+        WireTxErrorKind::TooLarge {
+            needed: (hdr_used.len() + body_len) as u32,
+            have: have as u32,
+        }
+    }
+
+    /// The error sent to the client after failing to send a reply with this error
+    ///
+    /// This is [`WireError::ResponseTooLarge`] for [`WireTxErrorKind::TooLarge`],
+    /// [`WireError::SerFailed`] for [`WireTxErrorKind::SerFailed`], and
+    /// [`WireError::TransportFailed`] for errors of the transport.
+    pub fn reply_error(&self) -> WireError {
+        match *self {
+            WireTxErrorKind::TooLarge { needed, have } => {
+                WireError::ResponseTooLarge(ResponseTooLarge { needed, have })
+            }
+            WireTxErrorKind::SerFailed => WireError::SerFailed,
+            _ => WireError::TransportFailed,
+        }
+    }
+
+    /// Did the transport fail to write the frame, rather than the frame failing to
+    /// serialize or fit into the buffer?
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            WireTxErrorKind::ConnectionClosed | WireTxErrorKind::Other | WireTxErrorKind::Timeout
+        )
+    }
+}
+
+/// What a [`Sender`] does when the transport fails to write a reply
+///
+/// Replies that fail to serialize, or don't fit into the buffer, are always
+/// reported to the client, see [`WireTxErrorKind::reply_error()`]. Set with
+/// [`Server::set_reply_failure()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplyFailure {
+    /// Send [`WireError::TransportFailed`] to the client
+    #[default]
+    Report,
+    /// Write the reply again, up to the given number of times, before reporting it
+    ///
+    /// Only [`WireTxErrorKind::Other`] and [`WireTxErrorKind::Timeout`] are retried,
+    /// as a closed connection won't recover by itself.
+    Retry(u8),
+    /// Send nothing, leaving the client to time out or retry the request
+    Drop,
 }
 
 /// A conversion trait to convert a user error into a base Kind type
@@ -98,6 +304,15 @@ impl AsWireTxErrorKind for WireTxErrorKind {
     }
 }
 
+/// The error of [`WireTx::try_send()`]
+#[derive(Debug, Clone, Copy)]
+pub enum TrySendError<E> {
+    /// The frame could not be taken without waiting, and was dropped
+    Full,
+    /// Sending failed, as with [`WireTx::send()`]
+    Tx(E),
+}
+
 //////////////////////////////////////////////////////////////////////////////
 // RX
 //////////////////////////////////////////////////////////////////////////////
@@ -114,6 +329,13 @@ pub trait WireRx {
     ///
     /// On success, the portion of `buf` that contains a single frame is returned.
     async fn receive<'a>(&mut self, buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error>;
+
+    /// Wait until the connection to the client is able to receive frames
+    ///
+    /// Called by [`Server::run()`] before the first frame is received. The default
+    /// implementation returns immediately, which is correct for transports that are
+    /// always connected.
+    async fn wait_connection(&mut self) {}
 }
 
 /// The base [`WireRx`] Error Kind
@@ -172,10 +394,60 @@ pub trait WireSpawn: Clone {
 
 /// The [`Sender`] type wraps a [`WireTx`] impl, and provides higher level functionality
 /// over it
-#[derive(Clone)]
+///
+/// ## Locking
+///
+/// A [`Sender`] holds no lock of its own, and no lock is held between calls: each
+/// call sends its frames through the [`WireTx`], which locks its buffer (if it has
+/// one) only while serializing and writing a single frame, and releases it before
+/// the call returns. A handler can therefore send with any clone of the sender at
+/// any time, e.g. reply with a clone while streaming messages with a
+/// [`Duplex`][duplex::Duplex], or from a task spawned while another task is in the
+/// middle of a reply, which then waits until that frame is written.
+///
+/// The only way to send re-entrantly is from code that runs WHILE a frame is being
+/// serialized: the [`Serialize`] impl of a message that is being sent, or the
+/// [`Display`][core::fmt::Display] impls formatted by [`Sender::log_fmt()`]. Sending
+/// from these, e.g. with a blocking executor, waits for a lock that is never
+/// released, so don't: compute what is needed before sending instead.
 pub struct Sender<Tx: WireTx> {
     tx: Tx,
     kkind: VarKeyKind,
+    keyed_errors: bool,
+    compress: bool,
+    crc: bool,
+    cipher: Option<&'static dyn BodyCipher>,
+    error_log: Option<&'static dyn RecordError>,
+    permit: Option<SpawnPermit>,
+    request_buf: Option<PooledBuf>,
+    replies: AtomicU32,
+    reply_failure: ReplyFailure,
+    link: Option<&'static dyn ConnectionSignal>,
+    // The state of `link` when the connection of this sender started, or `None` to
+    // follow the current connection
+    link_state: Option<u32>,
+}
+
+impl<Tx: WireTx> Clone for Sender<Tx> {
+    /// Clone the sender, WITHOUT the [`SpawnPermit`] or [`PooledBuf`] it may hold, and
+    /// with its own count of [`Sender::replies()`]
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            kkind: self.kkind,
+            keyed_errors: self.keyed_errors,
+            compress: self.compress,
+            crc: self.crc,
+            cipher: self.cipher,
+            error_log: self.error_log,
+            permit: None,
+            request_buf: None,
+            replies: AtomicU32::new(0),
+            reply_failure: self.reply_failure,
+            link: self.link,
+            link_state: self.link_state,
+        }
+    }
 }
 
 impl<Tx: WireTx> Sender<Tx> {
@@ -186,7 +458,157 @@ impl<Tx: WireTx> Sender<Tx> {
     ///
     /// `kkind` should usually come from [`Dispatch::min_key_len()`].
     pub fn new(tx: Tx, kkind: VarKeyKind) -> Self {
-        Self { tx, kkind }
+        Self {
+            tx,
+            kkind,
+            keyed_errors: false,
+            compress: false,
+            crc: false,
+            cipher: None,
+            error_log: None,
+            permit: None,
+            request_buf: None,
+            replies: AtomicU32::new(0),
+            reply_failure: ReplyFailure::Report,
+            link: None,
+            link_state: None,
+        }
+    }
+
+    /// Hold `permit` until this sender is dropped
+    ///
+    /// Used by [`define_dispatch!`][crate::define_dispatch] to count the live `spawn` handler tasks, see
+    /// [`spawn_limit`].
+    pub fn with_permit(mut self, permit: SpawnPermit) -> Self {
+        self.permit = Some(permit);
+        self
+    }
+
+    /// Hold `buf` until this sender is dropped
+    ///
+    /// Used by [`define_dispatch!`][crate::define_dispatch] to keep the buffer of the
+    /// request of a `spawn` handler task taken until it completes, see [`request_pool`].
+    pub fn with_request_buf(mut self, buf: Option<PooledBuf>) -> Self {
+        self.request_buf = buf;
+        self
+    }
+
+    /// Send errors as a [`KeyedError`] instead of a plain [`WireError`]
+    ///
+    /// See [`Server::set_keyed_errors()`].
+    ///
+    /// [`KeyedError`]: crate::standard_icd::KeyedError
+    /// [`WireError`]: crate::standard_icd::WireError
+    pub fn set_keyed_errors(&mut self, enabled: bool) {
+        self.keyed_errors = enabled;
+    }
+
+    /// Append a CRC to each frame sent with this sender
+    ///
+    /// Only set by [`Server::new_protected()`], so that no sender of the server can
+    /// send frames without a CRC. Frames with a CRC are never compressed, and
+    /// [`Sender::send_raw()`] still sends its bytes as-is.
+    fn set_frame_crc(&mut self, enabled: bool) {
+        self.crc = enabled;
+    }
+
+    /// Seal the body of each frame sent with this sender with `cipher`
+    ///
+    /// Only set by [`Server::new_protected()`], so that no sender of the server can
+    /// send plain bodies. Sealed frames are never compressed, and
+    /// [`Sender::send_raw()`] still sends its bytes as-is.
+    fn set_body_cipher(&mut self, cipher: Option<&'static dyn BodyCipher>) {
+        self.cipher = cipher;
+    }
+
+    /// Record each error sent with this sender in `log`
+    ///
+    /// Set by [`Server::new()`] from [`Dispatch::error_log()`], see the [`error_log`]
+    /// module.
+    pub fn set_error_log(&mut self, log: Option<&'static dyn RecordError>) {
+        self.error_log = log;
+    }
+
+    /// Choose what happens when the transport fails to write a reply
+    ///
+    /// See [`Server::set_reply_failure()`].
+    pub fn set_reply_failure(&mut self, policy: ReplyFailure) {
+        self.reply_failure = policy;
+    }
+
+    /// Fail the frames sent with this sender once the connection is lost, as told by
+    /// `signal`
+    ///
+    /// See [`Server::set_disconnect_signal()`]. The sender follows the current
+    /// connection of `signal`, until [`Server::run()`] ties it to the connection
+    /// being served.
+    pub fn set_disconnect_signal(&mut self, signal: Option<&'static dyn ConnectionSignal>) {
+        self.link = signal;
+        self.link_state = None;
+    }
+
+    /// The connection this sender belongs to, or `None` without a disconnect signal
+    ///
+    /// See the [`disconnect`] module.
+    pub fn connection(&self) -> Option<Connection> {
+        let link = self.link?;
+        let state = self.link_state.unwrap_or_else(|| link.state());
+        Some(Connection::with_state(link, state))
+    }
+
+    /// Whether the connection of this sender was lost
+    ///
+    /// Always `false` without a disconnect signal, see the [`disconnect`] module.
+    pub fn is_disconnected(&self) -> bool {
+        self.connection().is_some_and(|c| c.is_lost())
+    }
+
+    /// Wait until the connection of this sender is lost
+    ///
+    /// Without a disconnect signal, this never completes. See the [`disconnect`]
+    /// module.
+    pub async fn disconnected(&self) {
+        match self.connection() {
+            Some(conn) => conn.lost().await,
+            None => core::future::pending().await,
+        }
+    }
+
+    /// Run `send`, unless the connection of this sender is lost before it completes
+    async fn unless_disconnected<F>(&self, send: F) -> Result<(), Tx::Error>
+    where
+        F: core::future::Future<Output = Result<(), Tx::Error>>,
+    {
+        let (Some(conn), Some(closed)) = (self.connection(), Tx::closed_error()) else {
+            return send.await;
+        };
+        if conn.is_lost() {
+            return Err(closed);
+        }
+        timeout::with_timeout(conn.lost(), send)
+            .await
+            .unwrap_or(Err(closed))
+    }
+
+    /// Compress the replies sent with this sender
+    ///
+    /// Used by [`define_dispatch!`][crate::define_dispatch] for endpoints marked with
+    /// `[compress = true]`, see [`compress`][crate::compress].
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compress = enabled;
+        self
+    }
+
+    /// The number of replies sent with this sender, by [`Sender::reply()`],
+    /// [`Sender::reply_with_blob()`] and [`Sender::reply_raw()`], including those that
+    /// failed to send
+    ///
+    /// Used by [`define_dispatch!`][crate::define_dispatch] to check the [`Outcome`] of
+    /// `custom` handlers, see [`outcome`].
+    ///
+    /// [`Outcome`]: outcome::Outcome
+    pub fn replies(&self) -> u32 {
+        self.replies.load(Ordering::Relaxed)
     }
 
     /// Send a reply for the given endpoint
@@ -199,7 +621,60 @@ impl<Tx: WireTx> Sender<Tx> {
         let mut key = VarKey::Key8(E::RESP_KEY);
         key.shrink_to(self.kkind);
         let wh = VarHeader { key, seq_no };
-        self.tx.send::<E::Response>(wh, resp).await
+        self.replies.fetch_add(1, Ordering::Relaxed);
+        self.send_reply::<E::Response>(wh, resp).await
+    }
+
+    /// Send a reply for the given endpoint, followed by a binary attachment
+    ///
+    /// The body of the frame is the serialized `resp`, followed by the length of
+    /// `blob` as a varint, followed by the bytes of `blob`. This is the same as the
+    /// postcard encoding of `(E::Response, &[u8])`, but doesn't require the blob to
+    /// be part of the response type. Use
+    /// [`HostClient::send_resp_with_blob()`][crate::host_client::HostClient::send_resp_with_blob]
+    /// to receive both parts on the client.
+    ///
+    /// This is useful for responses made of some metadata and a large, variable
+    /// length buffer, like a file or a sample capture.
+    #[inline]
+    pub async fn reply_with_blob<E>(
+        &self,
+        seq_no: VarSeq,
+        resp: &E::Response,
+        blob: &[u8],
+    ) -> Result<(), Tx::Error>
+    where
+        E: crate::Endpoint,
+        E::Response: Serialize + Schema,
+    {
+        let mut key = VarKey::Key8(E::RESP_KEY);
+        key.shrink_to(self.kkind);
+        let wh = VarHeader { key, seq_no };
+        let msg = (resp, Blob(blob));
+        self.replies.fetch_add(1, Ordering::Relaxed);
+        self.send_reply(wh, &msg).await
+    }
+
+    /// Send a reply for the given endpoint, with a response that is already encoded
+    ///
+    /// `encoded` is used as-is as the body of the frame, so it must be the postcard
+    /// encoding of an `E::Response`, e.g. one received from another MCU that was
+    /// built with the same ICD. This is NOT checked: if it is not, the client fails
+    /// to decode the response. Only the key is taken from `E`, which keeps the key
+    /// from being mixed up with that of another endpoint.
+    ///
+    /// Like [`Sender::reply()`], the frame gets a CRC, is sealed, or is compressed
+    /// if enabled.
+    #[inline]
+    pub async fn reply_raw<E>(&self, seq_no: VarSeq, encoded: &[u8]) -> Result<(), Tx::Error>
+    where
+        E: crate::Endpoint,
+    {
+        let mut key = VarKey::Key8(E::RESP_KEY);
+        key.shrink_to(self.kkind);
+        let wh = VarHeader { key, seq_no };
+        self.replies.fetch_add(1, Ordering::Relaxed);
+        self.send_reply(wh, &RawBody(encoded)).await
     }
 
     /// Send a reply with the given Key
@@ -215,10 +690,13 @@ impl<Tx: WireTx> Sender<Tx> {
         let mut key = VarKey::Key8(key);
         key.shrink_to(self.kkind);
         let wh = VarHeader { key, seq_no };
-        self.tx.send::<T>(wh, resp).await
+        self.send_frame::<T>(wh, resp, false).await
     }
 
     /// Publish a Topic message
+    ///
+    /// This waits until the transport can take the message, see
+    /// [`Sender::try_publish()`] to drop it instead.
     #[inline]
     pub async fn publish<T>(&self, seq_no: VarSeq, msg: &T::Message) -> Result<(), Tx::Error>
     where
@@ -229,31 +707,520 @@ impl<Tx: WireTx> Sender<Tx> {
         let mut key = VarKey::Key8(T::TOPIC_KEY);
         key.shrink_to(self.kkind);
         let wh = VarHeader { key, seq_no };
-        self.tx.send::<T::Message>(wh, msg).await
+        self.send_frame::<T::Message>(wh, msg, false).await
+    }
+
+    /// Publish a Topic message, if it can be sent without waiting
+    ///
+    /// Unlike [`Sender::publish()`], which waits until the transport has room for the
+    /// message, this returns [`TrySendError::Full`] right away when it doesn't, and
+    /// the message is dropped. This suits messages that are superseded by the next
+    /// one, like telemetry, which can then be dropped or coalesced instead of
+    /// stalling the caller while the client is slow to drain them.
+    ///
+    /// Only some [`WireTx`] impls can send without waiting, such as the queue of the
+    /// `channel-sender` feature. With other impls, this always returns
+    /// [`TrySendError::Full`], see [`WireTx::try_send()`].
+    #[inline]
+    pub fn try_publish<T>(
+        &self,
+        seq_no: VarSeq,
+        msg: &T::Message,
+    ) -> Result<(), TrySendError<Tx::Error>>
+    where
+        T: ?Sized,
+        T: crate::Topic,
+        T::Message: Serialize + Schema,
+    {
+        let mut key = VarKey::Key8(T::TOPIC_KEY);
+        key.shrink_to(self.kkind);
+        let wh = VarHeader { key, seq_no };
+        self.try_send_frame::<T::Message>(wh, msg, false)
+    }
+
+    /// Publish a Topic message, replacing the previous message of the same topic if it
+    /// is still waiting to be sent
+    ///
+    /// For a high rate topic, like sensor readings, this keeps the client up to date
+    /// with the latest value instead of a backlog of old ones, and bounds the frames
+    /// queued for the topic to one. Ordering:
+    ///
+    /// * The latest message wins: intermediate messages that were never sent are
+    ///   dropped, so the client may skip values, but never receives an older value
+    ///   after a newer one of the same topic.
+    /// * A replaced message keeps the place of the one it replaces, relative to the
+    ///   latest messages of other topics. It is not ordered with frames sent in any
+    ///   other way, e.g. with [`Sender::publish()`].
+    ///
+    /// Only some [`WireTx`] impls queue frames, such as the queue of the
+    /// `channel-sender` feature, which has one slot per topic. With other impls, this
+    /// is the same as [`Sender::try_publish()`], see [`WireTx::try_send_latest()`].
+    #[inline]
+    pub fn publish_latest<T>(
+        &self,
+        seq_no: VarSeq,
+        msg: &T::Message,
+    ) -> Result<(), TrySendError<Tx::Error>>
+    where
+        T: ?Sized,
+        T: crate::Topic,
+        T::Message: Serialize + Schema,
+    {
+        let mut key = VarKey::Key8(T::TOPIC_KEY);
+        key.shrink_to(self.kkind);
+        let wh = VarHeader { key, seq_no };
+        self.try_send_frame::<T::Message>(wh, msg, true)
+    }
+
+    /// Send a frame without waiting, sealed and with a CRC if enabled, replacing a
+    /// queued frame with the same key if `latest` is set
+    fn try_send_frame<T>(
+        &self,
+        wh: VarHeader,
+        msg: &T,
+        latest: bool,
+    ) -> Result<(), TrySendError<Tx::Error>>
+    where
+        T: Serialize + ?Sized,
+    {
+        if let Some(cipher) = self.cipher {
+            let msg = &cipher::Sealed {
+                hdr: &wh,
+                msg,
+                cipher,
+            };
+            if self.crc {
+                self.try_send_raw_frame(wh, &crc::WithCrc { hdr: &wh, msg }, latest)
+            } else {
+                self.try_send_raw_frame(wh, msg, latest)
+            }
+        } else if self.crc {
+            self.try_send_raw_frame(wh, &crc::WithCrc { hdr: &wh, msg }, latest)
+        } else {
+            self.try_send_raw_frame(wh, msg, latest)
+        }
+    }
+
+    /// Send a frame as it is without waiting, see [`Self::try_send_frame()`]
+    fn try_send_raw_frame<T>(
+        &self,
+        wh: VarHeader,
+        msg: &T,
+        latest: bool,
+    ) -> Result<(), TrySendError<Tx::Error>>
+    where
+        T: Serialize + ?Sized,
+    {
+        if latest {
+            self.tx.try_send_latest(wh, msg)
+        } else {
+            self.tx.try_send(wh, msg)
+        }
+    }
+
+    /// Send a reply, compressed if enabled, writing it again on transport errors if
+    /// [`ReplyFailure::Retry`] is set
+    async fn send_reply<T>(&self, wh: VarHeader, msg: &T) -> Result<(), Tx::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut retries = match self.reply_failure {
+            ReplyFailure::Retry(n) => n,
+            _ => 0,
+        };
+        loop {
+            match self.send_frame(wh, msg, self.compress).await {
+                Err(e) if retries > 0 && retryable(e.as_kind()) => retries -= 1,
+                res => return res,
+            }
+        }
+    }
+
+    /// Send a frame, sealed and with a CRC if enabled, or compressed if `compress` is set
+    async fn send_frame<T>(&self, wh: VarHeader, msg: &T, compress: bool) -> Result<(), Tx::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.unless_disconnected(async {
+            if let Some(cipher) = self.cipher {
+                let msg = &cipher::Sealed {
+                    hdr: &wh,
+                    msg,
+                    cipher,
+                };
+                if self.crc {
+                    self.tx.send(wh, &crc::WithCrc { hdr: &wh, msg }).await
+                } else {
+                    self.tx.send(wh, msg).await
+                }
+            } else if self.crc {
+                self.tx.send(wh, &crc::WithCrc { hdr: &wh, msg }).await
+            } else if compress {
+                self.tx.send_compressed::<T>(wh, msg).await
+            } else {
+                self.tx.send::<T>(wh, msg).await
+            }
+        })
+        .await
+    }
+
+    /// The header of messages to the [`LoggingTopic`][crate::standard_icd::LoggingTopic]
+    /// sent with a CRC or sealed, which bypass the logging of the [`WireTx`]
+    fn log_header(&self) -> VarHeader {
+        let mut key = VarKey::Key8(crate::standard_icd::LoggingTopic::TOPIC_KEY);
+        key.shrink_to(self.kkind);
+        VarHeader {
+            key,
+            seq_no: VarSeq::Seq2(0),
+        }
+    }
+
+    /// Wait until the connection to the client is able to send frames
+    ///
+    /// Useful for tasks that send topic messages on their own, which would otherwise
+    /// fail (or be lost) while the client is not connected yet.
+    pub async fn wait_connected(&self) {
+        self.tx.wait_connection().await
     }
 
     /// Log a `str` directly to the [`LoggingTopic`][crate::standard_idc::LoggingTopic]
     #[inline]
     pub async fn log_str(&self, msg: &str) -> Result<(), Tx::Error> {
-        self.tx.send_log_str(self.kkind, msg).await
+        if self.crc || self.cipher.is_some() {
+            return self.send_frame(self.log_header(), msg, false).await;
+        }
+        self.unless_disconnected(self.tx.send_log_str(self.kkind, msg))
+            .await
     }
 
     /// Format a message to the [`LoggingTopic`][crate::standard_idc::LoggingTopic]
     #[inline]
     pub async fn log_fmt(&self, msg: Arguments<'_>) -> Result<(), Tx::Error> {
-        self.tx.send_log_fmt(self.kkind, msg).await
+        if self.crc || self.cipher.is_some() {
+            let msg = crc::FmtStr(msg);
+            return self.send_frame(self.log_header(), &msg, false).await;
+        }
+        self.unless_disconnected(self.tx.send_log_fmt(self.kkind, msg))
+            .await
+    }
+
+    /// Format a structured message to the [`LogRecordTopic`][crate::standard_icd::LogRecordTopic]
+    ///
+    /// The message is formatted on the stack, and truncated at a char boundary if it
+    /// is longer than [`LOG_RECORD_MAX_LEN`] bytes. Usually used with the
+    /// [`rpc_log!`][crate::rpc_log] macro.
+    ///
+    /// This never waits: like [`Sender::try_publish()`], the record is dropped
+    /// (without returning an error) if the transport has no room for it, so logging
+    /// can't stall the caller. With [`WireTx`] impls that can't send without waiting,
+    /// records are always dropped, see [`WireTx::try_send()`].
+    pub fn log_record(
+        &self,
+        level: crate::standard_icd::LogLevel,
+        msg: Arguments<'_>,
+    ) -> Result<(), Tx::Error> {
+        use crate::standard_icd::LogRecordTopic;
+
+        let mut buf = Truncating::<LOG_RECORD_MAX_LEN>(heapless::String::new());
+        // Only fails once the message is truncated, which is still sent
+        let _ = core::fmt::write(&mut buf, msg);
+        #[cfg(feature = "use-std")]
+        let record = crate::standard_icd::OwnedLogRecord {
+            level,
+            msg: String::from(buf.0.as_str()),
+        };
+        #[cfg(not(feature = "use-std"))]
+        let record = crate::standard_icd::LogRecord {
+            level,
+            msg: buf.0.as_str(),
+        };
+        match self.try_publish::<LogRecordTopic>(VarSeq::Seq2(0), &record) {
+            Ok(()) | Err(TrySendError::Full) => Ok(()),
+            Err(TrySendError::Tx(e)) => Err(e),
+        }
+    }
+
+    /// Wait until all messages sent so far have been handed to the transport
+    ///
+    /// This is useful before e.g. entering a low power mode, to make sure that the
+    /// last reply isn't left in a buffer of the transport. See [`WireTx::flush()`].
+    #[inline]
+    pub async fn flush(&self) -> Result<(), Tx::Error> {
+        self.tx.flush().await
+    }
+
+    /// Send `buf` as-is, WITHOUT a header or any encoding
+    ///
+    /// This is an escape hatch for handing off the connection to another protocol,
+    /// e.g. sending a final message before jumping to a DFU bootloader. The client
+    /// will not be able to decode these bytes as a postcard-rpc frame, so this should
+    /// not be used for anything else.
+    ///
+    /// The bytes are written like a single frame by the [`WireTx`], e.g. as a single
+    /// USB transfer.
+    #[inline]
+    pub async fn send_raw(&self, buf: &[u8]) -> Result<(), Tx::Error> {
+        self.unless_disconnected(self.tx.send_raw(buf)).await
+    }
+
+    /// Acknowledge the request with the given sequence number, before replying to it
+    ///
+    /// This tells the client that the request was received and accepted, before
+    /// e.g. starting a slow operation, usually in a `spawn` handler, which replies
+    /// with the result later. The acknowledgement is sent with the
+    /// [`ACK_KEY`][crate::standard_icd::ACK_KEY], so the client can tell it apart
+    /// from the response. Clients that don't wait for acknowledgements ignore it.
+    pub async fn ack(&self, seq_no: VarSeq) -> Result<(), Tx::Error> {
+        self.reply_keyed(seq_no, crate::standard_icd::ACK_KEY, &())
+            .await
+    }
+
+    /// Report the progress of the request with the given sequence number, before
+    /// replying to it
+    ///
+    /// `percent` is the percentage of the operation that is done, at most 100. This
+    /// can be sent any number of times, usually from a `spawn` handler running a slow
+    /// operation such as erasing flash, which then replies as usual once it is done.
+    /// Updates are sent with the [`PROGRESS_KEY`][crate::standard_icd::PROGRESS_KEY],
+    /// so the client can tell them apart from the response. Clients that don't wait
+    /// for progress ignore them.
+    pub async fn progress(&self, seq_no: VarSeq, percent: u8) -> Result<(), Tx::Error> {
+        self.reply_keyed(seq_no, crate::standard_icd::PROGRESS_KEY, &percent.min(100))
+            .await
     }
 
     /// Send a single error message
+    ///
+    /// This always sends a plain [`WireError`][crate::standard_icd::WireError]. Prefer
+    /// [`Sender::error_for()`] when replying to a request.
     pub async fn error(
         &self,
         seq_no: VarSeq,
         error: crate::standard_icd::WireError,
     ) -> Result<(), Tx::Error> {
+        if let Some(log) = self.error_log {
+            log.record(None, seq_no, &error);
+        }
         self.reply_keyed(seq_no, crate::standard_icd::ERROR_KEY, &error)
             .await
     }
 
+    /// Send a single error message in reply to the request with the given header
+    ///
+    /// If keyed errors are enabled, see [`Sender::set_keyed_errors()`], this sends
+    /// a [`KeyedError`][crate::standard_icd::KeyedError] containing the key of the
+    /// request. Otherwise, this is the same as [`Sender::error()`].
+    pub async fn error_for(
+        &self,
+        hdr: &VarHeader,
+        error: crate::standard_icd::WireError,
+    ) -> Result<(), Tx::Error> {
+        use crate::standard_icd::{KeyedError, ERROR_KEY, KEYED_ERROR_KEY};
+
+        if let Some(log) = self.error_log {
+            log.record(Some(hdr.key), hdr.seq_no, &error);
+        }
+        if self.keyed_errors {
+            let keyed = KeyedError {
+                key: hdr.key.into(),
+                error,
+            };
+            self.reply_keyed(hdr.seq_no, KEYED_ERROR_KEY, &keyed).await
+        } else {
+            self.reply_keyed(hdr.seq_no, ERROR_KEY, &error).await
+        }
+    }
+
+    /// Tell the client that the reply to the request with the given header failed to
+    /// send with `kind`
+    ///
+    /// The error sent is given by [`WireTxErrorKind::reply_error()`]. If the transport
+    /// failed, and [`ReplyFailure::Drop`] is set, nothing is sent.
+    pub async fn reply_failed(
+        &self,
+        hdr: &VarHeader,
+        kind: WireTxErrorKind,
+    ) -> Result<(), Tx::Error> {
+        if kind.is_transport() && self.reply_failure == ReplyFailure::Drop {
+            if let Some(log) = self.error_log {
+                log.record(Some(hdr.key), hdr.seq_no, &kind.reply_error());
+            }
+            return Ok(());
+        }
+        self.error_for(hdr, kind.reply_error()).await
+    }
+
+    /// Implements the [`ErrorLogEndpoint`][crate::standard_icd::ErrorLogEndpoint] endpoint
+    ///
+    /// Sends the errors in `log`, and then clears it if `clear` is set.
+    pub async fn send_error_log<const N: usize>(
+        &self,
+        hdr: &VarHeader,
+        log: &error_log::ErrorLog<N>,
+        clear: bool,
+    ) -> Result<(), Tx::Error> {
+        use crate::standard_icd::ErrorLogEndpoint;
+
+        let records = log.records();
+        #[cfg(feature = "use-std")]
+        let report = crate::standard_icd::OwnedErrorLogReport {
+            total: log.total(),
+            records: records.to_vec(),
+        };
+        #[cfg(not(feature = "use-std"))]
+        let report = crate::standard_icd::ErrorLogReport {
+            total: log.total(),
+            records: &records,
+        };
+        let res = self.reply::<ErrorLogEndpoint>(hdr.seq_no, &report).await;
+        if clear {
+            log.clear();
+        }
+        res
+    }
+
+    /// Implements the [`GetStatsEndpoint`][crate::standard_icd::GetStatsEndpoint] endpoint
+    ///
+    /// If the `metrics` feature is disabled, an [`UnknownKey`] error is sent instead.
+    ///
+    /// [`UnknownKey`]: crate::standard_icd::WireError::UnknownKey
+    pub async fn send_stats<const K: usize>(
+        &self,
+        hdr: &VarHeader,
+        stats: &mut metrics::DispatchStats<K>,
+        reset: bool,
+    ) -> Result<(), Tx::Error> {
+        #[cfg(feature = "metrics")]
+        {
+            use crate::standard_icd::GetStatsEndpoint;
+            #[cfg(feature = "use-std")]
+            let report = crate::standard_icd::OwnedStatsReport {
+                frames: stats.frames(),
+                errors: stats.errors(),
+                keys: stats.keys().to_vec(),
+            };
+            #[cfg(not(feature = "use-std"))]
+            let report = crate::standard_icd::StatsReport {
+                frames: stats.frames(),
+                errors: stats.errors(),
+                keys: stats.keys(),
+            };
+            let res = self.reply::<GetStatsEndpoint>(hdr.seq_no, &report).await;
+            if reset {
+                stats.reset();
+            }
+            res
+        }
+        #[cfg(not(feature = "metrics"))]
+        {
+            let _ = (stats, reset);
+            self.error_for(hdr, crate::standard_icd::WireError::UnknownKey)
+                .await
+        }
+    }
+
+    /// Implements the [`HandshakeEndpoint`][crate::standard_icd::HandshakeEndpoint] endpoint
+    pub async fn send_handshake(
+        &self,
+        hdr: &VarHeader,
+        device_map: &DeviceMap,
+    ) -> Result<(), Tx::Error> {
+        use crate::standard_icd::{HandshakeEndpoint, PROTOCOL_VERSION};
+
+        #[cfg(feature = "use-std")]
+        let handshake = crate::standard_icd::OwnedHandshake {
+            protocol_version: PROTOCOL_VERSION,
+            endpoints: device_map
+                .endpoints
+                .iter()
+                .map(|(path, req, resp)| (String::from(*path), *req, *resp))
+                .collect(),
+        };
+        #[cfg(not(feature = "use-std"))]
+        let handshake = crate::standard_icd::Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            endpoints: device_map.endpoints,
+        };
+        self.reply::<HandshakeEndpoint>(hdr.seq_no, &handshake)
+            .await
+    }
+
+    /// Implements the [`GetGroupsEndpoint`][crate::standard_icd::GetGroupsEndpoint]
+    /// endpoint
+    pub async fn send_groups(
+        &self,
+        hdr: &VarHeader,
+        device_map: &DeviceMap,
+    ) -> Result<(), Tx::Error> {
+        use crate::standard_icd::GetGroupsEndpoint;
+
+        #[cfg(feature = "use-std")]
+        let groups = crate::standard_icd::OwnedEndpointGroups {
+            endpoints: device_map
+                .groups
+                .iter()
+                .map(|(group, key)| (String::from(*group), *key))
+                .collect(),
+        };
+        #[cfg(not(feature = "use-std"))]
+        let groups = crate::standard_icd::EndpointGroups {
+            endpoints: device_map.groups,
+        };
+        self.reply::<GetGroupsEndpoint>(hdr.seq_no, &groups).await
+    }
+
+    /// Implements the [`EchoFrameEndpoint`][crate::standard_icd::EchoFrameEndpoint]
+    /// endpoint
+    ///
+    /// Replies with the header of the request, encoded again from `hdr`, and `body`
+    /// unchanged, so the client can see exactly what the server parsed.
+    pub async fn send_echo_frame(&self, hdr: &VarHeader, body: &[u8]) -> Result<(), Tx::Error> {
+        use crate::standard_icd::EchoFrameEndpoint;
+
+        let mut hdr_buf = [0u8; crate::max_size::MAX_HEADER_SIZE];
+        let Some((hdr_used, _)) = hdr.write_to_slice(&mut hdr_buf) else {
+            return self.error_for(hdr, WireError::SerFailed).await;
+        };
+        #[cfg(feature = "use-std")]
+        let echo = crate::standard_icd::OwnedEchoedFrame {
+            header: hdr_used.to_vec(),
+            body: body.to_vec(),
+        };
+        #[cfg(not(feature = "use-std"))]
+        let echo = crate::standard_icd::EchoedFrame {
+            header: hdr_used,
+            body,
+        };
+        self.reply::<EchoFrameEndpoint>(hdr.seq_no, &echo).await
+    }
+
+    /// Implements the [`CompatEndpoint`][crate::standard_icd::CompatEndpoint] endpoint
+    ///
+    /// `max_frame_len` is the longest frame the server can receive, or 0 if unknown.
+    pub async fn send_compat(
+        &self,
+        hdr: &VarHeader,
+        device_map: &DeviceMap,
+        max_frame_len: u32,
+    ) -> Result<(), Tx::Error> {
+        use crate::{
+            hash::fnv1a64::hash_icd,
+            standard_icd::{Compat, CompatEndpoint, CRATE_VERSION, PROTOCOL_VERSION},
+        };
+
+        let compat = Compat {
+            protocol_version: PROTOCOL_VERSION,
+            crate_version: CRATE_VERSION,
+            icd_hash: hash_icd(
+                device_map.endpoints,
+                device_map.topics_in,
+                device_map.topics_out,
+            ),
+            max_frame_len,
+        };
+        self.reply::<CompatEndpoint>(hdr.seq_no, &compat).await
+    }
+
     /// Implements the [`GetAllSchemasEndpoint`][crate::standard_icd::GetAllSchemasEndpoint] endpoint
     pub async fn send_all_schemas(
         &self,
@@ -355,6 +1322,36 @@ impl<Tx: WireTx> Sender<Tx> {
     }
 }
 
+/// Can writing a frame again succeed after failing with `kind`?
+fn retryable(kind: WireTxErrorKind) -> bool {
+    matches!(kind, WireTxErrorKind::Other | WireTxErrorKind::Timeout)
+}
+
+/// A binary attachment, serialized as a length-prefixed byte string
+struct Blob<'a>(&'a [u8]);
+
+impl Serialize for Blob<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Bytes that are already encoded, serialized as they are
+struct RawBody<'a>(&'a [u8]);
+
+impl Serialize for RawBody<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        // Tuples are serialized without a length, so each element is a single byte
+        let mut tup = serializer.serialize_tuple(self.0.len())?;
+        for b in self.0 {
+            tup.serialize_element(b)?;
+        }
+        tup.end()
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////
 // SERVER
 //////////////////////////////////////////////////////////////////////////////
@@ -371,6 +1368,24 @@ where
     rx: Rx,
     buf: Buf,
     dis: D,
+    reassembly: Option<reassembly::Reassembler<Buf>>,
+    crc: CrcMode,
+    cipher: Option<&'static dyn BodyCipher>,
+}
+
+/// How the frames on the link are protected, chosen when the [`Server`] is created
+///
+/// This can't be changed later, so every [`Sender`] of the server protects the frames
+/// it sends in the same way, no matter when it was obtained. See
+/// [`Server::new_protected()`].
+#[derive(Clone, Copy, Default)]
+pub struct LinkProtection {
+    /// Expect a CRC at the end of each received frame, and append one to each sent
+    /// frame. See the [`crc`] module.
+    pub crc: CrcMode,
+    /// Open the body of each received frame, and seal the body of each sent frame,
+    /// with this cipher. See the [`cipher`] module.
+    pub cipher: Option<&'static dyn BodyCipher>,
 }
 
 /// A type representing the different errors [`Server::run()`] may return
@@ -402,20 +1417,132 @@ where
     /// * The user provided dispatching method, usually generated by [`define_dispatch!()`][crate::define_dispatch]
     /// * a [`VarKeyKind`], which controls the key sizes sent by the [`WireTx`] impl
     pub fn new(tx: &Tx, rx: Rx, buf: Buf, dis: D, kkind: VarKeyKind) -> Self {
+        Self::new_protected(tx, rx, buf, dis, kkind, LinkProtection::default())
+    }
+
+    /// Create a new Server, that protects the frames on the link as given by
+    /// `protection`
+    ///
+    /// Takes the same arguments as [`Server::new()`], as well as the
+    /// [`LinkProtection`]. This is meant for links that can be observed or tampered
+    /// with, and the client must protect its frames in the same way.
+    ///
+    /// With a CRC, received frames with a wrong CRC are answered with
+    /// [`WireError::CrcMismatch`] or dropped, depending on the [`CrcMode`], and every
+    /// frame sent by a [`Sender`] of the server carries a CRC. This is meant for
+    /// transports that don't check the integrity of frames on their own, like a UART.
+    /// See the [`crc`] module for details.
+    ///
+    /// With a cipher, received frames that fail to open are answered with
+    /// [`WireError::Unauthorized`], and the body of every frame sent by a [`Sender`]
+    /// of the server is sealed. See the [`cipher`] module for details.
+    pub fn new_protected(
+        tx: &Tx,
+        rx: Rx,
+        buf: Buf,
+        mut dis: D,
+        kkind: VarKeyKind,
+        protection: LinkProtection,
+    ) -> Self {
+        let mut sender = Sender::new(tx.clone(), kkind);
+        sender.set_error_log(dis.error_log());
+        sender.set_frame_crc(protection.crc.enabled());
+        sender.set_body_cipher(protection.cipher);
+        dis.set_max_frame_len(buf.len());
         Self {
-            tx: Sender {
-                tx: tx.clone(),
-                kkind,
-            },
+            tx: sender,
             rx,
             buf,
             dis,
+            reassembly: None,
+            crc: protection.crc,
+            cipher: protection.cipher,
         }
     }
 
+    /// Create a new Server, that also reassembles fragmented frames
+    ///
+    /// Takes the same arguments as [`Server::new()`], as well as a second buffer used
+    /// for reassembling frames sent as multiple [`Fragment`]s. This buffer limits the
+    /// size of the reassembled frame, and may be larger than the receive buffer.
+    ///
+    /// See the [`reassembly`] module for more details.
+    ///
+    /// [`Fragment`]: crate::standard_icd::Fragment
+    pub fn new_with_reassembly(
+        tx: &Tx,
+        rx: Rx,
+        buf: Buf,
+        reassembly_buf: Buf,
+        dis: D,
+        kkind: VarKeyKind,
+    ) -> Self {
+        Self::new(tx, rx, buf, dis, kkind).with_reassembly(reassembly_buf)
+    }
+
+    /// Also reassemble fragmented frames, using `reassembly_buf`
+    ///
+    /// See [`Server::new_with_reassembly()`]. This allows reassembling frames on a
+    /// server created with [`Server::new_protected()`].
+    pub fn with_reassembly(mut self, reassembly_buf: Buf) -> Self {
+        let max_frame_len = self.buf.len().max(reassembly_buf.len());
+        self.dis.set_max_frame_len(max_frame_len);
+        self.reassembly = Some(reassembly::Reassembler::new(reassembly_buf));
+        self
+    }
+
+    /// Send errors as a [`KeyedError`] instead of a plain [`WireError`]
+    ///
+    /// A [`KeyedError`] also contains the key of the request that caused the error,
+    /// which allows the client to tell which request an error belongs to, even if
+    /// sequence numbers are reused. It is sent with the [`KEYED_ERROR_KEY`].
+    ///
+    /// This is disabled by default, as clients of older versions of postcard-rpc only
+    /// understand the plain [`WireError`]. [`HostClient`] accepts both forms.
+    ///
+    /// This only affects [`Sender`]s obtained AFTER calling this method.
+    ///
+    /// [`KeyedError`]: crate::standard_icd::KeyedError
+    /// [`WireError`]: crate::standard_icd::WireError
+    /// [`KEYED_ERROR_KEY`]: crate::standard_icd::KEYED_ERROR_KEY
+    /// [`HostClient`]: crate::host_client::HostClient
+    pub fn set_keyed_errors(&mut self, enabled: bool) {
+        self.tx.set_keyed_errors(enabled);
+    }
+
+    /// Choose what happens when the transport fails to write a reply
+    ///
+    /// By default, the client is sent [`WireError::TransportFailed`] instead, which
+    /// may fail to send as well. With [`ReplyFailure::Retry`], the reply is written
+    /// again first, e.g. to ride out a transient USB timeout. With
+    /// [`ReplyFailure::Drop`], nothing is sent, and the client times out. The error is
+    /// still counted, and recorded in the error log, if any.
+    ///
+    /// Like [`Server::set_keyed_errors()`], this only affects [`Sender`]s obtained
+    /// AFTER calling this method.
+    pub fn set_reply_failure(&mut self, policy: ReplyFailure) {
+        self.tx.set_reply_failure(policy);
+    }
+
+    /// Tell handlers when the connection is lost with `signal`, and fail their
+    /// replies from then on
+    ///
+    /// See the [`disconnect`] module for details. Disabled by default.
+    ///
+    /// Like [`Server::set_keyed_errors()`], this only affects [`Sender`]s obtained
+    /// AFTER calling this method.
+    pub fn set_disconnect_signal(&mut self, signal: Option<&'static dyn ConnectionSignal>) {
+        self.tx.set_disconnect_signal(signal);
+    }
+
     /// Get a copy of the [`Sender`] to pass to tasks that need it
+    ///
+    /// With a [disconnect signal][Server::set_disconnect_signal], the copy follows the
+    /// current connection, instead of belonging to the one being served.
     pub fn sender(&self) -> Sender<Tx> {
-        self.tx.clone()
+        let mut tx = self.tx.clone();
+        tx.link_state = None;
+        tx
     }
 
     /// Run until a fatal error occurs
@@ -425,13 +1552,54 @@ where
     ///
     /// The caller may decide to wait until a connection is re-established, reset any
     /// state, or immediately begin re-running.
+    ///
+    /// Frames are dispatched one at a time: the next frame is not received until the
+    /// handler of the previous frame has returned (for `spawn` handlers, until the
+    /// task has been spawned). The frames of a [`Batch`][crate::standard_icd::Batch]
+    /// are dispatched in order, as if each had been received on its own. See the "Concurrency" section of
+    /// [`define_dispatch!`][crate::define_dispatch] for handling requests concurrently.
+    ///
+    /// ## Connection events
+    ///
+    /// Before receiving the first frame, this waits for the connection to be ready,
+    /// see [`WireRx::wait_connection()`], so frames sent early are not lost. Then, in
+    /// order:
+    ///
+    /// 1. [`Dispatch::on_connected()`] is called
+    /// 2. Frames are received and dispatched, until a fatal error occurs. Each
+    ///    dispatch is surrounded by calls to [`Dispatch::on_dispatch_start()`] and
+    ///    [`Dispatch::on_dispatch_end()`]
+    /// 3. [`Dispatch::on_disconnected()`] is called, and the error is returned
+    ///
+    /// Calling `run` again waits for the next connection. With a
+    /// [disconnect signal][Server::set_disconnect_signal], the signal is connected
+    /// before step 1, and tripped before step 3.
     pub async fn run(&mut self) -> ServerError<Tx, Rx> {
+        self.rx.wait_connection().await;
+        if let Some(link) = self.tx.link {
+            link.connect();
+            self.tx.link_state = Some(link.state());
+        }
+        self.dis.on_connected();
+        let err = self.run_connected().await;
+        if let Some(link) = self.tx.link {
+            link.disconnect();
+        }
+        self.dis.on_disconnected();
+        err
+    }
+
+    /// Receive and dispatch frames, until a fatal error occurs
+    async fn run_connected(&mut self) -> ServerError<Tx, Rx> {
         loop {
             let Self {
                 tx,
                 rx,
                 buf,
                 dis: d,
+                reassembly,
+                crc,
+                cipher,
             } = self;
             let used = match rx.receive(buf).await {
                 Ok(u) => u,
@@ -444,28 +1612,193 @@ where
                     }
                 }
             };
-            let Some((hdr, body)) = VarHeader::take_from_slice(used) else {
+            let used = if crc.enabled() {
+                match crc::checked_len(used) {
+                    Some(len) => &mut used[..len],
+                    None => {
+                        // The header may be corrupted too, but it's the best we have
+                        let hdr = VarHeader::take_from_slice_flagged(used).map(|(hdr, ..)| hdr);
+                        if let (CrcMode::Reply, Some(hdr)) = (*crc, hdr) {
+                            if let Err(e) = tx.error(hdr.seq_no, WireError::CrcMismatch).await {
+                                if let Some(fatal) = fatal_tx::<Tx, Rx>(e) {
+                                    return fatal;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                used
+            };
+            // Fragments are opened one by one, before they are reassembled
+            let used = match cipher {
+                Some(cipher) => match cipher::open_frame(*cipher, used) {
+                    Some(len) => &mut used[..len],
+                    None => {
+                        let Some((hdr, ..)) = VarHeader::take_from_slice_flagged(used) else {
+                            continue;
+                        };
+                        if let Err(e) = tx.error(hdr.seq_no, WireError::Unauthorized).await {
+                            if let Some(fatal) = fatal_tx::<Tx, Rx>(e) {
+                                return fatal;
+                            }
+                        }
+                        continue;
+                    }
+                },
+                None => used,
+            };
+            let Some((hdr, token, body)) = take_request(used) else {
                 // TODO: send a nak on badly formed messages? We don't have
                 // much to say because we don't have a key or seq no or anything
                 continue;
             };
-            let fut = d.handle(tx, &hdr, body);
-            if let Err(e) = fut.await {
-                let kind = e.as_kind();
-                match kind {
-                    WireTxErrorKind::ConnectionClosed => return ServerError::TxFatal(e),
-                    WireTxErrorKind::Other => {}
-                    WireTxErrorKind::Timeout => return ServerError::TxFatal(e),
+
+            // A reset discards any partially reassembled frame
+            let reset_key =
+                VarKey::Key8(<crate::standard_icd::ResetEndpoint as crate::Endpoint>::REQ_KEY);
+            if hdr.key == reset_key {
+                if let Some(r) = reassembly.as_mut() {
+                    r.reset();
+                }
+            }
+
+            // Is this a fragment of a larger frame?
+            let frag_key =
+                VarKey::Key8(<crate::standard_icd::FragmentTopic as crate::Topic>::TOPIC_KEY);
+            let (hdr, token, body) = match reassembly.as_mut() {
+                Some(r) if hdr.key == frag_key => match r.push(hdr.seq_no, body) {
+                    // Not done yet
+                    Ok(None) => continue,
+                    // The token of the reassembled frame applies, not those of the fragments
+                    Ok(Some(frame)) => match take_request(frame) {
+                        Some(htb) => htb,
+                        None => continue,
+                    },
+                    Err(err) => {
+                        // The key of the reassembled request isn't known, so this
+                        // is always sent as a plain error
+                        if let Err(e) = tx.error(hdr.seq_no, err).await {
+                            if let Some(fatal) = fatal_tx::<Tx, Rx>(e) {
+                                return fatal;
+                            }
+                        }
+                        continue;
+                    }
+                },
+                _ => (hdr, token, body),
+            };
+
+            // Is this a batch of several frames? The tokens of the contained frames
+            // apply, not that of the batch
+            let batch_key =
+                VarKey::Key8(<crate::standard_icd::BatchTopic as crate::Topic>::TOPIC_KEY);
+            if hdr.key != batch_key {
+                if let Err(e) = Self::dispatch_frame(d, tx, rx, &hdr, token, body).await {
+                    return e;
+                }
+                continue;
+            }
+            let mut frames = postcard::from_bytes::<crate::standard_icd::Batch<'_>>(body)
+                .ok()
+                .map(|batch| batch.frames());
+            loop {
+                let htb = match frames.as_mut().map(Iterator::next) {
+                    // The end of the batch
+                    Some(None) => break,
+                    Some(Some(frame)) => frame
+                        .ok()
+                        .and_then(take_request)
+                        .filter(|(inner, _, _)| inner.key != batch_key),
+                    // The batch itself is malformed
+                    None => None,
+                };
+                let Some((hdr, token, body)) = htb else {
+                    // The rest of the batch can't be trusted
+                    if let Err(e) = tx.error(hdr.seq_no, WireError::DeserFailed).await {
+                        if let Some(fatal) = fatal_tx::<Tx, Rx>(e) {
+                            return fatal;
+                        }
+                    }
+                    break;
+                };
+                if let Err(e) = Self::dispatch_frame(d, tx, rx, &hdr, token, body).await {
+                    return e;
                 }
             }
         }
     }
+
+    /// Dispatch a single frame, returning an error only if it is fatal
+    async fn dispatch_frame(
+        d: &mut D,
+        tx: &Sender<Tx>,
+        rx: &mut Rx,
+        hdr: &VarHeader,
+        token: Option<AuthToken>,
+        body: &[u8],
+    ) -> Result<(), ServerError<Tx, Rx>> {
+        d.set_token(token);
+        d.on_dispatch_start(hdr);
+        let res = d.handle_with_rx(tx, hdr, body, rx).await;
+        d.on_dispatch_end(hdr);
+        if let Err(e) = res {
+            if let Some(fatal) = fatal_tx::<Tx, Rx>(e) {
+                return Err(fatal);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode the header of a received frame, and the [`AuthToken`] it carries, if any
+///
+/// Frames with a compressed body are rejected, as they are never sent to the server.
+fn take_request(frame: &[u8]) -> Option<(VarHeader, Option<AuthToken>, &[u8])> {
+    match VarHeader::take_from_slice_flagged(frame)? {
+        (_, flags, _, _) if flags.compressed => None,
+        (hdr, _, token, body) => Some((hdr, token, body)),
+    }
+}
+
+/// The error to stop [`Server::run()`] with after sending failed with `e`, or `None`
+/// if only the frame being sent is lost
+fn fatal_tx<Tx: WireTx, Rx: WireRx>(e: Tx::Error) -> Option<ServerError<Tx, Rx>> {
+    match e.as_kind() {
+        WireTxErrorKind::ConnectionClosed => Some(ServerError::TxFatal(e)),
+        WireTxErrorKind::Other => None,
+        WireTxErrorKind::Timeout => Some(ServerError::TxFatal(e)),
+        WireTxErrorKind::TooLarge { .. } => None,
+        WireTxErrorKind::SerFailed => None,
+    }
 }
 
 //////////////////////////////////////////////////////////////////////////////
 // DISPATCH TRAIT
 //////////////////////////////////////////////////////////////////////////////
 
+/// How a dispatcher would route a frame, returned by the `try_dispatch()` method
+/// generated by [`define_dispatch!`][crate::define_dispatch]
+///
+/// This only describes the routing by key, and whether the body can be deserialized
+/// for the handler. Checks that depend on the state of the dispatcher, like
+/// middleware, `auth`, `busy`, the length limits of endpoints and the
+/// `request_pool`, are not taken into account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchDecision {
+    /// The frame would be handled by the endpoint or topic with this (full size) key,
+    /// including the endpoints of the standard ICD
+    Handle(Key),
+    /// No endpoint or topic handles the key, so the frame would go to the fallback
+    Unknown,
+    /// The key of the frame is shorter than the keys used by the dispatcher
+    KeyTooSmall,
+    /// The frame would go to the endpoint or topic with this key, but the body can't be
+    /// deserialized as its request or message
+    DeserFailed(Key),
+}
+
 /// The dispatch trait handles an incoming endpoint or topic message
 ///
 /// The implementations of this trait are typically implemented by the
@@ -477,6 +1810,70 @@ pub trait Dispatch {
     /// The minimum key length required to avoid hash collisions
     fn min_key_len(&self) -> VarKeyKind;
 
+    /// Called by [`Server::run()`] once the connection is ready, before the first
+    /// frame is received
+    ///
+    /// The default implementation does nothing.
+    fn on_connected(&mut self) {}
+
+    /// The log recording the errors sent by the [`Sender`] of the [`Server`], if any
+    ///
+    /// See the [`error_log`] module. The default implementation returns `None`.
+    fn error_log(&self) -> Option<&'static dyn RecordError> {
+        None
+    }
+
+    /// Called by [`Server::run()`] after a fatal error, e.g. because the connection
+    /// was lost, before returning
+    ///
+    /// The default implementation does nothing.
+    fn on_disconnected(&mut self) {}
+
+    /// Called by [`Server::run()`] before a received frame is dispatched
+    ///
+    /// Together with [`Dispatch::on_dispatch_end()`], this allows measuring how long
+    /// each frame takes to handle, e.g. to feed a hardware watchdog only while no
+    /// handler has been running for too long. See the "Dispatch hooks" section of
+    /// [`define_dispatch!`][crate::define_dispatch].
+    ///
+    /// The hooks are called from the dispatch loop, and never concurrently or
+    /// nested: each `on_dispatch_start` is followed by exactly one
+    /// `on_dispatch_end` for the same header, before the next frame is received,
+    /// unless the future of `run` is dropped in between. They should return
+    /// quickly, as the next frame is not received until they have.
+    ///
+    /// The default implementation does nothing.
+    fn on_dispatch_start(&mut self, hdr: &VarHeader) {
+        let _ = hdr;
+    }
+
+    /// Called by [`Server::run()`] after a received frame has been dispatched,
+    /// whether or not handling it succeeded
+    ///
+    /// See [`Dispatch::on_dispatch_start()`]. The default implementation does nothing.
+    fn on_dispatch_end(&mut self, hdr: &VarHeader) {
+        let _ = hdr;
+    }
+
+    /// Called by [`Server::run()`] with the [`AuthToken`] carried by a received frame,
+    /// if any, before it is dispatched
+    ///
+    /// The token applies to the next call to [`Dispatch::handle_with_rx()`] only.
+    /// See the "Access control" section of [`define_dispatch!`][crate::define_dispatch].
+    ///
+    /// The default implementation ignores the token.
+    fn set_token(&mut self, token: Option<AuthToken>) {
+        let _ = token;
+    }
+
+    /// Called when the [`Server`] is created, with the longest frame it can receive
+    ///
+    /// This is reported by the [`CompatEndpoint`][crate::standard_icd::CompatEndpoint].
+    /// The default implementation ignores it.
+    fn set_max_frame_len(&mut self, len: usize) {
+        let _ = len;
+    }
+
     /// Handle a single incoming frame (endpoint or topic), and dispatch appropriately
     async fn handle(
         &mut self,
@@ -484,6 +1881,21 @@ pub trait Dispatch {
         hdr: &VarHeader,
         body: &[u8],
     ) -> Result<(), <Self::Tx as WireTx>::Error>;
+
+    /// Handle a single incoming frame, like [`Dispatch::handle()`], allowing
+    /// `transaction` handlers to receive further frames from `rx`
+    ///
+    /// Called by [`Server::run()`]. The default implementation ignores `rx`.
+    async fn handle_with_rx<Rx: WireRx>(
+        &mut self,
+        tx: &Sender<Self::Tx>,
+        hdr: &VarHeader,
+        body: &[u8],
+        rx: &mut Rx,
+    ) -> Result<(), <Self::Tx as WireTx>::Error> {
+        let _ = rx;
+        self.handle(tx, hdr, body).await
+    }
 }
 
 //////////////////////////////////////////////////////////////////////////////
@@ -0,0 +1,125 @@
+//! The result of handling a request, for handlers that decide how to reply
+//!
+//! Most handlers return the `Response` of their endpoint, which
+//! [`define_dispatch!`][crate::define_dispatch] then sends. Handlers of the `custom`
+//! kind return an [`Outcome`] instead, which also allows replying with an error,
+//! replying early, or not replying yet:
+//!
+//! ```rust,ignore
+//! async fn start_measurement(
+//!     context: &mut Context,
+//!     header: VarHeader,
+//!     req: MeasureRequest,
+//!     sender: &Sender<WireTxImpl>,
+//! ) -> Outcome<MeasureResult> {
+//!     if context.busy {
+//!         return Outcome::Error(WireError::Busy(Busy { retry_after_ms: 100 }));
+//!     }
+//!     // The measurement task replies once the ADC is done
+//!     context.pending.send((header.seq_no, req, sender.clone())).await;
+//!     Outcome::Deferred
+//! }
+//! ```
+//!
+//! ## Replying early
+//!
+//! A handler whose result is known before it completes, e.g. one that cleans up
+//! after a command, can reply with the [`Sender`] it is given right away, keep
+//! working, and then return [`Outcome::AlreadyReplied`]:
+//!
+//! ```rust,ignore
+//! async fn eject(
+//!     context: &mut Context,
+//!     header: VarHeader,
+//!     req: EjectRequest,
+//!     sender: &Sender<WireTxImpl>,
+//! ) -> Outcome<EjectResult> {
+//!     let result = context.tray.open(req).await;
+//!     let _ = sender.reply::<EjectEndpoint>(header.seq_no, &result).await;
+//!     // The client already has its reply while this runs
+//!     context.tray.park().await;
+//!     Outcome::AlreadyReplied
+//! }
+//! ```
+//!
+//! The dispatcher counts the replies sent with the [`Sender`] while the handler
+//! runs, see [`Outcome::check_replied()`]. A handler that replied AND returns a
+//! response or error would reply twice, and one that returns
+//! [`Outcome::AlreadyReplied`] without replying would leave the client waiting: in
+//! both cases nothing more is sent, and the dispatcher counts an error in its
+//! [`metrics`][crate::server::metrics]. A reply sent with a clone of the [`Sender`],
+//! e.g. from another task, is not counted, so use [`Outcome::Deferred`] for those.
+//!
+//! This is the building block for flavors of handlers that the macro doesn't
+//! provide: a generic wrapper that takes a handler, and returns a `custom` handler
+//! producing an [`Outcome`], can be used in the `handler` column like any other
+//! handler. [`Outcome::send()`] replies in the same way as the dispatcher, e.g. for
+//! wrappers that handle requests outside of `define_dispatch!`.
+//!
+//! The `fallback` handler for unknown keys returns an [`Outcome`] as well, see
+//! [`fallback`][super::fallback].
+
+use super::{AsWireTxErrorKind, Sender, WireTx};
+use crate::{header::VarHeader, standard_icd::WireError, Endpoint};
+
+/// The result of handling a request, see the [module docs][self]
+#[derive(Debug, PartialEq)]
+pub enum Outcome<T> {
+    /// Reply with this response
+    Reply(T),
+    /// Don't reply now. The handler has already replied, or a reply will be sent
+    /// later, e.g. by a task holding a clone of the [`Sender`].
+    Deferred,
+    /// Don't reply, the handler has already replied with the [`Sender`] it was given,
+    /// see [Replying early](self#replying-early)
+    AlreadyReplied,
+    /// Reply with this error instead of a response
+    Error(WireError),
+}
+
+impl<T> Outcome<T> {
+    /// Is this an [`Outcome::Error`]?
+    ///
+    /// Used to count the error in the dispatcher's
+    /// [`metrics`][crate::server::metrics].
+    pub fn is_error(&self) -> bool {
+        matches!(self, Outcome::Error(_))
+    }
+
+    /// Check this outcome against whether the handler replied itself, returning it
+    /// if they agree
+    ///
+    /// Returns `None` if the handler replied and also returned a response or an
+    /// error, or returned [`Outcome::AlreadyReplied`] without replying. Nothing more
+    /// should be sent for the request then. [`Outcome::Deferred`] agrees with both.
+    pub fn check_replied(self, replied: bool) -> Option<Self> {
+        match (self, replied) {
+            (Outcome::Reply(_) | Outcome::Error(_), true) => None,
+            (Outcome::AlreadyReplied, false) => None,
+            (outcome, _) => Some(outcome),
+        }
+    }
+
+    /// Send the reply to the request with the header `hdr`, if any
+    ///
+    /// A response that fails to send is handled with [`Sender::reply_failed()`], and
+    /// errors are sent with [`Sender::error_for()`].
+    pub async fn send<E, Tx>(self, sender: &Sender<Tx>, hdr: &VarHeader) -> Result<(), Tx::Error>
+    where
+        E: Endpoint<Response = T>,
+        T: serde::Serialize,
+        Tx: WireTx,
+    {
+        match self {
+            Outcome::Reply(resp) => {
+                if let Err(e) = sender.reply::<E>(hdr.seq_no, &resp).await {
+                    sender.reply_failed(hdr, e.as_kind()).await
+                } else {
+                    Ok(())
+                }
+            }
+            Outcome::Deferred | Outcome::AlreadyReplied => Ok(()),
+            Outcome::Error(err) => sender.error_for(hdr, err).await,
+        }
+    }
+}
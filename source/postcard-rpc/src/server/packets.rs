@@ -0,0 +1,155 @@
+//! Accumulation of packets into frames
+//!
+//! Transports like USB bulk endpoints deliver a frame as one or more packets. Every
+//! packet except the last has the max packet size of the endpoint, and the frame
+//! ends with a "short" packet, which is smaller than the max packet size. A frame
+//! that is an exact multiple of the max packet size is ended with a zero length
+//! packet.
+//!
+//! A single read may therefore return only part of a frame, for example when the
+//! transfer was interrupted. [`PacketAccumulator`] tracks the packets read so far,
+//! and tells the [`WireRx`][super::WireRx] impl when the frame is complete.
+//!
+//! If the frame does not fit in the receive buffer, the rest of the frame is read
+//! and discarded, up to and including the short packet ending it. The next frame
+//! then starts cleanly.
+
+/// The result of adding a packet to a [`PacketAccumulator`]
+#[derive(Debug, PartialEq)]
+pub enum Progress {
+    /// The frame is not complete yet, more packets must be read
+    Pending,
+    /// The frame is complete, and is this many bytes long
+    Frame(usize),
+    /// The frame was too long for the buffer, and has been discarded
+    TooLong,
+}
+
+/// Tracks the packets of a single frame, see the [module docs][self]
+pub struct PacketAccumulator {
+    max_packet: usize,
+    len: usize,
+    discarding: bool,
+}
+
+impl PacketAccumulator {
+    /// Create a new accumulator, for an endpoint with the given max packet size
+    pub const fn new(max_packet: usize) -> Self {
+        Self {
+            max_packet,
+            len: 0,
+            discarding: false,
+        }
+    }
+
+    /// The part of `buf` that the next packet should be read into
+    ///
+    /// Once the frame no longer fits, this is the whole buffer, and the contents
+    /// are discarded.
+    pub fn window<'a>(&self, buf: &'a mut [u8]) -> &'a mut [u8] {
+        if self.discarding {
+            buf
+        } else {
+            &mut buf[self.len..]
+        }
+    }
+
+    /// Record that a packet of `n` bytes was read into the [window][Self::window]
+    /// of a buffer of `buflen` bytes
+    pub fn push(&mut self, n: usize, buflen: usize) -> Progress {
+        let short = n < self.max_packet;
+        if self.discarding {
+            if short {
+                self.reset();
+                return Progress::TooLong;
+            }
+            return Progress::Pending;
+        }
+
+        self.len += n;
+        if short {
+            let len = self.len;
+            self.reset();
+            Progress::Frame(len)
+        } else {
+            if self.len >= buflen {
+                // No room for another packet, even if it is a zero length one.
+                // Discard the rest of the frame.
+                self.discarding = true;
+            }
+            Progress::Pending
+        }
+    }
+
+    /// Record that the packet did not fit in the [window][Self::window]
+    ///
+    /// The rest of the frame is discarded.
+    pub fn overflow(&mut self) {
+        self.discarding = true;
+    }
+
+    /// Discard any partially received frame
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.discarding = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PacketAccumulator, Progress};
+
+    fn feed(acc: &mut PacketAccumulator, buf: &mut [u8], packet: &[u8]) -> Progress {
+        let buflen = buf.len();
+        let window = acc.window(buf);
+        window[..packet.len()].copy_from_slice(packet);
+        acc.push(packet.len(), buflen)
+    }
+
+    #[test]
+    fn frame_in_three_reads() {
+        let frame: Vec<u8> = (0..150u8).collect();
+        let mut buf = [0u8; 256];
+        let mut acc = PacketAccumulator::new(64);
+
+        assert_eq!(feed(&mut acc, &mut buf, &frame[..64]), Progress::Pending);
+        assert_eq!(feed(&mut acc, &mut buf, &frame[64..128]), Progress::Pending);
+        assert_eq!(
+            feed(&mut acc, &mut buf, &frame[128..]),
+            Progress::Frame(150)
+        );
+        assert_eq!(&buf[..150], &frame[..]);
+
+        // The next frame starts at the beginning of the buffer
+        assert_eq!(feed(&mut acc, &mut buf, &[1, 2, 3]), Progress::Frame(3));
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn zero_length_packet_ends_frame() {
+        let mut buf = [0u8; 256];
+        let mut acc = PacketAccumulator::new(64);
+
+        assert_eq!(feed(&mut acc, &mut buf, &[7; 64]), Progress::Pending);
+        assert_eq!(feed(&mut acc, &mut buf, &[]), Progress::Frame(64));
+    }
+
+    #[test]
+    fn too_long_is_discarded() {
+        let mut buf = [0u8; 128];
+        let mut acc = PacketAccumulator::new(64);
+
+        assert_eq!(feed(&mut acc, &mut buf, &[1; 64]), Progress::Pending);
+        assert_eq!(feed(&mut acc, &mut buf, &[2; 64]), Progress::Pending);
+        assert_eq!(feed(&mut acc, &mut buf, &[3; 64]), Progress::Pending);
+        assert_eq!(feed(&mut acc, &mut buf, &[4; 10]), Progress::TooLong);
+
+        // A packet that doesn't fit the window is discarded as well
+        assert_eq!(feed(&mut acc, &mut buf, &[5; 64]), Progress::Pending);
+        acc.overflow();
+        assert_eq!(feed(&mut acc, &mut buf, &[6; 1]), Progress::TooLong);
+
+        assert_eq!(feed(&mut acc, &mut buf, &[8; 5]), Progress::Frame(5));
+        assert_eq!(&buf[..5], &[8; 5]);
+    }
+}
@@ -0,0 +1,164 @@
+//! Limiting the rate of frames the dispatcher handles
+//!
+//! A host that sends requests faster than the device handles them can keep the
+//! dispatcher, and the handlers it runs, busy indefinitely. A [`RateLimit`] is a
+//! [`Middleware`] implementing a token bucket: each frame takes a token, tokens are
+//! refilled at a fixed rate up to a burst size, and frames arriving while the bucket
+//! is empty are rejected with [`WireError::Busy`], without running their handler.
+//! The `retry_after_ms` of the error tells the host when the next token is available.
+//!
+//! ```rust,ignore
+//! fn now_ms() -> u32 {
+//!     embassy_time::Instant::now().as_millis() as u32
+//! }
+//!
+//! // 20 frames per second, and up to 5 at once
+//! static LIMIT: RateLimit = RateLimit::new(20, 5, now_ms);
+//! // Only 1 `FlashEndpoint` request per second
+//! static FLASH_LIMIT: RateLimit = RateLimit::new(1, 1, now_ms).only(FlashEndpoint::REQ_KEY);
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     middleware: [LIMIT, FLASH_LIMIT];
+//!     // ...
+//! }
+//! ```
+//!
+//! The clock is any function returning milliseconds, which may wrap around. Without
+//! [`RateLimit::only()`], all frames are counted, including topic messages and
+//! requests to the standard endpoints. The state of a limit is two atomics.
+//!
+//! [`WireError::Busy`]: crate::standard_icd::WireError::Busy
+
+use core::ops::ControlFlow;
+
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::{
+    header::{VarHeader, VarKey},
+    standard_icd::{Busy, WireError},
+    Key,
+};
+
+use super::middleware::Middleware;
+
+/// The fraction of a token counted by the bucket, so a rate in tokens per second is
+/// the refill in milli-tokens per millisecond
+const TOKEN: u32 = 1000;
+
+/// A token bucket limiting the rate of frames, see the [module docs][self]
+pub struct RateLimit {
+    // The fill of the bucket, in milli-tokens
+    tokens: AtomicU32,
+    // The time of the last refill
+    last_ms: AtomicU32,
+    rate: u32,
+    burst: u32,
+    key: Option<Key>,
+    now_ms: fn() -> u32,
+}
+
+impl RateLimit {
+    /// Create a new limit of `rate` frames per second, allowing up to `burst` frames at
+    /// once, with the clock `now_ms`
+    ///
+    /// The bucket starts full. `rate` and `burst` are at least 1.
+    pub const fn new(rate: u32, burst: u32, now_ms: fn() -> u32) -> Self {
+        let burst = if burst == 0 { 1 } else { burst };
+        Self {
+            tokens: AtomicU32::new(burst.saturating_mul(TOKEN)),
+            last_ms: AtomicU32::new(0),
+            rate: if rate == 0 { 1 } else { rate },
+            burst,
+            key: None,
+            now_ms,
+        }
+    }
+
+    /// Only count the frames with the (full size) `key`, e.g. the requests of a single
+    /// endpoint, and let all other frames through
+    pub const fn only(mut self, key: Key) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Take a token, or return the number of milliseconds until one is available
+    pub fn try_take(&self) -> Result<(), u32> {
+        let now = (self.now_ms)();
+        let elapsed = now.wrapping_sub(self.last_ms.swap(now, Ordering::AcqRel));
+        let max = self.burst.saturating_mul(TOKEN);
+        let mut taken = Ok(());
+        let _ = self
+            .tokens
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |tokens| {
+                let tokens = tokens
+                    .saturating_add(elapsed.saturating_mul(self.rate))
+                    .min(max);
+                match tokens.checked_sub(TOKEN) {
+                    Some(left) => {
+                        taken = Ok(());
+                        Some(left)
+                    }
+                    None => {
+                        taken = Err((TOKEN - tokens).div_ceil(self.rate));
+                        Some(tokens)
+                    }
+                }
+            });
+        taken
+    }
+
+    /// The number of whole tokens in the bucket, as of the last frame
+    pub fn available(&self) -> u32 {
+        self.tokens.load(Ordering::Acquire) / TOKEN
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, hdr: &VarHeader, _body: &[u8]) -> ControlFlow<WireError, ()> {
+        if let Some(key) = self.key {
+            if VarKey::Key8(key) != hdr.key {
+                return ControlFlow::Continue(());
+            }
+        }
+        match self.try_take() {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(retry_after_ms) => ControlFlow::Break(WireError::Busy(Busy { retry_after_ms })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use portable_atomic::{AtomicU32, Ordering};
+
+    use super::RateLimit;
+
+    static NOW: AtomicU32 = AtomicU32::new(0);
+
+    fn now_ms() -> u32 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn tokens_are_refilled() {
+        // 10 per second, 2 at once
+        let limit = RateLimit::new(10, 2, now_ms);
+
+        assert_eq!(limit.try_take(), Ok(()));
+        assert_eq!(limit.try_take(), Ok(()));
+        assert_eq!(limit.try_take(), Err(100));
+
+        // Half a token later
+        NOW.store(50, Ordering::Relaxed);
+        assert_eq!(limit.try_take(), Err(50));
+        NOW.store(100, Ordering::Relaxed);
+        assert_eq!(limit.try_take(), Ok(()));
+
+        // The bucket is never fuller than the burst
+        NOW.store(10_000, Ordering::Relaxed);
+        assert_eq!(limit.try_take(), Ok(()));
+        assert_eq!(limit.available(), 1);
+    }
+}
@@ -0,0 +1,180 @@
+//! Reassembly of frames sent as multiple [`Fragment`]s
+//!
+//! Frames that are larger than the receive buffer (or the max packet size of the
+//! transport) may be split by the client into [`Fragment`]s, sent on the
+//! [`FragmentTopic`][crate::standard_icd::FragmentTopic]. A [`Server`][super::Server]
+//! created with [`Server::new_with_reassembly()`][super::Server::new_with_reassembly]
+//! collects these fragments, and dispatches the original frame once it is complete.
+//!
+//! Only a single frame is reassembled at a time. A fragment with an offset of zero
+//! always starts a new frame, discarding any incomplete frame.
+
+use core::ops::DerefMut;
+
+use crate::{
+    header::VarSeq,
+    standard_icd::{Fragment, FrameTooLong, WireError},
+};
+
+/// A buffer used to reassemble a single fragmented frame
+pub struct Reassembler<Buf: DerefMut<Target = [u8]>> {
+    buf: Buf,
+    current: Option<InProgress>,
+}
+
+struct InProgress {
+    seq_no: VarSeq,
+    total_len: usize,
+    filled: usize,
+}
+
+impl<Buf: DerefMut<Target = [u8]>> Reassembler<Buf> {
+    /// Create a new reassembler, able to reassemble frames up to the size of `buf`
+    pub fn new(buf: Buf) -> Self {
+        Self { buf, current: None }
+    }
+
+    /// Add the serialized [`Fragment`] `body`, received with the given `seq_no`
+    ///
+    /// Returns the complete frame once the last fragment has been added, or `None`
+    /// if more fragments are needed. On error, any incomplete frame is discarded.
+    pub fn push(&mut self, seq_no: VarSeq, body: &[u8]) -> Result<Option<&[u8]>, WireError> {
+        let Ok(frag) = postcard::from_bytes::<Fragment<'_>>(body) else {
+            self.current = None;
+            return Err(WireError::DeserFailed);
+        };
+        match self.push_inner(seq_no, &frag) {
+            Ok(true) => {
+                let total_len = self.current.take().map(|c| c.total_len).unwrap_or(0);
+                Ok(Some(&self.buf[..total_len]))
+            }
+            Ok(false) => Ok(None),
+            Err(e) => {
+                self.current = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Discard any incomplete frame
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+
+    fn push_inner(&mut self, seq_no: VarSeq, frag: &Fragment<'_>) -> Result<bool, WireError> {
+        let total_len = frag.total_len as usize;
+        let offset = frag.offset as usize;
+
+        if offset == 0 {
+            if total_len > self.buf.len() {
+                return Err(WireError::FrameTooLong(FrameTooLong {
+                    len: frag.total_len,
+                    max: self.buf.len() as u32,
+                }));
+            }
+            self.current = Some(InProgress {
+                seq_no,
+                total_len,
+                filled: 0,
+            });
+        }
+
+        let Some(cur) = self.current.as_mut() else {
+            // Not the start of a frame, and no frame in progress
+            return Err(WireError::ReassemblyFailed);
+        };
+        // Fragments must continue exactly where the previous one ended
+        let good = cur.seq_no == seq_no && cur.total_len == total_len && cur.filled == offset;
+        if !good {
+            return Err(WireError::ReassemblyFailed);
+        }
+        // The offset is at most `total_len` here, but don't trust the length either
+        let end = match offset.checked_add(frag.data.len()) {
+            Some(end) if end <= total_len => end,
+            _ => return Err(WireError::ReassemblyFailed),
+        };
+
+        self.buf[offset..end].copy_from_slice(frag.data);
+        cur.filled = end;
+        Ok(cur.filled == cur.total_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Reassembler;
+    use crate::{
+        header::VarSeq,
+        standard_icd::{Fragment, WireError},
+    };
+
+    fn frag(total_len: u32, offset: u32, data: &[u8]) -> Vec<u8> {
+        postcard::to_stdvec(&Fragment {
+            total_len,
+            offset,
+            data,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn in_order() {
+        let mut r = Reassembler::new(vec![0u8; 8].into_boxed_slice());
+        let seq = VarSeq::Seq2(5);
+        assert_eq!(r.push(seq, &frag(6, 0, &[1, 2, 3])), Ok(None));
+        assert_eq!(r.push(seq, &frag(6, 3, &[4, 5])), Ok(None));
+        assert_eq!(
+            r.push(seq, &frag(6, 5, &[6])),
+            Ok(Some([1, 2, 3, 4, 5, 6].as_slice()))
+        );
+    }
+
+    #[test]
+    fn gaps_and_overlaps() {
+        let mut r = Reassembler::new(vec![0u8; 8].into_boxed_slice());
+        let seq = VarSeq::Seq2(5);
+
+        // Gap
+        assert_eq!(r.push(seq, &frag(6, 0, &[1, 2])), Ok(None));
+        assert_eq!(
+            r.push(seq, &frag(6, 3, &[4])),
+            Err(WireError::ReassemblyFailed)
+        );
+        // The incomplete frame was discarded
+        assert_eq!(
+            r.push(seq, &frag(6, 2, &[3])),
+            Err(WireError::ReassemblyFailed)
+        );
+
+        // Overlap
+        assert_eq!(r.push(seq, &frag(6, 0, &[1, 2])), Ok(None));
+        assert_eq!(
+            r.push(seq, &frag(6, 1, &[2, 3])),
+            Err(WireError::ReassemblyFailed)
+        );
+
+        // Past the end, or a different request
+        assert_eq!(
+            r.push(seq, &frag(2, 0, &[1, 2, 3])),
+            Err(WireError::ReassemblyFailed)
+        );
+        assert_eq!(r.push(seq, &frag(6, 0, &[1, 2])), Ok(None));
+        assert_eq!(
+            r.push(VarSeq::Seq2(6), &frag(6, 2, &[3])),
+            Err(WireError::ReassemblyFailed)
+        );
+
+        // Offsets that would overflow
+        assert_eq!(r.push(seq, &frag(6, 0, &[1, 2])), Ok(None));
+        assert_eq!(
+            r.push(seq, &frag(6, u32::MAX, &[3])),
+            Err(WireError::ReassemblyFailed)
+        );
+
+        // Too large
+        assert!(matches!(
+            r.push(seq, &frag(9, 0, &[1])),
+            Err(WireError::FrameTooLong(_))
+        ));
+    }
+}
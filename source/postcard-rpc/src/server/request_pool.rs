@@ -0,0 +1,159 @@
+//! Bounding the memory used by requests
+//!
+//! Each request is deserialized from the receive buffer of the server, and requests
+//! to `spawn` endpoints are then handled concurrently, each in its own task. Under
+//! bursty load, the memory used by requests in flight is only bounded by the number
+//! of tasks the executor can spawn.
+//!
+//! A dispatcher created with the optional `request_pool` setting of
+//! [`define_dispatch!`][crate::define_dispatch] takes one of a fixed number of
+//! buffers from a [`RequestPool`] for each request to an endpoint, copies the body
+//! of the request into it, and deserializes the request from there. The pool sets
+//! both the number of buffers and their size:
+//!
+//! ```rust,ignore
+//! static REQUESTS: RequestPool<4, 256> = RequestPool::new();
+//!
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     request_pool: REQUESTS;
+//!     // ...
+//! }
+//! ```
+//!
+//! When all buffers are taken, requests are rejected with [`WireError::Busy`], and
+//! requests longer than a buffer are rejected with [`WireError::FrameTooLong`]. The
+//! buffer is returned to the pool once the handler completes, for `spawn` handlers
+//! when the task completes and drops its [`Sender`][super::Sender]. Requests to the
+//! standard endpoints, and messages to topics, do not take a buffer.
+//!
+//! The pool bounds the bytes of the requests in flight, not the memory the handlers
+//! use: a request type that allocates, e.g. a `Vec` with the `use-std` feature,
+//! still allocates when deserialized.
+//!
+//! [`WireError::Busy`]: crate::standard_icd::WireError::Busy
+//! [`WireError::FrameTooLong`]: crate::standard_icd::WireError::FrameTooLong
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+use portable_atomic::{AtomicU32, Ordering};
+
+/// A fixed set of `N` buffers of `LEN` bytes each, at most 32 buffers
+pub struct RequestPool<const N: usize, const LEN: usize> {
+    // One bit per buffer, set while it is taken
+    taken: AtomicU32,
+    bufs: [UnsafeCell<[u8; LEN]>; N],
+}
+
+// SAFETY: each buffer is only accessed through the single `PooledBuf` that took it
+unsafe impl<const N: usize, const LEN: usize> Sync for RequestPool<N, LEN> {}
+
+impl<const N: usize, const LEN: usize> RequestPool<N, LEN> {
+    const ALL: u32 = if N == 32 { u32::MAX } else { (1u32 << N) - 1 };
+
+    /// Create a new pool, with all buffers available
+    pub const fn new() -> Self {
+        assert!(N <= 32, "a RequestPool has at most 32 buffers");
+        Self {
+            taken: AtomicU32::new(0),
+            bufs: [const { UnsafeCell::new([0u8; LEN]) }; N],
+        }
+    }
+
+    /// Take an available buffer, if any
+    pub fn try_take(&'static self) -> Option<PooledBuf> {
+        let mut idx = 0;
+        self.taken
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |taken| {
+                let free = !taken & Self::ALL;
+                idx = free.trailing_zeros();
+                (free != 0).then(|| taken | (1 << idx))
+            })
+            .ok()?;
+
+        // SAFETY: the bit of this buffer was clear, so no other `PooledBuf` refers to it
+        let buf = unsafe { &mut *self.bufs[idx as usize].get() };
+        Some(PooledBuf {
+            taken: &self.taken,
+            bit: 1 << idx,
+            buf,
+        })
+    }
+
+    /// The number of buffers that are not taken
+    pub fn available(&self) -> usize {
+        N - self.taken.load(Ordering::Acquire).count_ones() as usize
+    }
+
+    /// The total number of buffers
+    pub const fn size(&self) -> usize {
+        N
+    }
+
+    /// The length of each buffer
+    pub const fn buf_len(&self) -> usize {
+        LEN
+    }
+}
+
+impl<const N: usize, const LEN: usize> Default for RequestPool<N, LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A buffer taken from a [`RequestPool`], returned to it when dropped
+pub struct PooledBuf {
+    taken: &'static AtomicU32,
+    bit: u32,
+    buf: &'static mut [u8],
+}
+
+impl Deref for PooledBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        self.taken.fetch_and(!self.bit, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RequestPool;
+
+    #[test]
+    fn buffers_are_returned() {
+        static POOL: RequestPool<2, 8> = RequestPool::new();
+
+        let mut a = POOL.try_take().unwrap();
+        let b = POOL.try_take().unwrap();
+        assert!(POOL.try_take().is_none());
+        assert_eq!(POOL.available(), 0);
+        assert_eq!(a.len(), 8);
+        a[0] = 1;
+        assert_eq!(b[0], 0);
+
+        drop(a);
+        assert_eq!(POOL.available(), 1);
+        let _c = POOL.try_take().unwrap();
+        assert!(POOL.try_take().is_none());
+        drop(b);
+        assert_eq!(POOL.available(), 1);
+    }
+}
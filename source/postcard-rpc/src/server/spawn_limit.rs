@@ -0,0 +1,213 @@
+//! Limiting the number of live `spawn` handler tasks
+//!
+//! Each request to a `spawn` endpoint starts a new task. If the client sends many
+//! requests at once, this can exhaust the task pool of the executor, and the client
+//! receives `FailedToSpawn`, which doesn't tell it whether retrying may help.
+//!
+//! A dispatcher created with the optional `max_spawned` setting of
+//! [`define_dispatch!`][crate::define_dispatch] counts the tasks it has spawned, and
+//! replies with [`WireError::Busy`] instead of spawning once the limit is reached:
+//!
+//! ```rust,ignore
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     max_spawned: 4;
+//!     // ...
+//! }
+//! ```
+//!
+//! The [`Sender`][super::Sender] given to the spawned task holds a [`SpawnPermit`],
+//! and the task is counted until this `Sender` is dropped, which happens when the task
+//! completes. Clones of the `Sender` do not hold the permit.
+//!
+//! ## Fairness
+//!
+//! On its own, `max_spawned` is first come, first served: a flood of requests to a
+//! slow endpoint can take all the tasks, and requests to every other `spawn` endpoint
+//! are then rejected until they complete. With the optional `max_spawned_per_endpoint`
+//! setting, each endpoint may only have that many live tasks, so the remaining tasks
+//! are left for the other endpoints:
+//!
+//! ```rust,ignore
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     max_spawned: 4;
+//!     max_spawned_per_endpoint: 2;
+//!     // ...
+//! }
+//! ```
+//!
+//! Requests over the limit of their endpoint are rejected with [`WireError::Busy`],
+//! so the client retries them, while requests to other endpoints are spawned. The
+//! live tasks of each endpoint are counted with a [`KeyCount`].
+//!
+//! Each live task costs the memory of its future, which holds its request, its
+//! [`SpawnContext`][super::SpawnContext], and a [`Sender`][super::Sender] (e.g. the
+//! `pool_size` of an embassy task reserves this memory up front). Limiting the tasks
+//! bounds this memory, with or without the per-endpoint limit, and tasks complete in
+//! any order: each reply carries the `seq_no` of its own request.
+//!
+//! [`WireError::Busy`]: crate::standard_icd::WireError::Busy
+
+use portable_atomic::{AtomicUsize, Ordering};
+
+/// The number of live spawned tasks, and the limit for them
+pub struct SpawnLimit {
+    live: AtomicUsize,
+    max: usize,
+    max_per_key: usize,
+}
+
+/// Increment `live`, if it is less than `max`
+fn try_increment(live: &AtomicUsize, max: usize) -> bool {
+    live.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+        (n < max).then_some(n + 1)
+    })
+    .is_ok()
+}
+
+impl SpawnLimit {
+    /// Create a new limit, allowing up to `max` live tasks
+    pub const fn new(max: usize) -> Self {
+        Self::with_max_per_key(max, usize::MAX)
+    }
+
+    /// Create a new limit, allowing up to `max` live tasks, and up to `max_per_key`
+    /// live tasks counted by the same [`KeyCount`]
+    pub const fn with_max_per_key(max: usize, max_per_key: usize) -> Self {
+        Self {
+            live: AtomicUsize::new(0),
+            max,
+            max_per_key,
+        }
+    }
+
+    /// Count a new task, if there are less than `max` live tasks
+    pub fn try_acquire(&'static self) -> Option<SpawnPermit> {
+        // Not `then_some()`: dropping a permit that was never counted would uncount one
+        try_increment(&self.live, self.max).then(|| SpawnPermit {
+            limit: self,
+            key: None,
+        })
+    }
+
+    /// Count a new task of the endpoint counted by `key`, if there are less than
+    /// `max` live tasks, and less than `max_per_key` of them are counted by `key`
+    pub fn try_acquire_for(&'static self, key: &'static KeyCount) -> Option<SpawnPermit> {
+        if !try_increment(&key.live, self.max_per_key) {
+            return None;
+        }
+        if !try_increment(&self.live, self.max) {
+            key.live.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(SpawnPermit {
+            limit: self,
+            key: Some(key),
+        })
+    }
+
+    /// The number of live tasks
+    pub fn live(&self) -> usize {
+        self.live.load(Ordering::Acquire)
+    }
+
+    /// The maximum number of live tasks
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// The maximum number of live tasks counted by the same [`KeyCount`]
+    pub fn max_per_key(&self) -> usize {
+        self.max_per_key
+    }
+}
+
+/// The number of live tasks of a single endpoint, see [`SpawnLimit::try_acquire_for()`]
+pub struct KeyCount {
+    live: AtomicUsize,
+}
+
+impl KeyCount {
+    /// Create a new count, with no live tasks
+    pub const fn new() -> Self {
+        Self {
+            live: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of live tasks
+    pub fn live(&self) -> usize {
+        self.live.load(Ordering::Acquire)
+    }
+}
+
+impl Default for KeyCount {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A single live task, counted by a [`SpawnLimit`] until dropped
+pub struct SpawnPermit {
+    limit: &'static SpawnLimit,
+    key: Option<&'static KeyCount>,
+}
+
+impl Drop for SpawnPermit {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            key.live.fetch_sub(1, Ordering::AcqRel);
+        }
+        self.limit.live.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyCount, SpawnLimit};
+
+    #[test]
+    fn permits_are_returned() {
+        static LIMIT: SpawnLimit = SpawnLimit::new(2);
+
+        let a = LIMIT.try_acquire().unwrap();
+        let b = LIMIT.try_acquire().unwrap();
+        assert!(LIMIT.try_acquire().is_none());
+        assert_eq!(LIMIT.live(), 2);
+
+        drop(a);
+        assert_eq!(LIMIT.live(), 1);
+        let _c = LIMIT.try_acquire().unwrap();
+        assert!(LIMIT.try_acquire().is_none());
+        drop(b);
+        assert_eq!(LIMIT.live(), 1);
+    }
+
+    #[test]
+    fn keys_get_their_share() {
+        static LIMIT: SpawnLimit = SpawnLimit::with_max_per_key(3, 2);
+        static SLOW: KeyCount = KeyCount::new();
+        static FAST: KeyCount = KeyCount::new();
+
+        let a = LIMIT.try_acquire_for(&SLOW).unwrap();
+        let _b = LIMIT.try_acquire_for(&SLOW).unwrap();
+        assert!(LIMIT.try_acquire_for(&SLOW).is_none());
+        assert_eq!(SLOW.live(), 2);
+
+        // The remaining task is left for other keys
+        let _c = LIMIT.try_acquire_for(&FAST).unwrap();
+        assert!(LIMIT.try_acquire_for(&FAST).is_none());
+        assert_eq!(FAST.live(), 1);
+        assert_eq!(LIMIT.live(), 3);
+
+        drop(a);
+        assert_eq!(SLOW.live(), 1);
+        assert_eq!(LIMIT.live(), 2);
+        let _d = LIMIT.try_acquire_for(&FAST).unwrap();
+        assert!(LIMIT.try_acquire_for(&SLOW).is_none());
+        assert_eq!(SLOW.live(), 1);
+    }
+}
@@ -0,0 +1,66 @@
+//! Replying with an error when a handler runs too long
+//!
+//! A handler that never completes stalls the connection, as the server dispatches one
+//! frame at a time, and the client never receives a reply. An endpoint may limit how
+//! long its handler runs, by adding `[timeout_ms = N]` after the endpoint type in
+//! [`define_dispatch!`][crate::define_dispatch], which then also needs a `timer`:
+//!
+//! ```rust,ignore
+//! define_dispatch! {
+//!     app: MyApp;
+//!     // ...
+//!     // A function taking a number of milliseconds, and returning a future that
+//!     // completes once they have elapsed
+//!     timer: sleep_ms;
+//!
+//!     endpoints: {
+//!         list: ENDPOINT_LIST;
+//!
+//!         | EndpointTy                        | kind      | handler           |
+//!         | ----------                        | ----      | -------           |
+//!         | FooEndpoint [timeout_ms = 500]    | async     | foo_handler       |
+//!     };
+//!     // ...
+//! }
+//! ```
+//!
+//! The dispatcher races the handler against the timer. If the timer completes first,
+//! the handler is dropped at its current `.await` point, and the client receives
+//! [`WireError::HandlerTimeout`]. Handlers with a timeout must therefore be
+//! cancellation safe, e.g. not leave the context in an inconsistent state while
+//! awaiting. The time it takes to send the reply is not limited.
+//!
+//! Timeouts are supported for `async`, `custom`, `dedup`, `notify`, `notify_confirmed`,
+//! and `transaction` handlers. `blocking` and `ref` handlers can't be interrupted,
+//! and `spawn` handlers run in their own task, which the dispatcher doesn't wait for,
+//! so these reject `timeout_ms` at compile time. A spawned task can limit its own work with
+//! [`with_timeout()`], and reply with [`WireError::HandlerTimeout`] itself.
+//!
+//! The `dispatch_impl` modules of the server implementations with a timer provide a
+//! `sleep_ms` function that can be used as the `timer`.
+//!
+//! [`WireError::HandlerTimeout`]: crate::standard_icd::WireError::HandlerTimeout
+
+use core::{future::Future, pin::pin, task::Poll};
+
+/// Run `fut` until it completes, or until `timer` completes, whichever is first
+///
+/// Returns `None` if the timer completed first, in which case `fut` is dropped.
+pub async fn with_timeout<F, T>(timer: T, fut: F) -> Option<F::Output>
+where
+    F: Future,
+    T: Future<Output = ()>,
+{
+    let mut fut = pin!(fut);
+    let mut timer = pin!(timer);
+    core::future::poll_fn(|cx| {
+        if let Poll::Ready(out) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Some(out));
+        }
+        match timer.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+    .await
+}
@@ -0,0 +1,84 @@
+//! Receiving follow-up frames from within a handler
+//!
+//! Some exchanges need more than one request, e.g. a handler that sends a prompt to
+//! the client, waits for the answer, and only then replies. Handlers of the
+//! `transaction` kind of [`define_dispatch!`][crate::define_dispatch] are given a
+//! [`Transaction`], which receives the next frames from the client directly, instead
+//! of them being dispatched:
+//!
+//! ```rust,ignore
+//! async fn confirm_erase<Rx: WireRx>(
+//!     context: &mut TestContext,
+//!     header: VarHeader,
+//!     sector: u32,
+//!     sender: &Sender<WireTxImpl>,
+//!     mut txn: Transaction<'_, Rx>,
+//! ) -> EraseResult {
+//!     let _ = sender.publish::<ConfirmTopic>(header.seq_no, &sector).await;
+//!     let mut buf = [0u8; 64];
+//!     loop {
+//!         let Ok((hdr, body)) = txn.recv(&mut buf).await else {
+//!             return EraseResult::Aborted;
+//!         };
+//!         if hdr.key == VarKey::Key8(ConfirmEndpoint::REQ_KEY) {
+//!             // ...
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Frames received by the [`Transaction`] are NOT dispatched, so requests that are
+//! not part of the transaction must be answered by the handler, e.g. with
+//! [`Sender::error_for()`][super::Sender::error_for], or they are lost. Fragmented
+//! frames are not reassembled.
+
+use crate::header::VarHeader;
+
+use super::{AsWireRxErrorKind, WireRx, WireRxErrorKind};
+
+/// A handle to receive frames from the client, while a `transaction` handler runs
+///
+/// The main loop of the [`Server`][super::Server] does not receive any frames until
+/// the handler returns, so while a transaction is running, requests to ALL other
+/// endpoints are stalled. Keep transactions short, and give up after a timeout if
+/// the client does not answer.
+pub struct Transaction<'a, Rx: WireRx> {
+    rx: Option<&'a mut Rx>,
+}
+
+impl<'a, Rx: WireRx> Transaction<'a, Rx> {
+    /// Create a new transaction, receiving from `rx`
+    ///
+    /// Without a receiver, e.g. when frames are dispatched with
+    /// [`Dispatch::handle()`][super::Dispatch::handle], all calls to
+    /// [`recv()`][Self::recv] fail.
+    pub fn new(rx: Option<&'a mut Rx>) -> Self {
+        Self { rx }
+    }
+
+    /// Receive the next frame from the client, using `buf` as storage
+    ///
+    /// Returns [`WireRxErrorKind::Other`] if the frame has a malformed header, and
+    /// [`WireRxErrorKind::ConnectionClosed`] if there is no receiver.
+    pub async fn recv<'b>(
+        &mut self,
+        buf: &'b mut [u8],
+    ) -> Result<(VarHeader, &'b [u8]), WireRxErrorKind> {
+        let Some(rx) = self.rx.as_mut() else {
+            return Err(WireRxErrorKind::ConnectionClosed);
+        };
+        let used = rx.receive(buf).await.map_err(|e| e.as_kind())?;
+        VarHeader::take_from_slice(used).ok_or(WireRxErrorKind::Other)
+    }
+}
+
+/// A [`WireRx`] that can't be constructed, used when there is no receiver
+pub enum NoRx {}
+
+impl WireRx for NoRx {
+    type Error = WireRxErrorKind;
+
+    async fn receive<'a>(&mut self, _buf: &'a mut [u8]) -> Result<&'a mut [u8], Self::Error> {
+        match *self {}
+    }
+}
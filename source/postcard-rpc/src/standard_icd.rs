@@ -2,7 +2,7 @@
 //!
 //! This is used by [`define_dispatch!()`] as well.
 
-use crate::{endpoints, topics, Key, TopicDirection};
+use crate::{endpoints, header::VarKey, topics, Key, Key1, Key2, Key4, TopicDirection};
 use postcard_schema::Schema;
 use serde::{Deserialize, Serialize};
 
@@ -13,13 +13,108 @@ use postcard_schema::schema::NamedType;
 use postcard_schema::schema::owned::OwnedNamedType;
 
 /// The calculated Key for the type [`WireError`] and the path [`ERROR_PATH`]
+///
+/// This changes with any change to [`WireError`], see [`PROTOCOL_VERSION`].
 pub const ERROR_KEY: Key = Key::for_path::<WireError>(ERROR_PATH);
 
 /// The path string used for the error type
 pub const ERROR_PATH: &str = "error";
 
+/// The calculated Key for the type [`KeyedError`] and the path [`KEYED_ERROR_PATH`]
+pub const KEYED_ERROR_KEY: Key = Key::for_path::<KeyedError>(KEYED_ERROR_PATH);
+
+/// The path string used for the keyed error type
+///
+/// See [`Server::set_keyed_errors()`][crate::server::Server::set_keyed_errors].
+pub const KEYED_ERROR_PATH: &str = "error/keyed";
+
+/// The calculated Key for acknowledgements, with the path [`ACK_PATH`]
+///
+/// An acknowledgement is a frame with this key, an empty body, and the sequence
+/// number of the request it acknowledges. It is sent by
+/// [`Sender::ack()`][crate::server::Sender::ack] before the response, and tells the
+/// client that the request was received and accepted, see
+/// `HostClient::send_resp_with_ack()`.
+pub const ACK_KEY: Key = Key::for_path::<()>(ACK_PATH);
+
+/// The path string used for acknowledgements
+pub const ACK_PATH: &str = "ack";
+
+/// The calculated Key for progress updates, with the path [`PROGRESS_PATH`]
+///
+/// A progress update is a frame with this key, a `u8` body with the percentage of the
+/// operation that is done, and the sequence number of the request it belongs to. It
+/// is sent by [`Sender::progress()`][crate::server::Sender::progress] any number of
+/// times before the response, which is sent with the response key as usual. As the
+/// key differs, the client never mistakes an update for the response, see
+/// `HostClient::send_resp_with_progress()`.
+pub const PROGRESS_KEY: Key = Key::for_path::<u8>(PROGRESS_PATH);
+
+/// The path string used for progress updates
+pub const PROGRESS_PATH: &str = "progress";
+
+/// The calculated Key for the type [`DuplexClose`] and the path [`DUPLEX_CLOSE_PATH`]
+///
+/// Sent by either side of a duplex stream, with the sequence number of the stream,
+/// to end one or both directions of it. See
+/// [`server::duplex`][crate::server::duplex].
+pub const DUPLEX_CLOSE_KEY: Key = Key::for_path::<DuplexClose>(DUPLEX_CLOSE_PATH);
+
+/// The path string used for closing duplex streams
+pub const DUPLEX_CLOSE_PATH: &str = "duplex/close";
+
+/// The version of the postcard-rpc protocol, reported by the [`HandshakeEndpoint`]
+///
+/// This version only covers the protocol itself, not the endpoints of an
+/// application. It is bumped when a client and server using different versions
+/// can no longer communicate, for example when:
+///
+/// * the encoding of the frame header changes
+/// * the key of an existing standard endpoint or topic changes, e.g. because its
+///   request, response, or message type changed
+/// * [`WireError`] changes in any way, including a new variant at the end
+///
+/// The [`ERROR_KEY`] (and the [`KEYED_ERROR_KEY`]) is a hash of the whole schema
+/// of [`WireError`], so adding a variant changes it as much as removing one. A
+/// client with a different [`WireError`] doesn't recognize the error frames of the
+/// server, and its requests wait for a response that never comes.
+///
+/// Additive changes, such as new standard endpoints or topics, do NOT bump the
+/// version. Changes to application endpoints are detected by comparing keys, see
+/// [`Handshake`].
+///
+/// ## Versions
+///
+/// * 1: the protocol of `postcard-rpc` 0.10
+/// * 2: new variants of [`WireError`], and so a new [`ERROR_KEY`], see the
+///   changelog for the full list
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The version of the `postcard-rpc` crate, as `[major, minor, patch]`, reported by
+/// the [`CompatEndpoint`]
+///
+/// Unlike the [`PROTOCOL_VERSION`], this changes with every release, and is only
+/// reported for information, e.g. to show in a diagnostic.
+pub const CRATE_VERSION: [u16; 3] = [
+    parse_version(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_version(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_version(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+/// Parse a single number of the version of the crate
+const fn parse_version(s: &str) -> u16 {
+    let bytes = s.as_bytes();
+    let mut val = 0u16;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        val = val * 10 + (bytes[idx] - b'0') as u16;
+        idx += 1;
+    }
+    val
+}
+
 /// The given frame was too long
-#[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
 pub struct FrameTooLong {
     /// The length of the too-long frame
     pub len: u32,
@@ -27,16 +122,32 @@ pub struct FrameTooLong {
     pub max: u32,
 }
 
+/// A response didn't fit into the send buffer of the server
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct ResponseTooLarge {
+    /// The length of the response frame, including the header
+    pub needed: u32,
+    /// The length of the send buffer
+    pub have: u32,
+}
+
 /// The given frame was too short
-#[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
 pub struct FrameTooShort {
     /// The length of the too-short frame
     pub len: u32,
 }
 
+/// The server is temporarily unable to handle new requests
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct Busy {
+    /// How long the client should wait before retrying, in milliseconds
+    pub retry_after_ms: u32,
+}
+
 /// A protocol error that is handled outside of the normal request type, usually
 /// indicating a protocol-level error
-#[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
 pub enum WireError {
     /// The frame exceeded the buffering capabilities of the server
     FrameTooLong(FrameTooLong),
@@ -44,8 +155,9 @@ pub enum WireError {
     FrameTooShort(FrameTooShort),
     /// Deserialization of a message failed
     DeserFailed,
-    /// Serialization of a message failed, usually due to a lack of space to
-    /// buffer the serialized form
+    /// Serialization of a message failed. Responses that only failed due to a lack
+    /// of space to buffer the serialized form are reported as `ResponseTooLarge`
+    /// instead, where the transport supports it.
     SerFailed,
     /// The key associated with this request was unknown
     UnknownKey,
@@ -55,6 +167,242 @@ pub enum WireError {
     /// The provided key is below the minimum key size calculated to avoid hash
     /// collisions, and was rejected to avoid potential misunderstanding
     KeyTooSmall,
+    /// A [`Fragment`] did not continue the frame being reassembled, e.g. because
+    /// of a gap or overlap, and the frame was discarded
+    ReassemblyFailed,
+    /// The request was rejected without being handled, as the server is busy. The
+    /// request may be retried later.
+    Busy(Busy),
+    /// The handler did not complete within the timeout of the endpoint, and was
+    /// stopped. See the `server::timeout` module.
+    HandlerTimeout,
+    /// The endpoint requires an [`AuthToken`][crate::header::AuthToken], and the
+    /// request carried none, or one that the server didn't accept. Also sent for
+    /// frames whose body failed to open, see the `cipher` module.
+    Unauthorized,
+    /// The response didn't fit into the send buffer of the server
+    ResponseTooLarge(ResponseTooLarge),
+    /// The CRC of the frame didn't match its contents, and the frame was discarded.
+    /// See the `crc` module.
+    CrcMismatch,
+    /// The response was serialized, but the transport failed to write it, e.g. due
+    /// to a timeout of the USB endpoint. Unlike `SerFailed`, retrying the request
+    /// may succeed.
+    TransportFailed,
+}
+
+/// The key of a request, as it was received by the server
+///
+/// This carries the same information as a [`VarKey`], but is encoded as a regular
+/// postcard enum, so it can be used in a message body.
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub enum RequestKey {
+    /// A one byte key
+    Key1(u8),
+    /// A two byte key
+    Key2([u8; 2]),
+    /// A four byte key
+    Key4([u8; 4]),
+    /// An eight byte key
+    Key8(Key),
+}
+
+impl From<VarKey> for RequestKey {
+    fn from(value: VarKey) -> Self {
+        match value {
+            VarKey::Key1(k) => RequestKey::Key1(k.0),
+            VarKey::Key2(k) => RequestKey::Key2(k.0),
+            VarKey::Key4(k) => RequestKey::Key4(k.0),
+            VarKey::Key8(k) => RequestKey::Key8(k),
+        }
+    }
+}
+
+impl From<RequestKey> for VarKey {
+    fn from(value: RequestKey) -> Self {
+        match value {
+            RequestKey::Key1(k) => VarKey::Key1(Key1(k)),
+            RequestKey::Key2(k) => VarKey::Key2(Key2(k)),
+            RequestKey::Key4(k) => VarKey::Key4(Key4(k)),
+            RequestKey::Key8(k) => VarKey::Key8(k),
+        }
+    }
+}
+
+/// A [`WireError`], along with the key of the request that caused it
+///
+/// This is sent with the [`KEYED_ERROR_KEY`] instead of the plain [`WireError`],
+/// if enabled with [`Server::set_keyed_errors()`]. It allows a client to tell
+/// which request an error belongs to, even if a sequence number was reused.
+///
+/// [`Server::set_keyed_errors()`]: crate::server::Server::set_keyed_errors
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq)]
+pub struct KeyedError {
+    /// The key of the request, as received by the server
+    pub key: RequestKey,
+    /// The error
+    pub error: WireError,
+}
+
+/// Ends one or both directions of a duplex stream, sent with the [`DUPLEX_CLOSE_KEY`]
+///
+/// Either side may close a duplex stream at any time. After a `HalfClose`, the
+/// sender sends no more messages, but keeps receiving them until the other side
+/// closes as well. After a `Close`, neither side sends any more messages.
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub enum DuplexClose {
+    /// The sender is done sending, but still receives messages
+    HalfClose,
+    /// The stream is closed in both directions
+    Close,
+}
+
+/// A single fragment of a frame that is too large to be sent at once
+///
+/// Fragments are sent on the [`FragmentTopic`], using the sequence number of the
+/// original request. `data` contains the bytes of the original frame (header and
+/// body) starting at `offset`. Fragments must be sent in order, starting at an
+/// offset of zero. Once all `total_len` bytes have been received, the original
+/// frame is handled as if it had been sent at once.
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct Fragment<'a> {
+    /// The total length of the original frame
+    pub total_len: u32,
+    /// The offset of `data` in the original frame
+    pub offset: u32,
+    /// The bytes of the original frame carried by this fragment
+    pub data: &'a [u8],
+}
+
+/// Several frames packed into a single transfer
+///
+/// Batches are sent on the [`BatchTopic`], to save the per-transfer overhead of many
+/// small requests. `frames` contains the frames (header and body), each prefixed with
+/// its length as a varint, i.e. serialized as a `&[u8]`. The server handles them in
+/// order, as if they had been sent one by one, see [`Batch::frames()`]. Batches can't
+/// be nested.
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct Batch<'a> {
+    /// The length-prefixed frames of the batch
+    pub frames: &'a [u8],
+}
+
+impl<'a> Batch<'a> {
+    /// Iterate over the frames contained in the batch
+    pub fn frames(&self) -> BatchFrames<'a> {
+        BatchFrames { rest: self.frames }
+    }
+}
+
+/// An iterator over the frames of a [`Batch`], see [`Batch::frames()`]
+///
+/// If the rest of the batch is not a valid length-prefixed frame, this yields
+/// [`WireError::DeserFailed`] once, and then ends.
+pub struct BatchFrames<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for BatchFrames<'a> {
+    type Item = Result<&'a [u8], WireError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        match postcard::take_from_bytes::<&[u8]>(self.rest) {
+            Ok((frame, rest)) => {
+                self.rest = rest;
+                Some(Ok(frame))
+            }
+            Err(_) => {
+                self.rest = &[];
+                Some(Err(WireError::DeserFailed))
+            }
+        }
+    }
+}
+
+/// A periodic sign of life from the device, sent on the [`HeartbeatTopic`]
+///
+/// See [`server::heartbeat`][crate::server::heartbeat].
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct Heartbeat {
+    /// Incremented (wrapping) with every heartbeat, so lost heartbeats can be noticed
+    pub seq: u32,
+    /// The interval at which the device currently sends heartbeats, in milliseconds
+    pub interval_ms: u32,
+}
+
+/// What the device should run after rebooting, requested with the [`RebootEndpoint`]
+///
+/// See the "Rebooting" section of [`define_dispatch!`][crate::define_dispatch].
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RebootMode {
+    /// Restart the application
+    Application,
+    /// Start the bootloader, e.g. to update the firmware
+    Bootloader,
+}
+
+/// The severity of a [`LogRecord`]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum LogLevel {
+    /// Very detailed information, usually only useful when debugging
+    Trace,
+    /// Information useful when debugging
+    Debug,
+    /// Normal operation
+    Info,
+    /// Something unexpected happened, but operation continues
+    Warn,
+    /// Something failed
+    Error,
+}
+
+impl core::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let s = match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        };
+        f.pad(s)
+    }
+}
+
+/// A structured log message, sent on the [`LogRecordTopic`]
+///
+/// Unlike the [`LoggingTopic`], which carries only the message, this also carries
+/// the [`LogLevel`] of the message. See [`rpc_log!`][crate::rpc_log].
+#[cfg(not(feature = "use-std"))]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct LogRecord<'a> {
+    /// The severity of the message
+    pub level: LogLevel,
+    /// The formatted message
+    pub msg: &'a str,
+}
+
+/// A structured log message, sent on the [`LogRecordTopic`]
+///
+/// Unlike the [`LoggingTopic`], which carries only the message, this also carries
+/// the [`LogLevel`] of the message. See [`rpc_log!`][crate::rpc_log].
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedLogRecord {
+    /// The severity of the message
+    pub level: LogLevel,
+    /// The formatted message
+    pub msg: String,
+}
+
+#[cfg(feature = "use-std")]
+impl core::fmt::Display for OwnedLogRecord {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "[{:<5}] {}", self.level, self.msg)
+    }
 }
 
 /// A single element of schema information
@@ -124,13 +472,277 @@ pub struct SchemaTotals {
     pub errors: u32,
 }
 
+/// The number of frames dispatched for a single key
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct KeyCount {
+    /// The key of the handler
+    pub key: Key,
+    /// The number of frames dispatched with this key
+    pub count: u32,
+}
+
+/// Dispatcher throughput counters, see [`server::metrics`][crate::server::metrics]
+#[cfg(not(feature = "use-std"))]
+#[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct StatsReport<'a> {
+    /// The total number of frames dispatched
+    pub frames: u32,
+    /// The total number of errors
+    pub errors: u32,
+    /// The number of frames dispatched for each handler key
+    pub keys: &'a [KeyCount],
+}
+
+/// Dispatcher throughput counters, see [`server::metrics`][crate::server::metrics]
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedStatsReport {
+    /// The total number of frames dispatched
+    pub frames: u32,
+    /// The total number of errors
+    pub errors: u32,
+    /// The number of frames dispatched for each handler key
+    pub keys: Vec<KeyCount>,
+}
+
+/// An error sent by the server, as recorded by an
+/// [`ErrorLog`][crate::server::error_log::ErrorLog]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct ErrorRecord {
+    /// The key of the request that caused the error, if known
+    pub key: Option<RequestKey>,
+    /// The sequence number of the request that caused the error
+    pub seq_no: u32,
+    /// The error sent to the client
+    pub error: WireError,
+}
+
+/// The most recent errors sent by the server, see [`server::error_log`][crate::server::error_log]
+#[cfg(not(feature = "use-std"))]
+#[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct ErrorLogReport<'a> {
+    /// The total number of errors recorded, including those no longer in the log
+    pub total: u32,
+    /// The most recent errors, oldest first
+    pub records: &'a [ErrorRecord],
+}
+
+/// The most recent errors sent by the server, see [`server::error_log`][crate::server::error_log]
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedErrorLogReport {
+    /// The total number of errors recorded, including those no longer in the log
+    pub total: u32,
+    /// The most recent errors, oldest first
+    pub records: Vec<ErrorRecord>,
+}
+
+/// The response of the [`HandshakeEndpoint`]
+///
+/// Maps the path of each endpoint handled by the server to its current request and
+/// response keys. As the keys are calculated from the schema of the request and
+/// response types, a client can compare them against its own keys to find out which
+/// endpoints it can still use, instead of only receiving an `UnknownKey` error.
+#[cfg(not(feature = "use-std"))]
+#[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct Handshake<'a> {
+    /// The [`PROTOCOL_VERSION`] of the server
+    pub protocol_version: u32,
+    /// The list of endpoints by path string, request key, and response key
+    pub endpoints: &'a [(&'a str, Key, Key)],
+}
+
+/// The response of the [`HandshakeEndpoint`]
+///
+/// Maps the path of each endpoint handled by the server to its current request and
+/// response keys. As the keys are calculated from the schema of the request and
+/// response types, a client can compare them against its own keys to find out which
+/// endpoints it can still use, instead of only receiving an `UnknownKey` error.
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedHandshake {
+    /// The [`PROTOCOL_VERSION`] of the server
+    pub protocol_version: u32,
+    /// The list of endpoints by path string, request key, and response key
+    pub endpoints: Vec<(String, Key, Key)>,
+}
+
+/// Whether an endpoint known to the client is usable with a server, see
+/// [`OwnedHandshake::endpoint_status()`]
+#[cfg(feature = "use-std")]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EndpointStatus {
+    /// The server handles the endpoint with the same request and response types
+    Compatible,
+    /// The server handles an endpoint with the same path, but with different
+    /// request or response types, e.g. a different version of the endpoint
+    Mismatch {
+        /// The request key used by the server
+        request_key: Key,
+        /// The response key used by the server
+        response_key: Key,
+    },
+    /// The server does not handle an endpoint with this path
+    Missing,
+}
+
+#[cfg(feature = "use-std")]
+impl OwnedHandshake {
+    /// Is the protocol version of the server the same as ours?
+    pub fn protocol_matches(&self) -> bool {
+        self.protocol_version == PROTOCOL_VERSION
+    }
+
+    /// Check whether the endpoint `E` can be used with this server
+    pub fn endpoint_status<E: crate::Endpoint>(&self) -> EndpointStatus {
+        let Some((_, request_key, response_key)) =
+            self.endpoints.iter().find(|(path, _, _)| path == E::PATH)
+        else {
+            return EndpointStatus::Missing;
+        };
+        if *request_key == E::REQ_KEY && *response_key == E::RESP_KEY {
+            EndpointStatus::Compatible
+        } else {
+            EndpointStatus::Mismatch {
+                request_key: *request_key,
+                response_key: *response_key,
+            }
+        }
+    }
+}
+
+/// The response of the [`CompatEndpoint`]
+///
+/// A compact summary of what the server speaks, to check whether a client can talk
+/// to it at all, without fetching the keys of every endpoint with the
+/// [`HandshakeEndpoint`], or the whole schema report.
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Compat {
+    /// The [`PROTOCOL_VERSION`] of the server
+    pub protocol_version: u32,
+    /// The [`CRATE_VERSION`] of the server
+    pub crate_version: [u16; 3],
+    /// The hash of the ICD of the server, over all endpoints and topics of its
+    /// dispatcher, see [`hash_icd()`][crate::hash::fnv1a64::hash_icd]
+    pub icd_hash: u64,
+    /// The longest frame the server can receive, or 0 if unknown
+    pub max_frame_len: u32,
+}
+
+/// The response of the [`GetGroupsEndpoint`]
+///
+/// Lists the endpoints that were given a group in `define_dispatch!`, e.g. by the
+/// subsystem they belong to, so a client can organize them. Endpoints without a
+/// group are not listed.
+#[cfg(not(feature = "use-std"))]
+#[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct EndpointGroups<'a> {
+    /// The list of grouped endpoints, by group name and request key
+    pub endpoints: &'a [(&'a str, Key)],
+}
+
+/// The response of the [`GetGroupsEndpoint`]
+///
+/// Lists the endpoints that were given a group in `define_dispatch!`, e.g. by the
+/// subsystem they belong to, so a client can organize them. Endpoints without a
+/// group are not listed.
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedEndpointGroups {
+    /// The list of grouped endpoints, by group name and request key
+    pub endpoints: Vec<(String, Key)>,
+}
+
+#[cfg(feature = "use-std")]
+impl OwnedEndpointGroups {
+    /// The group of the endpoint with the request key `req_key`, if it has one
+    pub fn group_of(&self, req_key: Key) -> Option<&str> {
+        self.endpoints
+            .iter()
+            .find(|(_, key)| *key == req_key)
+            .map(|(group, _)| group.as_str())
+    }
+
+    /// The names of all groups, in the order they first appear
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = vec![];
+        for (group, _) in &self.endpoints {
+            if !names.contains(&group.as_str()) {
+                names.push(group);
+            }
+        }
+        names
+    }
+
+    /// The request keys of the endpoints in `group`
+    pub fn keys_in<'a>(&'a self, group: &'a str) -> impl Iterator<Item = Key> + 'a {
+        self.endpoints
+            .iter()
+            .filter(move |(g, _)| g == group)
+            .map(|(_, key)| *key)
+    }
+}
+
+/// The request of the [`EchoFrameEndpoint`]
+///
+/// The payload is not interpreted by the server, it is only there to be echoed
+/// back as part of the body of the request.
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct EchoRequest<'b> {
+    /// Arbitrary bytes, e.g. a pattern that exercises the framing of a transport
+    pub data: &'b [u8],
+}
+
+/// The response of the [`EchoFrameEndpoint`]
+///
+/// Contains the request frame as the server saw it: the header, encoded again from
+/// what the server parsed, and the body exactly as it was handed to the dispatcher,
+/// e.g. after decompression.
+#[cfg(not(feature = "use-std"))]
+#[derive(Serialize, Schema, Debug, PartialEq, Copy, Clone)]
+pub struct EchoedFrame<'a> {
+    /// The encoded header of the request
+    pub header: &'a [u8],
+    /// The body of the request
+    pub body: &'a [u8],
+}
+
+/// The response of the [`EchoFrameEndpoint`]
+///
+/// Contains the request frame as the server saw it: the header, encoded again from
+/// what the server parsed, and the body exactly as it was handed to the dispatcher,
+/// e.g. after decompression.
+#[cfg(feature = "use-std")]
+#[derive(Serialize, Deserialize, Schema, Debug, PartialEq, Clone)]
+pub struct OwnedEchoedFrame {
+    /// The encoded header of the request
+    pub header: Vec<u8>,
+    /// The body of the request
+    pub body: Vec<u8>,
+}
+
 endpoints! {
     list = STANDARD_ICD_ENDPOINTS;
     omit_std = true;
-    | EndpointTy            | RequestTy     | ResponseTy    | Path                       |
-    | ----------            | ---------     | ----------    | ----                       |
-    | PingEndpoint          | u32           | u32           | "postcard-rpc/ping"        |
-    | GetAllSchemasEndpoint | ()            | SchemaTotals  | "postcard-rpc/schemas/get" |
+    | EndpointTy            | RequestTy       | ResponseTy          | Path                         | Cfg                           |
+    | ----------            | ---------       | ----------          | ----                         | ---                           |
+    | PingEndpoint          | u32             | u32                 | "postcard-rpc/ping"          |                               |
+    | GetAllSchemasEndpoint | ()              | SchemaTotals        | "postcard-rpc/schemas/get"   |                               |
+    | GetStatsEndpoint      | bool            | StatsReport<'a>     | "postcard-rpc/stats/get"     | cfg(not(feature = "use-std")) |
+    | GetStatsEndpoint      | bool            | OwnedStatsReport    | "postcard-rpc/stats/get"     | cfg(feature = "use-std")      |
+    | HandshakeEndpoint     | ()              | Handshake<'a>       | "postcard-rpc/handshake"     | cfg(not(feature = "use-std")) |
+    | HandshakeEndpoint     | ()              | OwnedHandshake      | "postcard-rpc/handshake"     | cfg(feature = "use-std")      |
+    | HasEndpointEndpoint   | Key             | bool                | "postcard-rpc/has-endpoint"  |                               |
+    | ResetEndpoint         | ()              | u32                 | "postcard-rpc/reset"         |                               |
+    | SetHeartbeatEndpoint  | u32             | bool                | "postcard-rpc/heartbeat/set" |                               |
+    | ErrorLogEndpoint      | bool            | ErrorLogReport<'a>  | "postcard-rpc/errors/get"    | cfg(not(feature = "use-std")) |
+    | ErrorLogEndpoint      | bool            | OwnedErrorLogReport | "postcard-rpc/errors/get"    | cfg(feature = "use-std")      |
+    | RebootEndpoint        | RebootMode      | bool                | "postcard-rpc/reboot"        |                               |
+    | CompatEndpoint        | ()              | Compat              | "postcard-rpc/compat"        |                               |
+    | GetGroupsEndpoint     | ()              | EndpointGroups<'a>  | "postcard-rpc/groups/get"    | cfg(not(feature = "use-std")) |
+    | GetGroupsEndpoint     | ()              | OwnedEndpointGroups | "postcard-rpc/groups/get"    | cfg(feature = "use-std")      |
+    | EchoFrameEndpoint     | EchoRequest<'b> | EchoedFrame<'a>     | "postcard-rpc/echo-frame"    | cfg(not(feature = "use-std")) |
+    | EchoFrameEndpoint     | EchoRequest<'b> | OwnedEchoedFrame    | "postcard-rpc/echo-frame"    | cfg(feature = "use-std")      |
 }
 
 topics! {
@@ -143,6 +755,9 @@ topics! {
     | GetAllSchemaDataTopic | OwnedSchemaData   | "postcard-rpc/schema/data"    | cfg(feature = "use-std")      |
     | LoggingTopic          | str               | "postcard-rpc/logging"        | cfg(not(feature = "use-std")) |
     | LoggingTopic          | String            | "postcard-rpc/logging"        | cfg(feature = "use-std")      |
+    | LogRecordTopic        | LogRecord<'a>     | "postcard-rpc/log"            | cfg(not(feature = "use-std")) |
+    | LogRecordTopic        | OwnedLogRecord    | "postcard-rpc/log"            | cfg(feature = "use-std")      |
+    | HeartbeatTopic        | Heartbeat         | "postcard-rpc/heartbeat"      |                               |
 }
 
 topics! {
@@ -151,4 +766,6 @@ topics! {
     omit_std = true;
     | TopicTy           | MessageTy         | Path                          | Cfg                           |
     | -------           | ---------         | ----                          | ---                           |
+    | FragmentTopic     | Fragment<'a>      | "postcard-rpc/fragment"       |                               |
+    | BatchTopic        | Batch<'a>         | "postcard-rpc/batch"          |                               |
 }
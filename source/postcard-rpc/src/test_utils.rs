@@ -6,7 +6,7 @@ use crate::header::{VarHeader, VarKey, VarSeq, VarSeqKind};
 use crate::host_client::util::Stopper;
 use crate::{
     host_client::{HostClient, RpcFrame, WireRx, WireSpawn, WireTx},
-    Endpoint, Topic,
+    Endpoint, Key, Topic,
 };
 use postcard_schema::Schema;
 use serde::{de::DeserializeOwned, Serialize};
@@ -215,3 +215,114 @@ where
 
     (lfs, client)
 }
+
+/// Assert that the frame `actual` is byte for byte equal to `expected`
+///
+/// On a mismatch, this panics with the first difference, in the header or the body
+/// of the frames. See [Determinism](crate::encode#determinism).
+#[track_caller]
+pub fn assert_frame_eq(expected: &[u8], actual: &[u8]) {
+    if expected == actual {
+        return;
+    }
+    let (exp_hdr, exp_body) = split_frame(expected);
+    let (act_hdr, act_body) = split_frame(actual);
+    if exp_hdr != act_hdr {
+        panic!("frame headers differ:\n  expected: {exp_hdr:02X?}\n    actual: {act_hdr:02X?}");
+    }
+    let at = exp_body
+        .iter()
+        .zip(act_body)
+        .position(|(e, a)| e != a)
+        .unwrap_or(exp_body.len().min(act_body.len()));
+    panic!(
+        "frame bodies differ at byte {at}:\n  expected: {exp_body:02X?}\n    actual: {act_body:02X?}"
+    );
+}
+
+/// Split a frame into the bytes of its header and its body, or treat it all as the
+/// header if it has none
+fn split_frame(frame: &[u8]) -> (&[u8], &[u8]) {
+    match VarHeader::take_from_slice(frame) {
+        Some((_, body)) => frame.split_at(frame.len() - body.len()),
+        None => (frame, &[]),
+    }
+}
+
+/// Assert that `msg` encodes to the same bytes after a round trip through postcard
+///
+/// This catches messages that are not encoded deterministically, like a `HashMap`
+/// with more than one entry, whose order changes when it is deserialized again.
+/// See [Determinism](crate::encode#determinism).
+#[track_caller]
+pub fn assert_stable_encoding<T>(msg: &T)
+where
+    T: Serialize + DeserializeOwned,
+{
+    let first = postcard::to_stdvec(msg).expect("the message should serialize");
+    let again: T = postcard::from_bytes(&first).expect("the message should deserialize");
+    let second = postcard::to_stdvec(&again).expect("the message should serialize");
+    assert!(
+        first == second,
+        "the encoding of the message changed after a round trip:\n  first: {first:02X?}\n  again: {second:02X?}"
+    );
+}
+
+/// Assert that a dispatcher and a client handle the same endpoints, with the same types
+///
+/// `$dispatcher` is the type defined with [`define_dispatch!`][crate::define_dispatch],
+/// and `$client` the type defined with [`define_client!`][crate::define_client]. As
+/// the keys of an endpoint are hashes of its path and the schemas of its types, this
+/// fails if only one side was changed, listing each endpoint that differs, see
+/// [`assert_icd_match()`].
+///
+/// ```rust,ignore
+/// #[test]
+/// fn icd_matches() {
+///     postcard_rpc::assert_icd_match!(MyDispatcher, MyDeviceClient);
+/// }
+/// ```
+#[macro_export]
+macro_rules! assert_icd_match {
+    ($dispatcher:ty, $client:ty $(,)?) => {
+        $crate::test_utils::assert_icd_match(<$dispatcher>::ENDPOINTS, <$client>::ENDPOINTS)
+    };
+}
+
+/// Assert that the endpoints handled by a device and those of a client are the same
+///
+/// Both are lists of the path, request key and response key of each endpoint, like
+/// the `ENDPOINTS` of a dispatcher or a client. On a mismatch, this panics listing
+/// each endpoint that only one side has, or that has different keys, and so types,
+/// on each side. Usually used with [`assert_icd_match!`][crate::assert_icd_match].
+#[track_caller]
+pub fn assert_icd_match(device: &[(&str, Key, Key)], client: &[(&str, Key, Key)]) {
+    let mut diffs = vec![];
+    for (path, req_key, resp_key) in device {
+        match client.iter().find(|(p, _, _)| p == path) {
+            None => diffs.push(format!("endpoint {path:?}: missing on the client")),
+            Some((_, creq, cresp)) => {
+                if creq != req_key {
+                    diffs.push(format!(
+                        "endpoint {path:?}: request key {req_key:?} on the device, {creq:?} on the client"
+                    ));
+                }
+                if cresp != resp_key {
+                    diffs.push(format!(
+                        "endpoint {path:?}: response key {resp_key:?} on the device, {cresp:?} on the client"
+                    ));
+                }
+            }
+        }
+    }
+    for (path, _, _) in client {
+        if !device.iter().any(|(p, _, _)| p == path) {
+            diffs.push(format!("endpoint {path:?}: missing on the device"));
+        }
+    }
+    assert!(
+        diffs.is_empty(),
+        "the ICD of the device and the client differ:\n  {}",
+        diffs.join("\n  ")
+    );
+}
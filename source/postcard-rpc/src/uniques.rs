@@ -224,6 +224,25 @@ const fn dmv_eq(a: &DataModelVariant, b: &DataModelVariant) -> bool {
     }
 }
 
+/// Is the variant `idx` of the enum `nty` a newtype variant named `name`, holding
+/// the type `ty`?
+///
+/// Used by the [`response_enum!`][crate::response_enum] macro to check the schema of
+/// the enum it defines.
+#[doc(hidden)]
+pub const fn is_newtype_variant(nty: &NamedType, idx: usize, name: &str, ty: &NamedType) -> bool {
+    let DataModelType::Enum(vars) = nty.ty else {
+        return false;
+    };
+    if idx >= vars.len() || !str_eq(vars[idx].name, name) {
+        return false;
+    }
+    match vars[idx].ty {
+        DataModelVariant::NewtypeVariant(inner) => nty_eq(inner, ty),
+        _ => false,
+    }
+}
+
 //////////////////////////////////////////////////////////////////////////////
 // STAGE 1 - UPPER BOUND CALCULATION
 //////////////////////////////////////////////////////////////////////////////